@@ -0,0 +1,201 @@
+//! A client for the IPC socket, that survives server restarts
+
+use std::{path::PathBuf, time::Duration};
+
+use tokio::net::UnixStream;
+
+use crate::{ipc_recv_cancel_safe, ipc_send, IPCError, IPCMsg, IPCMsgKind};
+
+/// Connection to the server's IPC socket.
+///
+/// If the connection is lost (or the server says `Bye`), it is re-established (with backoff) the next time a message
+/// is received, re-sending the auth token. the server then sends a fresh `Haiii`, which consumers should use to resync
+pub struct IpcClient {
+    path: PathBuf,
+    token: Option<String>,
+    stream: Option<UnixStream>,
+    // (for `ipc_recv_cancel_safe`)
+    buffer: Vec<u8>,
+    amnt: usize,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl IpcClient {
+    /// Connects to the socket at `path`, sending `token` first (if given)
+    pub async fn connect(path: PathBuf, token: Option<String>) -> Result<Self, IPCError> {
+        let mut client = Self {
+            path,
+            token,
+            stream: None,
+            buffer: vec![],
+            amnt: 0,
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+        };
+        client.try_connect().await?;
+        Ok(client)
+    }
+
+    /// Sets the time waited between reconnection attempts, which doubles after every failure (up to `max`)
+    pub fn with_backoff(self, min: Duration, max: Duration) -> Self {
+        Self {
+            min_backoff: min,
+            max_backoff: max,
+            ..self
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    async fn try_connect(&mut self) -> Result<(), IPCError> {
+        let mut stream = UnixStream::connect(&self.path).await?;
+        if let Some(token) = &self.token {
+            ipc_send(
+                &mut stream,
+                &IPCMsg {
+                    kind: IPCMsgKind::Auth {
+                        token: token.clone(),
+                    },
+                },
+            )
+            .await?;
+        }
+        self.stream = Some(stream);
+        self.buffer.clear();
+        self.amnt = 0;
+        Ok(())
+    }
+
+    /// Connects again, waiting between attempts until it succeeds
+    async fn reconnect(&mut self) {
+        self.stream = None;
+        let mut backoff = self.min_backoff;
+        loop {
+            match self.try_connect().await {
+                Ok(()) => {
+                    tracing::debug!("Reconnected to IPC socket {:?}", self.path);
+                    return;
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        "Failed to reconnect to IPC socket {:?} ({e}), retrying in {backoff:?}",
+                        self.path
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+            }
+        }
+    }
+
+    /// Receives the next message from the server, reconnecting if necessary.
+    ///
+    /// `Bye` is returned (the server is shutting down, or rejected the token), the next call reconnects.
+    /// this is cancel safe
+    pub async fn recv(&mut self) -> IPCMsgKind {
+        loop {
+            if self.stream.is_none() {
+                self.reconnect().await;
+            }
+            let stream = self.stream.as_mut().unwrap();
+            match ipc_recv_cancel_safe::<IPCMsg>(&mut self.buffer, &mut self.amnt, stream).await {
+                Ok(IPCMsg {
+                    kind: IPCMsgKind::Bye,
+                }) => {
+                    self.stream = None;
+                    return IPCMsgKind::Bye;
+                }
+                Ok(msg) => return msg.kind,
+                Err(e) => {
+                    tracing::warn!("IPC connection lost ({e}), reconnecting");
+                    self.stream = None;
+                }
+            }
+        }
+    }
+
+    /// Sends a message to the server. if this fails, the connection is re-established by the next `recv`
+    pub async fn send(&mut self, kind: IPCMsgKind) -> Result<(), IPCError> {
+        let Some(stream) = &mut self.stream else {
+            return Err(IPCError::EOF);
+        };
+        let res = ipc_send(stream, &IPCMsg { kind }).await;
+        if res.is_err() {
+            self.stream = None;
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn reconnects_after_server_restart() {
+    use squirrel::api::station::{capabilities::KnownChannels, identity::KnownStations};
+    use tokio::net::UnixListener;
+
+    use crate::ipc_recv;
+
+    let dir = std::env::temp_dir().join(format!("mycelium-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ipc.sock");
+    let _ = std::fs::remove_file(&path);
+    let haiii = || IPCMsg {
+        kind: IPCMsgKind::Haiii {
+            stations: KnownStations::new(),
+            channels: KnownChannels::new(),
+        },
+    };
+    // checks the token, and greets the client
+    async fn accept(listener: &UnixListener, hello: IPCMsg) -> UnixStream {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let auth = ipc_recv::<IPCMsg>(&mut stream).await.unwrap();
+        assert!(matches!(auth.kind, IPCMsgKind::Auth { token } if token == "hunter2"));
+        ipc_send(&mut stream, &hello).await.unwrap();
+        stream
+    }
+
+    let listener = UnixListener::bind(&path).unwrap();
+    let (client, server) = tokio::join!(
+        IpcClient::connect(path.clone(), Some("hunter2".into())),
+        accept(&listener, haiii())
+    );
+    let mut client = client
+        .unwrap()
+        .with_backoff(Duration::from_millis(10), Duration::from_millis(50));
+    assert!(matches!(client.recv().await, IPCMsgKind::Haiii { .. }));
+
+    // the connection is dropped, and re-accepted
+    drop(server);
+    let (msg, _server) = tokio::join!(client.recv(), accept(&listener, haiii()));
+    assert!(matches!(msg, IPCMsgKind::Haiii { .. }));
+
+    // the server restarts (the socket is gone for a while)
+    drop(_server);
+    drop(listener);
+    std::fs::remove_file(&path).unwrap();
+    let restart = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut stream = accept(&listener, haiii()).await;
+        ipc_send(
+            &mut stream,
+            &IPCMsg {
+                kind: IPCMsgKind::Bye,
+            },
+        )
+        .await
+        .unwrap();
+        (listener, stream)
+    };
+    let (msg, (listener, _stream)) = tokio::join!(client.recv(), restart);
+    assert!(matches!(msg, IPCMsgKind::Haiii { .. }));
+    assert!(matches!(client.recv().await, IPCMsgKind::Bye));
+    assert!(!client.is_connected());
+    let (msg, _server) = tokio::join!(client.recv(), accept(&listener, haiii()));
+    assert!(matches!(msg, IPCMsgKind::Haiii { .. }));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
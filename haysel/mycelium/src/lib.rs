@@ -16,8 +16,13 @@ use squirrel::api::station::{
     capabilities::{Channel, ChannelData, ChannelID, KnownChannels},
     identity::{KnownStations, StationID},
 };
+use squirrel::transport::shared::TransportStats;
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 
+mod client;
+
+pub use client::IpcClient;
+
 #[derive(Debug, Error)]
 pub enum IPCError {
     #[error("IO Operation failed {0}")]
@@ -83,14 +88,94 @@ pub async fn ipc_recv_cancel_safe<T: DeserializeOwned>(
                     n => *amnt += n,
                 }
             } else {
+                let res = rmp_serde::from_slice(&buffer[8..][..the_rest]);
+                // keep anything received after this packet (the start of the next one)
                 buffer.copy_within(8 + the_rest..*amnt, 0);
-                *amnt = 0;
-                return Ok(rmp_serde::from_slice(&buffer[8..][..the_rest])?);
+                *amnt -= 8 + the_rest;
+                return Ok(res?);
             }
         }
     }
 }
 
+/// Counters describing server activity since it started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerMetrics {
+    /// transport packets received from weather stations
+    pub packets_received: u64,
+    /// datagrams that were ignored (not a valid packet, or over the rate limit)
+    pub packets_dropped: u64,
+    /// transport packets sent to weather stations
+    pub packets_sent: u64,
+    /// groups of data fully received from weather stations
+    pub transactions_completed: u64,
+    /// transactions that took too long, and were abandoned
+    pub transactions_timed_out: u64,
+    /// messages sent on the server's internal bus
+    pub bus_dispatched: u64,
+    /// internal bus messages missed by handlers that fell behind
+    pub bus_lagged: u64,
+}
+
+/// The latest `Diagnostics` received from a station (not persisted, so only stations that sent one since the
+/// server started are known)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StationDiagnostics {
+    pub received_at: DateTime<Utc>,
+    pub transport: TransportStats,
+}
+
+/// Where the next page of a `IPCMsgKind::QueryRange` starts. returned by the server, and passed back unchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryCursor {
+    /// time of the last reading returned
+    pub time: DateTime<Utc>,
+    /// number of readings at exactly `time` that have been returned
+    pub skip: u32,
+}
+
+/// How the server is doing, for supervisors (see `IPCMsgKind::HealthCheck`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthStatus {
+    /// the database is open, and accepting requests
+    pub db_open: bool,
+    /// new IPC clients are being accepted
+    pub ipc_listening: bool,
+    /// when a packet was last received from a weather station (since the server started)
+    pub last_data_received: Option<DateTime<Utc>>,
+    /// no packet has been received from a weather station for longer than expected.
+    /// (the server may be fine, and the stations not, so this does not count against [`is_healthy`][Self::is_healthy])
+    pub data_stale: bool,
+    /// handlers running on the server's internal bus
+    pub live_handlers: u64,
+}
+
+impl HealthStatus {
+    /// if the server is running (liveness), regardless of whether data is arriving
+    pub fn is_healthy(&self) -> bool {
+        self.db_open && self.ipc_listening
+    }
+
+    /// if the server is running, and receiving data from weather stations (readiness)
+    pub fn is_ready(&self) -> bool {
+        self.is_healthy() && !self.data_stale
+    }
+}
+
+/// Summary of what is stored in the database
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DBStats {
+    pub station_count: u64,
+    /// channels summed across all stations (a channel shared by two stations is counted twice)
+    pub channel_count: u64,
+    /// readings stored across all channels (events are not included)
+    pub approx_reading_count: u64,
+    /// bytes of the database file that have been allocated
+    pub bytes_used: u64,
+    /// size of the database file
+    pub bytes_capacity: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IPCMsg {
     pub kind: IPCMsgKind,
@@ -111,6 +196,16 @@ pub enum IPCMsgKind {
         recorded_at: DateTime<Utc>,
         by_channel: HashMap<ChannelID, ChannelData>,
     },
+    /// a reading crossed the threshold of an alarm (`raised`), or returned past it (see the server's `alarms` config)
+    Alarm {
+        station: StationID,
+        channel: ChannelID,
+        /// the reading that raised (or cleared) the alarm
+        value: f32,
+        /// name of the alarm
+        rule: String,
+        raised: bool,
+    },
     NewStation {
         id: StationID,
     },
@@ -127,10 +222,63 @@ pub enum IPCMsgKind {
         data: Vec<(DateTime<Utc>, f32)>,
         from_time: DateTime<Utc>,
     },
+    // response to QueryRange
+    QueryRangeResponse {
+        data: Vec<(DateTime<Utc>, f32)>,
+        /// None if this was the last page
+        next_cursor: Option<QueryCursor>,
+    },
+    // response to QueryLatest: the newest value of every channel, and when it was recorded
+    LatestResponse(HashMap<StationID, HashMap<ChannelID, (DateTime<Utc>, ChannelData)>>),
+    // response to QueryMetrics
+    MetricsResponse(ServerMetrics),
+    // response to QueryDBStats
+    DBStatsResponse(DBStats),
+    // response to QueryDiagnostics
+    DiagnosticsResponse(HashMap<StationID, StationDiagnostics>),
+    // response to HealthCheck
+    HealthResponse(HealthStatus),
+    // response to ForceSave, once saving is complete
+    SaveComplete {
+        /// number of handlers that saved
+        handlers: u32,
+    },
+    // response to BeginOTA
+    OTAQueued {
+        station: StationID,
+        /// false if the station is not connected (nothing was sent)
+        queued: bool,
+    },
     /// -- client to server --
+    /// must be the first message sent, if the server requires a token
+    Auth {
+        token: String,
+    },
     ClientDisconnect,
     QueryLastHourOf {
         station: StationID,
         channel: ChannelID,
     },
+    /// one page of the readings from `from` (inclusive) to `to` (exclusive), oldest first.
+    /// `cursor` is None for the first page, and the previous response's `next_cursor` after that
+    QueryRange {
+        station: StationID,
+        channel: ChannelID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        cursor: Option<QueryCursor>,
+    },
+    QueryLatest,
+    QueryMetrics,
+    QueryDBStats,
+    QueryDiagnostics,
+    /// liveness probe
+    HealthCheck,
+    /// save everything now (e.g. before a planned shutdown)
+    ForceSave,
+    /// send a firmware update to a (connected) station. it is downloaded after the station next sends data
+    BeginOTA {
+        station: StationID,
+        update: squirrel::api::BeginOTA,
+    },
 }
@@ -15,15 +15,21 @@ use std::{any::TypeId, fmt::Debug};
 pub trait GeneralRequirements: TypeNamed + AsAny + 'static {}
 impl<T: 'static> GeneralRequirements for T {}
 
-#[repr(transparent)]
 pub struct DynVar {
     val: Box<dyn GeneralRequirements + Sync + Send + 'static>,
+    /// type of `val`, recorded when it was created.
+    ///
+    /// all downcasts are checked against this, so a mismatched type is an error (not a mis-cast)
+    type_id: TypeId,
 }
 
 impl DynVar {
     #[must_use]
     pub fn new<T: GeneralRequirements + Sync + Send + 'static>(x: T) -> Self {
-        Self { val: Box::new(x) }
+        Self {
+            val: Box::new(x),
+            type_id: TypeId::of::<T>(),
+        }
     }
 
     #[must_use]
@@ -35,7 +41,8 @@ impl DynVar {
     #[must_use]
     #[allow(dead_code)]
     pub fn from_raw(val: Box<dyn GeneralRequirements + Sync + Send + 'static>) -> Self {
-        Self { val }
+        let type_id = (*val).as_any().type_id();
+        Self { val, type_id }
     }
 
     #[must_use]
@@ -45,35 +52,75 @@ impl DynVar {
 
     #[must_use]
     pub fn as_ref<T: GeneralRequirements>(&self) -> Option<&T> {
+        if !self.is::<T>() {
+            return None;
+        }
         (*self.val).as_any().downcast_ref()
     }
 
     #[must_use]
     pub fn as_mut<T: GeneralRequirements>(&mut self) -> Option<&mut T> {
+        if !self.is::<T>() {
+            return None;
+        }
         (*self.val).mut_any().downcast_mut()
     }
 
     pub fn try_to<T: GeneralRequirements>(self) -> Result<T, Self> {
-        if (*self.val).as_any().type_id() == TypeId::of::<T>() {
+        if !self.is::<T>() {
+            return Err(self);
+        }
+        // with `bus_dbg`, the cast is checked again (a failure here would be a bug in `AsAny`)
+        #[cfg(feature = "bus_dbg")]
+        {
+            Ok(*self
+                .val
+                .to_any()
+                .downcast()
+                .expect("DynVar: value is not of its recorded type"))
+        }
+        #[cfg(not(feature = "bus_dbg"))]
+        {
+            // SAFETY: `self.type_id` is the type of `val` (the constructors record it), and was just checked
             Ok(unsafe { *self.val.to_any().downcast().unwrap_unchecked() })
-        } else {
-            Err(self)
         }
     }
 
     #[must_use]
     pub fn is<T: GeneralRequirements>(&self) -> bool {
-        (*self.val).as_any().type_id() == TypeId::of::<T>()
+        let is = self.type_id == TypeId::of::<T>();
+        #[cfg(feature = "bus_dbg")]
+        assert_eq!(
+            is,
+            (*self.val).as_any().type_id() == TypeId::of::<T>(),
+            "DynVar: value is not of its recorded type"
+        );
+        is
     }
 
     #[must_use]
     pub fn clone_as<T: GeneralRequirements + Clone + Sync + Send + 'static>(&self) -> Option<Self> {
-        Some(Self {
-            val: Box::new(self.as_ref::<T>()?.clone()),
-        })
+        Some(Self::new(self.as_ref::<T>()?.clone()))
     }
 }
 
+#[cfg(test)]
+#[test]
+fn mismatched_type_is_error() {
+    let mut var = DynVar::new(5u32);
+    assert!(var.is::<u32>());
+    assert!(!var.is::<i32>());
+    assert!(var.as_ref::<i32>().is_none());
+    assert!(var.as_mut::<u64>().is_none());
+    assert_eq!(var.as_ref::<u32>(), Some(&5));
+    let var = var.try_to::<String>().unwrap_err();
+    assert_eq!(var.try_to::<u32>().unwrap(), 5);
+
+    let var = DynVar::from_raw(Box::new(String::from("x")));
+    assert!(var.is::<String>());
+    assert!(var.try_to::<&str>().is_err());
+}
+
 impl Debug for DynVar {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DynVar").finish_non_exhaustive()
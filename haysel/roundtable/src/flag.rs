@@ -23,6 +23,10 @@ impl Flag {
         self.waker.wake();
     }
 
+    pub fn is_set(&self) -> bool {
+        self.set.load(Relaxed)
+    }
+
     pub fn reset(&self) {
         self.set.store(false, Relaxed);
     }
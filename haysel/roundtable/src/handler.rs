@@ -1,6 +1,6 @@
 mod async_fn_ptr;
 mod decl;
-mod dispatch;
+pub(crate) mod dispatch;
 mod interface;
 mod macros;
 mod register;
@@ -11,8 +11,11 @@ use std::fmt::{Debug, Display};
 use crate::msg::{self, Str};
 
 pub use decl::MethodDecl;
-pub use dispatch::DispatchErr;
-pub use interface::{local::LocalInterface, Interface};
+pub use dispatch::{DispatchErr, HandlerError};
+pub use interface::{
+    local::{BgHandle, LocalInterface},
+    Interface, SpawnErr,
+};
 pub use register::MethodRegister;
 
 /// Trait that describes a handlers functionality.
@@ -126,7 +126,7 @@ where
         i: &'a LocalInterface,
     ) -> Result<BoxFuture<'a, Result<DynVar, DynVar>>, CallError> {
         let h_name = h.type_name();
-        let a_name = h.type_name();
+        let a_name = a.type_name();
         let h = h
             .as_mut::<H>()
             .ok_or(CallError::MismatchHandler(type_name::<H>(), h_name))?;
@@ -170,7 +170,7 @@ where
         i: &'a LocalInterface,
     ) -> Result<BoxFuture<'a, Result<DynVar, DynVar>>, CallError> {
         let h_name = h.type_name();
-        let a_name = h.type_name();
+        let a_name = a.type_name();
         let h = h
             .as_mut::<H>()
             .ok_or(CallError::MismatchHandler(type_name::<H>(), h_name))?;
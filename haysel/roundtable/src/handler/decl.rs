@@ -2,9 +2,12 @@ use std::{fmt::Debug, marker::PhantomData};
 
 use uuid::Uuid;
 
-use crate::handler::async_fn_ptr::HandlerCallableErased;
 #[cfg(feature = "bus_dbg")]
 use crate::msg::Str;
+use crate::{
+    dyn_var::DynVar,
+    handler::{async_fn_ptr::HandlerCallableErased, dispatch::HandlerError},
+};
 
 pub struct MethodDecl<const OWN: bool, At: 'static, Rt: 'static> {
     pub(crate) id: Uuid,
@@ -34,6 +37,9 @@ impl<const OWN: bool, At: 'static, Rt: 'static> MethodDecl<OWN, At, Rt> {
 /// Describes the (non-ID portion) of a method, incl its handler function
 pub struct MethodRaw {
     pub handler_func: Box<(dyn HandlerCallableErased + Sync + Send)>,
+    /// for methods registered with [`register_fallible`][super::MethodRegister::register_fallible],
+    /// splits the `Result<Rt, HandlerError>` they return into the response and the error for the caller
+    pub split_result: Option<fn(DynVar) -> Result<DynVar, HandlerError>>,
     #[cfg(feature = "bus_dbg")]
     pub handler_desc: Str,
 }
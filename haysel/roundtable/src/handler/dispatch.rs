@@ -1,4 +1,7 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Result;
 use tokio::time::timeout;
@@ -10,8 +13,23 @@ use crate::{
     handler::interface::Interface,
     id::Uid,
     msg::{self, HandlerInstance, ResponseErr},
+    SlowHandlerPolicy,
 };
 
+/// An error returned by a handler method to its caller, registered with
+/// [`register_fallible`][super::MethodRegister::register_fallible].
+///
+/// unlike the handler's own error type, this does not shut the handler down
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{0}")]
+pub struct HandlerError(pub String);
+
+impl HandlerError {
+    pub fn new(msg: impl std::fmt::Display) -> Self {
+        Self(msg.to_string())
+    }
+}
+
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum DispatchErr {
     #[error("No handlers handled the message: {0}")]
@@ -20,6 +38,10 @@ pub enum DispatchErr {
     NullResponse,
     #[error("An error occured while handling request: {0:#}")]
     HandlerError(#[from] ResponseErr),
+    #[error("The request failed: {0}")]
+    Failed(HandlerError),
+    #[error("The bus is closed (shutting down)")]
+    Closed,
 }
 
 pub async fn bus_dispatch_event(
@@ -31,6 +53,9 @@ pub async fn bus_dispatch_event(
     want_response: bool,
     want_verification: bool,
 ) -> Result<Option<DynVar>, DispatchErr> {
+    if int.is_closed() {
+        return Err(DispatchErr::Closed);
+    }
     let message_id = Uid::gen_with(&int.uid_src);
     let response = if let msg::Target::Instance(..) = target {
         if want_response {
@@ -57,10 +82,8 @@ pub async fn bus_dispatch_event(
         },
     });
     // avoid erroring when no tasks are watching the channel
-    if let Err(..) = int.comm.send(message.clone()) {
-        if want_response || want_verification {
-            return Err(DispatchErr::NoResponse("no active handlers"));
-        }
+    if !bus_send(&int, message.clone()).await && (want_response || want_verification) {
+        return Err(DispatchErr::NoResponse("no active handlers"));
     }
     #[allow(irrefutable_let_patterns)]
     let msg::MsgKind::Request {
@@ -72,7 +95,7 @@ pub async fn bus_dispatch_event(
     };
 
     match responder {
-        msg::Responder::NoVerify => Ok(None),
+        msg::Responder::NoVerify | msg::Responder::Collect { .. } => Ok(None),
         msg::Responder::Verify { waker } => {
             let Ok(..) = timeout(Duration::from_secs(15), waker).await else {
                 return Err(DispatchErr::NoResponse("timed out"));
@@ -88,6 +111,7 @@ pub async fn bus_dispatch_event(
                 } else {
                     match res.map(|x| *x) {
                         Some(Ok(ret)) => Ok(Some(ret)),
+                        Some(Err(ResponseErr::Failed(e))) => Err(DispatchErr::Failed(e)),
                         Some(Err(e)) => Err(e)?,
                         None => Ok(None),
                     }
@@ -102,3 +126,67 @@ pub async fn bus_dispatch_event(
         }
     }
 }
+
+/// sends a request to every handler matching `target`, and collects all responses received before `deadline`
+pub async fn bus_dispatch_collect(
+    int: Interface,
+    source: HandlerInstance,
+    target: msg::Target,
+    method: msg::MethodID,
+    arguments: DynVar,
+    deadline: Duration,
+) -> Result<Vec<Result<DynVar, ResponseErr>>, DispatchErr> {
+    if int.is_closed() {
+        return Err(DispatchErr::Closed);
+    }
+    let message = Arc::new(msg::Msg {
+        id: Uid::gen_with(&int.uid_src),
+        kind: msg::MsgKind::Request {
+            source,
+            target,
+            method,
+            arguments,
+            response: msg::Responder::Collect {
+                values: Mutex::new(vec![]),
+            },
+        },
+    });
+    if !bus_send(&int, message.clone()).await {
+        return Err(DispatchErr::NoResponse("no active handlers"));
+    }
+    tokio::time::sleep(deadline).await;
+    let msg::MsgKind::Request {
+        response: msg::Responder::Collect { values },
+        ..
+    } = &message.kind
+    else {
+        unreachable!()
+    };
+    // (responses that arrive after this are dropped)
+    let values = std::mem::take(&mut *values.lock().unwrap());
+    Ok(values)
+}
+
+/// puts `message` on the bus, returning false if there are no handlers to receive it
+async fn bus_send(int: &Interface, message: Arc<msg::Msg>) -> bool {
+    int.metrics.dispatched.fetch_add(1, Ordering::Relaxed);
+    let send_guard = if int.config.slow_handler_policy == SlowHandlerPolicy::Block {
+        let guard = int.send_lock.lock().await;
+        // wait for the slowest handler to make room
+        loop {
+            let drained = int.comm_drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+            if int.comm.len() < int.config.comm_queue_cap {
+                break;
+            }
+            drained.await;
+        }
+        Some(guard)
+    } else {
+        None
+    };
+    let sent = int.comm.send(message).is_ok();
+    drop(send_guard);
+    sent
+}
@@ -1,24 +1,70 @@
 use std::{
     any::type_name,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch, Mutex, Notify};
 
 #[cfg(feature = "bus_dbg")]
 use crate::msg::Str;
 use crate::{
     dyn_var::DynVar,
     handler::{
-        decl::MethodDecl, dispatch::bus_dispatch_event, runtime::HandlerTaskRt, HandlerInit,
+        decl::MethodDecl,
+        dispatch::{bus_dispatch_collect, bus_dispatch_event, HandlerError},
+        runtime::HandlerTaskRt,
+        HandlerInit,
     },
+    metrics::BusMetrics,
     msg::{self, HandlerInstance, Msg},
+    BusConfig,
 };
 
 use super::dispatch::DispatchErr;
 
 pub mod local;
 
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum SpawnErr {
+    #[error("Handler {0} failed to initialize: {1}")]
+    InitFailed(&'static str, HandlerError),
+    #[error("Handler {0} exited before finishing initialization (it panicked, or was shut down)")]
+    Exited(&'static str),
+}
+
+/// counts a handler in [`BusMetrics::live_handlers`] until dropped (also if its task panics)
+struct LiveHandler(Arc<BusMetrics>);
+
+impl LiveHandler {
+    fn new(metrics: Arc<BusMetrics>) -> Self {
+        metrics.live_handlers.fetch_add(1, Ordering::Relaxed);
+        Self(metrics)
+    }
+}
+
+impl Drop for LiveHandler {
+    fn drop(&mut self) {
+        self.0.live_handlers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn run_handler<H: HandlerInit>(rt: HandlerTaskRt<H>, metrics: Arc<BusMetrics>) {
+    let live = LiveHandler::new(metrics);
+    tokio::spawn(async move {
+        let _live = live;
+        let res = rt.run().await;
+        if let Err(e) = res {
+            error!("Runtime task exited with error: {e:#}");
+        } else {
+            trace!("Runtime task exited");
+        }
+    });
+}
+
 #[derive(Clone)]
 pub struct Interface {
     /// source for generating uids (faster than Uuid::new_v4, since it only requires a single
@@ -29,24 +75,57 @@ pub struct Interface {
     /// Arc is used to avoid cloning a (large) Msg value that will never need writing to
     /// TODO: arena allocate Msg?
     pub(crate) comm: broadcast::Sender<Arc<Msg>>,
+    pub(crate) config: BusConfig,
+    /// notified whenever a handler receives a message from `comm` (only with [`SlowHandlerPolicy::Block`])
+    ///
+    /// [`SlowHandlerPolicy::Block`]: crate::SlowHandlerPolicy::Block
+    pub(crate) comm_drained: Arc<Notify>,
+    /// held while waiting for room in `comm` and sending (only with [`SlowHandlerPolicy::Block`]),
+    /// so that concurrent senders can not overfill it
+    ///
+    /// [`SlowHandlerPolicy::Block`]: crate::SlowHandlerPolicy::Block
+    pub(crate) send_lock: Arc<Mutex<()>>,
+    pub(crate) metrics: Arc<BusMetrics>,
+    /// true once the [`Bus`][crate::Bus] has been closed
+    pub(crate) closed: watch::Receiver<bool>,
 }
 
 impl Interface {
+    pub fn metrics(&self) -> &BusMetrics {
+        &self.metrics
+    }
+
+    /// if the bus has been closed (it is shutting down), and requests will fail with [`DispatchErr::Closed`]
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
     pub fn spawn<H: HandlerInit>(&self, instance: H) -> HandlerInstance {
         let inter = self.clone();
         let rt = HandlerTaskRt::new(inter, instance);
         let inst = rt.id();
-        tokio::spawn(async move {
-            let res = rt.run().await;
-            if let Err(e) = res {
-                error!("Runtime task exited with error: {e:#}");
-            } else {
-                trace!("Runtime task exited");
-            }
-        });
+        run_handler(rt, self.metrics.clone());
         inst
     }
 
+    /// Like [`spawn`][Interface::spawn], but waits for the handler's [`init`][HandlerInit::init] to complete,
+    /// returning its error if it fails (for handlers that the rest of the program can not run without)
+    pub async fn spawn_checked<H: HandlerInit>(
+        &self,
+        instance: H,
+    ) -> Result<HandlerInstance, SpawnErr> {
+        let inter = self.clone();
+        let mut rt = HandlerTaskRt::new(inter, instance);
+        let ready = rt.notify_ready();
+        let inst = rt.id();
+        run_handler(rt, self.metrics.clone());
+        match ready.await {
+            Ok(Ok(())) => Ok(inst),
+            Ok(Err(e)) => Err(SpawnErr::InitFailed(type_name::<H>(), e)),
+            Err(..) => Err(SpawnErr::Exited(type_name::<H>())),
+        }
+    }
+
     /// Dispatch, no verification, no response
     pub async fn announce_as<At: Sync + Send + 'static, Rt: 'static>(
         &self,
@@ -97,6 +176,54 @@ impl Interface {
         Ok(())
     }
 
+    /// Dispatch to every handler matching `target`, returning the responses of all that answered before `deadline`
+    ///
+    /// always waits for the full `deadline` (there is no way to know how many handlers will answer).
+    /// handlers that fail are left out
+    pub async fn dispatch_collect_as<At: Sync + Send + 'static, Rt: 'static>(
+        &self,
+        source: HandlerInstance,
+        target: msg::Target,
+        method: MethodDecl<false, At, Rt>,
+        args: At,
+        deadline: Duration,
+    ) -> Result<Vec<Rt>, DispatchErr> {
+        let responses = bus_dispatch_collect(
+            self.clone(),
+            source,
+            target,
+            msg::MethodID {
+                id: method.id,
+                #[cfg(feature = "bus_dbg")]
+                id_desc: Str::Borrowed(method.desc),
+            },
+            DynVar::new(args),
+            deadline,
+        )
+        .await?;
+        Ok(responses
+            .into_iter()
+            .filter_map(|res| match res {
+                Ok(ret) => Some(ret),
+                Err(e) => {
+                    debug!("Handler failed to respond to collected query: {e:#}");
+                    None
+                }
+            })
+            .map(|ret| match ret.try_to() {
+                Ok(ret) => ret,
+                Err(ret) => {
+                    error!(
+                        "Mismatched return type - expected {}, found {}",
+                        type_name::<Rt>(),
+                        ret.type_name()
+                    );
+                    unreachable!("Mismatched return type");
+                }
+            })
+            .collect())
+    }
+
     /// Dispatch, returns the response
     pub async fn query_as<At: Sync + Send + 'static, Rt: 'static>(
         &self,
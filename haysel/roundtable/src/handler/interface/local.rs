@@ -1,5 +1,13 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use futures::{
-    future::{pending, BoxFuture},
+    future::{pending, select, BoxFuture, Either},
     Future,
 };
 use uuid::Uuid;
@@ -13,27 +21,149 @@ use crate::{
 
 pub struct LocalInterface {
     pub nonlocal: Interface,
-    pub(crate) bg_spawner: flume::Sender<(BoxFuture<'static, DynVar>, Uuid, &'static str)>,
+    pub(crate) bg_spawner: flume::Sender<BgTask>,
     pub(crate) update_metadata: Flag,
     pub(crate) shutdown: Flag,
     pub(crate) instance: HandlerInstance,
     pub(crate) message_source: Option<HandlerInstance>,
 }
 
+/// A background task (or one iteration of a loop), as run by the handler runtime
+pub(crate) struct BgTask {
+    /// resolves to the task's result (None if it was cancelled), and the next iteration (for loops)
+    pub(crate) future: BoxFuture<'static, (Option<DynVar>, Option<BgTask>)>,
+    pub(crate) method_id: Uuid,
+    pub(crate) method_desc: &'static str,
+}
+
+/// Handle to a task started with [`LocalInterface::bg_spawn`] or [`LocalInterface::bg_loop`]
+#[derive(Debug, Clone)]
+pub struct BgHandle {
+    cancel: Arc<Flag>,
+    // set once the task completes (and its event is generated), or for loops, once it stops
+    done: Arc<AtomicBool>,
+}
+
+impl BgHandle {
+    fn new() -> Self {
+        Self {
+            cancel: Arc::new(Flag::new()),
+            done: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// stop the task. if it has not already completed, its event will not be generated
+    pub fn cancel(&self) {
+        self.cancel.signal();
+    }
+
+    /// if the task has neither completed nor been cancelled
+    pub fn is_running(&self) -> bool {
+        !self.done.load(Ordering::Relaxed) && !self.cancel.is_set()
+    }
+
+    /// runs `f`, unless (or until) the task is cancelled
+    async fn run<T>(&self, f: impl Future<Output = T>) -> Option<T> {
+        if self.cancel.is_set() {
+            return None;
+        }
+        match select(Box::pin(f), &*self.cancel).await {
+            Either::Left((res, _)) => Some(res),
+            Either::Right(..) => None,
+        }
+    }
+}
+
 impl LocalInterface {
     /// runs `f` to completion, allowing other events to be processed in the meantime. when F completes,
     /// an event (with decl `m`) is generated *for this handler only* containing the results.
     ///
     /// This can be used for a pattern where, for example a socket's receive half is put into a background task,
-    /// waits to receive, then returns itself + what it received, and finally the handler spawns the task again.
+    /// waits to receive, then returns itself + what it received, and finally the handler spawns the task again
+    /// (see [`bg_loop`][Self::bg_loop], which does this automatically).
+    ///
+    /// the returned handle can be used to cancel the task (in which case no event is generated).
+    /// any tasks still running when the handler shuts down are cancelled
     pub fn bg_spawn<T: Sync + Send>(
         &self,
         m: MethodDecl<true, T, ()>,
         f: impl Future<Output = T> + Send + 'static,
-    ) {
-        let dyn_f: BoxFuture<'static, DynVar> = Box::pin(async move { DynVar::new(f.await) });
-        let MethodDecl { id, desc, .. } = m;
-        if let Err(..) = self.bg_spawner.send((dyn_f, id, desc)) {
+    ) -> BgHandle {
+        let handle = BgHandle::new();
+        let task_handle = handle.clone();
+        self.spawn_task(BgTask {
+            future: Box::pin(async move {
+                let res = task_handle.run(f).await;
+                task_handle.done.store(true, Ordering::Relaxed);
+                (res.map(DynVar::new), None)
+            }),
+            method_id: m.id,
+            method_desc: m.desc,
+        });
+        handle
+    }
+
+    /// like [`bg_spawn`][Self::bg_spawn], but once the event for a result of `f` has been handled,
+    /// `f` is called again to start the next iteration, so that (for example) a socket's receive loop can not be
+    /// stopped by forgetting to re-spawn it.
+    ///
+    /// `state` is passed to the first iteration, and each iteration returns it (for the next) along with its result,
+    /// so that it can be owned by the loop instead of shared with it (like the socket being received from).
+    ///
+    /// every result generates an event, but the loop stops after an `Err` (or if the handler method returns an error).
+    /// the returned handle can be used to stop the loop, cancelling the current iteration
+    pub fn bg_loop<S, T, E, F, Fut>(
+        &self,
+        m: MethodDecl<true, Result<T, E>, ()>,
+        state: S,
+        f: F,
+    ) -> BgHandle
+    where
+        S: Send + 'static,
+        T: Sync + Send + 'static,
+        E: Sync + Send + 'static,
+        F: FnMut(S) -> Fut + Send + 'static,
+        Fut: Future<Output = (S, Result<T, E>)> + Send + 'static,
+    {
+        let handle = BgHandle::new();
+        self.spawn_task(Self::loop_iteration(m, state, f, handle.clone()));
+        handle
+    }
+
+    fn loop_iteration<S, T, E, F, Fut>(
+        m: MethodDecl<true, Result<T, E>, ()>,
+        state: S,
+        mut f: F,
+        handle: BgHandle,
+    ) -> BgTask
+    where
+        S: Send + 'static,
+        T: Sync + Send + 'static,
+        E: Sync + Send + 'static,
+        F: FnMut(S) -> Fut + Send + 'static,
+        Fut: Future<Output = (S, Result<T, E>)> + Send + 'static,
+    {
+        let iteration = f(state);
+        BgTask {
+            future: Box::pin(async move {
+                let (res, next) = match handle.run(iteration).await {
+                    Some((state, res @ Ok(..))) => {
+                        (Some(res), Some(Self::loop_iteration(m, state, f, handle)))
+                    }
+                    res => {
+                        handle.done.store(true, Ordering::Relaxed);
+                        (res.map(|(_, res)| res), None)
+                    }
+                };
+                (res.map(DynVar::new), next)
+            }),
+            method_id: m.id,
+            method_desc: m.desc,
+        }
+    }
+
+    fn spawn_task(&self, task: BgTask) {
+        if let Err(..) = self.bg_spawner.send(task) {
             unreachable!("Failed to spawn background runner - handler runtime not listening");
         }
     }
@@ -80,6 +210,18 @@ impl LocalInterface {
             .await
     }
 
+    pub async fn dispatch_collect<At: Sync + Send + 'static, Rt: 'static>(
+        &self,
+        target: msg::Target,
+        method: MethodDecl<false, At, Rt>,
+        args: At,
+        deadline: Duration,
+    ) -> Result<Vec<Rt>, DispatchErr> {
+        self.nonlocal
+            .dispatch_collect_as(self.whoami(), target, method, args, deadline)
+            .await
+    }
+
     pub async fn announce<At: Sync + Send + 'static, Rt: 'static>(
         &self,
         target: msg::Target,
@@ -1,14 +1,18 @@
-use std::{collections::HashMap, marker::PhantomData};
+use std::{any::type_name, collections::HashMap, marker::PhantomData};
 
 use uuid::Uuid;
 
-use crate::handler::{
-    async_fn_ptr::{AsyncFnPtr, HandlerFn, HandlerFnOwnArgs},
-    decl::{MethodDecl, MethodRaw},
-    HandlerInit,
-};
 #[cfg(feature = "bus_dbg")]
 use crate::msg::Str;
+use crate::{
+    dyn_var::DynVar,
+    handler::{
+        async_fn_ptr::{AsyncFnPtr, HandlerFn, HandlerFnOwnArgs},
+        decl::{MethodDecl, MethodRaw},
+        dispatch::HandlerError,
+        HandlerInit,
+    },
+};
 
 /// Interface for registering methods on a handler.
 ///
@@ -39,17 +43,50 @@ impl<H: HandlerInit> MethodRegister<H> {
         func: Fn,
         decl: MethodDecl<false, At, Rt>,
     ) {
-        debug_assert!(self
-            .methods
-            .insert(
-                decl.id,
-                MethodRaw {
-                    handler_func: Box::new(HandlerFn::new(func)),
-                    #[cfg(feature = "bus_dbg")]
-                    handler_desc: Str::Borrowed(decl.desc),
-                },
-            )
-            .is_none());
+        self.insert(
+            decl.id,
+            decl.desc,
+            MethodRaw {
+                handler_func: Box::new(HandlerFn::new(func)),
+                split_result: None,
+                #[cfg(feature = "bus_dbg")]
+                handler_desc: Str::Borrowed(decl.desc),
+            },
+        );
+    }
+
+    /// Registers that this handler implements the given [`decl`][MethodDecl] with the handler function `func`,
+    /// which may fail without shutting down the handler
+    /// (signature: `async fn handler(&mut self, args: &ArgumentType, interface: &LocalInterface) -> Result<ReturnType, HandlerError>`)
+    ///
+    /// if `func` returns `Ok(Err(e))`, the caller's query returns [`DispatchErr::Failed(e)`][super::DispatchErr::Failed]
+    /// (dispatches and announces do not wait for the method to finish, so they never see it).
+    /// (`Err` from `func` is handled by [`on_error`][HandlerInit::on_error], like any other method)
+    pub fn register_fallible<
+        At: Send + Sync + 'static,
+        Rt: Send + Sync + 'static,
+        Fn: for<'a> AsyncFnPtr<'a, H, &'a At, Result<Rt, HandlerError>> + Copy + Sync + Send + 'static,
+    >(
+        &mut self,
+        func: Fn,
+        decl: MethodDecl<false, At, Rt>,
+    ) {
+        fn split_result<Rt: Send + Sync + 'static>(ret: DynVar) -> Result<DynVar, HandlerError> {
+            match ret.try_to::<Result<Rt, HandlerError>>() {
+                Ok(ret) => ret.map(DynVar::new),
+                Err(..) => unreachable!("fallible method returned the wrong type"),
+            }
+        }
+        self.insert(
+            decl.id,
+            decl.desc,
+            MethodRaw {
+                handler_func: Box::new(HandlerFn::new(func)),
+                split_result: Some(split_result::<Rt>),
+                #[cfg(feature = "bus_dbg")]
+                handler_desc: Str::Borrowed(decl.desc),
+            },
+        );
     }
 
     /// Registers that this handler implements the given [`decl`][MethodDecl] with the handler function `func`
@@ -68,16 +105,34 @@ impl<H: HandlerInit> MethodRegister<H> {
         func: Fn,
         decl: MethodDecl<true, At, Rt>,
     ) {
-        self.methods.insert(
+        self.insert(
             decl.id,
+            decl.desc,
             MethodRaw {
                 handler_func: Box::new(HandlerFnOwnArgs::new(func)),
+                split_result: None,
                 #[cfg(feature = "bus_dbg")]
                 handler_desc: Str::Borrowed(decl.desc),
             },
         );
     }
 
+    /// Panics if a method with the same id is already registered, since the later one would silently shadow it
+    /// (the same decl registered twice, or two decls with colliding ids)
+    fn insert(&mut self, id: Uuid, desc: &'static str, method: MethodRaw) {
+        if let Some(prev) = self.methods.get(&id) {
+            #[cfg(feature = "bus_dbg")]
+            let prev = &prev.handler_desc;
+            #[cfg(not(feature = "bus_dbg"))]
+            let prev = "[unknown]";
+            panic!(
+                "Method {id} registered twice on handler {} ({prev:?} and {desc:?})",
+                type_name::<H>()
+            );
+        }
+        self.methods.insert(id, method);
+    }
+
     pub(crate) fn finalize(self) -> HashMap<Uuid, MethodRaw> {
         self.methods
     }
@@ -1,8 +1,18 @@
-use std::{any::type_name, collections::HashMap, marker::PhantomData, sync::Arc};
+use std::{
+    any::type_name,
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{atomic::Ordering, Arc},
+    time::Instant,
+};
 
 use anyhow::Result;
-use futures::future::BoxFuture;
-use tokio::{select, sync::broadcast, task::JoinSet};
+use tokio::{
+    select,
+    sync::{broadcast, oneshot},
+    task::JoinSet,
+};
+use tracing::{field, Instrument};
 use uuid::Uuid;
 
 #[cfg(feature = "bus_dbg")]
@@ -12,21 +22,31 @@ use crate::{
     flag::Flag,
     handler::{
         decl::MethodRaw,
-        interface::{local::LocalInterface, Interface},
+        dispatch::HandlerError,
+        interface::{
+            local::{BgTask, LocalInterface},
+            Interface,
+        },
         register::MethodRegister,
         HandlerInit,
     },
     id::Uid,
     msg::{self, HandlerInstance, Msg},
+    SlowHandlerPolicy,
 };
 
+/// running background tasks: (result, next iteration, method id, method desc)
+type Background = JoinSet<(Option<DynVar>, Option<BgTask>, Uuid, &'static str)>;
+
 pub struct HandlerTaskRt<H: HandlerInit> {
     inter: LocalInterface,
-    bg_spawner_recv: flume::Receiver<(BoxFuture<'static, DynVar>, Uuid, &'static str)>,
+    bg_spawner_recv: flume::Receiver<BgTask>,
     hdl: DynVar,
     inst: HandlerInstance,
     methods: HashMap<Uuid, MethodRaw>,
     comm_filtered: flume::Receiver<Arc<Msg>>,
+    /// told the result of `init`, see [`Interface::spawn_checked`]
+    ready: Option<oneshot::Sender<Result<(), HandlerError>>>,
     _ph: PhantomData<H>,
 }
 
@@ -35,7 +55,7 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
         let discriminant = Uid::gen_with(&inter.uid_src);
         let (bg_spawner, bg_spawner_recv) = flume::unbounded();
         let mut comm = inter.comm.subscribe();
-        let (cf_send, comm_filtered) = flume::bounded(512);
+        let (cf_send, comm_filtered) = flume::bounded(inter.config.handler_queue_cap);
         let inst = HandlerInstance {
             typ: H::DECL,
             discriminant,
@@ -43,6 +63,9 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
             discriminant_desc: Str::Owned(String::from("[initial value]")),
         };
         let mut inst2 = inst.clone();
+        let metrics = inter.metrics.clone();
+        let policy = inter.config.slow_handler_policy;
+        let comm_drained = inter.comm_drained.clone();
         tokio::spawn(async move {
             inst2.discriminant_desc = Str::Owned(String::from("[initial value - filter task]"));
             let inst2 = inst2;
@@ -52,10 +75,18 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                     Ok(recvd) => recvd,
                     Err(broadcast::error::RecvError::Closed) => break,
                     Err(broadcast::error::RecvError::Lagged(num_missed)) => {
+                        metrics.lagged.fetch_add(num_missed, Ordering::Relaxed);
+                        if policy == SlowHandlerPolicy::Disconnect {
+                            error!("Handler task for handler {} lagged, skipped {num_missed} messages. disconnecting it from the bus", name);
+                            break;
+                        }
                         error!("Handler task for handler {} lagged, skipped {num_missed} messages. beware!", name);
                         continue;
                     }
                 };
+                if policy == SlowHandlerPolicy::Block {
+                    comm_drained.notify_waiters();
+                }
                 if match &recvd.kind {
                     msg::MsgKind::Request { target, method, .. } => {
                         trace!(
@@ -92,6 +123,7 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
             inst,
             methods: HashMap::default(),
             comm_filtered,
+            ready: None,
             _ph: PhantomData,
         };
         rt.update_metadata();
@@ -115,12 +147,22 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
         self.inst.clone()
     }
 
+    /// returns a receiver that is sent the result of `init` (and is dropped if the task exits without finishing it)
+    pub fn notify_ready(&mut self) -> oneshot::Receiver<Result<(), HandlerError>> {
+        let (send, recv) = oneshot::channel();
+        self.ready = Some(send);
+        recv
+    }
+
     pub async fn run(mut self) -> Result<()> {
         {
             let mut flag_err = false;
             let fut = async {
                 if let Err(e) = self.hdl.as_mut::<H>().unwrap().init(&self.inter).await {
                     warn!("Error occured during initialization (it will be handled, but the runtime task will abort)");
+                    if let Some(ready) = self.ready.take() {
+                        let _ = ready.send(Err(HandlerError::new(format!("{e:#}"))));
+                    }
                     self.hdl
                         .as_mut::<H>()
                         .unwrap()
@@ -141,24 +183,44 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                 }
             };
         }
-        let mut background = JoinSet::<(DynVar, Uuid, &'static str)>::new();
+        if let Some(ready) = self.ready.take() {
+            let _ = ready.send(Ok(()));
+        }
+        let mut background = JoinSet::new();
+        let res = self.event_loop(&mut background).await;
+        // abort (and wait for) any background tasks still running, so that nothing they own outlives the handler
+        background.shutdown().await;
+        res
+    }
+
+    async fn event_loop(&mut self, background: &mut Background) -> Result<()> {
         loop {
             select! {
-                message = self.comm_filtered.recv_async() => self.handle_message(message?).await?,
+                message = self.comm_filtered.recv_async() => {
+                    let Ok(message) = message else {
+                        // (the bus was closed, or this handler was disconnected from it)
+                        warn!("Handler {:?} is no longer receiving messages from the bus, shutting down", self.id());
+                        return Ok(());
+                    };
+                    self.handle_message(message).await?
+                }
                 _ = &self.inter.update_metadata => self.update_metadata(),
+                _ = &self.inter.shutdown => return Ok(()),
                 // Err is unreachable
-                (future, method_id, method_desc) = async { self.bg_spawner_recv.recv_async().await.unwrap() } => {
-                    background.spawn(async move {
-                        (future.await, method_id, method_desc)
-                    });
+                task = async { self.bg_spawner_recv.recv_async().await.unwrap() } => {
+                    Self::spawn_bg(background, task);
                 }
                 // if None, it will be ignored (good)
                 Some(result) = background.join_next() => {
                     #[allow(unused)]
-                    let Ok((result, method_id,  method_desc)) = result else {
+                    let Ok((result, next, method_id,  method_desc)) = result else {
                         error!("Background task panicked! - ignoring would-be return value");
                         continue
                     };
+                    let Some(result) = result else {
+                        trace!("Background task was cancelled");
+                        continue
+                    };
                     let Some(method_val) = self.methods.get(&method_id) else {
                         warn!("Background task would have called method on return that was not registered - its return value will be ignored");
                         continue
@@ -177,9 +239,14 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                     // TODO: pass result by-value?
                     let mut flag_err = false;
                     let fut = async {
-                        if let Err(e) = method_val.handler_func.call_owned(&mut self.hdl, result, &self.inter)
-                            .expect("unreachable: handler method type mismatch")
-                            .await {
+                        let call = match method_val.handler_func.call_owned(&mut self.hdl, result, &self.inter) {
+                            Ok(call) => call,
+                            Err(e) => {
+                                error!("Background task completed, but its method could not be called: {e}");
+                                return;
+                            }
+                        };
+                        if let Err(e) = call.await {
                                 //NOTE: this still has message_source set (on self.inter)
                                 debug!("An error occured handling request, handling error");
                                 self.hdl
@@ -202,6 +269,10 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                     };
                     // de-init event ctx
                     self.inter.message_source = None;
+                    // (for loops) only started once this iteration's event has been handled
+                    if let Some(next) = next {
+                        Self::spawn_bg(background, next);
+                    }
                 }
             }
         }
@@ -209,6 +280,18 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
         anyhow::Ok(())
     }
 
+    fn spawn_bg(background: &mut Background, task: BgTask) {
+        let BgTask {
+            future,
+            method_id,
+            method_desc,
+        } = task;
+        background.spawn(async move {
+            let (result, next) = future.await;
+            (result, next, method_id, method_desc)
+        });
+    }
+
     async fn handle_message(&mut self, message: Arc<Msg>) -> Result<()> {
         match &message.kind {
             msg::MsgKind::Request {
@@ -240,13 +323,25 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                 // call
                 let mut flag_err = false;
                 let fut = async {
-                    let result = method_val
-                        .handler_func
-                        .call(&mut self.hdl, arguments, &self.inter)
-                        .expect("unreachable: handler method type mismatch")
-                        .await;
+                    let result =
+                        match method_val
+                            .handler_func
+                            .call(&mut self.hdl, arguments, &self.inter)
+                        {
+                            Ok(call) => call.await,
+                            Err(e) => {
+                                // (a method was dispatched with a different argument type than it was registered with)
+                                error!("Failed to call method {}: {e}", method.id);
+                                return Err(msg::ResponseErr::CallFailed(e.to_string()));
+                            }
+                        };
                     match result {
-                        Ok(resp) => Ok(resp),
+                        Ok(resp) => match method_val.split_result {
+                            Some(split_result) => {
+                                split_result(resp).map_err(msg::ResponseErr::Failed)
+                            }
+                            None => Ok(resp),
+                        },
                         Err(err) => {
                             let err: H::Error = err.try_to().unwrap();
                             debug!("An error occured handling request, handling error");
@@ -256,10 +351,34 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                                 .on_error(err, &self.inter)
                                 .await;
                             flag_err = true;
-                            Err(msg::ResponseErr)
+                            Err(msg::ResponseErr::Internal)
                         }
                     }
                 };
+                // the call runs in a span, closed when the method returns with the time it took
+                // (arguments are not `Debug`, so only their type is recorded)
+                let span = debug_span!(
+                    "bus_method",
+                    method = field::Empty,
+                    ?source,
+                    ?target,
+                    args = field::Empty,
+                    elapsed = field::Empty,
+                );
+                #[cfg(feature = "bus_dbg")]
+                span.record("method", &*method.id_desc)
+                    .record("args", arguments.type_name());
+                #[cfg(not(feature = "bus_dbg"))]
+                span.record("method", field::display(method.id));
+                let fut = async {
+                    let start = Instant::now();
+                    let resp = fut.await;
+                    let elapsed = start.elapsed();
+                    tracing::Span::current().record("elapsed", field::debug(elapsed));
+                    trace!(?elapsed, ok = resp.is_ok(), "method returned");
+                    resp
+                }
+                .instrument(span);
                 let resp;
                 select! {
                     x = fut => {
@@ -267,22 +386,26 @@ impl<H: HandlerInit> HandlerTaskRt<H> {
                     }
                     _ = &self.inter.shutdown => {
                         flag_err = true;
-                        resp = Err(msg::ResponseErr);
+                        resp = Err(msg::ResponseErr::Internal);
                     }
                 };
                 // de-init event ctx
                 self.inter.message_source = None;
                 // if a response is desired, it is sent back.
                 // if not, it is dropped
-                if let (msg::Target::Instance(..), msg::Responder::Respond { value, waker }) =
-                    (target, response)
-                {
-                    if let Some(..) = value.put(resp) {
-                        error!("Spacific instance was targeted, but multiple instances accepted (response already contains a value)");
-                    } else {
-                        // wake the receiving task
-                        waker.signal();
+                match (target, response) {
+                    (msg::Target::Instance(..), msg::Responder::Respond { value, waker }) => {
+                        if let Some(..) = value.put(resp) {
+                            error!("Spacific instance was targeted, but multiple instances accepted (response already contains a value)");
+                        } else {
+                            // wake the receiving task
+                            waker.signal();
+                        }
+                    }
+                    (_, msg::Responder::Collect { values }) => {
+                        values.lock().unwrap().push(resp);
                     }
+                    _ => {}
                 }
                 if flag_err {
                     return Ok(());
@@ -17,6 +17,15 @@ impl Uid {
     pub(crate) const fn nil() -> Self {
         Self(0)
     }
+    /// the raw value (for recording messages, see [`record`][crate::record])
+    #[cfg(feature = "bus_dbg")]
+    pub(crate) const fn to_raw(self) -> u64 {
+        self.0
+    }
+    #[cfg(feature = "bus_dbg")]
+    pub(crate) const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
 }
 
 /// Generates a random Uuid at compile time
@@ -15,7 +15,11 @@ use std::{
     sync::{atomic::AtomicU64, Arc},
 };
 
-use tokio::{spawn, sync::broadcast};
+use tokio::{
+    select, spawn,
+    sync::{broadcast, watch, Mutex, Notify},
+    task::JoinHandle,
+};
 
 mod atomic_cell;
 pub mod common;
@@ -23,30 +27,86 @@ pub mod dyn_var;
 mod flag;
 pub mod handler;
 pub mod id;
+pub mod metrics;
 pub mod msg;
+#[cfg(feature = "bus_dbg")]
+pub mod record;
 #[cfg(test)]
 mod test;
 
 use self::handler::Interface;
 
-/// size of the inter-handler comm queue.
-/// this must be large enough that it will not fill up while a task is busy, because the queue only
-/// gets rid of a message once it is received by *all* receivers.
-const COMM_QUEUE_CAP: usize = 64;
+/// What happens to a handler that falls behind the bus (its messages are not received before the
+/// comm queue fills up)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SlowHandlerPolicy {
+    /// the handler misses the oldest messages (counted in [`metrics::BusMetrics::lagged`])
+    #[default]
+    Drop,
+    /// senders wait until every handler has received enough messages for there to be room.
+    ///
+    /// nothing is lost, but a handler that sends while its own queue is full will deadlock
+    Block,
+    /// the handler is shut down
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusConfig {
+    /// size of the inter-handler comm queue.
+    /// this must be large enough that it will not fill up while a task is busy, because the queue only
+    /// gets rid of a message once it is received by *all* receivers.
+    pub comm_queue_cap: usize,
+    /// size of each handler's queue of messages addressed to it (that it has not yet handled)
+    pub handler_queue_cap: usize,
+    pub slow_handler_policy: SlowHandlerPolicy,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            comm_queue_cap: 64,
+            handler_queue_cap: 512,
+            slow_handler_policy: SlowHandlerPolicy::Drop,
+        }
+    }
+}
 
 /// bussin
+///
+/// dropping (or [closing][Bus::close]) the bus closes it, after which requests fail with
+/// [`DispatchErr::Closed`][handler::DispatchErr::Closed]
 pub struct Bus {
     int: Interface,
+    close: watch::Sender<bool>,
+    /// logs every message sent on the bus, until it is closed
+    logger: Option<JoinHandle<()>>,
 }
 
 impl Bus {
     #[instrument]
-    pub async fn new() -> Self {
-        let (comm, _) = broadcast::channel(COMM_QUEUE_CAP);
+    pub async fn new(config: BusConfig) -> Self {
+        let (comm, _) = broadcast::channel(config.comm_queue_cap);
         let mut recv = comm.subscribe();
-        spawn(async move {
+        let comm_drained = Arc::new(Notify::new());
+        let comm_drained2 = comm_drained.clone();
+        let (close, closed) = watch::channel(false);
+        let mut closed2 = closed.clone();
+        let logger = spawn(async move {
             loop {
-                let msg: Arc<msg::Msg> = recv.recv().await.unwrap();
+                let msg: Arc<msg::Msg> = select! {
+                    msg = recv.recv() => match msg {
+                        Ok(msg) => msg,
+                        // this is only for logging, missing some is fine
+                        Err(broadcast::error::RecvError::Lagged(..)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    // (also if the sender is gone)
+                    _ = closed2.wait_for(|closed| *closed) => break,
+                };
+                if config.slow_handler_policy == SlowHandlerPolicy::Block {
+                    comm_drained2.notify_waiters();
+                }
                 match &msg.kind {
                     msg::MsgKind::Request {
                         source,
@@ -79,7 +139,24 @@ impl Bus {
             int: Interface {
                 uid_src: Arc::new(AtomicU64::new(0)),
                 comm,
+                config,
+                comm_drained,
+                send_lock: Arc::new(Mutex::new(())),
+                metrics: Arc::default(),
+                closed,
             },
+            close,
+            logger: Some(logger),
+        }
+    }
+
+    /// Closes the bus, and waits for its logging task to exit
+    pub async fn close(mut self) {
+        self.close.send_replace(true);
+        if let Some(logger) = self.logger.take() {
+            if let Err(e) = logger.await {
+                error!("Bus logging task failed: {e}");
+            }
         }
     }
 
@@ -89,6 +166,12 @@ impl Bus {
     }
 }
 
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.close.send_replace(true);
+    }
+}
+
 impl Deref for Bus {
     type Target = handler::Interface;
     fn deref(&self) -> &Self::Target {
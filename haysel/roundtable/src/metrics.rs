@@ -0,0 +1,28 @@
+//! counters describing bus activity
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters for a single [`Bus`][crate::Bus], shared by all of its interfaces
+#[derive(Debug, Default)]
+pub struct BusMetrics {
+    pub(crate) dispatched: AtomicU64,
+    pub(crate) lagged: AtomicU64,
+    pub(crate) live_handlers: AtomicU64,
+}
+
+impl BusMetrics {
+    /// number of messages sent on the bus
+    pub fn dispatched(&self) -> u64 {
+        self.dispatched.load(Ordering::Relaxed)
+    }
+
+    /// number of messages that handlers missed because they fell behind
+    pub fn lagged(&self) -> u64 {
+        self.lagged.load(Ordering::Relaxed)
+    }
+
+    /// number of handlers that have been spawned, and not yet exited
+    pub fn live_handlers(&self) -> u64 {
+        self.live_handlers.load(Ordering::Relaxed)
+    }
+}
@@ -1,10 +1,10 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Mutex};
 
 use super::atomic_cell::AtomicCell;
 use super::dyn_var::DynVar;
 use uuid::Uuid;
 
-use crate::flag::Flag;
+use crate::{flag::Flag, handler::HandlerError};
 
 use super::id::Uid;
 
@@ -76,8 +76,17 @@ pub struct HandlerInstance {
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
-#[error("An error occured while processing this request")]
-pub struct ResponseErr;
+pub enum ResponseErr {
+    /// the handler experienced an error (and handled it with `on_error`), or shut down
+    #[error("An error occured while processing this request")]
+    Internal,
+    /// the method failed, and returned this to the caller
+    #[error("{0}")]
+    Failed(HandlerError),
+    /// the method could not be called (the arguments were not of the type it was registered with)
+    #[error("{0}")]
+    CallFailed(String),
+}
 
 /// a channel used for sending a single response to a query.
 #[derive(Debug)]
@@ -100,6 +109,10 @@ pub(crate) enum Responder {
         /// see `value`
         waker: Flag,
     },
+    /// responses from every handler that accepted the request, collected until the requester's deadline
+    Collect {
+        values: Mutex<Vec<Result<DynVar, ResponseErr>>>,
+    },
 }
 
 /// the target for a request message (instance, any type, or any)
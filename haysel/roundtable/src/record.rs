@@ -0,0 +1,390 @@
+//! Recording the messages sent on a bus, and replaying them on another (for reproducing bugs offline)
+//!
+//! a recording is a file of JSON lines, one [`Record`] per message. method arguments are [`DynVar`]s, so they are only
+//! recorded for methods registered with [`ArgCodecs::with`]. other messages are still recorded (without arguments),
+//! but are skipped when replaying.
+//!
+//! handler type and method ids are generated at compile time, so a recording can only be replayed by the same build
+//! (methods are looked up by name, but handler types are not)
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    select,
+    sync::broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+use crate::{
+    dyn_var::DynVar,
+    handler::{dispatch::bus_dispatch_event, DispatchErr, Interface, MethodDecl},
+    id::Uid,
+    msg::{self, HandlerInstance, HandlerType, Str},
+    SlowHandlerPolicy,
+};
+
+type EncodeFn = fn(&DynVar) -> Option<serde_json::Value>;
+type DecodeFn = fn(serde_json::Value) -> serde_json::Result<DynVar>;
+
+/// how to record (and replay) the arguments of methods
+struct Codec {
+    method: Uuid,
+    encode: EncodeFn,
+    decode: DecodeFn,
+}
+
+/// Methods whose arguments are recorded, by name
+#[derive(Default)]
+pub struct ArgCodecs {
+    codecs: HashMap<&'static str, Codec>,
+}
+
+impl ArgCodecs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// record (and replay) the arguments of `method`
+    pub fn with<At: Serialize + DeserializeOwned + Sync + Send + 'static, Rt: 'static>(
+        mut self,
+        method: MethodDecl<false, At, Rt>,
+    ) -> Self {
+        self.codecs.insert(
+            method.desc,
+            Codec {
+                method: method.id,
+                encode: |args| serde_json::to_value(args.as_ref::<At>()?).ok(),
+                decode: |value| Ok(DynVar::new(serde_json::from_value::<At>(value)?)),
+            },
+        );
+        self
+    }
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record {
+    Request(Box<RecordedRequest>),
+    /// the recorder fell behind, and missed this many messages
+    Lagged {
+        missed: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// time since recording started
+    pub at: Duration,
+    pub source: RecordedInstance,
+    pub target: RecordedTarget,
+    pub method: Uuid,
+    pub method_desc: String,
+    /// type name of the arguments
+    pub argument_type: String,
+    /// None if the method has no codec (see [`ArgCodecs::with`])
+    pub arguments: Option<serde_json::Value>,
+    /// the kind of response the sender waited for (responses are not recorded)
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedType {
+    pub id: Uuid,
+    pub desc: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedInstance {
+    pub typ: RecordedType,
+    pub discriminant: u64,
+    pub desc: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedTarget {
+    Instance(RecordedInstance),
+    Type(RecordedType),
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedResponse {
+    None,
+    Verify,
+    Respond,
+    Collect,
+}
+
+impl From<&HandlerType> for RecordedType {
+    fn from(typ: &HandlerType) -> Self {
+        Self {
+            id: typ.id,
+            desc: typ.id_desc.to_string(),
+        }
+    }
+}
+
+impl From<&RecordedType> for HandlerType {
+    fn from(typ: &RecordedType) -> Self {
+        Self {
+            id: typ.id,
+            id_desc: Str::Owned(typ.desc.clone()),
+        }
+    }
+}
+
+impl From<&HandlerInstance> for RecordedInstance {
+    fn from(inst: &HandlerInstance) -> Self {
+        Self {
+            typ: (&inst.typ).into(),
+            discriminant: inst.discriminant.to_raw(),
+            desc: inst.discriminant_desc.to_string(),
+        }
+    }
+}
+
+impl From<&RecordedInstance> for HandlerInstance {
+    fn from(inst: &RecordedInstance) -> Self {
+        Self {
+            typ: (&inst.typ).into(),
+            discriminant: Uid::from_raw(inst.discriminant),
+            discriminant_desc: Str::Owned(inst.desc.clone()),
+        }
+    }
+}
+
+impl From<&msg::Target> for RecordedTarget {
+    fn from(target: &msg::Target) -> Self {
+        match target {
+            msg::Target::Instance(inst) => Self::Instance(inst.into()),
+            msg::Target::Type(typ) => Self::Type(typ.into()),
+            msg::Target::Any => Self::Any,
+        }
+    }
+}
+
+impl From<&RecordedTarget> for msg::Target {
+    fn from(target: &RecordedTarget) -> Self {
+        match target {
+            RecordedTarget::Instance(inst) => Self::Instance(inst.into()),
+            RecordedTarget::Type(typ) => Self::Type(typ.into()),
+            RecordedTarget::Any => Self::Any,
+        }
+    }
+}
+
+/// Counts of what a [`Recorder`] wrote
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecordStats {
+    pub recorded: u64,
+    /// requests recorded without their arguments (there was no codec for the method)
+    pub missing_arguments: u64,
+    /// messages that were missed entirely (the recorder fell behind)
+    pub lagged: u64,
+}
+
+/// Records every message sent on a bus (after it is started), until the bus is closed
+pub struct Recorder {
+    task: JoinHandle<io::Result<RecordStats>>,
+}
+
+impl Recorder {
+    /// Starts recording to `out`. it is written on a blocking thread, and flushed whenever that catches up, so a
+    /// recording is usable if the program crashes
+    pub fn start(int: &Interface, codecs: ArgCodecs, out: impl Write + Send + 'static) -> Self {
+        let recv = int.comm.subscribe();
+        let task = tokio::spawn(record(int.clone(), recv, codecs, out));
+        Self { task }
+    }
+
+    /// Waits for the bus to close, and for everything to be written
+    pub async fn finish(self) -> io::Result<RecordStats> {
+        self.task.await.map_err(io::Error::other)?
+    }
+}
+
+async fn record(
+    int: Interface,
+    mut recv: broadcast::Receiver<Arc<msg::Msg>>,
+    codecs: ArgCodecs,
+    out: impl Write + Send + 'static,
+) -> io::Result<RecordStats> {
+    let (queue, writes) = flume::bounded(WRITE_QUEUE);
+    let writer = tokio::task::spawn_blocking(move || write_records(writes, out));
+    let started = Instant::now();
+    let mut closed = int.closed.clone();
+    let mut stats = RecordStats::default();
+    let mut closing = false;
+    loop {
+        let msg = if closing {
+            // (messages sent before the bus was closed)
+            match recv.try_recv() {
+                Ok(msg) => Ok(msg),
+                Err(TryRecvError::Lagged(missed)) => Err(missed),
+                Err(..) => break,
+            }
+        } else {
+            select! {
+                msg = recv.recv() => match msg {
+                    Ok(msg) => Ok(msg),
+                    Err(RecvError::Lagged(missed)) => Err(missed),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = closed.wait_for(|closed| *closed) => {
+                    closing = true;
+                    continue;
+                }
+            }
+        };
+        let record = match msg {
+            Ok(msg) => {
+                if int.config.slow_handler_policy == SlowHandlerPolicy::Block {
+                    int.comm_drained.notify_waiters();
+                }
+                let record = record_msg(&msg, &codecs, started.elapsed());
+                if record.arguments.is_none() {
+                    stats.missing_arguments += 1;
+                }
+                stats.recorded += 1;
+                Record::Request(Box::new(record))
+            }
+            Err(missed) => {
+                stats.lagged += missed;
+                Record::Lagged { missed }
+            }
+        };
+        if queue.send_async(record).await.is_err() {
+            // (the writer failed, its error is returned below)
+            break;
+        }
+    }
+    drop(queue);
+    writer.await.map_err(io::Error::other)??;
+    Ok(stats)
+}
+
+/// records that can be waiting to be written before the recorder stops receiving messages (and possibly lags)
+const WRITE_QUEUE: usize = 256;
+
+fn write_records(writes: flume::Receiver<Record>, mut out: impl Write) -> io::Result<()> {
+    for record in writes.iter() {
+        serde_json::to_writer(&mut out, &record)?;
+        out.write_all(b"\n")?;
+        if writes.is_empty() {
+            out.flush()?;
+        }
+    }
+    out.flush()
+}
+
+fn record_msg(msg: &msg::Msg, codecs: &ArgCodecs, at: Duration) -> RecordedRequest {
+    let msg::MsgKind::Request {
+        source,
+        target,
+        method,
+        arguments,
+        response,
+    } = &msg.kind;
+    RecordedRequest {
+        at,
+        source: source.into(),
+        target: target.into(),
+        method: method.id,
+        method_desc: method.id_desc.to_string(),
+        argument_type: arguments.type_name().to_string(),
+        arguments: codecs
+            .codecs
+            .get(&*method.id_desc)
+            .and_then(|codec| (codec.encode)(arguments)),
+        response: match response {
+            msg::Responder::NoVerify => RecordedResponse::None,
+            msg::Responder::Verify { .. } => RecordedResponse::Verify,
+            msg::Responder::Respond { .. } => RecordedResponse::Respond,
+            msg::Responder::Collect { .. } => RecordedResponse::Collect,
+        },
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Failed to read recording: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid record on line {line}: {error}")]
+    Invalid {
+        line: usize,
+        error: serde_json::Error,
+    },
+    #[error("Failed to send replayed message: {0}")]
+    Dispatch(#[from] DispatchErr),
+}
+
+/// What [`replay`] did
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub sent: usize,
+    /// (line, method) of requests that could not be replayed, because their arguments were not recorded
+    /// (or there is no codec for the method)
+    pub skipped: Vec<(usize, String)>,
+    /// messages that the recorder missed
+    pub lagged: u64,
+}
+
+/// Sends the messages in `recording` on the bus, in order (and as fast as possible).
+///
+/// every message is sent without waiting for a response. messages targeting a specific instance are only received if
+/// it has the same id as when recorded (handler ids are assigned in order, so spawn the same handlers in the same order)
+pub async fn replay(
+    int: &Interface,
+    codecs: &ArgCodecs,
+    recording: impl BufRead,
+) -> Result<ReplayReport, ReplayError> {
+    let mut report = ReplayReport::default();
+    for (n, line) in recording.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str::<Record>(&line)
+            .map_err(|error| ReplayError::Invalid { line: n + 1, error })?;
+        let request = match record {
+            Record::Request(request) => *request,
+            Record::Lagged { missed } => {
+                report.lagged += missed;
+                continue;
+            }
+        };
+        let codec = codecs.codecs.get(request.method_desc.as_str());
+        let (Some(codec), Some(arguments)) = (codec, request.arguments) else {
+            report.skipped.push((n + 1, request.method_desc));
+            continue;
+        };
+        let arguments = (codec.decode)(arguments)
+            .map_err(|error| ReplayError::Invalid { line: n + 1, error })?;
+        bus_dispatch_event(
+            int.clone(),
+            (&request.source).into(),
+            (&request.target).into(),
+            msg::MethodID {
+                id: codec.method,
+                id_desc: Str::Owned(request.method_desc),
+            },
+            arguments,
+            false,
+            false,
+        )
+        .await?;
+        report.sent += 1;
+    }
+    Ok(report)
+}
@@ -1,7 +1,7 @@
 use std::{
     convert::Infallible,
     sync::{
-        atomic::{self, AtomicBool},
+        atomic::{self, AtomicBool, AtomicUsize},
         Arc,
     },
     time::Duration,
@@ -11,10 +11,14 @@ use tracing_test::traced_test;
 
 use super::{
     common::HDL_EXTERNAL,
-    handler::{HandlerInit, LocalInterface, MethodRegister},
-    handler_decl_t, method_decl,
+    handler::{
+        BgHandle, DispatchErr, HandlerError, HandlerInit, LocalInterface, MethodDecl,
+        MethodRegister, SpawnErr,
+    },
+    handler_decl_t, method_decl, method_decl_owned,
     msg::{HandlerType, Str},
-    Bus,
+    msg::{ResponseErr, Target},
+    Bus, BusConfig, SlowHandlerPolicy,
 };
 
 #[traced_test]
@@ -28,7 +32,7 @@ fn bus_send_message_rt() {
 }
 
 async fn bus_send_message() {
-    let bus = Bus::new().await;
+    let bus = Bus::new(BusConfig::default()).await;
     method_decl!(METHOD_1, Arc<AtomicBool>, ());
     struct Handler;
     impl Handler {
@@ -52,6 +56,7 @@ async fn bus_send_message() {
         }
     }
     let instance_id = bus.interface().spawn(Handler);
+    assert_eq!(bus.metrics().dispatched(), 0);
 
     let flag = Arc::new(AtomicBool::new(false));
     bus.interface()
@@ -61,4 +66,780 @@ async fn bus_send_message() {
     tokio::time::sleep(Duration::from_secs(1)).await;
     let value = flag.load(atomic::Ordering::Relaxed);
     assert!(value, "handler did not run");
+    assert_eq!(bus.metrics().dispatched(), 1);
+    assert_eq!(bus.metrics().lagged(), 0);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn burst_delivered_when_blocking() {
+    let bus = Bus::new(BusConfig {
+        comm_queue_cap: 4,
+        handler_queue_cap: 2,
+        slow_handler_policy: SlowHandlerPolicy::Block,
+    })
+    .await;
+    method_decl!(METHOD_COUNT, Arc<AtomicUsize>, ());
+    struct SlowHandler;
+    impl SlowHandler {
+        async fn count(
+            &mut self,
+            count: &Arc<AtomicUsize>,
+            _: &LocalInterface,
+        ) -> Result<(), <Self as HandlerInit>::Error> {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            count.fetch_add(1, atomic::Ordering::Relaxed);
+            Ok(())
+        }
+    }
+    impl HandlerInit for SlowHandler {
+        const DECL: HandlerType = handler_decl_t!("Slow test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Slow test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::count, METHOD_COUNT)
+        }
+    }
+    let instance_id = bus.interface().spawn(SlowHandler);
+
+    // many times the size of the queue
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..100 {
+        bus.interface()
+            .announce_as(
+                HDL_EXTERNAL,
+                Target::Instance(instance_id.clone()),
+                METHOD_COUNT,
+                count.clone(),
+            )
+            .await
+            .unwrap();
+    }
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while count.load(atomic::Ordering::Relaxed) < 100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("not every message was handled");
+    assert_eq!(bus.metrics().lagged(), 0);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn failing_method_returns_error() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl!(METHOD_CHECKED_DIV, (u32, u32), u32);
+    struct Handler;
+    impl Handler {
+        async fn checked_div(
+            &mut self,
+            &(a, b): &(u32, u32),
+            _: &LocalInterface,
+        ) -> Result<Result<u32, HandlerError>, <Self as HandlerInit>::Error> {
+            Ok(a.checked_div(b)
+                .ok_or_else(|| HandlerError::new("division by zero")))
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Fallible test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Fallible test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register_fallible(Self::checked_div, METHOD_CHECKED_DIV)
+        }
+    }
+    let instance_id = bus.interface().spawn(Handler);
+    let res = bus
+        .interface()
+        .query_as(
+            HDL_EXTERNAL,
+            instance_id.clone(),
+            METHOD_CHECKED_DIV,
+            (1, 0),
+        )
+        .await;
+    let Err(DispatchErr::Failed(err)) = res else {
+        panic!("expected the method to fail, got {res:?}");
+    };
+    assert_eq!(err.to_string(), "division by zero");
+    // the handler is still running
+    let res = bus
+        .interface()
+        .query_as(HDL_EXTERNAL, instance_id, METHOD_CHECKED_DIV, (6, 3))
+        .await;
+    assert_eq!(res.unwrap(), 2);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn dispatch_collect_from_all_instances() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl!(METHOD_SIZE, (), u64);
+    struct Shard(u64);
+    impl Shard {
+        async fn size(
+            &mut self,
+            _: &(),
+            _: &LocalInterface,
+        ) -> Result<u64, <Self as HandlerInit>::Error> {
+            Ok(self.0)
+        }
+    }
+    impl HandlerInit for Shard {
+        const DECL: HandlerType = handler_decl_t!("Shard test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Owned(format!("Shard test handler instance {}", self.0))
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::size, METHOD_SIZE)
+        }
+    }
+    for size in [1, 20, 300] {
+        bus.interface().spawn(Shard(size));
+    }
+    let mut sizes = bus
+        .interface()
+        .dispatch_collect_as(
+            HDL_EXTERNAL,
+            Target::Type(Shard::DECL),
+            METHOD_SIZE,
+            (),
+            Duration::from_millis(500),
+        )
+        .await
+        .unwrap();
+    sizes.sort();
+    assert_eq!(sizes, vec![1, 20, 300]);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn bg_task_cancelled() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl_owned!(EV_PRIV_DONE, (), ());
+    method_decl!(METHOD_CANCEL, (), ());
+    method_decl!(METHOD_SHUTDOWN, (), ());
+    /// sets its flag when dropped
+    struct DropFlag(Arc<AtomicBool>);
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, atomic::Ordering::Relaxed);
+        }
+    }
+    struct Handler {
+        // set if the background task's event was generated
+        completed: Arc<AtomicBool>,
+        // set when the background task is dropped
+        dropped: Arc<AtomicBool>,
+        task: Option<BgHandle>,
+    }
+    impl Handler {
+        async fn done(
+            &mut self,
+            _: (),
+            _: &LocalInterface,
+        ) -> Result<(), <Self as HandlerInit>::Error> {
+            self.completed.store(true, atomic::Ordering::Relaxed);
+            Ok(())
+        }
+        async fn cancel(
+            &mut self,
+            _: &(),
+            _: &LocalInterface,
+        ) -> Result<(), <Self as HandlerInit>::Error> {
+            let task = self.task.as_ref().unwrap();
+            assert!(task.is_running());
+            task.cancel();
+            assert!(!task.is_running());
+            Ok(())
+        }
+        async fn shutdown(
+            &mut self,
+            _: &(),
+            int: &LocalInterface,
+        ) -> Result<(), <Self as HandlerInit>::Error> {
+            int.shutdown().await
+        }
+    }
+    #[async_trait]
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Background task test handler");
+        type Error = Infallible;
+        async fn init(&mut self, int: &LocalInterface) -> Result<(), Self::Error> {
+            let flag = DropFlag(self.dropped.clone());
+            self.task = Some(int.bg_spawn(EV_PRIV_DONE, async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                drop(flag);
+            }));
+            Ok(())
+        }
+        fn describe(&self) -> Str {
+            Str::Borrowed("Background task test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register_owned(Self::done, EV_PRIV_DONE);
+            register.register(Self::cancel, METHOD_CANCEL);
+            register.register(Self::shutdown, METHOD_SHUTDOWN);
+        }
+    }
+    let spawn = || {
+        let completed = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicBool::new(false));
+        let instance = bus.interface().spawn(Handler {
+            completed: completed.clone(),
+            dropped: dropped.clone(),
+            task: None,
+        });
+        (instance, completed, dropped)
+    };
+
+    // cancelled by the handler
+    let (instance, completed, dropped) = spawn();
+    bus.interface()
+        .query_as(HDL_EXTERNAL, instance, METHOD_CANCEL, ())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(dropped.load(atomic::Ordering::Relaxed));
+    assert!(!completed.load(atomic::Ordering::Relaxed));
+
+    // cancelled by the handler shutting down
+    let (instance, completed, dropped) = spawn();
+    bus.interface()
+        .announce_as(
+            HDL_EXTERNAL,
+            Target::Instance(instance),
+            METHOD_SHUTDOWN,
+            (),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(
+        dropped.load(atomic::Ordering::Relaxed),
+        "task still running"
+    );
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    assert!(!completed.load(atomic::Ordering::Relaxed));
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn live_handlers_counted() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl!(METHOD_SHUTDOWN, (), ());
+    method_decl!(METHOD_PANIC, (), ());
+    struct Handler;
+    impl Handler {
+        async fn shutdown(&mut self, _: &(), int: &LocalInterface) -> Result<(), Infallible> {
+            int.shutdown().await
+        }
+        async fn panic(&mut self, _: &(), _: &LocalInterface) -> Result<(), Infallible> {
+            panic!("test handler panicked (this is expected)");
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Live handler test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Live handler test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::shutdown, METHOD_SHUTDOWN);
+            register.register(Self::panic, METHOD_PANIC);
+        }
+    }
+    let live_handlers = |n: u64| {
+        let metrics = bus.metrics();
+        tokio::time::timeout(Duration::from_secs(1), async move {
+            while metrics.live_handlers() != n {
+                tokio::task::yield_now().await;
+            }
+        })
+    };
+
+    assert_eq!(bus.metrics().live_handlers(), 0);
+    let a = bus.interface().spawn(Handler);
+    let b = bus.interface().spawn(Handler);
+    let _c = bus.interface().spawn(Handler);
+    assert_eq!(bus.metrics().live_handlers(), 3);
+
+    // handlers that shut down are no longer counted
+    bus.interface()
+        .announce_as(HDL_EXTERNAL, Target::Instance(a), METHOD_SHUTDOWN, ())
+        .await
+        .unwrap();
+    live_handlers(2)
+        .await
+        .expect("handler still counted after shutdown");
+
+    // .. and neither are those that panicked
+    bus.interface()
+        .announce_as(HDL_EXTERNAL, Target::Instance(b), METHOD_PANIC, ())
+        .await
+        .unwrap();
+    live_handlers(1)
+        .await
+        .expect("handler still counted after panicking");
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn bg_loop_rearms() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl_owned!(EV_PRIV_TICK, Result<usize, usize>, ());
+    method_decl!(METHOD_CANCEL, (), ());
+    struct Handler {
+        // the loop stops after this iteration (by returning Err)
+        fail_at: Option<usize>,
+        // results of each iteration, in the order their events were handled
+        seen: Arc<std::sync::Mutex<Vec<Result<usize, usize>>>>,
+        task: Option<BgHandle>,
+    }
+    impl Handler {
+        async fn tick(
+            &mut self,
+            res: Result<usize, usize>,
+            _: &LocalInterface,
+        ) -> Result<(), <Self as HandlerInit>::Error> {
+            self.seen.lock().unwrap().push(res);
+            Ok(())
+        }
+        async fn cancel(
+            &mut self,
+            _: &(),
+            _: &LocalInterface,
+        ) -> Result<(), <Self as HandlerInit>::Error> {
+            let task = self.task.as_ref().unwrap();
+            assert!(task.is_running());
+            task.cancel();
+            assert!(!task.is_running());
+            Ok(())
+        }
+    }
+    #[async_trait]
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Background loop test handler");
+        type Error = Infallible;
+        async fn init(&mut self, int: &LocalInterface) -> Result<(), Self::Error> {
+            let fail_at = self.fail_at;
+            // (the state is the number of the iteration)
+            self.task = Some(int.bg_loop(EV_PRIV_TICK, 0, move |i| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let res = match fail_at {
+                    Some(at) if at == i => Err(i),
+                    _ => Ok(i),
+                };
+                (i + 1, res)
+            }));
+            Ok(())
+        }
+        fn describe(&self) -> Str {
+            Str::Borrowed("Background loop test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register_owned(Self::tick, EV_PRIV_TICK);
+            register.register(Self::cancel, METHOD_CANCEL);
+        }
+    }
+    let spawn = |fail_at| {
+        let seen = Arc::new(std::sync::Mutex::new(vec![]));
+        let instance = bus.interface().spawn(Handler {
+            fail_at,
+            seen: seen.clone(),
+            task: None,
+        });
+        (instance, seen)
+    };
+
+    // re-armed after every iteration, until one fails
+    let (_, seen) = spawn(Some(3));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(*seen.lock().unwrap(), vec![Ok(0), Ok(1), Ok(2), Err(3)]);
+
+    // runs until cancelled
+    let (instance, seen) = spawn(None);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    bus.interface()
+        .query_as(HDL_EXTERNAL, instance, METHOD_CANCEL, ())
+        .await
+        .unwrap();
+    let stopped_at = seen.lock().unwrap().len();
+    assert!(stopped_at > 3, "loop was not re-armed");
+    assert!(seen
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .all(|(i, res)| *res == Ok(i)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(seen.lock().unwrap().len(), stopped_at);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn mismatched_arguments_rejected() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl!(METHOD_DOUBLE, u32, u32);
+    // same method, but (incorrectly) declared with different argument types
+    let mismatched = MethodDecl::<false, String, u32>::new(METHOD_DOUBLE.desc, METHOD_DOUBLE.id);
+    struct Handler;
+    impl Handler {
+        async fn double(
+            &mut self,
+            x: &u32,
+            _: &LocalInterface,
+        ) -> Result<u32, <Self as HandlerInit>::Error> {
+            Ok(x * 2)
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Type checking test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Type checking test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::double, METHOD_DOUBLE)
+        }
+    }
+    let instance_id = bus.interface().spawn(Handler);
+    let res = bus
+        .interface()
+        .query_as(HDL_EXTERNAL, instance_id.clone(), mismatched, "2".into())
+        .await;
+    assert!(
+        matches!(
+            res,
+            Err(DispatchErr::HandlerError(ResponseErr::CallFailed(..)))
+        ),
+        "{res:?}"
+    );
+    // and the handler is unaffected
+    let res = bus
+        .interface()
+        .query_as(HDL_EXTERNAL, instance_id, METHOD_DOUBLE, 2)
+        .await;
+    assert_eq!(res.unwrap(), 4);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn dispatch_after_close() {
+    method_decl!(METHOD_PING, (), ());
+    struct Handler;
+    impl Handler {
+        async fn ping(&mut self, _: &(), _: &LocalInterface) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Closed bus test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Closed bus test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::ping, METHOD_PING)
+        }
+    }
+
+    // dropping the bus stops its logging task cleanly
+    let mut bus = Bus::new(BusConfig::default()).await;
+    let int = bus.interface();
+    let instance_id = int.spawn(Handler);
+    int.query_as(HDL_EXTERNAL, instance_id.clone(), METHOD_PING, ())
+        .await
+        .unwrap();
+    let logger = bus.logger.take().unwrap();
+    drop(bus);
+    tokio::time::timeout(Duration::from_secs(1), logger)
+        .await
+        .expect("logging task did not exit")
+        .expect("logging task panicked");
+
+    // requests fail (instead of timing out waiting for a response)
+    assert!(int.is_closed());
+    let res = int
+        .query_as(HDL_EXTERNAL, instance_id.clone(), METHOD_PING, ())
+        .await;
+    assert!(matches!(res, Err(DispatchErr::Closed)), "{res:?}");
+    let res = int
+        .dispatch_collect_as(
+            HDL_EXTERNAL,
+            Target::Any,
+            METHOD_PING,
+            (),
+            Duration::from_millis(10),
+        )
+        .await;
+    assert!(matches!(res, Err(DispatchErr::Closed)));
+
+    let bus = Bus::new(BusConfig::default()).await;
+    let int = bus.interface();
+    bus.close().await;
+    assert!(matches!(
+        int.announce_as(HDL_EXTERNAL, Target::Any, METHOD_PING, ())
+            .await,
+        Err(DispatchErr::Closed)
+    ));
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn method_call_span() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl!(METHOD_ADD, (u32, u32), u32);
+    struct Handler;
+    impl Handler {
+        async fn add(
+            &mut self,
+            &(a, b): &(u32, u32),
+            _: &LocalInterface,
+        ) -> Result<u32, <Self as HandlerInit>::Error> {
+            Ok(a + b)
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Adding test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Adding test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::add, METHOD_ADD)
+        }
+    }
+    let instance_id = bus.interface().spawn(Handler);
+    let res = bus
+        .interface()
+        .query_as(HDL_EXTERNAL, instance_id, METHOD_ADD, (2, 3))
+        .await;
+    assert_eq!(res.unwrap(), 5);
+    // the method ran inside the span, which was closed (with the elapsed time) when it returned.
+    // (the handler runs in its own task, outside of this test's span, so the logs are matched by target instead)
+    tracing_test::internal::logs_assert("roundtable::handler::runtime", |lines| {
+        lines
+            .iter()
+            .find(|line| {
+                line.contains("bus_method{")
+                    && line.contains("method=\"METHOD_ADD\"")
+                    && line.contains("args=\"(u32, u32)\"")
+                    && line.contains("elapsed=")
+                    && line.contains("method returned")
+            })
+            .map(|_| ())
+            .ok_or_else(|| String::from("no `method returned` event in the method's span"))
+    })
+    .unwrap();
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+#[should_panic(expected = "(\"METHOD_FIRST\" and \"METHOD_SECOND\")")]
+async fn colliding_methods_detected() {
+    let bus = Bus::new(BusConfig::default()).await;
+    // (as if `const_uuid_v4!` had generated the same id twice)
+    const ID: uuid::Uuid = crate::const_uuid_v4!();
+    const METHOD_FIRST: MethodDecl<false, (), ()> = MethodDecl::new("METHOD_FIRST", ID);
+    const METHOD_SECOND: MethodDecl<false, (), ()> = MethodDecl::new("METHOD_SECOND", ID);
+    struct Handler;
+    impl Handler {
+        async fn first(&mut self, _: &(), _: &LocalInterface) -> Result<(), Infallible> {
+            Ok(())
+        }
+        async fn second(&mut self, _: &(), _: &LocalInterface) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Colliding test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Colliding test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::first, METHOD_FIRST);
+            register.register(Self::second, METHOD_SECOND);
+        }
+    }
+    // panics when the handler's methods are first registered
+    bus.interface().spawn(Handler);
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn spawn_checked_reports_init() {
+    let bus = Bus::new(BusConfig::default()).await;
+    method_decl!(METHOD_PING, (), ());
+    /// fails to initialize if `Err`, panics if `None`
+    struct Handler(Option<Result<(), String>>);
+    impl Handler {
+        async fn ping(&mut self, _: &(), _: &LocalInterface) -> Result<(), String> {
+            Ok(())
+        }
+    }
+    #[async_trait]
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Initializing test handler");
+        type Error = String;
+        async fn init(&mut self, _: &LocalInterface) -> Result<(), Self::Error> {
+            self.0.clone().expect("init panicked")
+        }
+        fn describe(&self) -> Str {
+            Str::Borrowed("Initializing test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::ping, METHOD_PING)
+        }
+    }
+    let inst = bus.spawn_checked(Handler(Some(Ok(())))).await.unwrap();
+    bus.dispatch_as(HDL_EXTERNAL, inst, METHOD_PING, ())
+        .await
+        .unwrap();
+
+    let res = bus
+        .spawn_checked(Handler(Some(Err("no database".into()))))
+        .await;
+    let Err(SpawnErr::InitFailed(_, err)) = res else {
+        panic!("expected init to fail, got {res:?}");
+    };
+    assert_eq!(err.to_string(), "no database");
+
+    let res = bus.spawn_checked(Handler(None)).await;
+    assert!(matches!(res, Err(SpawnErr::Exited(..))), "{res:?}");
+}
+
+#[traced_test]
+#[tokio::test(flavor = "multi_thread")]
+async fn record_and_replay() {
+    use std::{
+        io::{self, Write},
+        sync::Mutex,
+    };
+
+    use crate::record::{self, ArgCodecs, Record, RecordedResponse, RecordedTarget, Recorder};
+
+    method_decl!(METHOD_LOG, u32, ());
+    // (can not be serialized)
+    method_decl!(METHOD_OPAQUE, Arc<AtomicBool>, ());
+    struct Handler(Arc<Mutex<Vec<u32>>>);
+    impl Handler {
+        async fn log(&mut self, n: &u32, _: &LocalInterface) -> Result<(), Infallible> {
+            self.0.lock().unwrap().push(*n);
+            Ok(())
+        }
+        async fn opaque(
+            &mut self,
+            _: &Arc<AtomicBool>,
+            _: &LocalInterface,
+        ) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+    impl HandlerInit for Handler {
+        const DECL: HandlerType = handler_decl_t!("Recorded test handler");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Recorded test handler instance")
+        }
+        fn methods(&self, register: &mut MethodRegister<Self>) {
+            register.register(Self::log, METHOD_LOG);
+            register.register(Self::opaque, METHOD_OPAQUE);
+        }
+    }
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let codecs = || ArgCodecs::new().with(METHOD_LOG);
+
+    let bus = Bus::new(BusConfig::default()).await;
+    let logged = Arc::new(Mutex::new(vec![]));
+    let inst = bus.spawn(Handler(logged.clone()));
+    let recording = Shared::default();
+    let recorder = Recorder::start(&bus.interface(), codecs(), recording.clone());
+    bus.announce_as(HDL_EXTERNAL, Target::Any, METHOD_LOG, 1)
+        .await
+        .unwrap();
+    bus.dispatch_as(HDL_EXTERNAL, inst.clone(), METHOD_LOG, 2)
+        .await
+        .unwrap();
+    bus.announce_as(HDL_EXTERNAL, Target::Any, METHOD_OPAQUE, Arc::default())
+        .await
+        .unwrap();
+    bus.announce_as(HDL_EXTERNAL, Target::Any, METHOD_LOG, 3)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(*logged.lock().unwrap(), [1, 2, 3]);
+    bus.close().await;
+    let stats = recorder.finish().await.unwrap();
+    assert_eq!(
+        (stats.recorded, stats.missing_arguments, stats.lagged),
+        (4, 1, 0)
+    );
+
+    let recording = recording.0.lock().unwrap().clone();
+    let records = recording
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect::<Vec<Record>>();
+    let requests = records
+        .iter()
+        .map(|record| match record {
+            Record::Request(req) => req,
+            Record::Lagged { .. } => panic!("recorder lagged"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        requests
+            .iter()
+            .map(|req| req.method_desc.as_str())
+            .collect::<Vec<_>>(),
+        ["METHOD_LOG", "METHOD_LOG", "METHOD_OPAQUE", "METHOD_LOG"]
+    );
+    assert_eq!(requests[0].arguments, Some(serde_json::json!(1)));
+    assert_eq!(requests[0].target, RecordedTarget::Any);
+    assert_eq!(requests[1].response, RecordedResponse::Verify);
+    assert!(
+        matches!(&requests[1].target, RecordedTarget::Instance(target) if target.desc == "Recorded test handler instance")
+    );
+    assert_eq!(requests[2].arguments, None);
+    assert_eq!(
+        requests[2].argument_type,
+        std::any::type_name::<Arc<AtomicBool>>()
+    );
+    assert_eq!(requests[0].source.typ.desc, "External event dispatcher");
+
+    // replayed on a fresh bus, with the handler spawned the same way (so it has the same id)
+    let bus = Bus::new(BusConfig::default()).await;
+    let replayed = Arc::new(Mutex::new(vec![]));
+    assert_eq!(bus.spawn(Handler(replayed.clone())), inst);
+    let report = record::replay(&bus.interface(), &codecs(), recording.as_slice())
+        .await
+        .unwrap();
+    assert_eq!(report.sent, 3);
+    assert_eq!(report.skipped, [(3, "METHOD_OPAQUE".to_string())]);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(*replayed.lock().unwrap(), [1, 2, 3]);
+
+    // not a recording
+    assert!(matches!(
+        record::replay(&bus.interface(), &codecs(), "{}".as_bytes()).await,
+        Err(record::ReplayError::Invalid { line: 1, .. })
+    ));
 }
@@ -7,7 +7,13 @@ use self::station::{
     identity::StationID,
 };
 
+use crate::transport::shared::TransportStats;
+
+pub mod compression;
 pub mod station;
+mod test;
+
+pub use compression::Compression;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PacketKind {
@@ -16,6 +22,10 @@ pub enum PacketKind {
     // provides mappings of channel names -> uuids
     ChannelMappings(ChannelMappings),
     Data(SomeData),
+    // sent to a station to have it update its firmware
+    BeginOTA(BeginOTA),
+    // sent periodically by a station, describing how well its connection is working
+    Diagnostics(Diagnostics),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,14 +35,54 @@ pub struct OnConnect {
     // chrono rfc3339 timestamp
     pub station_build_date: String,
     pub channels: Vec<Channel>,
+    /// channels whose sensor failed its self test at boot (no data should be expected for them)
+    #[serde(default)]
+    pub degraded_channels: Vec<ChannelName>,
+    /// one-time token the station was provisioned with, which a server that only accepts known stations exchanges
+    /// for accepting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provisioning_token: Option<String>,
+    /// compression codecs the station supports (see [`compression`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<Compression>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChannelMappings {
     pub map: HashMap<ChannelName, ChannelID>,
+    /// declared channels that were not mapped, because the server already knows a channel by that name with a
+    /// different value or type
+    #[serde(default)]
+    pub conflicts: Vec<ChannelName>,
+    /// compression the station may use for the packets it sends from now on (see [`compression`])
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+}
+
+/// Firmware update, downloaded by the station from `url` (which must be HTTPS)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BeginOTA {
+    pub url: String,
+    /// SHA-256 of the image
+    pub sha256: [u8; 32],
+    /// ECDSA (P-256) signature of `sha256`, DER encoded
+    pub signature: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SomeData {
     pub per_channel: HashMap<ChannelID, ChannelData>,
+    /// when the data was recorded (unix timestamp, seconds), None if the station's clock is not synced
+    #[serde(default)]
+    pub recorded_at: Option<i64>,
+    /// increases with every `Data` sent by a station (including across reboots), so duplicates can be dropped.
+    /// 0 if the station does not number its data
+    #[serde(default)]
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostics {
+    /// the station's transport stats since it last sent `Diagnostics` (or booted)
+    pub transport: TransportStats,
 }
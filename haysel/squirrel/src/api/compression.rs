@@ -0,0 +1,164 @@
+//! Compression of serialized packets, negotiated when a station connects
+//!
+//! a station lists the codecs it supports in [`OnConnect::compression`](super::OnConnect::compression), and the server
+//! chooses one in [`ChannelMappings::compression`](super::ChannelMappings::compression). after that, the station may
+//! compress the packets it sends (the server can always tell if a packet is compressed, see [`COMPRESSED_MARKER`]).
+//! stations that list nothing (including those built before compression existed) never compress anything
+
+use std::borrow::Cow;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
+
+/// first byte of a compressed packet, followed by the codec, and the compressed packet.
+///
+/// msgpack never uses this byte, so it can not be the start of an uncompressed packet
+pub const COMPRESSED_MARKER: u8 = 0xC1;
+
+/// largest a packet may decompress to (a compressed packet claiming to be larger is invalid)
+pub const MAX_DECOMPRESSED_SIZE: usize = 1 << 20;
+
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    IntoPrimitive,
+    TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4 block format, with the decompressed size prepended (little endian u32)
+    Lz4,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecompressError {
+    #[error("Unknown compression codec {0:#04x}")]
+    UnknownCodec(u8),
+    #[error("Compressed packet is truncated")]
+    Truncated,
+    #[error("Compressed packet claims to be {0} bytes, more than the maximum of {MAX_DECOMPRESSED_SIZE}")]
+    TooLarge(usize),
+    #[error("Compressed packet is invalid: {0}")]
+    Invalid(String),
+}
+
+impl Compression {
+    /// codecs supported by this build, most preferred first
+    pub const SUPPORTED: &'static [Compression] = &[Compression::Lz4];
+
+    pub fn is_none(&self) -> bool {
+        *self == Self::None
+    }
+
+    /// the codec to use with a peer that supports `theirs` (the first of [`Compression::SUPPORTED`] that they do)
+    pub fn negotiate(theirs: &[Compression]) -> Self {
+        Self::SUPPORTED
+            .iter()
+            .find(|codec| theirs.contains(codec))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Compresses a serialized packet. it is sent uncompressed if the codec is `None`, or compressing does not make it
+    /// smaller
+    pub fn compress<'a>(self, packet: &'a [u8]) -> Cow<'a, [u8]> {
+        let body = match self {
+            Self::None => return Cow::Borrowed(packet),
+            Self::Lz4 => lz4_flex::compress_prepend_size(packet),
+        };
+        if body.len() + 2 >= packet.len() {
+            return Cow::Borrowed(packet);
+        }
+        let mut compressed = Vec::with_capacity(body.len() + 2);
+        compressed.extend([COMPRESSED_MARKER, self.into()]);
+        compressed.extend(body);
+        Cow::Owned(compressed)
+    }
+}
+
+/// Decompresses a packet from [`Compression::compress`] (packets that are not compressed are returned as is)
+pub fn decompress(packet: &[u8]) -> Result<Cow<'_, [u8]>, DecompressError> {
+    let [COMPRESSED_MARKER, codec, body @ ..] = packet else {
+        return Ok(Cow::Borrowed(packet));
+    };
+    match Compression::try_from(*codec).map_err(|_| DecompressError::UnknownCodec(*codec))? {
+        Compression::None => Ok(Cow::Borrowed(body)),
+        Compression::Lz4 => {
+            let size = body
+                .get(..4)
+                .ok_or(DecompressError::Truncated)?
+                .try_into()
+                .map(u32::from_le_bytes)
+                .unwrap() as usize;
+            if size > MAX_DECOMPRESSED_SIZE {
+                return Err(DecompressError::TooLarge(size));
+            }
+            lz4_flex::decompress(&body[4..], size)
+                .map(Cow::Owned)
+                .map_err(|e| DecompressError::Invalid(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn compress_roundtrip() {
+    // (repetitive, like a packet with many channels)
+    let packet = b"temperature temperature_f humidity pressure ".repeat(20);
+    let compressed = Compression::Lz4.compress(&packet);
+    assert!(compressed.len() < packet.len());
+    assert_eq!(
+        compressed[..2],
+        [COMPRESSED_MARKER, Compression::Lz4.into()]
+    );
+    assert_eq!(decompress(&compressed).unwrap(), packet);
+    // not worth compressing
+    assert_eq!(
+        Compression::Lz4.compress(b"\x81\xa4Data"),
+        &b"\x81\xa4Data"[..]
+    );
+    assert_eq!(Compression::None.compress(&packet), &packet[..]);
+    assert_eq!(decompress(&packet).unwrap(), packet);
+
+    assert_eq!(
+        decompress(&[COMPRESSED_MARKER, 0x7f]),
+        Err(DecompressError::UnknownCodec(0x7f))
+    );
+    assert_eq!(
+        decompress(&[COMPRESSED_MARKER, Compression::Lz4.into(), 1, 0]),
+        Err(DecompressError::Truncated)
+    );
+    let mut huge = compressed.to_vec();
+    huge[2..6].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        decompress(&huge),
+        Err(DecompressError::TooLarge(u32::MAX as usize))
+    );
+    assert!(matches!(
+        decompress(&compressed[..compressed.len() - 4]),
+        Err(DecompressError::Invalid(..))
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn negotiation() {
+    assert_eq!(Compression::negotiate(&[]), Compression::None);
+    assert_eq!(
+        Compression::negotiate(&[Compression::None]),
+        Compression::None
+    );
+    assert_eq!(
+        Compression::negotiate(&[Compression::None, Compression::Lz4]),
+        Compression::Lz4
+    );
+}
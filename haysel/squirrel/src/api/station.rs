@@ -1,2 +1,5 @@
 pub mod capabilities;
+#[cfg(feature = "server-utils")]
+pub mod delta;
+pub mod formula;
 pub mod identity;
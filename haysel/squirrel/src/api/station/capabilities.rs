@@ -3,6 +3,10 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[cfg(feature = "server-utils")]
+use super::delta::RegistryDelta;
+use super::formula::Formula;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")] //internally tagged
 pub enum ChannelValue {
@@ -36,6 +40,13 @@ pub enum ChannelType {
     /// event that occurs based on an external trigger. (EX: lightning)
     /// the fact that the event occured at this time is significant
     Triggered,
+    /// value is derived (by the server) from other `Float` channels, whenever a station sends data for all of them
+    /// (EX: dew point, from temperature and humidity)
+    Computed {
+        /// channels that are used as input to `formula` (`Formula::Input(n)` refers to `inputs[n]`)
+        inputs: Vec<ChannelID>,
+        formula: Formula,
+    },
 }
 
 /// Name of a reading channel (temperature, humidity, lightning, etc)
@@ -74,14 +85,68 @@ pub struct Channel {
     pub name: ChannelName,
     pub value: ChannelValue,
     pub ty: ChannelType,
+    /// unit of the channel's values, for display (EX: "°C", "V"). not used for events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// human readable description of what the channel measures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 pub type ChannelID = Uuid;
 
+/// The id a channel named `name` is given when it is first registered.
+///
+/// this is derived only from the name, so a channel keeps its id even if the registry is lost
+/// (and multiple servers agree on it)
+pub fn channel_id_for_name(name: &ChannelName) -> ChannelID {
+    // FNV-1a (128 bit), std's hashers are not guaranteed to be stable between releases
+    let mut hash = 0x6c62272e07bb014262b821756295c58du128;
+    for byte in name.name.bytes() {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+    }
+    ChannelID::new_v8(hash.to_be_bytes())
+}
+
 #[cfg(feature = "server-utils")]
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(from = "ChannelsRepr", into = "ChannelsRepr")]
 pub struct KnownChannels {
     channels: HashMap<ChannelID, Channel>,
+    /// reverse of `channels` (rebuilt when loaded)
+    by_name: HashMap<ChannelName, ChannelID>,
+}
+
+/// [`KnownChannels`] as saved (`channels.json`)
+#[cfg(feature = "server-utils")]
+#[derive(Serialize, Deserialize)]
+struct ChannelsRepr {
+    channels: HashMap<ChannelID, Channel>,
+}
+
+#[cfg(feature = "server-utils")]
+impl From<ChannelsRepr> for KnownChannels {
+    fn from(repr: ChannelsRepr) -> Self {
+        let by_name = repr
+            .channels
+            .iter()
+            .map(|(id, ch)| (ch.name.clone(), *id))
+            .collect();
+        Self {
+            channels: repr.channels,
+            by_name,
+        }
+    }
+}
+
+#[cfg(feature = "server-utils")]
+impl From<KnownChannels> for ChannelsRepr {
+    fn from(known: KnownChannels) -> Self {
+        Self {
+            channels: known.channels,
+        }
+    }
 }
 
 #[cfg(feature = "server-utils")]
@@ -89,6 +154,7 @@ impl KnownChannels {
     pub fn new() -> Self {
         KnownChannels {
             channels: HashMap::default(),
+            by_name: HashMap::default(),
         }
     }
 
@@ -97,23 +163,57 @@ impl KnownChannels {
     }
 
     pub fn id_by_name(&self, name: &ChannelName) -> Option<ChannelID> {
-        self.channels
-            .iter()
-            .find(|(_, n)| &n.name == name)
-            .map(|(id, _)| id.clone())
+        self.by_name.get(name).copied()
+    }
+
+    pub fn find_by_name(&self, name: &ChannelName) -> Option<(ChannelID, &Channel)> {
+        let id = self.id_by_name(name)?;
+        Some((id, &self.channels[&id]))
     }
 
     /// Returns Err(new_channel) if a channel with the new channels name already exists
-    pub fn insert_channel(&mut self, channel: Channel) -> Result<ChannelID, Channel> {
+    ///
+    /// the new channel's id is [`channel_id_for_name`], unless that is already taken
+    pub fn insert_channel(&mut self, channel: Channel) -> Result<ChannelID, Box<Channel>> {
         if self.id_by_name(&channel.name).is_some() {
-            Err(channel)
+            Err(Box::new(channel))
         } else {
-            let id = ChannelID::new_v4();
-            self.channels.insert(id, channel);
+            let mut id = channel_id_for_name(&channel.name);
+            if self.channels.contains_key(&id) {
+                id = ChannelID::new_v4();
+            }
+            self.insert(id, channel);
             Ok(id)
         }
     }
 
+    /// The id of the channel named `name`, registering `channel` (as `name`) if there is none
+    ///
+    /// if the channel already exists, `channel` is ignored (it is not checked against the existing one)
+    pub fn get_or_create_id(&mut self, name: &ChannelName, channel: Channel) -> ChannelID {
+        match self.id_by_name(name) {
+            Some(id) => id,
+            None => self
+                .insert_channel(Channel {
+                    name: name.clone(),
+                    ..channel
+                })
+                .unwrap(),
+        }
+    }
+
+    fn insert(&mut self, id: ChannelID, channel: Channel) {
+        self.remove(&id);
+        self.by_name.insert(channel.name.clone(), id);
+        self.channels.insert(id, channel);
+    }
+
+    fn remove(&mut self, id: &ChannelID) {
+        if let Some(old) = self.channels.remove(id) {
+            self.by_name.remove(&old.name);
+        }
+    }
+
     /// returns Err(id_of_existing) if a channel with the name already exists
     pub fn insert_channel_with_id(
         &mut self,
@@ -123,7 +223,7 @@ impl KnownChannels {
         if let Some(existing_id) = self.id_by_name(&channel.name) {
             Err(existing_id)
         } else {
-            self.channels.insert(id, channel);
+            self.insert(id, channel);
             Ok(())
         }
     }
@@ -131,4 +231,176 @@ impl KnownChannels {
     pub fn channels(&self) -> impl Iterator<Item = (&ChannelID, &ChannelName)> {
         self.channels.iter().map(|(k, v)| (k, &v.name))
     }
+
+    /// Changes to the channels needed to turn `self` into `other`
+    ///
+    /// channels are never modified once created, so only additions and removals are found
+    pub fn diff(&self, other: &KnownChannels) -> RegistryDelta {
+        let mut delta = RegistryDelta {
+            added_channels: other
+                .channels
+                .iter()
+                .filter(|(id, _)| !self.channels.contains_key(id))
+                .map(|(id, ch)| (*id, ch.clone()))
+                .collect(),
+            removed_channels: self
+                .channels
+                .keys()
+                .filter(|id| !other.channels.contains_key(id))
+                .copied()
+                .collect(),
+            ..Default::default()
+        };
+        delta.sort();
+        delta
+    }
+
+    /// Applies the channel changes in `delta` (station changes are ignored, see [`KnownStations::apply`])
+    ///
+    /// [`KnownStations::apply`]: super::identity::KnownStations::apply
+    pub fn apply(&mut self, delta: &RegistryDelta) {
+        for id in &delta.removed_channels {
+            self.remove(id);
+        }
+        for (id, ch) in &delta.added_channels {
+            self.insert(*id, ch.clone());
+        }
+    }
+
+    pub fn is_computed(&self, id: &ChannelID) -> bool {
+        self.channels
+            .get(id)
+            .is_some_and(|ch| matches!(ch.ty, ChannelType::Computed { .. }))
+    }
+
+    /// Evaluate all computed channels that can be derived from `data` (all of their inputs are present, and are `Float`s)
+    ///
+    /// computed channels are not used as inputs to other computed channels
+    pub fn compute_derived(
+        &self,
+        data: &HashMap<ChannelID, ChannelData>,
+    ) -> HashMap<ChannelID, ChannelData> {
+        let mut derived = HashMap::new();
+        for (id, ch) in &self.channels {
+            let ChannelType::Computed { inputs, formula } = &ch.ty else {
+                continue;
+            };
+            let Some(values) = inputs
+                .iter()
+                .map(|input| match data.get(input) {
+                    Some(ChannelData::Float(v)) if !self.is_computed(input) => Some(*v),
+                    _ => None,
+                })
+                .collect::<Option<Vec<f32>>>()
+            else {
+                continue;
+            };
+            match formula.eval(&values) {
+                Ok(v) => {
+                    derived.insert(*id, ChannelData::Float(v));
+                }
+                Err(e) => warn!("Failed to compute channel {id} ({:?}): {e}", ch.name),
+            }
+        }
+        derived
+    }
+}
+
+#[cfg(feature = "server-utils")]
+#[test]
+fn compute_dew_point_channel() {
+    let mut known = KnownChannels::new();
+    let periodic = |name: &str| Channel {
+        name: name.into(),
+        value: ChannelValue::Float,
+        ty: ChannelType::Periodic,
+        unit: None,
+        description: None,
+    };
+    let temp = known.insert_channel(periodic("temperature")).unwrap();
+    let humid = known.insert_channel(periodic("humidity")).unwrap();
+    let battery = known.insert_channel(periodic("battery")).unwrap();
+    let dew_point = known
+        .insert_channel(Channel {
+            name: "dew_point".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Computed {
+                inputs: vec![temp, humid],
+                formula: Formula::dew_point(),
+            },
+            unit: None,
+            description: None,
+        })
+        .unwrap();
+    assert!(known.is_computed(&dew_point));
+    assert!(!known.is_computed(&temp));
+
+    let data = HashMap::from([
+        (temp, ChannelData::Float(20.0)),
+        (humid, ChannelData::Float(50.0)),
+        (battery, ChannelData::Float(3.7)),
+    ]);
+    let derived = known.compute_derived(&data);
+    assert_eq!(derived.len(), 1);
+    let Some(ChannelData::Float(dp)) = derived.get(&dew_point) else {
+        panic!("dew point was not computed");
+    };
+    assert!((dp - 9.26).abs() < 0.01);
+
+    // missing an input
+    let data = HashMap::from([(temp, ChannelData::Float(20.0))]);
+    assert!(known.compute_derived(&data).is_empty());
+}
+
+#[cfg(feature = "server-utils")]
+#[test]
+fn channel_ids_stable_by_name() {
+    let periodic = |name: &str| Channel {
+        name: name.into(),
+        value: ChannelValue::Float,
+        ty: ChannelType::Periodic,
+        unit: None,
+        description: None,
+    };
+    let mut known = KnownChannels::new();
+    let temp = known.insert_channel(periodic("temperature")).unwrap();
+    let humid = known.get_or_create_id(&"humidity".into(), periodic("humidity"));
+    assert_eq!(
+        known.get_or_create_id(&"humidity".into(), periodic("humidity")),
+        humid
+    );
+    let (id, ch) = known.find_by_name(&"temperature".into()).unwrap();
+    assert_eq!((id, &ch.name), (temp, &"temperature".into()));
+    assert!(known.find_by_name(&"pressure".into()).is_none());
+
+    // reloaded (ex: after a restart)
+    let saved = rmp_serde::to_vec_named(&known).unwrap();
+    let mut loaded = rmp_serde::from_slice::<KnownChannels>(&saved).unwrap();
+    assert_eq!(loaded.id_by_name(&"temperature".into()), Some(temp));
+    assert_eq!(
+        loaded.get_or_create_id(&"humidity".into(), periodic("humidity")),
+        humid
+    );
+
+    // or if the registry was lost entirely
+    let mut fresh = KnownChannels::new();
+    assert_eq!(
+        fresh.get_or_create_id(&"humidity".into(), periodic("humidity")),
+        humid
+    );
+    assert_eq!(fresh.insert_channel(periodic("temperature")).unwrap(), temp);
+    assert_ne!(temp, humid);
+
+    // channels registered with random ids keep them
+    let old = ChannelID::new_v4();
+    let mut known = KnownChannels::new();
+    known
+        .insert_channel_with_id(periodic("battery"), old)
+        .unwrap();
+    let saved = rmp_serde::to_vec_named(&known).unwrap();
+    let mut loaded = rmp_serde::from_slice::<KnownChannels>(&saved).unwrap();
+    assert_eq!(
+        loaded.get_or_create_id(&"battery".into(), periodic("battery")),
+        old
+    );
 }
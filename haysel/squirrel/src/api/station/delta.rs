@@ -0,0 +1,194 @@
+//! Differences between two versions of the station / channel registry
+//!
+//! (e.g. for a client to catch up after reconnecting, without replacing its whole copy)
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    capabilities::{Channel, ChannelID, KnownChannels},
+    identity::{KnownStations, StationID},
+};
+
+/// Changes to [`KnownStations`] and [`KnownChannels`].
+///
+/// created with `diff` on either (or [`RegistryDelta::between`] for both), and applied with their `apply`.
+/// each change corresponds to one of the registry's events (new station, new channel, station associated with channel)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegistryDelta {
+    pub added_stations: Vec<StationID>,
+    pub removed_stations: Vec<StationID>,
+    pub added_channels: Vec<(ChannelID, Channel)>,
+    pub removed_channels: Vec<ChannelID>,
+    /// channels newly supported by a station (including every channel of an added station)
+    pub associated: Vec<(StationID, ChannelID)>,
+    /// channels no longer supported by a (still existing) station
+    pub dissociated: Vec<(StationID, ChannelID)>,
+}
+
+impl RegistryDelta {
+    /// Changes needed to turn `from` into `to`
+    pub fn between(
+        from: (&KnownStations, &KnownChannels),
+        to: (&KnownStations, &KnownChannels),
+    ) -> Self {
+        let mut delta = from.0.diff(to.0);
+        let channels = from.1.diff(to.1);
+        delta.added_channels = channels.added_channels;
+        delta.removed_channels = channels.removed_channels;
+        delta
+    }
+
+    /// Applies this to both `stations` and `channels`
+    pub fn apply_to(&self, stations: &mut KnownStations, channels: &mut KnownChannels) {
+        channels.apply(self);
+        stations.apply(self);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_stations.is_empty()
+            && self.removed_stations.is_empty()
+            && self.added_channels.is_empty()
+            && self.removed_channels.is_empty()
+            && self.associated.is_empty()
+            && self.dissociated.is_empty()
+    }
+
+    /// (so that deltas are deterministic, registries are unordered)
+    pub(super) fn sort(&mut self) {
+        self.added_stations.sort();
+        self.removed_stations.sort();
+        self.added_channels.sort_by_key(|(id, _)| *id);
+        self.removed_channels.sort();
+        self.associated.sort();
+        self.dissociated.sort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::api::station::{
+        capabilities::{ChannelType, ChannelValue},
+        identity::StationInfo,
+    };
+
+    fn channel(name: &str) -> Channel {
+        Channel {
+            name: name.into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: None,
+            description: None,
+        }
+    }
+
+    fn station(stations: &mut KnownStations, channels: &[ChannelID]) -> StationID {
+        let id = Uuid::new_v4();
+        stations
+            .insert_station(
+                id,
+                StationInfo {
+                    supports_channels: channels.to_vec(),
+                },
+            )
+            .unwrap();
+        id
+    }
+
+    /// applying the delta from `a` to `b` to a copy of `a` must result in `b`
+    fn assert_roundtrip(
+        a: (&KnownStations, &KnownChannels),
+        b: (&KnownStations, &KnownChannels),
+    ) -> RegistryDelta {
+        let delta = RegistryDelta::between(a, b);
+        let (mut stations, mut channels) = (a.0.clone(), a.1.clone());
+        delta.apply_to(&mut stations, &mut channels);
+        assert!(RegistryDelta::between((&stations, &channels), b).is_empty());
+        delta
+    }
+
+    #[test]
+    fn additions() {
+        let (stations, channels) = (KnownStations::new(), KnownChannels::new());
+        let (mut new_stations, mut new_channels) = (stations.clone(), channels.clone());
+        let temp = new_channels.insert_channel(channel("temperature")).unwrap();
+        let humid = new_channels.insert_channel(channel("humidity")).unwrap();
+        let id = station(&mut new_stations, &[temp, humid]);
+
+        let delta = assert_roundtrip((&stations, &channels), (&new_stations, &new_channels));
+        assert_eq!(delta.added_stations, vec![id]);
+        let mut added = vec![temp, humid];
+        added.sort();
+        assert_eq!(
+            delta
+                .added_channels
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            added
+        );
+        assert_eq!(
+            delta.associated,
+            added.iter().map(|ch| (id, *ch)).collect::<Vec<_>>()
+        );
+        assert!(delta.removed_stations.is_empty());
+        assert!(delta.removed_channels.is_empty());
+        assert!(delta.dissociated.is_empty());
+
+        // nothing changed
+        assert!(RegistryDelta::between(
+            (&new_stations, &new_channels),
+            (&new_stations, &new_channels)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn removals() {
+        let (mut stations, mut channels) = (KnownStations::new(), KnownChannels::new());
+        let temp = channels.insert_channel(channel("temperature")).unwrap();
+        let kept = station(&mut stations, &[]);
+        let removed = station(&mut stations, &[temp]);
+
+        let (mut new_stations, new_channels) = (KnownStations::new(), KnownChannels::new());
+        new_stations
+            .insert_station(
+                kept,
+                StationInfo {
+                    supports_channels: vec![],
+                },
+            )
+            .unwrap();
+        let delta = assert_roundtrip((&stations, &channels), (&new_stations, &new_channels));
+        assert_eq!(delta.removed_stations, vec![removed]);
+        assert_eq!(delta.removed_channels, vec![temp]);
+        // (the association goes with the station)
+        assert!(delta.dissociated.is_empty());
+        assert!(delta.added_stations.is_empty());
+        assert!(delta.added_channels.is_empty());
+    }
+
+    #[test]
+    fn association_changes() {
+        let (mut stations, mut channels) = (KnownStations::new(), KnownChannels::new());
+        let temp = channels.insert_channel(channel("temperature")).unwrap();
+        let humid = channels.insert_channel(channel("humidity")).unwrap();
+        let id = station(&mut stations, &[temp]);
+
+        // the station stops reporting temperature, and starts reporting humidity
+        let mut new_stations = stations.clone();
+        new_stations.map_info(&id, |_, info| info.supports_channels = vec![humid]);
+        let delta = assert_roundtrip((&stations, &channels), (&new_stations, &channels));
+        assert_eq!(delta.associated, vec![(id, humid)]);
+        assert_eq!(delta.dissociated, vec![(id, temp)]);
+        assert!(delta.added_stations.is_empty());
+        assert!(delta.added_channels.is_empty());
+
+        // (and back)
+        let delta = assert_roundtrip((&new_stations, &channels), (&stations, &channels));
+        assert_eq!(delta.associated, vec![(id, temp)]);
+        assert_eq!(delta.dissociated, vec![(id, humid)]);
+    }
+}
@@ -0,0 +1,154 @@
+//! formulas used to derive the value of computed channels from other channels
+
+use std::ops;
+
+use serde::{Deserialize, Serialize};
+
+/// A small expression, evaluated over the values of a computed channel's inputs.
+///
+/// This is deliberately not a full scripting language, there are no variables, loops, or conditionals
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Formula {
+    /// a constant value
+    Const(f32),
+    /// the value of an input channel (index into the `inputs` of [`super::capabilities::ChannelType::Computed`])
+    Input(usize),
+    Add(Box<Formula>, Box<Formula>),
+    Sub(Box<Formula>, Box<Formula>),
+    Mul(Box<Formula>, Box<Formula>),
+    Div(Box<Formula>, Box<Formula>),
+    /// lhs raised to the power of rhs
+    Pow(Box<Formula>, Box<Formula>),
+    Call(Function, Box<Formula>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Function {
+    /// negation
+    Neg,
+    Abs,
+    Sqrt,
+    /// natural log
+    Ln,
+    /// e^x
+    Exp,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum FormulaError {
+    #[error("Formula references input {0}, which was not provided")]
+    MissingInput(usize),
+    #[error("Formula evaluated to a non-finite value ({0})")]
+    NotFinite(f32),
+}
+
+impl Formula {
+    /// evaluate the formula, given the values of its inputs (in the same order as the channel's `inputs`)
+    pub fn eval(&self, inputs: &[f32]) -> Result<f32, FormulaError> {
+        let res = match self {
+            Self::Const(v) => *v,
+            Self::Input(idx) => *inputs.get(*idx).ok_or(FormulaError::MissingInput(*idx))?,
+            Self::Add(lhs, rhs) => lhs.eval(inputs)? + rhs.eval(inputs)?,
+            Self::Sub(lhs, rhs) => lhs.eval(inputs)? - rhs.eval(inputs)?,
+            Self::Mul(lhs, rhs) => lhs.eval(inputs)? * rhs.eval(inputs)?,
+            Self::Div(lhs, rhs) => lhs.eval(inputs)? / rhs.eval(inputs)?,
+            Self::Pow(lhs, rhs) => lhs.eval(inputs)?.powf(rhs.eval(inputs)?),
+            Self::Call(func, arg) => {
+                let arg = arg.eval(inputs)?;
+                match func {
+                    Function::Neg => -arg,
+                    Function::Abs => arg.abs(),
+                    Function::Sqrt => arg.sqrt(),
+                    Function::Ln => arg.ln(),
+                    Function::Exp => arg.exp(),
+                }
+            }
+        };
+        // catches division by zero, log of negative numbers, etc
+        if res.is_finite() {
+            Ok(res)
+        } else {
+            Err(FormulaError::NotFinite(res))
+        }
+    }
+
+    /// the largest input index used by this formula (if any)
+    pub fn max_input(&self) -> Option<usize> {
+        match self {
+            Self::Const(_) => None,
+            Self::Input(idx) => Some(*idx),
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Pow(lhs, rhs) => lhs.max_input().max(rhs.max_input()),
+            Self::Call(_, arg) => arg.max_input(),
+        }
+    }
+
+    pub fn call(func: Function, arg: Formula) -> Self {
+        Self::Call(func, Box::new(arg))
+    }
+
+    pub fn pow(self, rhs: Formula) -> Self {
+        Self::Pow(Box::new(self), Box::new(rhs))
+    }
+
+    /// Dew point (in °C), using the Magnus formula.
+    ///
+    /// inputs are `[temperature (°C), relative humidity (%)]`
+    pub fn dew_point() -> Self {
+        const B: f32 = 17.62;
+        const C: f32 = 243.12;
+        let (t, rh) = (Self::Input(0), Self::Input(1));
+        // gamma = ln(rh / 100) + (b * t) / (c + t)
+        let gamma = Self::call(Function::Ln, rh / Self::Const(100.0))
+            + (Self::Const(B) * t.clone()) / (Self::Const(C) + t);
+        // dew point = (c * gamma) / (b - gamma)
+        (Self::Const(C) * gamma.clone()) / (Self::Const(B) - gamma)
+    }
+}
+
+macro_rules! impl_op {
+    ($trait:ident, $fn:ident, $variant:ident) => {
+        impl ops::$trait for Formula {
+            type Output = Formula;
+            fn $fn(self, rhs: Formula) -> Formula {
+                Formula::$variant(Box::new(self), Box::new(rhs))
+            }
+        }
+    };
+}
+
+impl_op!(Add, add, Add);
+impl_op!(Sub, sub, Sub);
+impl_op!(Mul, mul, Mul);
+impl_op!(Div, div, Div);
+
+#[test]
+fn eval_basic() {
+    let f = (Formula::Input(0) + Formula::Const(2.0)) * Formula::Input(1);
+    assert_eq!(f.eval(&[1.0, 3.0]), Ok(9.0));
+    assert_eq!(f.max_input(), Some(1));
+    assert_eq!(f.eval(&[1.0]), Err(FormulaError::MissingInput(1)));
+    let f = Formula::Input(0) / Formula::Const(0.0);
+    assert!(matches!(f.eval(&[1.0]), Err(FormulaError::NotFinite(_))));
+    let f = Formula::call(Function::Sqrt, Formula::Input(0));
+    assert!(matches!(f.eval(&[-1.0]), Err(FormulaError::NotFinite(_))));
+    let f = Formula::Input(0).pow(Formula::Const(2.0));
+    assert_eq!(f.eval(&[-3.0]), Ok(9.0));
+}
+
+#[test]
+fn eval_dew_point() {
+    let f = Formula::dew_point();
+    assert_eq!(f.max_input(), Some(1));
+    // reference values (Magnus formula, b=17.62 c=243.12)
+    for (t, rh, expected) in [(20.0, 50.0, 9.26), (25.0, 80.0, 21.31), (0.0, 100.0, 0.0)] {
+        let dp = f.eval(&[t, rh]).unwrap();
+        assert!(
+            (dp - expected).abs() < 0.01,
+            "dew point of {t}C at {rh}% was {dp}, expected {expected}"
+        );
+    }
+}
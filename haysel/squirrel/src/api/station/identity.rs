@@ -3,6 +3,9 @@
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "server-utils")]
 use std::collections::HashMap;
+
+#[cfg(feature = "server-utils")]
+use super::delta::RegistryDelta;
 use uuid::Uuid;
 
 pub type StationID = Uuid;
@@ -59,4 +62,62 @@ impl KnownStations {
     pub fn stations(&self) -> impl Iterator<Item = &StationID> {
         self.ids.keys()
     }
+
+    /// Changes to the stations (and their channels) needed to turn `self` into `other`
+    pub fn diff(&self, other: &KnownStations) -> RegistryDelta {
+        let mut delta = RegistryDelta::default();
+        for (id, info) in &other.ids {
+            let before = match self.ids.get(id) {
+                Some(before) => &before.supports_channels[..],
+                None => {
+                    delta.added_stations.push(*id);
+                    &[]
+                }
+            };
+            for ch in &info.supports_channels {
+                if !before.contains(ch) {
+                    delta.associated.push((*id, *ch));
+                }
+            }
+            for ch in before {
+                if !info.supports_channels.contains(ch) {
+                    delta.dissociated.push((*id, *ch));
+                }
+            }
+        }
+        delta.removed_stations = self
+            .ids
+            .keys()
+            .filter(|id| !other.ids.contains_key(id))
+            .copied()
+            .collect();
+        delta.sort();
+        delta
+    }
+
+    /// Applies the station changes in `delta` (channel changes are ignored, see [`KnownChannels::apply`])
+    ///
+    /// [`KnownChannels::apply`]: super::capabilities::KnownChannels::apply
+    pub fn apply(&mut self, delta: &RegistryDelta) {
+        for id in &delta.removed_stations {
+            self.ids.remove(id);
+        }
+        for id in &delta.added_stations {
+            self.ids.entry(*id).or_insert_with(|| StationInfo {
+                supports_channels: vec![],
+            });
+        }
+        for (id, ch) in &delta.associated {
+            if let Some(info) = self.ids.get_mut(id) {
+                if !info.supports_channels.contains(ch) {
+                    info.supports_channels.push(*ch);
+                }
+            }
+        }
+        for (id, ch) in &delta.dissociated {
+            if let Some(info) = self.ids.get_mut(id) {
+                info.supports_channels.retain(|c| c != ch);
+            }
+        }
+    }
 }
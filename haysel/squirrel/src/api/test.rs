@@ -0,0 +1,263 @@
+//! locks down the wire format of [`PacketKind`]
+//!
+//! stations are not updated at the same time as the server, so a change in how packets are serialized (renaming a
+//! field, reordering enum variants, ...) must be deliberate. each packet is compared to a committed fixture in
+//! `fixtures/wire/`. if a change is intended, regenerate them with `UPDATE_WIRE_FIXTURES=1 cargo test -p squirrel`
+//!
+//! maps only ever have one entry here, because `HashMap` iteration order (and so the serialized bytes) is random
+#![cfg(test)]
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use uuid::Uuid;
+
+use super::{
+    station::{
+        capabilities::{Channel, ChannelData, ChannelName, ChannelType, ChannelValue},
+        formula::Formula,
+    },
+    BeginOTA, ChannelMappings, Compression, Diagnostics, OnConnect, PacketKind, SomeData,
+};
+use crate::transport::shared::TransportStats;
+
+const STATION: Uuid = Uuid::from_u128(0x2a3e5c1b_8f4d_4e0a_9b61_7d2c4f0e1a35);
+const TEMPERATURE: Uuid = Uuid::from_u128(0x5b1f0c9e_3d2a_4c8b_a7e6_0f4d9c2b1e83);
+const LIGHTNING: Uuid = Uuid::from_u128(0xc4e2a7d9_6b0f_4a31_8e5c_2d9f7b1a0c64);
+
+fn connect() -> PacketKind {
+    PacketKind::Connect(OnConnect {
+        station_id: STATION,
+        station_build_rev: "0123abc".into(),
+        station_build_date: "2024-03-24T12:00:00+00:00".into(),
+        channels: vec![
+            Channel {
+                name: "temperature".into(),
+                value: ChannelValue::Float,
+                ty: ChannelType::Periodic,
+                unit: Some("°C".into()),
+                description: Some("air temperature".into()),
+            },
+            Channel {
+                name: "lightning".into(),
+                value: ChannelValue::Event(HashMap::from([(
+                    "strike".into(),
+                    vec!["distance".into()],
+                )])),
+                ty: ChannelType::Triggered,
+                unit: None,
+                description: None,
+            },
+            Channel {
+                name: "temperature_f".into(),
+                value: ChannelValue::Float,
+                ty: ChannelType::Computed {
+                    inputs: vec![TEMPERATURE],
+                    formula: Formula::Add(
+                        Box::new(Formula::Mul(
+                            Box::new(Formula::Input(0)),
+                            Box::new(Formula::Const(1.8)),
+                        )),
+                        Box::new(Formula::Const(32.0)),
+                    ),
+                },
+                unit: None,
+                description: None,
+            },
+        ],
+        degraded_channels: vec!["lightning".into()],
+        provisioning_token: None,
+        compression: vec![],
+    })
+}
+
+fn channel_mappings() -> PacketKind {
+    PacketKind::ChannelMappings(ChannelMappings {
+        map: HashMap::from([("temperature".into(), TEMPERATURE)]),
+        conflicts: vec!["lightning".into()],
+        compression: Compression::None,
+    })
+}
+
+fn data_periodic() -> PacketKind {
+    PacketKind::Data(SomeData {
+        per_channel: HashMap::from([(TEMPERATURE, ChannelData::Float(21.5))]),
+        recorded_at: Some(1_711_281_600),
+        seq: 42,
+    })
+}
+
+fn data_event() -> PacketKind {
+    PacketKind::Data(SomeData {
+        per_channel: HashMap::from([(
+            LIGHTNING,
+            ChannelData::Event {
+                sub: "strike".into(),
+                data: HashMap::from([("distance".into(), 12.0)]),
+            },
+        )]),
+        recorded_at: None,
+        seq: 43,
+    })
+}
+
+fn begin_ota() -> PacketKind {
+    PacketKind::BeginOTA(BeginOTA {
+        url: "https://example.com/hayselnut.bin".into(),
+        sha256: std::array::from_fn(|i| i as u8),
+        signature: vec![0x30, 0x45, 0x02, 0x20],
+    })
+}
+
+fn diagnostics() -> PacketKind {
+    PacketKind::Diagnostics(Diagnostics {
+        transport: TransportStats {
+            packets_sent: 120,
+            retransmits: 3,
+            transactions: 40,
+            timeouts: 1,
+            rtt_total_ms: 1_480,
+            rtt_samples: 117,
+            rtt_max_ms: 95,
+        },
+    })
+}
+
+fn fixtures() -> [(&'static str, PacketKind); 6] {
+    [
+        ("connect", connect()),
+        ("channel_mappings", channel_mappings()),
+        ("data_periodic", data_periodic()),
+        ("data_event", data_event()),
+        ("begin_ota", begin_ota()),
+        ("diagnostics", diagnostics()),
+    ]
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/wire")
+        .join(format!("{name}.msgpack"))
+}
+
+/// serialized the same way as by the station and server
+fn to_wire(packet: &PacketKind) -> Vec<u8> {
+    rmp_serde::to_vec_named(packet).unwrap()
+}
+
+#[test]
+fn wire_format_matches_fixtures() {
+    let update = std::env::var_os("UPDATE_WIRE_FIXTURES").is_some();
+    for (name, packet) in fixtures() {
+        let path = fixture_path(name);
+        let bytes = to_wire(&packet);
+        if update {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &bytes).unwrap();
+            continue;
+        }
+        let fixture = fs::read(&path)
+            .unwrap_or_else(|e| panic!("failed to read fixture {}: {e}", path.display()));
+        assert!(
+            bytes == fixture,
+            "serialized `{name}` does not match its fixture (the wire format changed)"
+        );
+    }
+}
+
+#[test]
+fn fixtures_deserialize() {
+    for (name, packet) in fixtures() {
+        let fixture = fs::read(fixture_path(name)).unwrap();
+        let decoded = rmp_serde::from_slice::<PacketKind>(&fixture)
+            .unwrap_or_else(|e| panic!("failed to deserialize fixture `{name}`: {e}"));
+        // (none of the packets implement PartialEq)
+        assert_eq!(format!("{decoded:?}"), format!("{packet:?}"), "{name}");
+    }
+}
+
+#[test]
+fn roundtrip() {
+    for (name, packet) in fixtures() {
+        let decoded = rmp_serde::from_slice::<PacketKind>(&to_wire(&packet)).unwrap();
+        assert_eq!(to_wire(&decoded), to_wire(&packet), "{name}");
+    }
+}
+
+#[test]
+fn optional_fields_default() {
+    // packets from stations (and servers) built before `recorded_at`, `seq`, `degraded_channels`, and `conflicts`
+    // existed
+    #[derive(serde::Serialize)]
+    struct OldSomeData {
+        per_channel: HashMap<Uuid, ChannelData>,
+    }
+    #[derive(serde::Serialize)]
+    struct OldChannelMappings {
+        map: HashMap<ChannelName, Uuid>,
+    }
+    #[derive(serde::Serialize)]
+    enum OldPacketKind {
+        #[allow(dead_code)]
+        Connect,
+        ChannelMappings(OldChannelMappings),
+        Data(OldSomeData),
+    }
+    let old = rmp_serde::to_vec_named(&OldPacketKind::Data(OldSomeData {
+        per_channel: HashMap::from([(TEMPERATURE, ChannelData::Float(1.0))]),
+    }))
+    .unwrap();
+    let PacketKind::Data(data) = rmp_serde::from_slice(&old).unwrap() else {
+        panic!("wrong packet kind");
+    };
+    assert_eq!(data.recorded_at, None);
+    assert_eq!(data.seq, 0);
+
+    let old = rmp_serde::to_vec_named(&OldPacketKind::ChannelMappings(OldChannelMappings {
+        map: HashMap::from([("temperature".into(), TEMPERATURE)]),
+    }))
+    .unwrap();
+    let PacketKind::ChannelMappings(mappings) = rmp_serde::from_slice(&old).unwrap() else {
+        panic!("wrong packet kind");
+    };
+    assert_eq!(mappings.map.len(), 1);
+    assert!(mappings.conflicts.is_empty());
+}
+
+#[test]
+fn channel_metadata_optional() {
+    // channels declared before `unit` and `description` existed
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct OldChannel {
+        name: ChannelName,
+        value: ChannelValue,
+        ty: ChannelType,
+    }
+    let old = OldChannel {
+        name: "temperature".into(),
+        value: ChannelValue::Float,
+        ty: ChannelType::Periodic,
+    };
+    let decoded =
+        rmp_serde::from_slice::<Channel>(&rmp_serde::to_vec_named(&old).unwrap()).unwrap();
+    assert_eq!(decoded.unit, None);
+    assert_eq!(decoded.description, None);
+    // and without them, the same as before
+    assert_eq!(
+        rmp_serde::to_vec_named(&decoded).unwrap(),
+        rmp_serde::to_vec_named(&old).unwrap()
+    );
+
+    let with = Channel {
+        unit: Some("°C".into()),
+        description: Some("air temperature".into()),
+        ..decoded
+    };
+    let decoded =
+        rmp_serde::from_slice::<Channel>(&rmp_serde::to_vec_named(&with).unwrap()).unwrap();
+    assert_eq!(decoded.unit.as_deref(), Some("°C"));
+    assert_eq!(decoded.description.as_deref(), Some("air temperature"));
+    // older versions ignore them
+    let old =
+        rmp_serde::from_slice::<OldChannel>(&rmp_serde::to_vec_named(&with).unwrap()).unwrap();
+    assert_eq!(old.name, ChannelName::from("temperature"));
+}
@@ -0,0 +1,173 @@
+//! Prints captured squirrel packets in a readable form.
+//!
+//! usage: `squirrel-decode [FILE]` (reads stdin if no file is given)
+//!
+//! the input is either a pcap capture (UDP over ethernet or raw IPv4), or text with one packet per line,
+//! as hex (whitespace and `:` between bytes is ignored). text lines starting with `#` are skipped
+
+use std::{
+    fs,
+    io::{self, Read},
+    process::ExitCode,
+};
+
+use squirrel::transport::{extract_packet_type, read_packet};
+
+const PCAP_MAGIC_LE: [u8; 4] = [0xd4, 0xc3, 0xb2, 0xa1];
+const PCAP_MAGIC_BE: [u8; 4] = [0xa1, 0xb2, 0xc3, 0xd4];
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IPPROTO_UDP: u8 = 17;
+
+fn main() -> ExitCode {
+    let input = match std::env::args().nth(1) {
+        Some(path) => fs::read(&path).map_err(|e| format!("failed to read {path}: {e}")),
+        None => {
+            let mut buf = vec![];
+            io::stdin()
+                .read_to_end(&mut buf)
+                .map(|_| buf)
+                .map_err(|e| format!("failed to read stdin: {e}"))
+        }
+    };
+    let packets = input.and_then(|input| {
+        if input.starts_with(&PCAP_MAGIC_LE) || input.starts_with(&PCAP_MAGIC_BE) {
+            pcap_payloads(&input)
+        } else {
+            hex_lines(&String::from_utf8_lossy(&input))
+        }
+    });
+    match packets {
+        Ok(packets) => {
+            for (n, bytes) in packets.iter().enumerate() {
+                println!("{n:>4}: {}", describe(bytes));
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn describe(bytes: &[u8]) -> String {
+    match read_packet(bytes) {
+        Some(packet) => packet.to_string(),
+        None => match extract_packet_type(bytes) {
+            Some(ty) => format!("<not a packet: {} bytes, type 0x{ty:02x}>", bytes.len()),
+            None => format!("<not a packet: {} bytes>", bytes.len()),
+        },
+    }
+}
+
+fn hex_lines(text: &str) -> Result<Vec<Vec<u8>>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| parse_hex(line).map_err(|e| format!("line {}: {e}", n + 1)))
+        .collect()
+}
+
+fn parse_hex(line: &str) -> Result<Vec<u8>, String> {
+    let digits = line
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or_else(|| format!("{c:?} is not a hex digit"))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits".into());
+    }
+    Ok(digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect())
+}
+
+/// UDP payloads of every (IPv4) packet in a pcap capture
+fn pcap_payloads(capture: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let truncated = || "pcap capture is truncated".to_string();
+    let big_endian = capture.starts_with(&PCAP_MAGIC_BE);
+    let u32_at = |bytes: &[u8], at: usize| -> Option<u32> {
+        let b = bytes.get(at..at + 4)?.try_into().unwrap();
+        Some(match big_endian {
+            true => u32::from_be_bytes(b),
+            false => u32::from_le_bytes(b),
+        })
+    };
+    let link_type = u32_at(capture, 20).ok_or_else(truncated)?;
+    if link_type != LINKTYPE_ETHERNET && link_type != LINKTYPE_RAW {
+        return Err(format!("unsupported pcap link type {link_type}"));
+    }
+    let mut payloads = vec![];
+    let mut rest = &capture[PCAP_HEADER_SIZE..];
+    while !rest.is_empty() {
+        let captured = u32_at(rest, 8).ok_or_else(truncated)? as usize;
+        let record = rest
+            .get(PCAP_RECORD_HEADER_SIZE..PCAP_RECORD_HEADER_SIZE + captured)
+            .ok_or_else(truncated)?;
+        rest = &rest[PCAP_RECORD_HEADER_SIZE + captured..];
+        let ip = match link_type {
+            LINKTYPE_ETHERNET => match record.get(12..14) {
+                Some(&[a, b]) if u16::from_be_bytes([a, b]) == ETHERTYPE_IPV4 => &record[14..],
+                _ => continue,
+            },
+            _ => record,
+        };
+        if let Some(payload) = udp_payload(ip) {
+            payloads.push(payload.to_vec());
+        }
+    }
+    Ok(payloads)
+}
+
+fn udp_payload(ip: &[u8]) -> Option<&[u8]> {
+    let version = ip.first()? >> 4;
+    let header_len = (ip.first()? & 0x0f) as usize * 4;
+    if version != 4 || *ip.get(9)? != IPPROTO_UDP {
+        return None;
+    }
+    let udp = ip.get(header_len..)?;
+    let len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    udp.get(8..len.max(8))
+}
+
+#[cfg(test)]
+#[test]
+fn decode_inputs() {
+    let text =
+        "# a transaction\n01000000 00000000 bb 00 e803\n\n07:01:00:00:01:00:00:00:bb:02:00:00\n";
+    let packets = hex_lines(text).unwrap();
+    assert_eq!(
+        packets.iter().map(|p| describe(p)).collect::<Vec<_>>(),
+        ["Cmd #1 Tx (frame size 1000)", "Cmd #263 re #1 Confirm"]
+    );
+    assert!(hex_lines("bb 0").is_err());
+    assert!(hex_lines("zz").is_err());
+    assert_eq!(describe(&[0; 12]), "<not a packet: 12 bytes, type 0x00>");
+
+    // the same Tx, captured over ethernet
+    let cmd = &packets[0];
+    let mut capture = PCAP_MAGIC_LE.to_vec();
+    capture.extend([2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0]);
+    capture.extend(LINKTYPE_ETHERNET.to_le_bytes());
+    let mut frame = vec![0; 12];
+    frame.extend(ETHERTYPE_IPV4.to_be_bytes());
+    frame.extend([0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP]);
+    frame.extend([0; 10]);
+    frame.extend([0x1f, 0x90, 0x1f, 0x90]);
+    frame.extend((8 + cmd.len() as u16).to_be_bytes());
+    frame.extend([0, 0]);
+    frame.extend(cmd);
+    capture.extend([0; 8]);
+    capture.extend((frame.len() as u32).to_le_bytes());
+    capture.extend((frame.len() as u32).to_le_bytes());
+    capture.extend(&frame);
+    assert_eq!(pcap_payloads(&capture).unwrap(), std::slice::from_ref(cmd));
+    assert!(pcap_payloads(&capture[..capture.len() - 1]).is_err());
+}
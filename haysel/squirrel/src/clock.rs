@@ -0,0 +1,84 @@
+//! Time source used by time-dependent logic (transaction timeouts, autosaves, ...)
+//!
+//! code that needs the current time takes an `Arc<dyn Clock>` instead of calling `Instant::now()` / `Utc::now()`,
+//! so that tests can use a [`MockClock`] and advance time without sleeping.
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+
+pub trait Clock: Debug + Send + Sync {
+    /// monotonic time, for measuring durations
+    fn now_instant(&self) -> Instant;
+    /// wall-clock time, for timestamps
+    fn now_utc(&self) -> DateTime<Utc>;
+}
+
+/// The real time, from the system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to. clones share the same time
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    inner: Arc<Mutex<(Instant, DateTime<Utc>)>>,
+}
+
+impl MockClock {
+    /// starts at the current (real) time
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new((Instant::now(), Utc::now()))),
+        }
+    }
+
+    /// moves both the monotonic and wall-clock time forward by `by`
+    pub fn advance(&self, by: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.0 += by;
+        inner.1 += chrono::Duration::from_std(by).expect("duration out of range");
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now_instant(&self) -> Instant {
+        self.inner.lock().unwrap().0
+    }
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.inner.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn mock_clock_advances() {
+    let clock = MockClock::new();
+    let (instant, utc) = (clock.now_instant(), clock.now_utc());
+    // does not move on its own
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(clock.now_instant(), instant);
+
+    // and clones share the time
+    clock.clone().advance(Duration::from_secs(90));
+    assert_eq!(clock.now_instant() - instant, Duration::from_secs(90));
+    assert_eq!(clock.now_utc() - utc, chrono::Duration::seconds(90));
+}
@@ -9,4 +9,5 @@ extern crate log;
 extern crate tracing;
 
 pub mod api;
+pub mod clock;
 pub mod transport;
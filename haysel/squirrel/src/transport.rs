@@ -1,6 +1,7 @@
-use std::mem::size_of;
+use std::{fmt, mem::size_of};
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use static_assertions::const_assert_eq;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
@@ -9,7 +10,12 @@ pub mod server;
 pub mod shared;
 
 // https://stackoverflow.com/questions/1098897/what-is-the-largest-safe-udp-packet-size-on-the-internet#1099359
+/// largest UDP payload that is safe to send anywhere. frames are this size unless a larger one is negotiated
 pub const UDP_MAX_SIZE: usize = 508;
+/// largest UDP payload that frames can be negotiated up to (a 1500B ethernet MTU, minus the IPv4 and UDP headers).
+///
+/// this is also the size of [`Frame`], which is kept on the stack (so jumbo frames are not supported)
+pub const UDP_MAX_SIZE_NEGOTIATED: usize = 1472;
 
 pub const PACKET_TYPE_FRAME: u8 = 0xAA;
 pub const PACKET_TYPE_COMMAND: u8 = 0xBB;
@@ -41,13 +47,28 @@ pub struct Frame {
     pub packet_ty: u8,
     pub _pad: u8,
     pub len: u16,
-    pub data: [u8; FRAME_BUF_SIZE],
+    /// only the first `len` bytes are sent
+    pub data: [u8; MAX_FRAME_BUF_SIZE],
 }
 
 const FRAME_NON_DATA_SIZE: usize = 4 + 4 + 1 + 1 + 2;
-const FRAME_BUF_SIZE: usize = UDP_MAX_SIZE - FRAME_NON_DATA_SIZE;
+/// default (and minimum supported) amount of data in a frame
+pub const FRAME_BUF_SIZE: usize = UDP_MAX_SIZE - FRAME_NON_DATA_SIZE;
+/// maximum amount of data in a frame
+pub const MAX_FRAME_BUF_SIZE: usize = UDP_MAX_SIZE_NEGOTIATED - FRAME_NON_DATA_SIZE;
 
-const_assert_eq!(size_of::<Frame>(), UDP_MAX_SIZE);
+const_assert_eq!(size_of::<Frame>(), UDP_MAX_SIZE_NEGOTIATED);
+
+/// The frame size (bytes of data per frame) to use for a transaction, given the largest each side supports.
+///
+/// a size of 0 (sent by peers from before frame sizes were negotiated) means [`FRAME_BUF_SIZE`]
+pub fn negotiate_frame_size(ours: usize, theirs: u16) -> usize {
+    let theirs = match theirs {
+        0 => FRAME_BUF_SIZE,
+        n => n as usize,
+    };
+    ours.min(theirs).clamp(1, MAX_FRAME_BUF_SIZE)
+}
 
 impl Frame {
     pub fn from_bytes_compact(bytes: &[u8]) -> Option<Self> {
@@ -72,18 +93,22 @@ pub struct Cmd {
     pub responding_to: u32,
     pub packet_ty: u8,
     pub command: u8,
-    pub padding: [u8; 2],
+    /// for `Tx` and `Rx`, the largest frame size (see [`negotiate_frame_size`]) the client supports.
+    /// for responses from the server, the frame size that was negotiated. 0 if not specified
+    pub frame_size: u16,
 }
 
 // c  s       c     s       c     s       c        s
 // Tx Confirm Frame Confirm Frame Confirm Complete Confirm
 // c  s     c       (timeout)     c       s
 // Rx Frame Confirm /* dropped */ Confirm Complete
+// c/s  s/c
+// Ping Pong (between transactions, to check that the other side is still there)
 //
 // a note on repeat transmission:
 //  - the repeat (from the client) should have the same UID as the original
 //  - the response (from the server) should also be identical to the first response
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive, Serialize)]
 #[repr(u8)]
 pub enum CmdKind {
     // c-> s inform transmit
@@ -94,20 +119,25 @@ pub enum CmdKind {
     Confirm,
     // s ->/<- c inform complete
     Complete,
+    // s ->/<- c check if alive (only between transactions)
+    Ping,
+    // s ->/<- c response to `Ping`
+    Pong,
 }
 
 pub fn read_packet(buf: &[u8]) -> Option<Packet> {
     Some(match extract_packet_type(buf)? {
-        PACKET_TYPE_FRAME => Packet::Frame(Frame::from_bytes_compact(buf)?),
+        PACKET_TYPE_FRAME => Packet::Frame(Box::new(Frame::from_bytes_compact(buf)?)),
         PACKET_TYPE_COMMAND => Packet::Cmd(Cmd::read_from(buf)?),
         _ => None?,
     })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Packet {
     Cmd(Cmd),
-    Frame(Frame),
+    /// (boxed, frames are much larger than commands)
+    Frame(Box<Frame>),
 }
 
 impl Packet {
@@ -121,14 +151,199 @@ impl Packet {
     pub fn uid(&self) -> u32 {
         match self {
             Packet::Cmd(Cmd { packet, .. }) => *packet,
-            Packet::Frame(Frame { packet, .. }) => *packet,
+            Packet::Frame(frame) => frame.packet,
         }
     }
 
     pub fn responding_to(&self) -> u32 {
         match self {
             Packet::Cmd(Cmd { responding_to, .. }) => *responding_to,
-            Packet::Frame(Frame { responding_to, .. }) => *responding_to,
+            Packet::Frame(frame) => frame.responding_to,
+        }
+    }
+}
+
+/// bytes of frame data that are shown when displaying a [`Frame`]
+const DISPLAY_DATA_BYTES: usize = 16;
+
+/// `#uid`, and `re #uid` if it is a response
+fn fmt_ids(f: &mut fmt::Formatter<'_>, packet: u32, responding_to: u32) -> fmt::Result {
+    write!(f, "#{packet}")?;
+    if responding_to != 0 {
+        write!(f, " re #{responding_to}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cmd ")?;
+        fmt_ids(f, self.packet, self.responding_to)?;
+        match CmdKind::try_from(self.command) {
+            Ok(kind) => write!(f, " {kind:?}")?,
+            Err(_) => write!(f, " <unknown command 0x{:02x}>", self.command)?,
+        }
+        if self.frame_size != 0 {
+            write!(f, " (frame size {})", self.frame_size)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Frame ")?;
+        fmt_ids(f, self.packet, self.responding_to)?;
+        write!(f, ", {} bytes", self.len)?;
+        let data = &self.data[..(self.len as usize).min(MAX_FRAME_BUF_SIZE)];
+        if !data.is_empty() {
+            write!(f, ":")?;
+            for byte in data.iter().take(DISPLAY_DATA_BYTES) {
+                write!(f, " {byte:02x}")?;
+            }
+            if data.len() > DISPLAY_DATA_BYTES {
+                write!(f, " ..")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cmd(c) => c.fmt(f),
+            Self::Frame(fr) => fr.fmt(f),
+        }
+    }
+}
+
+// (for logging and tooling only, the wire format is the zerocopy representation)
+impl Serialize for Cmd {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Cmd", 4)?;
+        s.serialize_field("packet", &self.packet)?;
+        s.serialize_field("responding_to", &self.responding_to)?;
+        // the name of the command, or its value if it is unknown
+        match CmdKind::try_from(self.command) {
+            Ok(kind) => s.serialize_field("command", &kind)?,
+            Err(_) => s.serialize_field("command", &self.command)?,
         }
+        s.serialize_field("frame_size", &self.frame_size)?;
+        s.end()
     }
 }
+
+impl Serialize for Frame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Frame", 4)?;
+        s.serialize_field("packet", &self.packet)?;
+        s.serialize_field("responding_to", &self.responding_to)?;
+        s.serialize_field("len", &self.len)?;
+        // only the data that is sent
+        s.serialize_field(
+            "data",
+            &self.data[..(self.len as usize).min(MAX_FRAME_BUF_SIZE)],
+        )?;
+        s.end()
+    }
+}
+
+impl Serialize for Packet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Cmd(c) => serializer.serialize_newtype_variant("Packet", 0, "Cmd", c),
+            Self::Frame(fr) => serializer.serialize_newtype_variant("Packet", 1, "Frame", fr),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn display_packets() {
+    let show = |bytes: &[u8]| read_packet(bytes).unwrap().to_string();
+    // Tx from a client, supporting 1000 byte frames
+    assert_eq!(
+        show(&[1, 0, 0, 0, 0, 0, 0, 0, 0xBB, 0, 0xe8, 0x03]),
+        "Cmd #1 Tx (frame size 1000)"
+    );
+    // the server confirming it
+    assert_eq!(
+        show(&[7, 1, 0, 0, 1, 0, 0, 0, 0xBB, 2, 0, 0]),
+        "Cmd #263 re #1 Confirm"
+    );
+    assert_eq!(
+        show(&[2, 0, 0, 0, 0, 0, 0, 0, 0xBB, 0x42, 0, 0]),
+        "Cmd #2 <unknown command 0x42>"
+    );
+    let frame = |data: &[u8]| {
+        let mut bytes = vec![3, 0, 0, 0, 7, 1, 0, 0, 0xAA, 0];
+        bytes.extend((data.len() as u16).to_le_bytes());
+        bytes.extend(data);
+        show(&bytes)
+    };
+    assert_eq!(frame(&[]), "Frame #3 re #263, 0 bytes");
+    assert_eq!(
+        frame(&[0x93, 0x01, 0xc0, 0xff]),
+        "Frame #3 re #263, 4 bytes: 93 01 c0 ff"
+    );
+    assert_eq!(
+        frame(&(0..20).collect::<Vec<u8>>()),
+        "Frame #3 re #263, 20 bytes: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f .."
+    );
+    // not a packet
+    assert_eq!(read_packet(&[0; 12]), None);
+}
+
+#[cfg(test)]
+#[test]
+fn frame_size_negotiation() {
+    // the smaller of the two
+    assert_eq!(negotiate_frame_size(1000, 200), 200);
+    assert_eq!(negotiate_frame_size(200, 1000), 200);
+    assert_eq!(
+        negotiate_frame_size(MAX_FRAME_BUF_SIZE, MAX_FRAME_BUF_SIZE as u16),
+        MAX_FRAME_BUF_SIZE
+    );
+    // a peer that does not negotiate
+    assert_eq!(negotiate_frame_size(MAX_FRAME_BUF_SIZE, 0), FRAME_BUF_SIZE);
+    assert_eq!(negotiate_frame_size(100, 0), 100);
+    // out of range
+    assert_eq!(
+        negotiate_frame_size(u16::MAX as usize, u16::MAX),
+        MAX_FRAME_BUF_SIZE
+    );
+    assert_eq!(negotiate_frame_size(0, 100), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn serialize_packets() {
+    let confirm = Cmd {
+        packet: 263,
+        responding_to: 1,
+        packet_ty: PACKET_TYPE_COMMAND,
+        command: CmdKind::Confirm.into(),
+        frame_size: 1000,
+    };
+    assert_eq!(
+        serde_json::to_value(Packet::Cmd(confirm)).unwrap(),
+        serde_json::json!({
+            "Cmd": { "packet": 263, "responding_to": 1, "command": "Confirm", "frame_size": 1000 }
+        })
+    );
+    let unknown = Cmd {
+        command: 0x42,
+        ..confirm
+    };
+    assert_eq!(serde_json::to_value(unknown).unwrap()["command"], 0x42);
+    let mut frame = Frame::new_zeroed();
+    frame.len = 3;
+    frame.data[..4].copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(
+        serde_json::to_value(Packet::Frame(Box::new(frame))).unwrap(),
+        serde_json::json!({
+            "Frame": { "packet": 0, "responding_to": 0, "len": 3, "data": [1, 2, 3] }
+        })
+    );
+}
@@ -3,23 +3,223 @@ use std::time::Duration;
 use tokio::net::UdpSocket;
 
 use crate::transport::{
-    shared::{self, send_and_wait},
-    Cmd, CmdKind, Frame, Packet, UidGenerator, FRAME_BUF_SIZE, PACKET_TYPE_COMMAND,
-    PACKET_TYPE_FRAME,
+    negotiate_frame_size,
+    shared::{self, send_and_wait, TransportStats},
+    Cmd, CmdKind, Frame, Packet, UidGenerator, FRAME_BUF_SIZE, MAX_FRAME_BUF_SIZE,
+    PACKET_TYPE_COMMAND, PACKET_TYPE_FRAME,
 };
 
 const MAX_ATTEMPTS: usize = 5;
 const RETRY_WAIT_DUR: Duration = Duration::from_millis(5000);
 
+/// Client side of the transport, talking to a server's [`super::server::ClientInterface`] over a connected socket.
+///
+/// each `send`/`recv` is a complete transaction (the Tx/Rx command exchange, every frame, and the final
+/// `Complete`), with packets retransmitted if a response does not arrive in time
+#[derive(Debug)]
+pub struct ClientTransport {
+    sock: UdpSocket,
+    uid_gen: UidGenerator,
+    max_attempts: usize,
+    retry_wait: Duration,
+    max_frame_size: usize,
+    stats: TransportStats,
+}
+
+impl ClientTransport {
+    /// `sock` must be connected to the server
+    pub fn new(sock: UdpSocket) -> Self {
+        assert!(sock.peer_addr().is_ok(), "Socket must be connected");
+        Self {
+            sock,
+            uid_gen: UidGenerator::new(),
+            max_attempts: MAX_ATTEMPTS,
+            retry_wait: RETRY_WAIT_DUR,
+            max_frame_size: FRAME_BUF_SIZE,
+            stats: TransportStats::default(),
+        }
+    }
+
+    /// the largest frame size (bytes of data per frame) to use, if the server supports it. defaults to [`FRAME_BUF_SIZE`]
+    pub fn with_max_frame_size(self, max_frame_size: usize) -> Self {
+        assert!((1..=MAX_FRAME_BUF_SIZE).contains(&max_frame_size));
+        Self {
+            max_frame_size,
+            ..self
+        }
+    }
+
+    /// how many times each packet is sent, and how long to wait for a response to it, before giving up with
+    /// [`shared::SendError::TimedOut`]
+    pub fn with_retry(self, max_attempts: usize, retry_wait: Duration) -> Self {
+        assert!(max_attempts > 0);
+        Self {
+            max_attempts,
+            retry_wait,
+            ..self
+        }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.sock
+    }
+
+    pub fn into_socket(self) -> UdpSocket {
+        self.sock
+    }
+
+    /// transport stats since this was created (or they were last taken)
+    pub fn stats(&self) -> &TransportStats {
+        &self.stats
+    }
+
+    /// returns the transport stats, and starts counting from zero again
+    pub fn take_stats(&mut self) -> TransportStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    /// send `data` to the server
+    pub async fn send(&mut self, data: &[u8]) -> Result<(), shared::SendError> {
+        send(
+            &self.sock,
+            data,
+            &mut self.uid_gen,
+            self.max_attempts,
+            self.retry_wait,
+            self.max_frame_size,
+            &mut self.stats,
+        )
+        .await
+    }
+
+    /// receive the next thing the server has queued, or `None` if there is nothing
+    pub async fn recv(&mut self) -> Result<Option<Vec<u8>>, shared::SendError> {
+        recv(
+            &self.sock,
+            &mut self.uid_gen,
+            self.max_attempts,
+            self.retry_wait,
+            self.max_frame_size,
+            &mut self.stats,
+        )
+        .await
+    }
+}
+
+/// Client side of `Ping`/`Pong`, for checking that the server is still there between transactions
+#[derive(Debug, Default)]
+pub struct Keepalive {
+    // if a `Ping` has been sent, and not answered
+    outstanding: bool,
+    missed: u32,
+}
+
+impl Keepalive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the `Ping` to send to the server
+    pub fn ping(&mut self, uid_gen: &mut UidGenerator) -> Packet {
+        if self.outstanding {
+            self.missed += 1;
+        }
+        self.outstanding = true;
+        Packet::Cmd(Cmd {
+            packet: uid_gen.next(),
+            responding_to: 0,
+            packet_ty: PACKET_TYPE_COMMAND,
+            command: CmdKind::Ping as _,
+            frame_size: 0,
+        })
+    }
+
+    /// handle a packet received from the server between transactions, returning what to send back (if anything)
+    pub fn handle(&mut self, packet: Packet, uid_gen: &mut UidGenerator) -> Option<Packet> {
+        let Packet::Cmd(cmd) = packet else {
+            return None;
+        };
+        if cmd.command == CmdKind::Ping as u8 {
+            self.outstanding = false;
+            self.missed = 0;
+            Some(Packet::Cmd(Cmd {
+                packet: uid_gen.next(),
+                responding_to: cmd.packet,
+                packet_ty: PACKET_TYPE_COMMAND,
+                command: CmdKind::Pong as _,
+                frame_size: 0,
+            }))
+        } else {
+            if cmd.command == CmdKind::Pong as u8 {
+                self.outstanding = false;
+                self.missed = 0;
+            }
+            None
+        }
+    }
+
+    /// number of `Ping`s in a row that the server did not answer before the next one was sent
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+}
+
+/// send `data` to the server, counting how it went in `stats`
 pub async fn mvp_send(
     sock: &UdpSocket,
     data: &[u8],
     uid_gen: &mut UidGenerator,
+    stats: &mut TransportStats,
+) -> Result<(), shared::SendError> {
+    send(
+        sock,
+        data,
+        uid_gen,
+        MAX_ATTEMPTS,
+        RETRY_WAIT_DUR,
+        FRAME_BUF_SIZE,
+        stats,
+    )
+    .await
+}
+
+async fn send(
+    sock: &UdpSocket,
+    data: &[u8],
+    uid_gen: &mut UidGenerator,
+    max_attempts: usize,
+    retry_wait: Duration,
+    max_frame_size: usize,
+    stats: &mut TransportStats,
+) -> Result<(), shared::SendError> {
+    let res = send_transaction(
+        sock,
+        data,
+        uid_gen,
+        max_attempts,
+        retry_wait,
+        max_frame_size,
+        stats,
+    )
+    .await;
+    stats.finished(&res);
+    res
+}
+
+async fn send_transaction(
+    sock: &UdpSocket,
+    data: &[u8],
+    uid_gen: &mut UidGenerator,
+    max_attempts: usize,
+    retry_wait: Duration,
+    max_frame_size: usize,
+    stats: &mut TransportStats,
 ) -> Result<(), shared::SendError> {
     assert!(sock.peer_addr().is_ok(), "Socket must be connected");
 
     let Packet::Cmd(Cmd {
         packet: mut respond_to,
+        frame_size,
         ..
     }) = send_and_wait(
         sock,
@@ -28,38 +228,41 @@ pub async fn mvp_send(
             responding_to: 0,
             packet_ty: PACKET_TYPE_COMMAND,
             command: CmdKind::Tx as _,
-            padding: Default::default(),
+            frame_size: max_frame_size as _,
         }),
         shared::ExpectedResponse::Command {
             cmd: CmdKind::Confirm,
         },
-        MAX_ATTEMPTS,
-        RETRY_WAIT_DUR,
+        max_attempts,
+        retry_wait,
+        stats,
     )
     .await?
     else {
         unreachable!()
     };
 
-    for chunk in data.chunks(FRAME_BUF_SIZE) {
-        let mut arr_chunk = [0u8; FRAME_BUF_SIZE];
+    let frame_size = negotiate_frame_size(max_frame_size, frame_size);
+    for chunk in data.chunks(frame_size) {
+        let mut arr_chunk = [0u8; MAX_FRAME_BUF_SIZE];
         arr_chunk[0..chunk.len()].copy_from_slice(chunk);
 
         let Packet::Cmd(c) = send_and_wait(
             sock,
-            Packet::Frame(Frame {
+            Packet::Frame(Box::new(Frame {
                 packet: uid_gen.next(),
                 responding_to: respond_to,
                 packet_ty: PACKET_TYPE_FRAME,
                 _pad: 0,
                 len: chunk.len() as u16,
                 data: arr_chunk,
-            }),
+            })),
             shared::ExpectedResponse::Command {
                 cmd: CmdKind::Confirm,
             },
-            MAX_ATTEMPTS,
-            RETRY_WAIT_DUR,
+            max_attempts,
+            retry_wait,
+            stats,
         )
         .await?
         else {
@@ -76,13 +279,14 @@ pub async fn mvp_send(
             responding_to: respond_to,
             packet_ty: PACKET_TYPE_COMMAND,
             command: CmdKind::Complete as _,
-            padding: Default::default(),
+            frame_size: 0,
         }),
         shared::ExpectedResponse::Command {
             cmd: CmdKind::Confirm,
         },
-        MAX_ATTEMPTS,
-        RETRY_WAIT_DUR,
+        max_attempts,
+        retry_wait,
+        stats,
     )
     .await?
     else {
@@ -95,6 +299,47 @@ pub async fn mvp_send(
 pub async fn mvp_recv(
     sock: &UdpSocket,
     uid_gen: &mut UidGenerator,
+    stats: &mut TransportStats,
+) -> Result<Option<Vec<u8>>, shared::SendError> {
+    recv(
+        sock,
+        uid_gen,
+        MAX_ATTEMPTS,
+        RETRY_WAIT_DUR,
+        FRAME_BUF_SIZE,
+        stats,
+    )
+    .await
+}
+
+async fn recv(
+    sock: &UdpSocket,
+    uid_gen: &mut UidGenerator,
+    max_attempts: usize,
+    retry_wait: Duration,
+    max_frame_size: usize,
+    stats: &mut TransportStats,
+) -> Result<Option<Vec<u8>>, shared::SendError> {
+    let res = recv_transaction(
+        sock,
+        uid_gen,
+        max_attempts,
+        retry_wait,
+        max_frame_size,
+        stats,
+    )
+    .await;
+    stats.finished(&res);
+    res
+}
+
+async fn recv_transaction(
+    sock: &UdpSocket,
+    uid_gen: &mut UidGenerator,
+    max_attempts: usize,
+    retry_wait: Duration,
+    max_frame_size: usize,
+    stats: &mut TransportStats,
 ) -> Result<Option<Vec<u8>>, shared::SendError> {
     assert!(sock.peer_addr().is_ok(), "Socket must be connected");
 
@@ -105,18 +350,19 @@ pub async fn mvp_recv(
             responding_to: 0,
             packet_ty: PACKET_TYPE_COMMAND,
             command: CmdKind::Rx as _,
-            padding: Default::default(),
+            frame_size: max_frame_size as _,
         }),
         shared::ExpectedResponse::FrameOrCommand {
             cmd: CmdKind::Complete,
         },
-        MAX_ATTEMPTS,
-        RETRY_WAIT_DUR,
+        max_attempts,
+        retry_wait,
+        stats,
     )
     .await?
     {
         Packet::Cmd(c) => {
-            debug_assert_eq!(c.command, CmdKind::Complete as _); // validated in `send_and_wait`
+            debug_assert_eq!(c.command, CmdKind::Complete as u8); // validated in `send_and_wait`
             return Ok(None);
         }
         Packet::Frame(f) => f,
@@ -133,19 +379,20 @@ pub async fn mvp_recv(
                 responding_to: respond_to,
                 packet_ty: PACKET_TYPE_COMMAND,
                 command: CmdKind::Confirm as _,
-                padding: Default::default(),
+                frame_size: 0,
             }),
             shared::ExpectedResponse::FrameOrCommand {
                 cmd: CmdKind::Complete,
             },
-            MAX_ATTEMPTS,
-            RETRY_WAIT_DUR,
+            max_attempts,
+            retry_wait,
+            stats,
         )
         .await?
         {
             Packet::Cmd(c) => {
                 //TODO: actually utilize this way of sending no-op Rx, and make it work with Tx as well
-                debug_assert_eq!(c.command, CmdKind::Complete as _); // validated in `send_and_wait`
+                debug_assert_eq!(c.command, CmdKind::Complete as u8); // validated in `send_and_wait`
                 break;
             }
             Packet::Frame(f) => {
@@ -155,5 +402,232 @@ pub async fn mvp_recv(
         };
     }
 
-    Ok(Some(buf))
+    // the server sends a single empty frame if it has nothing queued
+    Ok((!buf.is_empty()).then_some(buf))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use super::{ClientTransport, Keepalive};
+    use crate::transport::{
+        read_packet,
+        server::{recv_next_packet, ClientInterface, DispatchEvent},
+        shared::SendError,
+        Cmd, CmdKind, Packet, UidGenerator, FRAME_BUF_SIZE, MAX_FRAME_BUF_SIZE,
+        PACKET_TYPE_COMMAND, UDP_MAX_SIZE_NEGOTIATED,
+    };
+
+    /// a server for a single client, which sends everything that it receives on `received`.
+    ///
+    /// the first `drop_confirms` `Confirm`s it sends are lost (never reach the client)
+    async fn server(
+        queued: Vec<Vec<u8>>,
+        max_frame_size: usize,
+        mut drop_confirms: usize,
+    ) -> (
+        UdpSocket,
+        flume::Receiver<Vec<u8>>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(sock.local_addr().unwrap()).await.unwrap();
+        let (send, received) = flume::unbounded();
+        let task = tokio::spawn(async move {
+            let mut inter =
+                ClientInterface::new(Duration::from_secs(30)).with_max_frame_size(max_frame_size);
+            for data in queued {
+                inter.queue(data);
+            }
+            while let Ok(res) = recv_next_packet(&sock).await {
+                let Some((from, packet)) = res else {
+                    continue;
+                };
+                for ev in inter.handle(packet) {
+                    match ev {
+                        DispatchEvent::Send(Packet::Cmd(c))
+                            if c.command == CmdKind::Confirm as u8 && drop_confirms > 0 =>
+                        {
+                            drop_confirms -= 1;
+                        }
+                        DispatchEvent::Send(p) => {
+                            sock.send_to(p.as_bytes(), from).await.unwrap();
+                        }
+                        DispatchEvent::Received(data) => send.send(data).unwrap(),
+                        DispatchEvent::TimedOut => panic!("transaction timed out"),
+                    }
+                }
+            }
+        });
+        (client, received, task)
+    }
+
+    #[tokio::test]
+    async fn send_to_server() {
+        let (sock, received, task) = server(vec![], FRAME_BUF_SIZE, 0).await;
+        let mut client = ClientTransport::new(sock).with_retry(3, Duration::from_millis(500));
+        // a single frame, and enough to need several
+        let small = b"hello".to_vec();
+        let large = (0..FRAME_BUF_SIZE * 3 + 7)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        client.send(&small).await.unwrap();
+        client.send(&large).await.unwrap();
+        assert_eq!(received.recv_async().await.unwrap(), small);
+        assert_eq!(received.recv_async().await.unwrap(), large);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn recv_from_server() {
+        let first = (0..FRAME_BUF_SIZE * 2).map(|i| i as u8).collect::<Vec<_>>();
+        let second = b"mappings".to_vec();
+        let (sock, _received, task) =
+            server(vec![first.clone(), second.clone()], FRAME_BUF_SIZE, 0).await;
+        let mut client = ClientTransport::new(sock).with_retry(3, Duration::from_millis(500));
+        // oldest first, and each only once
+        assert_eq!(client.recv().await.unwrap(), Some(first));
+        assert_eq!(client.recv().await.unwrap(), Some(second));
+        assert_eq!(client.recv().await.unwrap(), None);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn negotiated_frame_size() {
+        let data = (0..MAX_FRAME_BUF_SIZE * 2)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        // the server supports larger frames than the client, and the other way around
+        for (server_max, client_max) in [(MAX_FRAME_BUF_SIZE, 64), (64, MAX_FRAME_BUF_SIZE)] {
+            let (sock, received, task) = server(vec![data.clone()], server_max, 0).await;
+            let mut client = ClientTransport::new(sock)
+                .with_retry(3, Duration::from_millis(500))
+                .with_max_frame_size(client_max);
+            client.send(&data).await.unwrap();
+            assert_eq!(received.recv_async().await.unwrap(), data);
+            assert_eq!(client.recv().await.unwrap(), Some(data.clone()));
+            task.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn compressed_data() {
+        use std::collections::HashMap;
+
+        use crate::api::{
+            compression::{self, COMPRESSED_MARKER},
+            station::capabilities::ChannelData,
+            Compression, PacketKind, SomeData,
+        };
+
+        let (sock, received, task) = server(vec![], FRAME_BUF_SIZE, 0).await;
+        let mut client = ClientTransport::new(sock).with_retry(3, Duration::from_millis(500));
+        // a station with many channels
+        let packet = PacketKind::Data(SomeData {
+            per_channel: (0..40)
+                .map(|i| (uuid::Uuid::from_u128(i), ChannelData::Float(20.0)))
+                .collect::<HashMap<_, _>>(),
+            recorded_at: Some(1_711_281_600),
+            seq: 7,
+        });
+        let serialized = rmp_serde::to_vec_named(&packet).unwrap();
+        // (as chosen by the server, for a station that supports everything)
+        let codec = Compression::negotiate(Compression::SUPPORTED);
+        assert_eq!(codec, Compression::Lz4);
+        let compressed = codec.compress(&serialized);
+        assert_eq!(compressed[0], COMPRESSED_MARKER);
+        assert!(compressed.len() < serialized.len());
+        client.send(&compressed).await.unwrap();
+        let data = received.recv_async().await.unwrap();
+        assert_eq!(data, compressed.as_ref());
+        let decompressed = compression::decompress(&data).unwrap();
+        assert_eq!(decompressed, serialized);
+        let Ok(PacketKind::Data(decoded)) = rmp_serde::from_slice(&decompressed) else {
+            panic!("wrong packet kind");
+        };
+        assert_eq!((decoded.per_channel.len(), decoded.seq), (40, 7));
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn counts_retransmits() {
+        let (sock, received, task) = server(vec![], FRAME_BUF_SIZE, 1).await;
+        let mut client = ClientTransport::new(sock).with_retry(3, Duration::from_millis(200));
+        client.send(b"hello").await.unwrap();
+        assert_eq!(received.recv_async().await.unwrap(), b"hello");
+        // Tx (twice, the first confirm was dropped), the frame, and Complete
+        let stats = *client.stats();
+        assert_eq!(stats.retransmits, 1);
+        assert_eq!(stats.packets_sent, 4);
+        assert_eq!(stats.transactions, 1);
+        assert_eq!(stats.timeouts, 0);
+        // only responses are timed, so one per packet that was answered
+        assert_eq!(stats.rtt_samples, 3);
+        assert!(stats.mean_rtt().unwrap() < Duration::from_millis(200));
+
+        assert_eq!(client.take_stats(), stats);
+        client.send(b"again").await.unwrap();
+        assert_eq!(client.stats().retransmits, 0);
+        assert_eq!(client.stats().transactions, 1);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn keepalive() {
+        let (sock, _received, task) = server(vec![], FRAME_BUF_SIZE, 0).await;
+        let mut uid_gen = UidGenerator::new();
+        let mut keepalive = Keepalive::new();
+        let mut buf = [0u8; UDP_MAX_SIZE_NEGOTIATED];
+        for _ in 0..3 {
+            let ping = keepalive.ping(&mut uid_gen);
+            sock.send(ping.as_bytes()).await.unwrap();
+            let n = sock.recv(&mut buf).await.unwrap();
+            let pong = read_packet(&buf[..n]).unwrap();
+            assert_eq!(pong.responding_to(), ping.uid());
+            assert!(keepalive.handle(pong, &mut uid_gen).is_none());
+        }
+        assert_eq!(keepalive.missed(), 0);
+        task.abort();
+
+        // the server is gone
+        keepalive.ping(&mut uid_gen);
+        keepalive.ping(&mut uid_gen);
+        keepalive.ping(&mut uid_gen);
+        assert_eq!(keepalive.missed(), 2);
+        // and pings from the server are answered
+        let reply = keepalive.handle(
+            Packet::Cmd(Cmd {
+                packet: 5,
+                responding_to: 0,
+                packet_ty: PACKET_TYPE_COMMAND,
+                command: CmdKind::Ping as _,
+                frame_size: 0,
+            }),
+            &mut uid_gen,
+        );
+        assert!(
+            matches!(reply, Some(Packet::Cmd(c)) if c.command == CmdKind::Pong as u8 && c.responding_to == 5)
+        );
+        assert_eq!(keepalive.missed(), 0);
+    }
+
+    #[tokio::test]
+    async fn no_server() {
+        // bound, but never responds
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sock.connect(silent.local_addr().unwrap()).await.unwrap();
+        let mut client = ClientTransport::new(sock).with_retry(2, Duration::from_millis(50));
+        assert!(matches!(
+            client.send(b"hello").await,
+            Err(SendError::TimedOut)
+        ));
+        assert!(matches!(client.recv().await, Err(SendError::TimedOut)));
+        assert_eq!(client.stats().timeouts, 2);
+        assert_eq!(client.stats().retransmits, 2);
+    }
 }
@@ -2,19 +2,22 @@ use std::{
     collections::VecDeque,
     mem::swap,
     net::SocketAddr,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use num_enum::TryFromPrimitive;
 use tokio::{io, net::UdpSocket};
 
+use crate::clock::{Clock, SystemClock};
+
 use super::{
-    read_packet, Cmd, CmdKind, Frame, Packet, UidGenerator, FRAME_BUF_SIZE, PACKET_TYPE_COMMAND,
-    PACKET_TYPE_FRAME, UDP_MAX_SIZE,
+    negotiate_frame_size, read_packet, Cmd, CmdKind, Frame, Packet, UidGenerator, FRAME_BUF_SIZE,
+    MAX_FRAME_BUF_SIZE, PACKET_TYPE_COMMAND, PACKET_TYPE_FRAME, UDP_MAX_SIZE_NEGOTIATED,
 };
 
 pub async fn recv_next_packet(sock: &UdpSocket) -> io::Result<Option<(SocketAddr, Packet)>> {
-    let mut buf = [0; UDP_MAX_SIZE];
+    let mut buf = [0; UDP_MAX_SIZE_NEGOTIATED];
     let (amnt, from) = sock.recv_from(&mut buf).await?;
     if amnt > buf.len() {
         return Ok(None);
@@ -56,10 +59,21 @@ pub struct ClientInterface {
     // time since entering `Receiving` or `Sending` state
     transaction_time: Instant,
     max_transaction_time: Duration,
+    // largest frame size this side supports
+    max_frame_size: usize,
+    // frame size negotiated for the current (or last) transaction
+    frame_size: usize,
     recev_buf: Vec<u8>,
     send_queue: VecDeque<Vec<u8>>,
     send_buf: Vec<u8>,
     last_sent_send_buf: Vec<u8>,
+    // if a `Ping` has been sent, and nothing has been received since
+    ping_outstanding: bool,
+    // number of `Ping`s in a row that went unanswered
+    missed_pings: u32,
+    // address of the client (only used for logging)
+    peer: Option<SocketAddr>,
+    clock: Arc<dyn Clock>,
 }
 
 impl ClientInterface {
@@ -72,22 +86,158 @@ impl ClientInterface {
             uid_gen: UidGenerator::new(),
             transaction_time: Instant::now(), //never used
             max_transaction_time,
+            max_frame_size: FRAME_BUF_SIZE,
+            frame_size: FRAME_BUF_SIZE,
             recev_buf: vec![],
             send_queue: Default::default(),
             send_buf: vec![],
             last_sent_send_buf: vec![],
+            ping_outstanding: false,
+            missed_pings: 0,
+            peer: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// the address of the client, included in logged state transitions
+    pub fn with_peer(self, peer: SocketAddr) -> Self {
+        Self {
+            peer: Some(peer),
+            ..self
         }
     }
 
+    /// the time source for transaction timeouts. defaults to [`SystemClock`]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// the largest frame size (bytes of data per frame) to use, if the client supports it. defaults to [`FRAME_BUF_SIZE`]
+    pub fn with_max_frame_size(self, max_frame_size: usize) -> Self {
+        assert!((1..=MAX_FRAME_BUF_SIZE).contains(&max_frame_size));
+        Self {
+            max_frame_size,
+            ..self
+        }
+    }
+
+    /// frame size negotiated with the client for the current (or last) transaction
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
     pub fn queue(&mut self, to_send: Vec<u8>) {
         self.send_queue.push_front(to_send);
     }
 
+    /// if there is no transaction in progress (a client in this state can be safely forgotten)
+    pub fn is_resting(&self) -> bool {
+        self.state == State::Resting
+    }
+
+    /// if there is no transaction in progress (or the last one is finished, but was not followed by another)
+    fn is_idle(&self) -> bool {
+        matches!(
+            self.state,
+            State::Resting | State::TheoreticallyDoneReceiving | State::TheoreticallyDoneSending
+        )
+    }
+
+    /// A `Ping` to send to the client, or None if it is in the middle of a transaction.
+    ///
+    /// anything received from the client counts as an answer to it
+    pub fn ping(&mut self) -> Option<Packet> {
+        if !self.is_idle() {
+            return None;
+        }
+        if self.ping_outstanding {
+            self.missed_pings += 1;
+        }
+        self.ping_outstanding = true;
+        Some(Packet::Cmd(Cmd {
+            packet: self.uid_gen.next(),
+            responding_to: 0,
+            packet_ty: PACKET_TYPE_COMMAND,
+            command: CmdKind::Ping as _,
+            frame_size: 0,
+        }))
+    }
+
+    /// number of `Ping`s in a row that the client did not answer before the next one was sent
+    pub fn missed_pings(&self) -> u32 {
+        self.missed_pings
+    }
+
+    /// Abandon any in-progress transaction and discard all queued data, returning to `Resting`.
+    ///
+    /// used when the client has restarted, and anything that was queued for it is now stale.
+    /// if the transaction that was just received is still waiting on its final `Complete` to be
+    /// confirmed, that is left alone (so that the client can finish it)
+    pub fn reset(&mut self) {
+        if self.state != State::TheoreticallyDoneReceiving {
+            self.state = State::Resting;
+        }
+        self.recev_buf.clear();
+        self.send_queue.clear();
+        self.send_buf.clear();
+        self.last_sent_send_buf.clear();
+    }
+
     pub fn handle(&mut self, packet: Packet) -> Vec<DispatchEvent> {
+        // the client is alive
+        self.ping_outstanding = false;
+        self.missed_pings = 0;
+        if let Packet::Cmd(cmd) = packet {
+            if cmd.command == CmdKind::Ping as u8 {
+                let mut dispatch = vec![];
+                // not answered mid-transaction (the client would not be listening for it)
+                if self.is_idle() {
+                    dispatch.push(DispatchEvent::Send(Packet::Cmd(Cmd {
+                        packet: self.uid_gen.next(),
+                        responding_to: cmd.packet,
+                        packet_ty: PACKET_TYPE_COMMAND,
+                        command: CmdKind::Pong as _,
+                        frame_size: 0,
+                    })));
+                }
+                return dispatch;
+            } else if cmd.command == CmdKind::Pong as u8 {
+                return vec![];
+            }
+        }
+        let old = self.state;
+        let (uid, responding_to) = (packet.uid(), packet.responding_to());
+        let dispatch = self.transition(packet);
+        if self.state != old {
+            tracing::debug!(
+                peer = ?self.peer,
+                old = ?old,
+                new = ?self.state,
+                packet = uid,
+                "transport state transition"
+            );
+        } else if dispatch.is_empty() {
+            // every branch that accepts a packet responds to it
+            tracing::trace!(
+                peer = ?self.peer,
+                state = ?self.state,
+                packet = uid,
+                responding_to,
+                "packet ignored (it does not match the current transaction)"
+            );
+        }
+        dispatch
+    }
+
+    /// state machine for everything other than `Ping`/`Pong`
+    fn transition(&mut self, packet: Packet) -> Vec<DispatchEvent> {
         let mut dispatch = vec![];
-        //info!("state: {:?}", self.state);
         if let State::Receiving | State::Sending = self.state {
-            if self.transaction_time.elapsed() > self.max_transaction_time {
+            let elapsed = self
+                .clock
+                .now_instant()
+                .saturating_duration_since(self.transaction_time);
+            if elapsed > self.max_transaction_time {
                 self.state = State::Resting;
                 dispatch.push(DispatchEvent::TimedOut);
                 return dispatch;
@@ -99,14 +249,18 @@ impl ClientInterface {
                 | State::TheoreticallyDoneReceiving
                 | State::TheoreticallyDoneSending,
                 Packet::Cmd(Cmd {
-                    packet, command, ..
+                    packet,
+                    command,
+                    frame_size,
+                    ..
                 }),
-            ) if command == CmdKind::Tx as _ || command == CmdKind::Rx as _ => {
+            ) if command == CmdKind::Tx as u8 || command == CmdKind::Rx as u8 => {
                 self.respond_to = packet;
+                self.frame_size = negotiate_frame_size(self.max_frame_size, frame_size);
                 match CmdKind::try_from_primitive(command).unwrap() {
                     CmdKind::Tx => {
                         self.state = State::ReceivingStart; // Tx is POV of the CLIENT
-                        self.transaction_time = Instant::now();
+                        self.transaction_time = self.clock.now_instant();
                         self.recev_buf.clear();
                         dispatch.push(DispatchEvent::Send(Packet::Cmd(Cmd {
                             packet: {
@@ -116,16 +270,16 @@ impl ClientInterface {
                             responding_to: self.respond_to,
                             packet_ty: PACKET_TYPE_COMMAND,
                             command: CmdKind::Confirm as _,
-                            padding: [0; 2],
+                            frame_size: self.frame_size as _,
                         })));
                     }
                     CmdKind::Rx => {
                         self.state = State::SendingStart;
-                        self.transaction_time = Instant::now();
+                        self.transaction_time = self.clock.now_instant();
                         // send_queue value only removed when sending is done
                         self.send_buf = self.send_queue.back().cloned().unwrap_or(vec![]);
                         self.last_sent_send_buf.clear();
-                        dispatch.push(DispatchEvent::Send(Packet::Frame(Frame {
+                        dispatch.push(DispatchEvent::Send(Packet::Frame(Box::new(Frame {
                             packet: {
                                 self.last_sent = self.uid_gen.next();
                                 self.last_sent
@@ -133,26 +287,28 @@ impl ClientInterface {
                             responding_to: self.respond_to,
                             packet_ty: PACKET_TYPE_FRAME,
                             _pad: 0,
-                            len: self.send_buf.len().clamp(0, FRAME_BUF_SIZE) as _,
+                            len: self.send_buf.len().clamp(0, self.frame_size) as _,
                             data: {
-                                let mut buf = [0u8; FRAME_BUF_SIZE];
+                                let mut buf = [0u8; MAX_FRAME_BUF_SIZE];
                                 let mut past_buf = self
                                     .send_buf
-                                    .split_off(FRAME_BUF_SIZE.clamp(0, self.send_buf.len()));
+                                    .split_off(self.frame_size.clamp(0, self.send_buf.len()));
                                 swap(&mut self.send_buf, &mut past_buf);
                                 self.last_sent_send_buf = past_buf.clone();
                                 buf[0..past_buf.len()].copy_from_slice(&past_buf);
                                 buf
                             },
-                        })));
+                        }))));
+                    }
+                    CmdKind::Confirm | CmdKind::Complete | CmdKind::Ping | CmdKind::Pong => {
+                        unreachable!()
                     }
-                    CmdKind::Confirm | CmdKind::Complete => unreachable!(),
                 }
             }
             (State::Resting, _) => {}
             // receiving
             (State::ReceivingStart, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Tx as _ && cmd.packet == self.respond_to =>
+                if cmd.command == CmdKind::Tx as u8 && cmd.packet == self.respond_to =>
             {
                 // this is a repitition of the initial Transmit init packet.
                 // respond again, identically to the first time.
@@ -161,7 +317,7 @@ impl ClientInterface {
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Confirm as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
             }
             (State::ReceivingStart, Packet::Cmd(..)) => {}
@@ -177,13 +333,14 @@ impl ClientInterface {
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Confirm as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
                 self.state = State::Receiving;
             }
             (State::ReceivingStart, Packet::Frame(..)) => {}
             (State::Receiving, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Complete as _ && cmd.responding_to == self.last_sent =>
+                if cmd.command == CmdKind::Complete as u8
+                    && cmd.responding_to == self.last_sent =>
             {
                 self.respond_to = cmd.packet;
                 // the first end-transaction packet.
@@ -196,7 +353,7 @@ impl ClientInterface {
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Confirm as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
                 self.state = State::TheoreticallyDoneReceiving;
             }
@@ -209,7 +366,7 @@ impl ClientInterface {
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Confirm as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
             }
             (State::Receiving, Packet::Frame(fr)) if fr.responding_to == self.last_sent => {
@@ -225,43 +382,43 @@ impl ClientInterface {
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Confirm as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
             }
             (State::Receiving, Packet::Frame(..)) => {}
             (State::TheoreticallyDoneReceiving, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Complete as _ && cmd.packet == self.respond_to =>
+                if cmd.command == CmdKind::Complete as u8 && cmd.packet == self.respond_to =>
             {
                 dispatch.push(DispatchEvent::Send(Packet::Cmd(Cmd {
                     packet: self.last_sent,
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Confirm as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
             }
             (State::TheoreticallyDoneReceiving, _) => {}
             // sending
             (State::SendingStart, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Rx as _ && cmd.packet == self.respond_to =>
+                if cmd.command == CmdKind::Rx as u8 && cmd.packet == self.respond_to =>
             {
                 // repeat the Rx init packet
-                dispatch.push(DispatchEvent::Send(Packet::Frame(Frame {
+                dispatch.push(DispatchEvent::Send(Packet::Frame(Box::new(Frame {
                     packet: self.last_sent,
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_FRAME,
                     _pad: 0,
                     len: self.last_sent_send_buf.len() as _,
                     data: {
-                        let mut buf = [0u8; FRAME_BUF_SIZE];
+                        let mut buf = [0u8; MAX_FRAME_BUF_SIZE];
                         buf[0..self.last_sent_send_buf.len()]
                             .copy_from_slice(&self.last_sent_send_buf);
                         buf
                     },
-                })));
+                }))));
             }
             (State::SendingStart, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Confirm as _ && cmd.responding_to == self.last_sent =>
+                if cmd.command == CmdKind::Confirm as u8 && cmd.responding_to == self.last_sent =>
             {
                 self.respond_to = cmd.packet;
                 // send the next frame (or end the transaction), go into Sending mode (or done mode)
@@ -274,12 +431,13 @@ impl ClientInterface {
                         responding_to: self.respond_to,
                         packet_ty: PACKET_TYPE_COMMAND,
                         command: CmdKind::Complete as _,
-                        padding: [0; 2],
+                        frame_size: self.frame_size as _,
                     })));
-
+                    // everything has been sent (and confirmed)
+                    self.send_queue.pop_back();
                     self.state = State::TheoreticallyDoneSending;
                 } else {
-                    dispatch.push(DispatchEvent::Send(Packet::Frame(Frame {
+                    dispatch.push(DispatchEvent::Send(Packet::Frame(Box::new(Frame {
                         packet: {
                             self.last_sent = self.uid_gen.next();
                             self.last_sent
@@ -287,24 +445,24 @@ impl ClientInterface {
                         responding_to: self.respond_to,
                         packet_ty: PACKET_TYPE_FRAME,
                         _pad: 0,
-                        len: self.send_buf.len().clamp(0, FRAME_BUF_SIZE) as _,
+                        len: self.send_buf.len().clamp(0, self.frame_size) as _,
                         data: {
-                            let mut buf = [0u8; FRAME_BUF_SIZE];
+                            let mut buf = [0u8; MAX_FRAME_BUF_SIZE];
                             let mut past_buf = self
                                 .send_buf
-                                .split_off(FRAME_BUF_SIZE.clamp(0, self.send_buf.len()));
+                                .split_off(self.frame_size.clamp(0, self.send_buf.len()));
                             swap(&mut self.send_buf, &mut past_buf);
                             self.last_sent_send_buf = past_buf.clone();
                             buf[0..past_buf.len()].copy_from_slice(&past_buf);
                             buf
                         },
-                    })));
+                    }))));
                     self.state = State::Sending;
                 }
             }
             (State::SendingStart, _) => {}
             (State::Sending, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Confirm as _ && cmd.responding_to == self.last_sent =>
+                if cmd.command == CmdKind::Confirm as u8 && cmd.responding_to == self.last_sent =>
             {
                 self.respond_to = cmd.packet;
                 // send the next frame
@@ -317,11 +475,12 @@ impl ClientInterface {
                         responding_to: self.respond_to,
                         packet_ty: PACKET_TYPE_COMMAND,
                         command: CmdKind::Complete as _,
-                        padding: [0; 2],
+                        frame_size: self.frame_size as _,
                     })));
+                    self.send_queue.pop_back();
                     self.state = State::TheoreticallyDoneSending;
                 } else {
-                    dispatch.push(DispatchEvent::Send(Packet::Frame(Frame {
+                    dispatch.push(DispatchEvent::Send(Packet::Frame(Box::new(Frame {
                         packet: {
                             self.last_sent = self.uid_gen.next();
                             self.last_sent
@@ -329,48 +488,48 @@ impl ClientInterface {
                         responding_to: self.respond_to,
                         packet_ty: PACKET_TYPE_FRAME,
                         _pad: 0,
-                        len: self.send_buf.len().clamp(0, FRAME_BUF_SIZE) as _,
+                        len: self.send_buf.len().clamp(0, self.frame_size) as _,
                         data: {
-                            let mut buf = [0u8; FRAME_BUF_SIZE];
+                            let mut buf = [0u8; MAX_FRAME_BUF_SIZE];
                             let mut past_buf = self
                                 .send_buf
-                                .split_off(FRAME_BUF_SIZE.clamp(0, self.send_buf.len()));
+                                .split_off(self.frame_size.clamp(0, self.send_buf.len()));
                             swap(&mut self.send_buf, &mut past_buf);
                             self.last_sent_send_buf = past_buf.clone();
                             buf[0..past_buf.len()].copy_from_slice(&past_buf);
                             buf
                         },
-                    })));
+                    }))));
                 }
             }
             (State::Sending, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Confirm as _ && cmd.packet == self.respond_to =>
+                if cmd.command == CmdKind::Confirm as u8 && cmd.packet == self.respond_to =>
             {
                 // repeat the last frame
-                dispatch.push(DispatchEvent::Send(Packet::Frame(Frame {
+                dispatch.push(DispatchEvent::Send(Packet::Frame(Box::new(Frame {
                     packet: self.last_sent,
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_FRAME,
                     _pad: 0,
                     len: self.last_sent_send_buf.len() as _,
                     data: {
-                        let mut buf = [0u8; FRAME_BUF_SIZE];
+                        let mut buf = [0u8; MAX_FRAME_BUF_SIZE];
                         buf[0..self.last_sent_send_buf.len()]
                             .copy_from_slice(&self.last_sent_send_buf);
                         buf
                     },
-                })));
+                }))));
             }
             (State::Sending, _) => {}
             (State::TheoreticallyDoneSending, Packet::Cmd(cmd))
-                if cmd.command == CmdKind::Confirm as _ && cmd.packet == self.respond_to =>
+                if cmd.command == CmdKind::Confirm as u8 && cmd.packet == self.respond_to =>
             {
                 dispatch.push(DispatchEvent::Send(Packet::Cmd(Cmd {
                     packet: self.last_sent,
                     responding_to: self.respond_to,
                     packet_ty: PACKET_TYPE_COMMAND,
                     command: CmdKind::Complete as _,
-                    padding: [0; 2],
+                    frame_size: self.frame_size as _,
                 })));
             }
             (State::TheoreticallyDoneSending, _) => {}
@@ -378,3 +537,180 @@ impl ClientInterface {
         dispatch
     }
 }
+
+#[cfg(test)]
+#[test]
+fn negotiates_frame_size() {
+    let start = |command: CmdKind, frame_size: u16| {
+        Packet::Cmd(Cmd {
+            packet: 1,
+            responding_to: 0,
+            packet_ty: PACKET_TYPE_COMMAND,
+            command: command as _,
+            frame_size,
+        })
+    };
+    let mut inter = ClientInterface::new(Duration::from_secs(30)).with_max_frame_size(300);
+    let [DispatchEvent::Send(Packet::Cmd(confirm))] = &inter.handle(start(CmdKind::Tx, 100))[..]
+    else {
+        panic!("expected a Confirm");
+    };
+    assert_eq!(confirm.frame_size, 100);
+    assert_eq!(inter.frame_size(), 100);
+
+    // sends frames of the negotiated size
+    let mut inter = ClientInterface::new(Duration::from_secs(30)).with_max_frame_size(64);
+    inter.queue(vec![7; 200]);
+    let [DispatchEvent::Send(Packet::Frame(frame))] = &inter.handle(start(CmdKind::Rx, 1000))[..]
+    else {
+        panic!("expected a Frame");
+    };
+    assert_eq!(frame.len, 64);
+    assert_eq!(inter.frame_size(), 64);
+
+    // clients that do not negotiate get the default
+    let mut inter =
+        ClientInterface::new(Duration::from_secs(30)).with_max_frame_size(MAX_FRAME_BUF_SIZE);
+    inter.queue(vec![7; 2000]);
+    let [DispatchEvent::Send(Packet::Frame(frame))] = &inter.handle(start(CmdKind::Rx, 0))[..]
+    else {
+        panic!("expected a Frame");
+    };
+    assert_eq!(frame.len as usize, FRAME_BUF_SIZE);
+}
+
+#[cfg(test)]
+#[test]
+fn transaction_times_out() {
+    use crate::clock::MockClock;
+
+    let tx = Packet::Cmd(Cmd {
+        packet: 1,
+        responding_to: 0,
+        packet_ty: PACKET_TYPE_COMMAND,
+        command: CmdKind::Tx as _,
+        frame_size: 0,
+    });
+    let frame = |packet: u32, responding_to: u32| {
+        Packet::Frame(Box::new(Frame {
+            packet,
+            responding_to,
+            packet_ty: PACKET_TYPE_FRAME,
+            _pad: 0,
+            len: 1,
+            data: [0; MAX_FRAME_BUF_SIZE],
+        }))
+    };
+    let confirmed = |events: &[DispatchEvent]| {
+        let [DispatchEvent::Send(Packet::Cmd(confirm))] = events else {
+            panic!("expected a Confirm, got {events:?}");
+        };
+        assert_eq!(confirm.command, CmdKind::Confirm as u8);
+        confirm.packet
+    };
+    let clock = MockClock::new();
+    let mut inter =
+        ClientInterface::new(Duration::from_secs(30)).with_clock(Arc::new(clock.clone()));
+    let last = confirmed(&inter.handle(tx));
+    let last = confirmed(&inter.handle(frame(2, last)));
+
+    // slow, but within the limit
+    clock.advance(Duration::from_secs(29));
+    let last = confirmed(&inter.handle(frame(3, last)));
+
+    clock.advance(Duration::from_secs(2));
+    let [DispatchEvent::TimedOut] = &inter.handle(frame(4, last))[..] else {
+        panic!("expected the transaction to time out");
+    };
+    assert!(inter.is_resting());
+}
+
+#[cfg(test)]
+#[test]
+fn ping_pong_while_resting() {
+    let cmd = |packet: u32, command: CmdKind| {
+        Packet::Cmd(Cmd {
+            packet,
+            responding_to: 0,
+            packet_ty: PACKET_TYPE_COMMAND,
+            command: command as _,
+            frame_size: 0,
+        })
+    };
+    let mut inter = ClientInterface::new(Duration::from_secs(30));
+    // answered, without starting a transaction
+    let [DispatchEvent::Send(Packet::Cmd(pong))] = &inter.handle(cmd(7, CmdKind::Ping))[..] else {
+        panic!("expected a Pong");
+    };
+    assert_eq!(pong.command, CmdKind::Pong as u8);
+    assert_eq!(pong.responding_to, 7);
+    assert!(inter.is_resting());
+
+    // pings to the client count as missed until it sends something
+    assert!(inter.ping().is_some());
+    assert!(inter.ping().is_some());
+    let Some(Packet::Cmd(ping)) = inter.ping() else {
+        panic!("expected a Ping");
+    };
+    assert_eq!(ping.command, CmdKind::Ping as u8);
+    assert_eq!(inter.missed_pings(), 2);
+    assert!(inter.handle(cmd(ping.packet, CmdKind::Pong)).is_empty());
+    assert_eq!(inter.missed_pings(), 0);
+
+    // not during a transaction
+    inter.handle(cmd(8, CmdKind::Tx));
+    assert!(inter.ping().is_none());
+    assert!(inter.handle(cmd(9, CmdKind::Ping)).is_empty());
+    assert_eq!(inter.missed_pings(), 0);
+}
+
+#[cfg(test)]
+#[tracing_test::traced_test]
+#[test]
+fn logs_state_transitions() {
+    let cmd = |packet: u32, responding_to: u32, command: CmdKind| {
+        Packet::Cmd(Cmd {
+            packet,
+            responding_to,
+            packet_ty: PACKET_TYPE_COMMAND,
+            command: command as _,
+            frame_size: 0,
+        })
+    };
+    let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+    let mut inter = ClientInterface::new(Duration::from_secs(30)).with_peer(peer);
+    let [DispatchEvent::Send(confirm)] = &inter.handle(cmd(1, 0, CmdKind::Tx))[..] else {
+        panic!("expected a Confirm");
+    };
+    let mut data = [0u8; MAX_FRAME_BUF_SIZE];
+    data[..4].copy_from_slice(b"data");
+    inter.handle(Packet::Frame(Box::new(Frame {
+        packet: 2,
+        responding_to: confirm.uid(),
+        packet_ty: PACKET_TYPE_FRAME,
+        _pad: 0,
+        len: 4,
+        data,
+    })));
+    // (a stale packet)
+    inter.handle(cmd(100, 100, CmdKind::Complete));
+    let [_, DispatchEvent::Send(confirm)] =
+        &inter.handle(cmd(3, inter.last_sent, CmdKind::Complete))[..]
+    else {
+        panic!("expected the data and a Confirm");
+    };
+    assert_eq!(confirm.responding_to(), 3);
+
+    for (old, new, packet) in [
+        ("Resting", "ReceivingStart", 1),
+        ("ReceivingStart", "Receiving", 2),
+        ("Receiving", "TheoreticallyDoneReceiving", 3),
+    ] {
+        assert!(logs_contain(&format!(
+            "transport state transition peer=Some(10.0.0.1:4000) old={old} new={new} packet={packet}"
+        )));
+    }
+    assert!(logs_contain(
+        "packet ignored (it does not match the current transaction) peer=Some(10.0.0.1:4000) state=Receiving packet=100"
+    ));
+}
@@ -1,4 +1,5 @@
 use futures::{select, FutureExt};
+use serde::{Deserialize, Serialize};
 use std::{
     io,
     time::{Duration, Instant},
@@ -6,17 +7,75 @@ use std::{
 use tokio::{net::UdpSocket, time::sleep_until};
 
 use crate::transport::{
-    extract_packet_type, read_packet, CmdKind, Packet, PACKET_TYPE_COMMAND, UDP_MAX_SIZE,
+    extract_packet_type, read_packet, CmdKind, Packet, PACKET_TYPE_COMMAND, UDP_MAX_SIZE_NEGOTIATED,
 };
 
 #[derive(Debug, thiserror::Error)]
 pub enum SendError {
+    /// I/O error that is likely caused by a (temporary) problem with the network, and may go away after reconnecting
     #[error("I/O Error: {0:?}")]
-    IOError(#[from] io::Error),
+    IOError(io::Error),
+    /// I/O error that is not known to be caused by a fixable problem
+    #[error("Fatal I/O Error: {0:?}")]
+    Fatal(io::Error),
     #[error("Timed out")]
     TimedOut,
 }
 
+impl From<io::Error> for SendError {
+    fn from(e: io::Error) -> Self {
+        use io::ErrorKind::*;
+        match e.kind() {
+            HostUnreachable | NetworkUnreachable | NetworkDown | AddrNotAvailable
+            | ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected
+            | BrokenPipe | TimedOut | Interrupted | WouldBlock | OutOfMemory => Self::IOError(e),
+            _ => Self::Fatal(e),
+        }
+    }
+}
+
+/// Counters describing how well the transport is working, accumulated across transactions (reset with `Default`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportStats {
+    /// packets sent, including retransmissions
+    pub packets_sent: u64,
+    /// packets that were sent again, because no response arrived in time
+    pub retransmits: u64,
+    /// transactions (`send`/`recv`) that completed
+    pub transactions: u64,
+    /// transactions that were abandoned, because a packet was never answered
+    pub timeouts: u64,
+    /// sum of the round trip times measured (in milliseconds)
+    pub rtt_total_ms: u64,
+    /// number of round trip times measured
+    pub rtt_samples: u64,
+    /// longest round trip time measured (in milliseconds)
+    pub rtt_max_ms: u64,
+}
+
+impl TransportStats {
+    /// mean round trip time, if any were measured
+    pub fn mean_rtt(&self) -> Option<Duration> {
+        (self.rtt_samples > 0).then(|| Duration::from_millis(self.rtt_total_ms / self.rtt_samples))
+    }
+
+    /// count a transaction that finished with `res`
+    pub(crate) fn finished<T>(&mut self, res: &Result<T, SendError>) {
+        match res {
+            Ok(..) => self.transactions += 1,
+            Err(SendError::TimedOut) => self.timeouts += 1,
+            Err(..) => {}
+        }
+    }
+
+    fn record_rtt(&mut self, rtt: Duration) {
+        let ms = rtt.as_millis().min(u64::MAX as u128) as u64;
+        self.rtt_total_ms = self.rtt_total_ms.saturating_add(ms);
+        self.rtt_samples += 1;
+        self.rtt_max_ms = self.rtt_max_ms.max(ms);
+    }
+}
+
 pub enum ExpectedResponse {
     FrameOrCommand { cmd: CmdKind },
     Command { cmd: CmdKind },
@@ -28,14 +87,16 @@ pub async fn send_and_wait(
     expected_response: ExpectedResponse,
     max_attempts: usize,
     wait_dur: Duration,
+    stats: &mut TransportStats,
 ) -> Result<Packet, SendError> {
     assert!(max_attempts > 0);
     let bytes = to.as_bytes();
 
     let next_wait_end = || Instant::now() + wait_dur;
     let mut wait_end;
-    let mut buf = vec![0u8; UDP_MAX_SIZE];
+    let mut buf = vec![0u8; UDP_MAX_SIZE_NEGOTIATED];
     let mut attempt = 0usize;
+    let mut sent_at;
 
     'send: loop {
         attempt += 1;
@@ -43,6 +104,11 @@ pub async fn send_and_wait(
             return Err(SendError::TimedOut);
         }
         sock.send(bytes).await?;
+        stats.packets_sent += 1;
+        if attempt > 1 {
+            stats.retransmits += 1;
+        }
+        sent_at = Instant::now();
         wait_end = next_wait_end();
         break loop {
             let amnt;
@@ -82,12 +148,50 @@ pub async fn send_and_wait(
                 }
             };
             if let Packet::Cmd(c) = p {
-                if c.command != expected_command as _ {
+                if c.command != expected_command as u8 {
                     debug!("send_and_wait: expected packet with command {:?}, received packet with command {:?} (ignoring)", expected_command, c.command);
                     continue;
                 }
             }
+            // (measured from the last send, a response to an earlier one would make this shorter than it really is)
+            stats.record_rtt(sent_at.elapsed());
             break Ok(p);
         };
     }
 }
+
+#[cfg(test)]
+#[test]
+fn io_error_classification() {
+    use io::ErrorKind::*;
+    for kind in [
+        HostUnreachable,
+        NetworkUnreachable,
+        AddrNotAvailable,
+        ConnectionRefused,
+        ConnectionReset,
+        TimedOut,
+        // (ENOMEM/ENOBUFS, when lwIP runs out of buffers)
+        OutOfMemory,
+    ] {
+        assert!(
+            matches!(
+                SendError::from(io::Error::from(kind)),
+                SendError::IOError(..)
+            ),
+            "{kind:?} should be retryable"
+        );
+    }
+    for kind in [
+        PermissionDenied,
+        InvalidInput,
+        AddrInUse,
+        Unsupported,
+        Other,
+    ] {
+        assert!(
+            matches!(SendError::from(io::Error::from(kind)), SendError::Fatal(..)),
+            "{kind:?} should be fatal"
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Notifying IPC clients when readings cross a threshold (see `config::Alarm`)
+
+use std::collections::{HashMap, HashSet};
+
+use mycelium::station::{
+    capabilities::{Channel, ChannelData, ChannelID, KnownChannels},
+    identity::StationID,
+};
+use roundtable::{
+    handler::{DispatchErr, HandlerInit, LocalInterface, MethodRegister},
+    handler_decl_t, method_decl,
+    msg::{self, Str},
+};
+
+use crate::{
+    core::config,
+    dispatch::application::{Record, EV_WEATHER_DATA_RECEIVED},
+    registry::EV_META_NEW_CHANNEL,
+};
+
+// an alarm was raised or cleared
+method_decl!(EV_ALARM, AlarmEvent, ());
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmEvent {
+    pub station: StationID,
+    pub channel: ChannelID,
+    /// the reading that raised (or cleared) the alarm
+    pub value: f32,
+    /// name of the rule (see [`config::Alarm::name`])
+    pub rule: String,
+    /// false if the alarm was cleared
+    pub raised: bool,
+}
+
+/// Alarm rules, and which are currently raised
+#[derive(Debug)]
+pub struct Alarms {
+    rules: Vec<config::Alarm>,
+    /// (index of the rule, station, channel) of raised alarms
+    raised: HashSet<(usize, StationID, ChannelID)>,
+}
+
+impl Alarms {
+    pub fn new(rules: Vec<config::Alarm>) -> Self {
+        Self {
+            rules,
+            raised: HashSet::new(),
+        }
+    }
+
+    /// alarms raised or cleared by a reading of `channel` (named `name`).
+    ///
+    /// an alarm is raised once when a reading crosses its threshold, and cleared once a reading returns past the
+    /// threshold by at least its hysteresis
+    pub fn check(
+        &mut self,
+        station: StationID,
+        channel: ChannelID,
+        name: &str,
+        value: f32,
+    ) -> Vec<AlarmEvent> {
+        let mut events = vec![];
+        for (idx, rule) in self.rules.iter().enumerate() {
+            if rule.channel != name || rule.station.is_some_and(|id| id != station) {
+                continue;
+            }
+            let (crossed, returned) = match (rule.above, rule.below) {
+                (Some(above), _) => (value > above, value < above - rule.hysteresis),
+                (None, Some(below)) => (value < below, value > below + rule.hysteresis),
+                (None, None) => continue,
+            };
+            let key = (idx, station, channel);
+            let raised = self.raised.contains(&key);
+            if !raised && crossed {
+                self.raised.insert(key);
+            } else if raised && returned {
+                self.raised.remove(&key);
+            } else {
+                continue;
+            }
+            events.push(AlarmEvent {
+                station,
+                channel,
+                value,
+                rule: rule.name(),
+                raised: !raised,
+            });
+        }
+        events
+    }
+}
+
+/// Checks incoming readings against the alarm rules, announcing [`EV_ALARM`] when one is raised or cleared
+pub struct AlarmDispatch {
+    alarms: Alarms,
+    /// channel names, by id (alarm rules refer to channels by name)
+    names: HashMap<ChannelID, String>,
+}
+
+impl AlarmDispatch {
+    pub fn new(rules: Vec<config::Alarm>, channels: &KnownChannels) -> Self {
+        Self {
+            alarms: Alarms::new(rules),
+            names: channels
+                .channels()
+                .map(|(id, name)| (*id, name.as_ref().clone()))
+                .collect(),
+        }
+    }
+
+    async fn new_channel(
+        &mut self,
+        (id, ch): &(ChannelID, Channel),
+        _int: &LocalInterface,
+    ) -> Result<(), DispatchErr> {
+        self.names.insert(*id, ch.name.as_ref().clone());
+        Ok(())
+    }
+
+    async fn check(&mut self, data: &Record, int: &LocalInterface) -> Result<(), DispatchErr> {
+        for (channel, value) in &data.data {
+            let (Some(name), ChannelData::Float(value)) = (self.names.get(channel), value) else {
+                continue;
+            };
+            for event in self.alarms.check(data.recorded_by, *channel, name, *value) {
+                if event.raised {
+                    warn!(
+                        "Alarm {:?} raised by station {} ({name} = {value})",
+                        event.rule, event.station
+                    );
+                } else {
+                    info!(
+                        "Alarm {:?} cleared by station {} ({name} = {value})",
+                        event.rule, event.station
+                    );
+                }
+                int.announce(msg::Target::Any, EV_ALARM, event).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HandlerInit for AlarmDispatch {
+    const DECL: msg::HandlerType = handler_decl_t!("Alarm dispatch");
+    type Error = DispatchErr;
+    fn describe(&self) -> Str {
+        Str::Borrowed("Alarm dispatch")
+    }
+    fn methods(&self, reg: &mut MethodRegister<Self>) {
+        reg.register(Self::check, EV_WEATHER_DATA_RECEIVED);
+        reg.register(Self::new_channel, EV_META_NEW_CHANNEL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn rule(above: Option<f32>, below: Option<f32>, hysteresis: f32) -> config::Alarm {
+        config::Alarm {
+            name: None,
+            channel: "battery".into(),
+            station: None,
+            above,
+            below,
+            hysteresis,
+        }
+    }
+
+    /// (value, raised) of each event from checking `values` in order
+    fn run(alarms: &mut Alarms, station: StationID, values: &[f32]) -> Vec<(f32, bool)> {
+        let channel = Uuid::nil();
+        values
+            .iter()
+            .flat_map(|&v| alarms.check(station, channel, "battery", v))
+            .map(|ev| (ev.value, ev.raised))
+            .collect()
+    }
+
+    #[test]
+    fn rising_edge() {
+        let mut alarms = Alarms::new(vec![rule(None, Some(3.3), 0.0)]);
+        let station = Uuid::new_v4();
+        // only raised once, while the readings stay past the threshold
+        assert_eq!(
+            run(&mut alarms, station, &[3.7, 3.3, 3.2, 3.1, 3.25]),
+            [(3.2, true)]
+        );
+        // other channels and stations are separate
+        assert_eq!(
+            alarms.check(station, Uuid::new_v4(), "temperature", 0.0),
+            []
+        );
+        let events = alarms.check(Uuid::new_v4(), Uuid::nil(), "battery", 3.0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].rule, "battery below 3.3");
+        assert!(events[0].raised);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut alarms = Alarms::new(vec![rule(Some(40.0), None, 2.0)]);
+        let station = Uuid::new_v4();
+        // hovering around the threshold only raises it once
+        assert_eq!(
+            run(&mut alarms, station, &[40.5, 39.5, 40.5, 38.5, 40.1]),
+            [(40.5, true)]
+        );
+        // until it returns past the hysteresis
+        assert_eq!(
+            run(&mut alarms, station, &[37.9, 39.0, 40.2]),
+            [(37.9, false), (40.2, true)]
+        );
+    }
+
+    #[test]
+    fn clear() {
+        let mut alarms = Alarms::new(vec![rule(None, Some(3.3), 0.1)]);
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(run(&mut alarms, a, &[3.0]), [(3.0, true)]);
+        assert_eq!(run(&mut alarms, b, &[3.1]), [(3.1, true)]);
+        // each station's alarm is cleared separately
+        assert_eq!(run(&mut alarms, a, &[3.35, 3.5]), [(3.5, false)]);
+        assert_eq!(run(&mut alarms, a, &[3.6]), []);
+        assert_eq!(run(&mut alarms, b, &[3.2]), []);
+        // and can be raised again
+        assert_eq!(run(&mut alarms, a, &[3.0]), [(3.0, true)]);
+        // a rule for one station ignores the others
+        let mut alarms = Alarms::new(vec![config::Alarm {
+            station: Some(a),
+            ..rule(None, Some(3.3), 0.0)
+        }]);
+        assert_eq!(run(&mut alarms, b, &[3.0]), []);
+        assert_eq!(
+            run(&mut alarms, a, &[3.0, 3.4]),
+            [(3.0, true), (3.4, false)]
+        );
+    }
+}
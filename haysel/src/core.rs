@@ -1,5 +1,7 @@
 //! Utilities / smaller functions *core* to the function of haysel (unlike that in src/misc)
 
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
 use std::net::SocketAddr;
 
 use anyhow::Result;
@@ -8,14 +10,18 @@ use trust_dns_resolver::TokioAsyncResolver;
 
 pub mod args;
 pub mod autosave;
+pub mod bus_record;
 pub mod commands;
 pub mod config;
+pub mod health;
 pub mod log;
+pub mod retention;
 pub mod rt;
 pub mod shutdown;
 
 pub use autosave::AutosaveDispatch;
 pub use log::{init_logging_no_file, init_logging_with_file};
+pub use retention::RetentionDispatch;
 
 /// it is necessary to bind the server to the real external ip address,
 /// or risk confusing issues (forgot what, but it's bad)
@@ -24,10 +30,10 @@ pub async fn lookup_server_ip(url: String, port: u16) -> Result<Vec<SocketAddr>>
         "Performing DNS lookup of server's extranal IP (url={})",
         url
     );
-    let resolver = TokioAsyncResolver::tokio(
-        resolveconf::ResolverConfig::default(),
-        resolveconf::ResolverOpts::default(),
-    );
+    let mut opts = resolveconf::ResolverOpts::default();
+    // the server listens on both (if it has both)
+    opts.ip_strategy = resolveconf::LookupIpStrategy::Ipv4AndIpv6;
+    let resolver = TokioAsyncResolver::tokio(resolveconf::ResolverConfig::default(), opts);
     let addrs = resolver
         .lookup_ip(url)
         .await?
@@ -39,3 +45,34 @@ pub async fn lookup_server_ip(url: String, port: u16) -> Result<Vec<SocketAddr>>
         .collect::<Vec<_>>();
     Ok::<_, anyhow::Error>(addrs)
 }
+
+/// restrict `sock` to sending and receiving through the network interface `iface` (`SO_BINDTODEVICE`)
+#[cfg(target_os = "linux")]
+pub fn bind_to_interface(sock: &tokio::net::UdpSocket, iface: &str) -> Result<()> {
+    use nix::sys::socket::{setsockopt, sockopt::BindToDevice};
+    setsockopt(sock, BindToDevice, &OsString::from(iface))?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_to_interface(_sock: &tokio::net::UdpSocket, _iface: &str) -> Result<()> {
+    bail!("binding to a specific interface is only supported on linux")
+}
+
+/// split addresses into `(ipv4, ipv6)`, as a separate socket is needed for each
+pub fn split_by_family(addrs: &[SocketAddr]) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+    addrs.iter().partition(|addr| addr.is_ipv4())
+}
+
+#[cfg(test)]
+#[test]
+fn split_addrs_by_family() {
+    let addrs: Vec<SocketAddr> = vec![
+        "203.0.113.5:8998".parse().unwrap(),
+        "[2001:db8::5]:8998".parse().unwrap(),
+        "203.0.113.6:8998".parse().unwrap(),
+    ];
+    let (v4, v6) = split_by_family(&addrs);
+    assert_eq!(v4, vec![addrs[0], addrs[2]]);
+    assert_eq!(v6, vec![addrs[1]]);
+}
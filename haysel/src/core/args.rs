@@ -50,4 +50,14 @@ pub struct RunArgs {
         help = "do not write a PID file, do not check for a PID file, do not trap ctrl+c, imply --overwrite-reinit (incompatable with --daemonize)"
     )]
     pub no_safeguards: bool,
+    #[arg(
+        long,
+        help = "Open the database read-only (it will never be modified), dropping any data received from weather stations. useful for inspecting a copy of a production database"
+    )]
+    pub read_only: bool,
+    #[arg(
+        long,
+        help = "Record every message sent on the internal bus to this file (JSON lines), for debugging"
+    )]
+    pub record_bus: Option<PathBuf>,
 }
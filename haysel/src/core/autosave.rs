@@ -1,50 +1,117 @@
-use std::{convert::Infallible, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use roundtable::{
     common::EV_BUILTIN_AUTOSAVE,
     handler::{HandlerInit, LocalInterface, MethodRegister},
-    handler_decl_t, method_decl_owned,
+    handler_decl_t, method_decl, method_decl_owned,
     msg::{self, Str},
 };
-use tokio::time::{interval_at, Instant, Interval};
+use squirrel::clock::Clock;
+use tokio::sync::Notify;
+
+/// how long a forced save waits for handlers to finish saving
+const FORCED_SAVE_DEADLINE: Duration = Duration::from_secs(2);
+/// how often the clock is checked, to see if a save is due
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct AutosaveDispatch {
     interval: Duration,
+    /// notified by a forced save, to restart the interval from then (instead of saving again shortly after)
+    reset: Arc<Notify>,
+    clock: Arc<dyn Clock>,
+    check_every: Duration,
 }
 
 impl AutosaveDispatch {
-    pub fn new(every: Duration) -> Self {
-        Self { interval: every }
+    pub fn new(every: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            interval: every,
+            reset: Arc::new(Notify::new()),
+            clock,
+            check_every: CLOCK_CHECK_INTERVAL,
+        }
+    }
+
+    /// how often the clock is checked. defaults to [`CLOCK_CHECK_INTERVAL`]
+    #[cfg(test)]
+    pub fn with_check_every(self, check_every: Duration) -> Self {
+        Self {
+            check_every,
+            ..self
+        }
+    }
+
+    /// waits in the background until a save is due (an interval from now, by the clock), restarting the wait if a
+    /// forced save happens first
+    fn wait_for_save(&self, int: &LocalInterface) {
+        let (reset, clock, every) = (self.reset.clone(), self.clock.clone(), self.interval);
+        let mut check = tokio::time::interval(self.check_every);
+        check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        int.bg_spawn(EV_PRIV_TIMER_COMPLETED, async move {
+            let mut due = clock.now_instant() + every;
+            loop {
+                tokio::select! {
+                    _ = check.tick() => if clock.now_instant() >= due {
+                        break;
+                    },
+                    _ = reset.notified() => due = clock.now_instant() + every,
+                }
+            }
+        });
     }
 
-    #[instrument(skip(self, interval, int))]
+    #[instrument(skip(self, int))]
     async fn timer_complete(
         &mut self,
-        mut interval: Interval,
+        _: (),
         int: &LocalInterface,
     ) -> Result<(), <Self as HandlerInit>::Error> {
         debug!("saving...");
         int.announce(msg::Target::Any, EV_BUILTIN_AUTOSAVE, ())
             .await
             .unwrap(); // unreachable
-        int.bg_spawn(EV_PRIV_TIMER_COMPLETED, async move {
-            interval.tick().await;
-            interval
-        });
+        self.wait_for_save(int);
         Ok(())
     }
+
+    /// saves now, returning the number of handlers that finished saving within `FORCED_SAVE_DEADLINE`
+    #[instrument(skip(self, int))]
+    async fn force_save(
+        &mut self,
+        _: &(),
+        int: &LocalInterface,
+    ) -> Result<usize, <Self as HandlerInit>::Error> {
+        debug!("saving (forced)...");
+        // (if the timer is not being waited on, the reset is kept until it is)
+        self.reset.notify_one();
+        match int
+            .dispatch_collect(
+                msg::Target::Any,
+                EV_BUILTIN_AUTOSAVE,
+                (),
+                FORCED_SAVE_DEADLINE,
+            )
+            .await
+        {
+            Ok(saved) => Ok(saved.len()),
+            Err(e) => {
+                warn!("Forced save failed: {e:#}");
+                Ok(0)
+            }
+        }
+    }
 }
 
-method_decl_owned!(EV_PRIV_TIMER_COMPLETED, Interval, ());
+method_decl_owned!(EV_PRIV_TIMER_COMPLETED, (), ());
+// save immediately, returns the number of handlers that saved
+method_decl!(EV_AUTOSAVE_FORCE, (), usize);
 
 #[async_trait]
 impl HandlerInit for AutosaveDispatch {
     const DECL: roundtable::msg::HandlerType = handler_decl_t!("Autosave event dispatcher");
     type Error = Infallible;
     async fn init(&mut self, int: &LocalInterface) -> Result<(), Self::Error> {
-        let mut interval = interval_at(Instant::now() + self.interval, self.interval);
-        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
-        let _ = self.timer_complete(interval, int).await;
+        let _ = self.timer_complete((), int).await;
         Ok(())
     }
     fn describe(&self) -> Str {
@@ -55,5 +122,135 @@ impl HandlerInit for AutosaveDispatch {
     }
     fn methods(&self, reg: &mut MethodRegister<Self>) {
         reg.register_owned(Self::timer_complete, EV_PRIV_TIMER_COMPLETED);
+        reg.register(Self::force_save, EV_AUTOSAVE_FORCE);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use roundtable::{common::HDL_EXTERNAL, Bus};
+    use squirrel::clock::MockClock;
+
+    use super::*;
+    use crate::tsdb3::{bus::TStopDBus3, DB};
+
+    /// counts the saves it is told to do
+    struct Saver(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl HandlerInit for Saver {
+        const DECL: msg::HandlerType = handler_decl_t!("Test save counter");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Test save counter")
+        }
+        fn methods(&self, reg: &mut MethodRegister<Self>) {
+            reg.register(Self::save, EV_BUILTIN_AUTOSAVE);
+        }
+    }
+
+    impl Saver {
+        async fn save(&mut self, _: &(), _int: &LocalInterface) -> Result<(), Infallible> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    const CHECK_EVERY: Duration = Duration::from_millis(5);
+
+    /// waits for `saves` to reach `expected` (the clock is checked in the background, so this takes a little real time)
+    async fn saves_reach(saves: &AtomicUsize, expected: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while saves.load(Ordering::SeqCst) < expected {
+                tokio::time::sleep(CHECK_EVERY).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for a save");
+        assert_eq!(saves.load(Ordering::SeqCst), expected);
+    }
+
+    /// checks that `saves` stays at `expected` for a while (after the clock was checked a few times)
+    async fn saves_stay(saves: &AtomicUsize, expected: usize) {
+        tokio::time::sleep(CHECK_EVERY * 10).await;
+        assert_eq!(saves.load(Ordering::SeqCst), expected);
+    }
+
+    #[tokio::test]
+    async fn interval_honored() {
+        let bus = Bus::new(Default::default()).await;
+        let saves = Arc::new(AtomicUsize::new(0));
+        let clock = MockClock::new();
+        bus.spawn_checked(Saver(saves.clone())).await.unwrap();
+        // saves once on startup, then every interval
+        bus.spawn_checked(
+            AutosaveDispatch::new(Duration::from_secs(60), Arc::new(clock.clone()))
+                .with_check_every(CHECK_EVERY),
+        )
+        .await
+        .unwrap();
+        saves_reach(&saves, 1).await;
+        for expected in [2, 3] {
+            clock.advance(Duration::from_secs(30));
+            saves_stay(&saves, expected - 1).await;
+            clock.advance(Duration::from_secs(30));
+            saves_reach(&saves, expected).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn forced_save_flushes_database() {
+        let bus = Bus::new(Default::default()).await;
+        let saves = Arc::new(AtomicUsize::new(0));
+        bus.spawn_checked(Saver(saves.clone())).await.unwrap();
+        let mut db = DB::new_in_ram(4096).unwrap();
+        db.init();
+        bus.spawn_checked(TStopDBus3::new(db)).await.unwrap();
+        let autosave = bus
+            .spawn_checked(AutosaveDispatch::new(
+                Duration::from_secs(3600),
+                Arc::new(MockClock::new()),
+            ))
+            .await
+            .unwrap();
+
+        // the counter, and the database (the counter also saved on startup)
+        let saved = bus
+            .query_as(HDL_EXTERNAL, autosave, EV_AUTOSAVE_FORCE, ())
+            .await
+            .unwrap();
+        assert_eq!(saved, 2);
+        assert_eq!(saves.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn forced_save_restarts_interval() {
+        let bus = Bus::new(Default::default()).await;
+        let saves = Arc::new(AtomicUsize::new(0));
+        let clock = MockClock::new();
+        bus.spawn_checked(Saver(saves.clone())).await.unwrap();
+        let autosave = bus
+            .spawn_checked(
+                AutosaveDispatch::new(Duration::from_secs(60), Arc::new(clock.clone()))
+                    .with_check_every(CHECK_EVERY),
+            )
+            .await
+            .unwrap();
+        saves_reach(&saves, 1).await;
+        clock.advance(Duration::from_secs(20));
+        bus.query_as(HDL_EXTERNAL, autosave, EV_AUTOSAVE_FORCE, ())
+            .await
+            .unwrap();
+        assert_eq!(saves.load(Ordering::SeqCst), 2);
+        // the next periodic save is a whole interval after the forced one (not the startup one)
+        clock.advance(Duration::from_secs(50));
+        saves_stay(&saves, 2).await;
+        clock.advance(Duration::from_secs(10));
+        saves_reach(&saves, 3).await;
     }
 }
@@ -0,0 +1,61 @@
+//! Recording the messages sent on the bus to a file, for debugging (`haysel run --record-bus`)
+//!
+//! see [`roundtable::record`]. recordings can be replayed with [`roundtable::record::replay`], using [`arg_codecs`]
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::Result;
+use roundtable::{
+    handler::Interface,
+    record::{ArgCodecs, Recorder},
+};
+
+use crate::{
+    core::autosave::EV_AUTOSAVE_FORCE,
+    dispatch::{
+        transport::{
+            EV_TRANS_CLI_DATA_RECVD, EV_TRANS_CLI_EVICT, EV_TRANS_CLI_MISSED_PINGS,
+            EV_TRANS_CLI_PING, EV_TRANS_CLI_QUEUE_DATA, EV_TRANS_CLI_RESET, EV_TRANS_CLI_STATE,
+        },
+        EV_CONTROLLER_CHECK_SEQ, EV_CONTROLLER_HEARTBEAT, EV_CONTROLLER_RESET_SEQ,
+    },
+    registry::{
+        EV_META_NEW_CHANNEL, EV_META_NEW_STATION, EV_META_STATION_ASSOC_CHANNEL,
+        EV_REGISTRY_CHECK_SOURCE, EV_REGISTRY_COMPUTE_DERIVED, EV_REGISTRY_PROCESS_CONNECT,
+        EV_REGISTRY_QUERY_CHANNEL, EV_REGISTRY_QUERY_HISTORY, EV_REGISTRY_RECORD_DIAGNOSTICS,
+    },
+};
+
+/// the methods whose arguments are recorded (those that can be serialized)
+pub fn arg_codecs() -> ArgCodecs {
+    ArgCodecs::new()
+        .with(EV_AUTOSAVE_FORCE)
+        .with(EV_CONTROLLER_CHECK_SEQ)
+        .with(EV_CONTROLLER_RESET_SEQ)
+        .with(EV_CONTROLLER_HEARTBEAT)
+        .with(EV_TRANS_CLI_QUEUE_DATA)
+        .with(EV_TRANS_CLI_RESET)
+        .with(EV_TRANS_CLI_EVICT)
+        .with(EV_TRANS_CLI_STATE)
+        .with(EV_TRANS_CLI_PING)
+        .with(EV_TRANS_CLI_MISSED_PINGS)
+        .with(EV_TRANS_CLI_DATA_RECVD)
+        .with(EV_REGISTRY_QUERY_CHANNEL)
+        .with(EV_REGISTRY_QUERY_HISTORY)
+        .with(EV_REGISTRY_PROCESS_CONNECT)
+        .with(EV_REGISTRY_CHECK_SOURCE)
+        .with(EV_REGISTRY_COMPUTE_DERIVED)
+        .with(EV_REGISTRY_RECORD_DIAGNOSTICS)
+        .with(EV_META_NEW_STATION)
+        .with(EV_META_NEW_CHANNEL)
+        .with(EV_META_STATION_ASSOC_CHANNEL)
+}
+
+/// Records every message sent on `bus` to `path` (overwriting it), until the bus is closed
+pub fn start(bus: &Interface, path: &Path) -> Result<Recorder> {
+    let file = File::create(path)?;
+    warn!(
+        "Recording all bus messages to {path:?} (this is slow, and the recording will contain station data)"
+    );
+    Ok(Recorder::start(bus, arg_codecs(), BufWriter::new(file)))
+}
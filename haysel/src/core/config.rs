@@ -1,8 +1,14 @@
-use std::path::PathBuf;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
+use mycelium::station::{formula::Formula, identity::StationID};
 use serde::Deserialize;
 
+use crate::tsdb3;
+
 #[cfg(test)]
 #[test]
 fn load_example_config() {
@@ -18,6 +24,176 @@ fn load_example_config() {
     println!("{:?}", settings.try_deserialize::<Config>().unwrap());
 }
 
+#[cfg(test)]
+#[test]
+fn bind_address() {
+    let example = include_str!("../../config.example.toml");
+    let cfg = from_str(example).unwrap();
+    assert_eq!(cfg.server.bind_addr(), None);
+    assert_eq!(cfg.server.bind_interface, None);
+
+    let cfg = from_str(&example.replace(
+        "port = 8998",
+        "port = 8998\nbind_address = \"192.168.1.20\"\nbind_interface = \"eth1\"",
+    ))
+    .unwrap();
+    assert_eq!(
+        cfg.server.bind_addr(),
+        Some("192.168.1.20:8998".parse().unwrap())
+    );
+    assert_eq!(cfg.server.bind_interface.as_deref(), Some("eth1"));
+
+    let cfg =
+        from_str(&example.replace("port = 8998", "port = 8998\nbind_address = \"::\"")).unwrap();
+    assert_eq!(cfg.server.bind_addr(), Some("[::]:8998".parse().unwrap()));
+}
+
+#[cfg(test)]
+#[test]
+fn log_format() {
+    let example = include_str!("../../config.example.toml");
+    assert_eq!(from_str(example).unwrap().log.format, LogFormat::pretty);
+    let cfg =
+        from_str(&example.replace("# [log]\n# format = \"json\"", "[log]\nformat = \"json\""))
+            .unwrap();
+    assert_eq!(cfg.log.format, LogFormat::json);
+    assert!(
+        from_str(&example.replace("# [log]\n# format = \"json\"", "[log]\nformat = \"xml\""))
+            .is_err()
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn validate_collects_all_problems() {
+    let example = include_str!("../../config.example.toml");
+    let tmp = crate::misc::testing::temp_dir();
+    let dir = tmp.path();
+    let db_file = dir.join("data.tsdb3");
+    std::fs::write(&db_file, []).unwrap();
+    let valid = example
+        .replace("\"data/\"", &format!("{:?}", dir.join("data")))
+        .replace("\"run/\"", &format!("{:?}", dir.join("run")))
+        .replace("\"testing.tsdb2\"", &format!("{db_file:?}"));
+    let fields = |conf: &str| {
+        from_str(conf)
+            .unwrap()
+            .validate()
+            .map_err(|errs| errs.into_iter().map(|e| e.field).collect::<Vec<_>>())
+    };
+    assert_eq!(fields(&valid), Ok(()));
+
+    let invalid = valid
+        .replace(
+            &format!("{:?}", dir.join("data")),
+            "\"/nonexistent/haysel/data\"",
+        )
+        .replace("\"example.com\"", "\"not a -hostname\"")
+        .replace("autosave_interval_secs = 30", "autosave_interval_secs = 0")
+        .replace(&format!("[[database.files]]\npath = {db_file:?}"), "");
+    assert_eq!(
+        fields(&invalid),
+        Err(vec![
+            "directory.data",
+            "server.url",
+            "database.files",
+            "database.autosave_interval_secs"
+        ])
+    );
+
+    let invalid = valid
+        .replace("port = 8998", "port = 0")
+        .replace("interval_secs = 60", "interval_secs = 0")
+        .replace("comm_queue_cap = 64", "comm_queue_cap = 0")
+        .replace(
+            "max_stations = 16",
+            "max_stations = 17\nadmission = \"allowlist\"",
+        )
+        .replace(
+            "[[database.files]]",
+            "[[database.files]]\npath = \"a\"\n[[database.files]]",
+        )
+        .replace("initial_size_bytes = 16000000", "initial_size_bytes = 1000");
+    assert_eq!(
+        fields(&invalid),
+        Err(vec![
+            "server.port",
+            "server.keepalive.interval_secs",
+            "database.files",
+            "database.initial_size_bytes",
+            "registry.max_stations",
+            "registry.admission",
+            "bus.comm_queue_cap"
+        ])
+    );
+
+    let invalid =
+        format!("{valid}\n[retention]\nraw_days = 30\nrollup_days = 7\ninterval_secs = 0\n");
+    assert_eq!(
+        fields(&invalid),
+        Err(vec!["retention.rollup_days", "retention.interval_secs"])
+    );
+    let invalid =
+        format!("{valid}\n[retention]\nraw_days = 0\nrollup_bucket_secs = 0\nrollup_days = 7\n");
+    assert_eq!(
+        fields(&invalid),
+        Err(vec!["retention.raw_days", "retention.rollup_bucket_secs"])
+    );
+    let retention = format!(
+        "{valid}\n[retention]\nraw_days = 30\nrollup_bucket_secs = 3600\nrollup_days = 730\n"
+    );
+    assert_eq!(fields(&retention), Ok(()));
+    assert_eq!(
+        from_str(&retention).unwrap().retention,
+        Some(RetentionPolicy {
+            raw_days: 30,
+            rollup_bucket_secs: Some(3600),
+            rollup_days: Some(730),
+            interval_secs: 3600,
+        })
+    );
+
+    let invalid = format!(
+        "{valid}\n[[alarms]]\nchannel = \"battery\"\nbelow = 3.3\nhysteresis = 0.1\n\
+         [[alarms]]\nchannel = \"temperature\"\nabove = 40.0\nbelow = -10.0\n\
+         [[alarms]]\nchannel = \"humidity\"\nabove = 90.0\nhysteresis = -1.0\n\
+         [[alarms]]\nchannel = \"pressure\"\nbelow = 950.0\nhysteresis = inf\n"
+    );
+    assert_eq!(fields(&invalid), Err(vec!["alarms", "alarms", "alarms"]));
+
+    let computed = format!(
+        "{valid}\n[[computed]]\nname = \"temperature_f\"\ninputs = [\"temperature\"]\nunit = \"°F\"\n\
+         formula = {{ Add = [{{ Mul = [{{ Input = 0 }}, {{ Const = 1.8 }}] }}, {{ Const = 32.0 }}] }}\n"
+    );
+    assert_eq!(fields(&computed), Ok(()));
+    let cfg = from_str(&computed).unwrap();
+    assert_eq!(cfg.computed[0].formula.eval(&[100.0]), Ok(212.0));
+    let invalid = format!(
+        "{computed}\n[[computed]]\nname = \"temperature_f\"\ninputs = []\nformula = {{ Const = 1.0 }}\n\
+         [[computed]]\nname = \"wind_chill\"\ninputs = [\"wind\"]\nformula = {{ Input = 1 }}\n"
+    );
+    assert_eq!(
+        fields(&invalid),
+        Err(vec!["computed", "computed", "computed"])
+    );
+
+    // a missing storage file is reported (and not the missing url, which is unused with a bind address)
+    let invalid = valid
+        .replace("\"example.com\"", "\"\"")
+        .replace("port = 8998", "port = 8998\nbind_address = \"0.0.0.0\"");
+    assert_eq!(fields(&invalid), Ok(()));
+    // (it is created when the database is opened)
+    std::fs::remove_file(&db_file).unwrap();
+    assert_eq!(fields(&invalid), Ok(()));
+    let invalid = invalid.replace(
+        &format!("{db_file:?}"),
+        &format!("{:?}", dir.join("missing").join("data.tsdb3")),
+    );
+    assert_eq!(fields(&invalid), Err(vec!["database.files[0].path"]));
+    let invalid = valid.replace(&format!("{db_file:?}"), &format!("{dir:?}"));
+    assert_eq!(fields(&invalid), Err(vec!["database.files[0].path"]));
+}
+
 pub fn from_str(conf: &str) -> Result<self::Config> {
     let settings = config::Config::builder()
         .add_source(config::File::from_str(conf, config::FileFormat::Toml))
@@ -26,7 +202,7 @@ pub fn from_str(conf: &str) -> Result<self::Config> {
     Ok(settings)
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct Config {
     /// directories to store various things
     pub directory: Directories,
@@ -34,10 +210,329 @@ pub struct Config {
     pub server: Server,
     /// database configuration
     pub database: Database,
+    /// how long old data is kept (by default, forever)
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    /// message bus tuning
+    #[serde(default)]
+    pub bus: Bus,
+    /// IPC socket settings
+    #[serde(default)]
+    pub ipc: Ipc,
+    /// limits on what stations may register
+    #[serde(default)]
+    pub registry: Registry,
+    /// readings that IPC clients are notified of
+    #[serde(default)]
+    pub alarms: Vec<Alarm>,
+    /// channels the server derives from other channels
+    #[serde(default)]
+    pub computed: Vec<ComputedChannel>,
+    /// log output (which messages are logged is set by `RUST_LOG`)
+    #[serde(default)]
+    pub log: Log,
     /// misc
     pub misc: Misc,
 }
 
+/// A problem with the configuration, found by [`Config::validate`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("`{field}`: {problem}")]
+pub struct ConfigError {
+    /// path to the offending field (e.g. `database.files`)
+    pub field: &'static str,
+    pub problem: String,
+}
+
+impl ConfigError {
+    fn new(field: &'static str, problem: impl Into<String>) -> Self {
+        Self {
+            field,
+            problem: problem.into(),
+        }
+    }
+}
+
+impl Config {
+    /// Checks for problems that would otherwise only be found once the server is running, returning all of them
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+        check_dir(&mut errors, "directory.data", &self.directory.data);
+        check_dir(&mut errors, "directory.run", &self.directory.run);
+        if self.server.bind_address.is_none() && !is_valid_host(&self.server.url) {
+            errors.push(ConfigError::new(
+                "server.url",
+                format!(
+                    "{:?} is not a valid hostname or IP address",
+                    self.server.url
+                ),
+            ));
+        }
+        if self.server.port == 0 {
+            errors.push(ConfigError::new("server.port", "must not be zero"));
+        }
+        if self.server.rate_limit.packets_per_sec == 0 {
+            errors.push(ConfigError::new(
+                "server.rate_limit.packets_per_sec",
+                "must not be zero (no packets would be accepted)",
+            ));
+        }
+        if self.server.max_peers == 0 {
+            errors.push(ConfigError::new(
+                "server.max_peers",
+                "must not be zero (no packets would be accepted)",
+            ));
+        }
+        if let Some(keepalive) = self.server.keepalive {
+            if keepalive.interval_secs == 0 {
+                errors.push(ConfigError::new(
+                    "server.keepalive.interval_secs",
+                    "must not be zero",
+                ));
+            }
+            if keepalive.max_missed == 0 {
+                errors.push(ConfigError::new(
+                    "server.keepalive.max_missed",
+                    "must not be zero",
+                ));
+            }
+        }
+        if self.database.storage == StorageMode::file {
+            match self.database.files.as_slice() {
+                [] => errors.push(ConfigError::new(
+                    "database.files",
+                    "storage mode 'file' was selected, but no files were given",
+                )),
+                [file] if file.path.exists() && !file.path.is_file() => errors.push(ConfigError::new(
+                    "database.files[0].path",
+                    format!("{:?} is not a file", file.path),
+                )),
+                // (a missing file is created)
+                [file]
+                    if !file.path.exists()
+                        && !file
+                            .path
+                            .parent()
+                            .is_some_and(|dir| dir.as_os_str().is_empty() || dir.is_dir()) =>
+                {
+                    errors.push(ConfigError::new(
+                        "database.files[0].path",
+                        format!(
+                            "{:?} does not exist, and can not be created (its directory does not exist)",
+                            file.path
+                        ),
+                    ))
+                }
+                [_] => {}
+                [..] => errors.push(ConfigError::new(
+                    "database.files",
+                    "storage mode 'file' was selected, but multiple files were given. TSDB v3 does not yet support this",
+                )),
+            }
+        }
+        if self.database.initial_size_bytes < tsdb3::MIN_FREE_SPACE {
+            errors.push(ConfigError::new(
+                "database.initial_size_bytes",
+                format!("must be at least {}", tsdb3::MIN_FREE_SPACE),
+            ));
+        }
+        if self.database.autosave_interval_secs == 0 {
+            errors.push(ConfigError::new(
+                "database.autosave_interval_secs",
+                "must not be zero",
+            ));
+        }
+        if let Some(retention) = &self.retention {
+            if retention.raw_days == 0 {
+                errors.push(ConfigError::new("retention.raw_days", "must not be zero"));
+            }
+            if retention.rollup_bucket_secs == Some(0) {
+                errors.push(ConfigError::new(
+                    "retention.rollup_bucket_secs",
+                    "must not be zero",
+                ));
+            }
+            match retention.rollup_days {
+                Some(_) if retention.rollup_bucket_secs.is_none() => errors.push(ConfigError::new(
+                    "retention.rollup_days",
+                    "was set, but `rollup_bucket_secs` was not (nothing is rolled up)",
+                )),
+                Some(days) if days < retention.raw_days => errors.push(ConfigError::new(
+                    "retention.rollup_days",
+                    "must be at least `raw_days`",
+                )),
+                _ => {}
+            }
+            if retention.interval_secs == 0 {
+                errors.push(ConfigError::new(
+                    "retention.interval_secs",
+                    "must not be zero",
+                ));
+            }
+        }
+        if self.ipc.token.as_ref().is_some_and(|t| t.is_empty()) {
+            errors.push(ConfigError::new("ipc.token", "must not be empty"));
+        }
+        check_limit(
+            &mut errors,
+            "registry.max_stations",
+            self.registry.max_stations,
+            tsdb3::MAX_STATIONS,
+        );
+        check_limit(
+            &mut errors,
+            "registry.max_channels_per_station",
+            self.registry.max_channels_per_station,
+            tsdb3::MAX_CHANNELS_PER_STATION,
+        );
+        if self.registry.admission == AdmissionMode::allowlist
+            && self.registry.allowed_stations.is_empty()
+            && self.registry.provisioning_tokens.is_empty()
+        {
+            errors.push(ConfigError::new(
+                "registry.admission",
+                "allowlist mode was selected, but no stations or provisioning tokens were given (no station could connect)",
+            ));
+        }
+        if self
+            .registry
+            .provisioning_tokens
+            .iter()
+            .any(|t| t.is_empty())
+        {
+            errors.push(ConfigError::new(
+                "registry.provisioning_tokens",
+                "must not be empty",
+            ));
+        }
+        for alarm in &self.alarms {
+            if alarm.above.is_some() == alarm.below.is_some() {
+                errors.push(ConfigError::new(
+                    "alarms",
+                    format!(
+                        "{:?} must set exactly one of `above` or `below`",
+                        alarm.name()
+                    ),
+                ));
+            } else if !alarm.above.or(alarm.below).is_some_and(f32::is_finite) {
+                errors.push(ConfigError::new(
+                    "alarms",
+                    format!("{:?} has a threshold that is not a number", alarm.name()),
+                ));
+            }
+            if !alarm.hysteresis.is_finite() {
+                errors.push(ConfigError::new(
+                    "alarms",
+                    format!("{:?} has a `hysteresis` that is not a number", alarm.name()),
+                ));
+            } else if alarm.hysteresis < 0.0 {
+                errors.push(ConfigError::new(
+                    "alarms",
+                    format!("{:?} has a negative `hysteresis`", alarm.name()),
+                ));
+            }
+        }
+        let mut names = std::collections::HashSet::new();
+        for computed in &self.computed {
+            if computed.name.is_empty() {
+                errors.push(ConfigError::new("computed", "a channel has an empty name"));
+            } else if !names.insert(&computed.name) {
+                errors.push(ConfigError::new(
+                    "computed",
+                    format!("{:?} is defined more than once", computed.name),
+                ));
+            }
+            if computed.inputs.is_empty() {
+                errors.push(ConfigError::new(
+                    "computed",
+                    format!("{:?} has no inputs", computed.name),
+                ));
+            } else if let Some(idx) = computed
+                .formula
+                .max_input()
+                .filter(|idx| *idx >= computed.inputs.len())
+            {
+                errors.push(ConfigError::new(
+                    "computed",
+                    format!(
+                        "{:?} uses input {idx}, but only has {} inputs",
+                        computed.name,
+                        computed.inputs.len()
+                    ),
+                ));
+            }
+        }
+        if self.bus.comm_queue_cap == 0 {
+            errors.push(ConfigError::new("bus.comm_queue_cap", "must not be zero"));
+        }
+        if self.bus.handler_queue_cap == 0 {
+            errors.push(ConfigError::new(
+                "bus.handler_queue_cap",
+                "must not be zero",
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// `path` must be a directory, or be able to be created as one (by `RecordsPath::ensure_exists_blocking`)
+fn check_dir(errors: &mut Vec<ConfigError>, field: &'static str, path: &Path) {
+    if path.exists() {
+        if !path.is_dir() {
+            errors.push(ConfigError::new(
+                field,
+                format!("{path:?} exists, and is not a directory"),
+            ));
+        }
+    } else {
+        let parent = match path.parent() {
+            Some(p) if p != Path::new("") => p,
+            _ => Path::new("."),
+        };
+        if !parent.is_dir() {
+            errors.push(ConfigError::new(
+                field,
+                format!(
+                    "{path:?} does not exist, and can not be created ({parent:?} does not exist)"
+                ),
+            ));
+        }
+    }
+}
+
+/// `limit` must be at least one, and no more than the database can hold (`max`)
+fn check_limit(errors: &mut Vec<ConfigError>, field: &'static str, limit: usize, max: usize) {
+    if limit == 0 {
+        errors.push(ConfigError::new(field, "must not be zero"));
+    } else if limit > max {
+        errors.push(ConfigError::new(
+            field,
+            format!("must be at most {max} (the most the database can hold)"),
+        ));
+    }
+}
+
+/// if `host` is an IP address, or a syntactically valid hostname
+fn is_valid_host(host: &str) -> bool {
+    if host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct Directories {
     /// the directory to store persistant data (e.g. the
@@ -56,6 +551,247 @@ pub struct Server {
     pub url: String,
     /// the port to run the server
     pub port: u16,
+    /// listen on this address, instead of the addresses `url` resolves to
+    /// (e.g. when the server is behind NAT, or on a multi-homed host)
+    #[serde(default)]
+    pub bind_address: Option<IpAddr>,
+    /// only send and receive through this network interface (linux only, may require `CAP_NET_RAW`)
+    #[serde(default)]
+    pub bind_interface: Option<String>,
+    /// limits on how fast a single address may send packets
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    /// most addresses to keep track of at once (the least recently seen is forgotten to make room for a new one)
+    #[serde(default = "default_max_peers")]
+    pub max_peers: usize,
+    /// periodically ping stations, and forget those that stop responding.
+    /// disabled by default (stations that sleep between readings can not answer)
+    #[serde(default)]
+    pub keepalive: Option<Keepalive>,
+}
+
+fn default_max_peers() -> usize {
+    1024
+}
+
+impl Server {
+    /// the configured address to listen on, if any (otherwise, `url` is looked up)
+    pub fn bind_addr(&self) -> Option<SocketAddr> {
+        self.bind_address
+            .map(|addr| SocketAddr::new(addr, self.port))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct Keepalive {
+    /// seconds between pings
+    pub interval_secs: u64,
+    /// number of pings in a row a station may miss before it is evicted
+    pub max_missed: u32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct RateLimit {
+    /// sustained packets per second allowed from a single address
+    pub packets_per_sec: u32,
+    /// number of packets that may be sent at once, above the sustained rate
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            packets_per_sec: 50,
+            burst: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct Bus {
+    /// size of the queue shared by all handlers
+    pub comm_queue_cap: usize,
+    /// size of each handler's own queue
+    pub handler_queue_cap: usize,
+    /// what to do with handlers that fall behind (when the shared queue fills up)
+    pub slow_handler_policy: SlowHandlerPolicy,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        let defaults = roundtable::BusConfig::default();
+        Self {
+            comm_queue_cap: defaults.comm_queue_cap,
+            handler_queue_cap: defaults.handler_queue_cap,
+            slow_handler_policy: SlowHandlerPolicy::drop,
+        }
+    }
+}
+
+impl From<Bus> for roundtable::BusConfig {
+    fn from(bus: Bus) -> Self {
+        Self {
+            comm_queue_cap: bus.comm_queue_cap,
+            handler_queue_cap: bus.handler_queue_cap,
+            slow_handler_policy: match bus.slow_handler_policy {
+                SlowHandlerPolicy::drop => roundtable::SlowHandlerPolicy::Drop,
+                SlowHandlerPolicy::block => roundtable::SlowHandlerPolicy::Block,
+                SlowHandlerPolicy::disconnect => roundtable::SlowHandlerPolicy::Disconnect,
+            },
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum SlowHandlerPolicy {
+    /// the handler misses messages
+    drop,
+    /// senders wait for the handler to catch up
+    block,
+    /// the handler is shut down
+    disconnect,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Ipc {
+    /// if the IPC socket is created (nothing can query the server if it is not)
+    #[serde(default = "default_ipc_enabled")]
+    pub enabled: bool,
+    /// if set, clients must send this token (as their first message) before anything is sent to them
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+fn default_ipc_enabled() -> bool {
+    true
+}
+
+impl Default for Ipc {
+    fn default() -> Self {
+        Self {
+            enabled: default_ipc_enabled(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct Log {
+    /// format of log messages (on stdout, and in the log files)
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum LogFormat {
+    /// multi-line, for reading in a terminal
+    #[default]
+    pretty,
+    /// one JSON object per line, for log aggregation
+    json,
+    /// one line per message
+    compact,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Registry {
+    /// the most stations that may register (further new stations are refused)
+    pub max_stations: usize,
+    /// the most channels a station may have, including computed channels
+    pub max_channels_per_station: usize,
+    /// which stations may connect
+    #[serde(default)]
+    pub admission: AdmissionMode,
+    /// stations that may connect in `allowlist` mode
+    #[serde(default)]
+    pub allowed_stations: Vec<StationID>,
+    /// one-time tokens a station may present to be accepted in `allowlist` mode (each may be claimed by one station)
+    #[serde(default)]
+    pub provisioning_tokens: Vec<String>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            max_stations: tsdb3::MAX_STATIONS,
+            max_channels_per_station: tsdb3::MAX_CHANNELS_PER_STATION,
+            admission: AdmissionMode::default(),
+            allowed_stations: vec![],
+            provisioning_tokens: vec![],
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum AdmissionMode {
+    /// any station may connect
+    #[default]
+    open,
+    /// only stations in `allowed_stations`, or that claim one of `provisioning_tokens`
+    allowlist,
+}
+
+impl From<Registry> for crate::registry::Limits {
+    fn from(registry: Registry) -> Self {
+        Self {
+            max_stations: registry.max_stations,
+            max_channels_per_station: registry.max_channels_per_station,
+        }
+    }
+}
+
+/// Notify IPC clients when readings of a channel cross a threshold (and when they return)
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Alarm {
+    /// sent to clients with the alarm (defaults to a description of the rule, see [`Alarm::name`])
+    #[serde(default)]
+    pub name: Option<String>,
+    /// name of the channel to watch
+    pub channel: String,
+    /// only watch this station's readings (by default, any station with the channel)
+    #[serde(default)]
+    pub station: Option<StationID>,
+    /// raised when a reading is above this
+    #[serde(default)]
+    pub above: Option<f32>,
+    /// raised when a reading is below this (exactly one of `above` or `below` must be set)
+    #[serde(default)]
+    pub below: Option<f32>,
+    /// how far a reading must return past the threshold to clear the alarm, so that readings close to the threshold do
+    /// not raise it over and over
+    #[serde(default)]
+    pub hysteresis: f32,
+}
+
+impl Alarm {
+    /// the configured name, or a description of the rule (e.g. `battery below 3.3`)
+    pub fn name(&self) -> String {
+        match (&self.name, self.above, self.below) {
+            (Some(name), ..) => name.clone(),
+            (None, Some(above), _) => format!("{} above {above}", self.channel),
+            (None, None, Some(below)) => format!("{} below {below}", self.channel),
+            (None, None, None) => self.channel.clone(),
+        }
+    }
+}
+
+/// A channel computed by the server from other channels (see `ChannelType::Computed`), whenever a station sends data
+/// for all of its inputs. it is registered on startup, and stations may not declare computed channels themselves
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ComputedChannel {
+    /// name of the channel
+    pub name: String,
+    /// names of the channels used as input to `formula` (`{ Input = n }` refers to `inputs[n]`)
+    pub inputs: Vec<String>,
+    pub formula: Formula,
+    /// unit of the values, for display
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -66,6 +802,59 @@ pub struct Database {
     /// not necessary to provide if `StorageMode::DefaultFile` is selected
     #[serde(default)]
     pub files: Vec<File>,
+    /// size of a newly created database file, and how much it grows by when it fills up
+    #[serde(default = "default_initial_size")]
+    pub initial_size_bytes: u64,
+    /// seconds between saves of the database and registry
+    #[serde(default = "default_autosave_interval")]
+    pub autosave_interval_secs: u64,
+    /// compress data chunks once they fill up (existing chunks are read either way)
+    #[serde(default)]
+    pub compress: bool,
+}
+
+fn default_autosave_interval() -> u64 {
+    30
+}
+
+fn default_initial_size() -> u64 {
+    16_000_000
+}
+
+/// What old data to free, applied to every channel (see [`crate::core::RetentionDispatch`])
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// days to keep readings and events as they were recorded
+    pub raw_days: u32,
+    /// older readings are rolled up into the mean of each bucket this long (instead of being deleted)
+    #[serde(default)]
+    pub rollup_bucket_secs: Option<u32>,
+    /// days to keep the rollups (by default, forever)
+    #[serde(default)]
+    pub rollup_days: Option<u32>,
+    /// seconds between applying the policy
+    #[serde(default = "default_retention_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_retention_interval() -> u64 {
+    60 * 60
+}
+
+impl RetentionPolicy {
+    pub fn raw_age(&self) -> chrono::Duration {
+        chrono::Duration::days(self.raw_days.into())
+    }
+
+    pub fn rollup_bucket(&self) -> Option<chrono::Duration> {
+        self.rollup_bucket_secs
+            .map(|secs| chrono::Duration::seconds(secs.into()))
+    }
+
+    pub fn rollup_age(&self) -> Option<chrono::Duration> {
+        self.rollup_days
+            .map(|days| chrono::Duration::days(days.into()))
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -0,0 +1,175 @@
+//! Liveness probe, for running the server under a supervisor (exposed over IPC)
+
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use mycelium::HealthStatus;
+use roundtable::{
+    handler::{HandlerInit, LocalInterface, MethodRegister},
+    handler_decl_t, method_decl,
+    msg::{self, Str},
+};
+use squirrel::clock::{Clock, SystemClock};
+
+use crate::dispatch::EV_CONTROLLER_HEARTBEAT;
+
+/// how long components have to answer `EV_HEALTH_ALIVE`
+const ALIVE_DEADLINE: Duration = Duration::from_millis(500);
+
+/// parts of the server that answer [`EV_HEALTH_ALIVE`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Database,
+    Ipc,
+}
+
+// the server's current status
+method_decl!(EV_HEALTH_PING, (), HealthStatus);
+// answered by each component that is running
+method_decl!(EV_HEALTH_ALIVE, (), Component);
+
+pub struct HealthCheck {
+    /// newest heartbeat from a controller
+    last_data: Option<DateTime<Utc>>,
+    /// when this started (data is not stale until `stale_after` after this, if none has been received)
+    started: DateTime<Utc>,
+    stale_after: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl HealthCheck {
+    /// data is reported as stale if nothing is received from a weather station for `stale_after`
+    pub fn new(stale_after: Duration) -> Self {
+        Self {
+            last_data: None,
+            started: SystemClock.now_utc(),
+            stale_after,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// the time source used to decide if data is stale. defaults to [`SystemClock`]
+    #[cfg(test)]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            started: clock.now_utc(),
+            clock,
+            ..self
+        }
+    }
+
+    fn status(&self, alive: &[Component], live_handlers: u64) -> HealthStatus {
+        let since = self.last_data.unwrap_or(self.started);
+        let data_stale = self
+            .clock
+            .now_utc()
+            .signed_duration_since(since)
+            .to_std()
+            .is_ok_and(|age| age > self.stale_after);
+        HealthStatus {
+            db_open: alive.contains(&Component::Database),
+            ipc_listening: alive.contains(&Component::Ipc),
+            last_data_received: self.last_data,
+            data_stale,
+            live_handlers,
+        }
+    }
+
+    async fn ping(&mut self, _: &(), int: &LocalInterface) -> Result<HealthStatus, Infallible> {
+        let alive = match int
+            .dispatch_collect(msg::Target::Any, EV_HEALTH_ALIVE, (), ALIVE_DEADLINE)
+            .await
+        {
+            Ok(alive) => alive,
+            Err(e) => {
+                warn!("Failed to check which components are alive: {e:#}");
+                vec![]
+            }
+        };
+        Ok(self.status(&alive, int.nonlocal.metrics().live_handlers()))
+    }
+
+    async fn heartbeat(
+        &mut self,
+        time: &DateTime<Utc>,
+        _int: &LocalInterface,
+    ) -> Result<(), Infallible> {
+        self.last_data = self.last_data.max(Some(*time));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl HandlerInit for HealthCheck {
+    const DECL: msg::HandlerType = handler_decl_t!("Health check");
+    type Error = Infallible;
+    fn describe(&self) -> Str {
+        Str::Borrowed("Health check")
+    }
+    fn methods(&self, reg: &mut MethodRegister<Self>) {
+        reg.register(Self::ping, EV_HEALTH_PING);
+        reg.register(Self::heartbeat, EV_CONTROLLER_HEARTBEAT);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use roundtable::{common::HDL_EXTERNAL, Bus};
+    use squirrel::clock::MockClock;
+
+    use super::*;
+    use crate::tsdb3::{bus::TStopDBus3, DB};
+
+    #[tokio::test]
+    async fn reflects_received_data() {
+        let bus = Bus::new(Default::default()).await;
+        let clock = MockClock::new();
+        let health = bus
+            .spawn_checked(
+                HealthCheck::new(Duration::from_secs(600)).with_clock(Arc::new(clock.clone())),
+            )
+            .await
+            .unwrap();
+        let mut db = DB::new_in_ram(4096).unwrap();
+        db.init();
+        bus.spawn_checked(TStopDBus3::new(db)).await.unwrap();
+        let ping = || bus.query_as(HDL_EXTERNAL, health.clone(), EV_HEALTH_PING, ());
+
+        // nothing received yet, but the server just started
+        let status = ping().await.unwrap();
+        assert!(status.db_open);
+        assert!(!status.ipc_listening);
+        assert_eq!(status.last_data_received, None);
+        assert!(!status.data_stale);
+        assert_eq!(status.live_handlers, 2);
+
+        // a reading was just received
+        clock.advance(Duration::from_secs(3600));
+        let received = clock.now_utc();
+        bus.announce_as(
+            HDL_EXTERNAL,
+            msg::Target::Any,
+            EV_CONTROLLER_HEARTBEAT,
+            received,
+        )
+        .await
+        .unwrap();
+        let status = ping().await.unwrap();
+        assert_eq!(status.last_data_received, Some(received));
+        assert!(!status.data_stale);
+
+        // .. a long time ago
+        clock.advance(Duration::from_secs(601));
+        let status = ping().await.unwrap();
+        assert_eq!(status.last_data_received, Some(received));
+        assert!(status.data_stale);
+        assert!(!status.is_ready());
+        // (stale data does not mean the server itself is unhealthy)
+        let status = HealthStatus {
+            ipc_listening: true,
+            ..status
+        };
+        assert!(status.is_healthy());
+        assert!(!status.is_ready());
+    }
+}
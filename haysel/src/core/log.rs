@@ -2,8 +2,17 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use tracing::metadata::LevelFilter;
+use tracing::Subscriber;
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_subscriber::{fmt::Layer, prelude::*, registry, EnvFilter};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    prelude::*,
+    registry,
+    registry::LookupSpan,
+    EnvFilter, Layer,
+};
+
+use super::config::LogFormat;
 
 #[must_use]
 #[allow(unused)]
@@ -15,7 +24,7 @@ pub struct Guard {
 pub fn init_logging_no_file() -> Result<Guard> {
     println!("initializing stdout logging");
     let (stdout, guard1) = tracing_appender::non_blocking(std::io::stdout());
-    let stdout_layer = Layer::new().with_writer(stdout).pretty();
+    let stdout_layer = fmt::Layer::new().with_writer(stdout).pretty();
     let global_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::TRACE.into())
         .from_env()
@@ -26,13 +35,20 @@ pub fn init_logging_no_file() -> Result<Guard> {
         inner1: None,
     })
 }
-pub fn init_logging_with_file(log_dir: PathBuf) -> Result<Guard> {
+pub fn init_logging_with_file(log_dir: PathBuf, format: LogFormat) -> Result<Guard> {
     println!("initializing stdout+file logging");
     let appender = tracing_appender::rolling::hourly(log_dir, "haysel.log");
     let (logfile, guard0) = tracing_appender::non_blocking(appender);
-    let logfile_layer = Layer::new().with_writer(logfile).compact();
+    // (log files are not read in a terminal)
+    let logfile_layer = fmt_layer(
+        logfile,
+        match format {
+            LogFormat::pretty => LogFormat::compact,
+            other => other,
+        },
+    );
     let (stdout, guard1) = tracing_appender::non_blocking(std::io::stdout());
-    let stdout_layer = Layer::new().with_writer(stdout).pretty();
+    let stdout_layer = fmt_layer(stdout, format);
     let global_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::TRACE.into())
         .from_env()
@@ -47,3 +63,55 @@ pub fn init_logging_with_file(log_dir: PathBuf) -> Result<Guard> {
         inner1: Some(guard1),
     })
 }
+
+/// a layer writing to `writer` in `format`
+fn fmt_layer<S, W>(writer: W, format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::Layer::new().with_writer(writer);
+    match format {
+        LogFormat::pretty => layer.pretty().boxed(),
+        LogFormat::json => layer.json().boxed(),
+        LogFormat::compact => layer.compact().boxed(),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn json_lines() {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+    impl io::Write for Buf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buf = Buf::default();
+    let writer = buf.clone();
+    let subscriber = registry().with(fmt_layer(move || writer.clone(), LogFormat::json));
+    tracing::subscriber::with_default(subscriber, || {
+        info!(station = 7, "first message");
+        warn!("second\nmessage");
+    });
+    let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    let lines = out
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["level"], "INFO");
+    assert_eq!(lines[0]["fields"]["message"], "first message");
+    assert_eq!(lines[0]["fields"]["station"], 7);
+    assert_eq!(lines[1]["fields"]["message"], "second\nmessage");
+}
@@ -0,0 +1,310 @@
+//! periodically frees the space used by old data, as set by the `[retention]` config (see [`RetentionPolicy`])
+
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use roundtable::{
+    handler::{DispatchErr, HandlerInit, LocalInterface, MethodDecl, MethodRegister},
+    handler_decl_t, method_decl, method_decl_owned,
+    msg::{HandlerInstance, Str},
+};
+use squirrel::clock::{Clock, SystemClock};
+use tokio::time::Interval;
+
+use crate::{
+    core::config::RetentionPolicy,
+    tsdb3::{
+        bus::{DeleteBefore, RollUp, EV_DB_CHANNELS, EV_DB_DELETE_BEFORE, EV_DB_ROLL_UP},
+        Reclaimed, Tier,
+    },
+};
+
+/// most chunks freed by a single request to the database, so that it is never busy with this for long
+const CHUNKS_PER_STEP: usize = 16;
+
+pub struct RetentionDispatch {
+    policy: RetentionPolicy,
+    database: HandlerInstance,
+    clock: Arc<dyn Clock>,
+}
+
+impl RetentionDispatch {
+    pub fn new(policy: RetentionPolicy, database: HandlerInstance) -> Self {
+        Self {
+            policy,
+            database,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// the time source used to decide what is old. defaults to [`SystemClock`]
+    #[cfg(test)]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// applies the policy to every channel, returning how much was freed
+    async fn apply(&self, int: &LocalInterface) -> Result<Reclaimed, DispatchErr> {
+        let now = self.clock.now_utc();
+        let raw_before = now - self.policy.raw_age();
+        let mut total = Reclaimed::default();
+        for (station, channel) in int.query(self.database.clone(), EV_DB_CHANNELS, ()).await? {
+            if let Some(bucket) = self.policy.rollup_bucket() {
+                total += self
+                    .until_done(int, EV_DB_ROLL_UP, |max_chunks| RollUp {
+                        station,
+                        channel,
+                        older_than: raw_before,
+                        bucket,
+                        max_chunks,
+                    })
+                    .await?;
+                if let Some(age) = self.policy.rollup_age() {
+                    total += self
+                        .until_done(int, EV_DB_DELETE_BEFORE, |max_chunks| DeleteBefore {
+                            station,
+                            channel,
+                            tier: Tier::Rollup(bucket),
+                            before: now - age,
+                            max_chunks,
+                        })
+                        .await?;
+                }
+            }
+            // (events are never rolled up, and readings are deleted if they are not)
+            total += self
+                .until_done(int, EV_DB_DELETE_BEFORE, |max_chunks| DeleteBefore {
+                    station,
+                    channel,
+                    tier: Tier::Raw,
+                    before: raw_before,
+                    max_chunks,
+                })
+                .await?;
+        }
+        Ok(total)
+    }
+
+    /// repeats `method` (a few chunks at a time) until there is nothing more to free
+    async fn until_done<A: Sync + Send + 'static>(
+        &self,
+        int: &LocalInterface,
+        method: MethodDecl<false, A, Reclaimed>,
+        args: impl Fn(usize) -> A,
+    ) -> Result<Reclaimed, DispatchErr> {
+        let mut total = Reclaimed::default();
+        loop {
+            total += int
+                .query(self.database.clone(), method, args(CHUNKS_PER_STEP))
+                .await?;
+            if !total.more {
+                return Ok(total);
+            }
+            // (so that anything else waiting on the database goes first)
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[instrument(skip(self, interval, int))]
+    async fn timer_complete(
+        &mut self,
+        mut interval: Interval,
+        int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        let _ = self.apply_now(&(), int).await;
+        int.bg_spawn(EV_PRIV_TIMER_COMPLETED, async move {
+            interval.tick().await;
+            interval
+        });
+        Ok(())
+    }
+
+    /// applies the policy now, returning how much was freed
+    #[instrument(skip(self, int))]
+    async fn apply_now(
+        &mut self,
+        _: &(),
+        int: &LocalInterface,
+    ) -> Result<Reclaimed, <Self as HandlerInit>::Error> {
+        match self.apply(int).await {
+            Ok(reclaimed) => {
+                if reclaimed.chunks > 0 {
+                    info!(
+                        "Retention policy freed {} chunks ({}B)",
+                        reclaimed.chunks, reclaimed.bytes
+                    );
+                }
+                Ok(reclaimed)
+            }
+            Err(e) => {
+                warn!("Failed to apply the retention policy: {e:#}");
+                Ok(Reclaimed::default())
+            }
+        }
+    }
+}
+
+method_decl_owned!(EV_PRIV_TIMER_COMPLETED, Interval, ());
+// apply the retention policy immediately, returns how much was freed
+method_decl!(EV_RETENTION_APPLY, (), Reclaimed);
+
+#[async_trait]
+impl HandlerInit for RetentionDispatch {
+    const DECL: roundtable::msg::HandlerType = handler_decl_t!("Retention policy dispatcher");
+    type Error = Infallible;
+    async fn init(&mut self, int: &LocalInterface) -> Result<(), Self::Error> {
+        // (the first tick is immediate, so the policy is applied on startup)
+        let mut interval = tokio::time::interval(Duration::from_secs(self.policy.interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        int.bg_spawn(EV_PRIV_TIMER_COMPLETED, async move {
+            interval.tick().await;
+            interval
+        });
+        Ok(())
+    }
+    fn describe(&self) -> Str {
+        Str::Owned(format!(
+            "Retention policy dispatch (every: {}s)",
+            self.policy.interval_secs
+        ))
+    }
+    fn methods(&self, reg: &mut MethodRegister<Self>) {
+        reg.register_owned(Self::timer_complete, EV_PRIV_TIMER_COMPLETED);
+        reg.register(Self::apply_now, EV_RETENTION_APPLY);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, DurationRound, Utc};
+    use roundtable::{common::HDL_EXTERNAL, Bus};
+    use squirrel::clock::MockClock;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::tsdb3::{
+        bus::{TStopDBus3, EV_DB_QUERY},
+        query::QueryBuilder,
+        DB,
+    };
+
+    const INTERVAL: chrono::Duration = chrono::Duration::minutes(5);
+
+    /// a reading every 5 minutes for the 60 days before `now` (each the number of readings before it), and the
+    /// time of the first
+    fn dataset(now: DateTime<Utc>) -> (DB, Uuid, Uuid, DateTime<Utc>) {
+        let mut db = DB::new_in_ram(1_000_000).unwrap();
+        db.init();
+        let (sid, cid) = (Uuid::new_v4(), Uuid::new_v4());
+        db.insert_station(sid).unwrap();
+        db.insert_channels(sid, [cid]).unwrap();
+        let start = now - chrono::Duration::days(60);
+        for i in 0..60 * 288 {
+            db.insert_data(sid, cid, start + INTERVAL * i, i as f32)
+                .unwrap();
+        }
+        (db, sid, cid, start)
+    }
+
+    /// applies `policy` to [`dataset`], returning what is left: (readings, 10 minute rollups), and the start
+    async fn apply(
+        policy: RetentionPolicy,
+    ) -> (
+        Vec<(DateTime<Utc>, f32)>,
+        Vec<(DateTime<Utc>, f32)>,
+        DateTime<Utc>,
+    ) {
+        let clock = MockClock::new();
+        // (so that each 10 minute bucket has two readings)
+        let now = clock
+            .now_utc()
+            .duration_trunc(chrono::Duration::minutes(10))
+            .unwrap();
+        let (db, sid, cid, start) = dataset(now);
+        let bus = Bus::new(Default::default()).await;
+        let database = bus.spawn_checked(TStopDBus3::new(db)).await.unwrap();
+        let retention = bus
+            .spawn_checked(
+                RetentionDispatch::new(policy, database.clone()).with_clock(Arc::new(clock)),
+            )
+            .await
+            .unwrap();
+        // (handled after the pass on startup)
+        bus.query_as(HDL_EXTERNAL, retention, EV_RETENTION_APPLY, ())
+            .await
+            .unwrap();
+        let query = |tier| {
+            bus.query_as(
+                HDL_EXTERNAL,
+                database.clone(),
+                EV_DB_QUERY,
+                QueryBuilder::new()
+                    .with_station(sid)
+                    .with_channel(cid)
+                    .with_tier(tier)
+                    .with_after(start - chrono::Duration::seconds(1))
+                    .verify()
+                    .unwrap(),
+            )
+        };
+        let raw = query(Tier::Raw).await.unwrap();
+        let rollups = query(Tier::Rollup(chrono::Duration::minutes(10)))
+            .await
+            .unwrap();
+        (raw, rollups, start)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rolls_up_then_drops() {
+        let policy = RetentionPolicy {
+            raw_days: 30,
+            rollup_bucket_secs: Some(600),
+            rollup_days: Some(45),
+            interval_secs: 3600,
+        };
+        let (raw, rollups, start) = apply(policy).await;
+        let raw_before = start + chrono::Duration::days(30);
+        // (whole chunks of 512 are freed)
+        let chunk = INTERVAL * 512;
+        // every reading that is not old is kept, and most of the old ones are gone
+        let expected = (0..60 * 288)
+            .map(|i| (start + INTERVAL * i, i as f32))
+            .filter(|&(t, _)| t + chunk > raw_before)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            raw[raw.len() - 30 * 288..],
+            expected[expected.len() - 30 * 288..]
+        );
+        assert!(raw.len() < expected.len());
+        assert!(raw[0].0 + chunk > raw_before);
+
+        // the readings that were removed are rolled up, and the old rollups dropped
+        let rollups_before = start + chrono::Duration::days(15);
+        assert!(!rollups.is_empty());
+        assert!(rollups[0].0 + chunk * 2 > rollups_before);
+        assert!(rollups.last().unwrap().0 < raw[0].0);
+        for (i, &(time, mean)) in rollups.iter().enumerate() {
+            let first = ((time - start).num_minutes() / 5) as f32;
+            assert_eq!(mean, first + 0.5);
+            if i > 0 {
+                assert_eq!(time - rollups[i - 1].0, chrono::Duration::minutes(10));
+            }
+        }
+        assert_eq!(rollups.last().unwrap().0 + INTERVAL * 2, raw[0].0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drops_without_rollup() {
+        let policy = RetentionPolicy {
+            raw_days: 30,
+            rollup_bucket_secs: None,
+            rollup_days: None,
+            interval_secs: 3600,
+        };
+        let (raw, rollups, start) = apply(policy).await;
+        let raw_before = start + chrono::Duration::days(30);
+        assert!(raw[0].0 + INTERVAL * 512 > raw_before);
+        assert!(raw.len() > 30 * 288);
+        assert_eq!(raw.last().unwrap().1, (60 * 288 - 1) as f32);
+        assert!(rollups.is_empty());
+    }
+}
@@ -78,11 +78,19 @@ pub fn stage1_daemon(mut args: RunArgs) -> Result<()> {
         let buf = std::fs::read_to_string(&args.config)?;
         core::config::from_str(&buf)?
     };
+    if let Err(errors) = cfg.validate() {
+        eprintln!("ERROR: Invalid configuration ({} problems):", errors.len());
+        for error in &errors {
+            eprintln!("     | {error}");
+        }
+        bail!("Invalid config");
+    }
 
     let records_dir = misc::RecordsPath::new(cfg.directory.data.clone());
     records_dir.ensure_exists_blocking()?;
     let run_dir = misc::RecordsPath::new(cfg.directory.run.clone());
-    run_dir.ensure_exists_blocking()?;
+    // holds the IPC socket, which gives access to station data
+    run_dir.ensure_private_blocking()?;
     let log_dir = misc::RecordsPath::new(run_dir.path("log"));
     log_dir.ensure_exists_blocking()?;
 
@@ -101,7 +109,7 @@ pub fn stage1_daemon(mut args: RunArgs) -> Result<()> {
     }
 
     println!("Init logging");
-    let guard = core::init_logging_with_file(run_dir.path("log"))?;
+    let guard = core::init_logging_with_file(run_dir.path("log"), cfg.log.format)?;
     if args.no_safeguards {
         warn!("Running in no-safeguard testing mode: this is NOT what you want for production use");
         warn!("--overwrite-reinit is implied by --no-safeguards: if this leads to loss of data, please consider the name of the argument and that you may have wanted to RTFM first");
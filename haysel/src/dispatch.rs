@@ -1,11 +1,29 @@
 //! Communication with clients (weather stations)
 
-use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use squirrel::transport::{server::recv_next_packet, Packet};
-use tokio::{io, net::UdpSocket};
+use chrono::{DateTime, Utc};
+use mycelium::station::identity::StationID;
+use squirrel::{
+    clock::{Clock, SystemClock},
+    transport::{server::recv_next_packet, Packet},
+};
+use tokio::{
+    io,
+    net::UdpSocket,
+    time::{interval_at, Interval},
+};
 
 pub mod application;
+mod dedup;
+mod peers;
+mod ratelimit;
+pub mod replay;
 pub mod transport;
 
 use roundtable::{
@@ -16,35 +34,79 @@ use roundtable::{
 
 pub use transport::{
     TransportClient, EV_TRANS_CLI_DATA_RECVD, EV_TRANS_CLI_QUEUE_DATA, EV_TRANS_CLI_REQ_SEND_PKT,
+    EV_TRANS_CLI_RESET,
 };
 
+use crate::{
+    core::config::{Keepalive, RateLimit},
+    metrics::METRICS,
+};
 use application::AppClient;
-use transport::EV_TRANS_CLI_IDENT_APP;
+use dedup::SeqTracker;
+use peers::PeerTable;
+use transport::{
+    EV_TRANS_CLI_EVICT, EV_TRANS_CLI_IDENT_APP, EV_TRANS_CLI_MISSED_PINGS, EV_TRANS_CLI_PING,
+    EV_TRANS_CLI_STATE,
+};
+
+/// how long a station may go without sending anything before its client handlers are shut down
+const PEER_IDLE_TTL: Duration = Duration::from_secs(10 * 60);
+/// shortest time between heartbeats (`EV_CONTROLLER_HEARTBEAT`)
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct Controller {
     sock: Arc<UdpSocket>,
-    active_clients: HashMap<SocketAddr, HandlerInstance>,
-    active_clients_inv: HashMap<HandlerInstance, SocketAddr>,
+    active_clients: PeerTable<HandlerInstance>,
+    // last time `active_clients` was checked for idle clients
+    last_evict: Instant,
+    // last time a heartbeat was announced
+    last_heartbeat: Option<Instant>,
     max_trans_t: Duration,
     registry: HandlerInstance,
+    // if data received from clients should be dropped instead of recorded
+    read_only: bool,
+    // last data sequence number received from each station
+    seqs: SeqTracker,
+    // if (and how often) to ping clients
+    keepalive: Option<Keepalive>,
+    // time source, shared with the client handlers
+    clock: Arc<dyn Clock>,
 }
 
 // sent by `Controller` to the relevant `TransportClient` when it receives a packet
 // (target determined using `active_clients`)
 method_decl!(EV_CONTROLLER_RECEIVED, Packet, ());
 
+// used by `AppClient` to check if data from a station is new, or a duplicate that should be dropped
+method_decl!(EV_CONTROLLER_CHECK_SEQ, (StationID, u64), bool);
+
+// used by `AppClient` when a station connects, as its data sequence numbers may have restarted
+method_decl!(EV_CONTROLLER_RESET_SEQ, StationID, ());
+
+// announced by `Controller` when it receives packets from weather stations (at most every `HEARTBEAT_INTERVAL`),
+// with the time they were received
+method_decl!(EV_CONTROLLER_HEARTBEAT, DateTime<Utc>, ());
+
 method_decl_owned!(
     EV_PRIV_CONTROLLER_RECEIVED,
     io::Result<Option<(SocketAddr, Packet)>>,
     ()
 );
 
+method_decl_owned!(EV_PRIV_CONTROLLER_PING_TIMER, Interval, ());
+
 #[async_trait]
 impl HandlerInit for Controller {
     const DECL: msg::HandlerType = handler_decl_t!("Weather station interface [controller]");
     type Error = Infallible;
     async fn init(&mut self, int: &LocalInterface) -> Result<(), Self::Error> {
         self.recv_next(int);
+        if let Some(keepalive) = self.keepalive {
+            let every = Duration::from_secs(keepalive.interval_secs);
+            let mut interval = interval_at(tokio::time::Instant::now() + every, every);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            Self::next_ping(interval, int);
+        }
         Ok(())
     }
     fn describe(&self) -> Str {
@@ -56,17 +118,44 @@ impl HandlerInit for Controller {
     fn methods(&self, reg: &mut MethodRegister<Self>) {
         reg.register_owned(Self::handle_receved, EV_PRIV_CONTROLLER_RECEIVED);
         reg.register(Self::send_packet, EV_TRANS_CLI_REQ_SEND_PKT);
+        reg.register(Self::client_state, EV_TRANS_CLI_STATE);
+        reg.register(Self::check_seq, EV_CONTROLLER_CHECK_SEQ);
+        reg.register(Self::reset_seq, EV_CONTROLLER_RESET_SEQ);
+        reg.register(Self::client_missed_pings, EV_TRANS_CLI_MISSED_PINGS);
+        reg.register_owned(Self::ping_clients, EV_PRIV_CONTROLLER_PING_TIMER);
     }
 }
 
 impl Controller {
-    pub fn new(sock: UdpSocket, max_trans_t: Duration, registry: HandlerInstance) -> Self {
+    pub fn new(
+        sock: UdpSocket,
+        max_trans_t: Duration,
+        registry: HandlerInstance,
+        read_only: bool,
+        rate_limit: RateLimit,
+        max_peers: usize,
+        keepalive: Option<Keepalive>,
+    ) -> Self {
         Self {
             sock: Arc::new(sock),
-            active_clients: HashMap::new(),
-            active_clients_inv: HashMap::new(),
+            active_clients: PeerTable::new(PEER_IDLE_TTL, max_trans_t, rate_limit, max_peers),
+            last_evict: Instant::now(),
+            last_heartbeat: None,
             max_trans_t,
             registry,
+            read_only,
+            seqs: SeqTracker::new(),
+            keepalive,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// the time source for idle eviction, rate limiting, and transaction timeouts. defaults to [`SystemClock`]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            last_evict: clock.now_instant(),
+            clock,
+            ..self
         }
     }
 
@@ -77,7 +166,7 @@ impl Controller {
             let pkt = recv_next_packet(&sock).await;
             trace!("controller: received [transport] packet");
             pkt
-        })
+        });
     }
 
     #[instrument(skip(self, pkt, int))]
@@ -86,7 +175,7 @@ impl Controller {
         pkt: &Packet,
         int: &LocalInterface,
     ) -> Result<(), <Self as HandlerInit>::Error> {
-        let Some(addr) = self.active_clients_inv.get(&int.event_source()) else {
+        let Some(addr) = self.active_clients.addr_of(&int.event_source()) else {
             error!("Controller::send_packet used by a handler that was not one of its clients - the event will be ignored");
             return Ok(());
         };
@@ -98,9 +187,122 @@ impl Controller {
         Ok(())
     }
 
-    fn insert_active_client(&mut self, addr: SocketAddr, instance: HandlerInstance) {
-        self.active_clients.insert(addr, instance.clone());
-        self.active_clients_inv.insert(instance, addr);
+    async fn client_state(
+        &mut self,
+        resting: &bool,
+        int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        self.active_clients
+            .set_resting(&int.event_source(), *resting);
+        Ok(())
+    }
+
+    async fn client_missed_pings(
+        &mut self,
+        missed: &u32,
+        int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        self.active_clients
+            .set_missed_pings(&int.event_source(), *missed);
+        Ok(())
+    }
+
+    fn next_ping(mut interval: Interval, int: &LocalInterface) {
+        int.bg_spawn(EV_PRIV_CONTROLLER_PING_TIMER, async move {
+            interval.tick().await;
+            interval
+        });
+    }
+
+    /// shuts down the handlers of clients that stopped answering pings, and pings the rest
+    async fn ping_clients(
+        &mut self,
+        interval: Interval,
+        int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        let max_missed = self.keepalive.map_or(u32::MAX, |k| k.max_missed);
+        for (addr, instance) in self.active_clients.evict_unresponsive(max_missed) {
+            debug!("Client at {addr:?} stopped answering pings, shutting down its interfaces");
+            // not verified, the client may be waiting on us
+            if let Err(e) = int
+                .announce(msg::Target::Instance(instance), EV_TRANS_CLI_EVICT, ())
+                .await
+            {
+                warn!("Failed to evict client for {addr:?}: {e:#}");
+            }
+        }
+        for instance in self.active_clients.instances() {
+            if let Err(e) = int
+                .announce(
+                    msg::Target::Instance(instance.clone()),
+                    EV_TRANS_CLI_PING,
+                    (),
+                )
+                .await
+            {
+                warn!("Failed to ping client: {e:#}");
+            }
+        }
+        Self::next_ping(interval, int);
+        Ok(())
+    }
+
+    async fn check_seq(
+        &mut self,
+        (station, seq): &(StationID, u64),
+        _int: &LocalInterface,
+    ) -> Result<bool, <Self as HandlerInit>::Error> {
+        Ok(self.seqs.accept(*station, *seq))
+    }
+
+    async fn reset_seq(
+        &mut self,
+        station: &StationID,
+        _int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        self.seqs.reset(*station);
+        Ok(())
+    }
+
+    /// shuts down the handlers of clients that have been idle for too long
+    async fn evict_idle(&mut self, now: Instant, int: &LocalInterface) {
+        // checking every packet would be wasteful, and clients are only evicted after a long time anyway
+        if now.saturating_duration_since(self.last_evict) < PEER_IDLE_TTL / 4 {
+            return;
+        }
+        self.last_evict = now;
+        for (addr, instance) in self.active_clients.evict_idle(now) {
+            debug!("Client interfaces for {addr:?} have been idle, shutting them down");
+            // not verified, the client may be waiting on us
+            if let Err(e) = int
+                .announce(msg::Target::Instance(instance), EV_TRANS_CLI_EVICT, ())
+                .await
+            {
+                warn!("Failed to evict client for {addr:?}: {e:#}");
+            }
+        }
+        trace!("{} active clients", self.active_clients.len());
+    }
+
+    /// tells anything that is listening (the health check) that packets are being received
+    async fn heartbeat(&mut self, now: Instant, int: &LocalInterface) {
+        if self
+            .last_heartbeat
+            .is_some_and(|last| now.saturating_duration_since(last) < HEARTBEAT_INTERVAL)
+        {
+            return;
+        }
+        self.last_heartbeat = Some(now);
+        if let Err(e) = int
+            .announce(
+                msg::Target::Any,
+                EV_CONTROLLER_HEARTBEAT,
+                self.clock.now_utc(),
+            )
+            .await
+        {
+            debug!("Failed to announce heartbeat: {e:#}");
+        }
     }
 
     #[instrument(skip(self, res, int))]
@@ -112,17 +314,32 @@ impl Controller {
         match res {
             Ok(Some((addr, pkt))) => {
                 trace!("Received packet {pkt:?} from {addr:?}");
-                let target = if self.active_clients.contains_key(&addr) {
-                    self.active_clients.get(&addr).unwrap().clone()
+                let now = self.clock.now_instant();
+                self.evict_idle(now, int).await;
+                let target = if let Some(target) = self.active_clients.get(&addr).cloned() {
+                    if !self.active_clients.admit(&addr, now) {
+                        debug!("{addr:?} is sending too fast, dropping packet");
+                        METRICS.packet_dropped();
+                        self.recv_next(int);
+                        return Ok(());
+                    }
+                    target
                 } else {
                     debug!("New client interfaces created for {addr:?}");
-                    let trans_cli = TransportClient::new(addr, self.max_trans_t, int.whoami());
+                    let trans_cli = TransportClient::new(
+                        addr,
+                        self.max_trans_t,
+                        int.whoami(),
+                        self.clock.clone(),
+                    );
                     let trans_cli_inst = int.nonlocal.spawn(trans_cli);
                     let appl_cli = AppClient::new(
                         addr,
                         int.whoami(),
                         trans_cli_inst.clone(),
                         self.registry.clone(),
+                        self.read_only,
+                        self.clock.clone(),
                     );
                     let appl_cli_inst = int.nonlocal.spawn(appl_cli);
                     int.dispatch(
@@ -132,9 +349,23 @@ impl Controller {
                     )
                     .await
                     .unwrap();
-                    self.insert_active_client(addr, trans_cli_inst.clone());
+                    if let Some((old_addr, old)) =
+                        self.active_clients
+                            .insert(addr, trans_cli_inst.clone(), now)
+                    {
+                        debug!("Too many clients, shutting down the interfaces for {old_addr:?}");
+                        // not verified, the client may be waiting on us
+                        if let Err(e) = int
+                            .announce(msg::Target::Instance(old), EV_TRANS_CLI_EVICT, ())
+                            .await
+                        {
+                            warn!("Failed to evict client for {old_addr:?}: {e:#}");
+                        }
+                    }
                     trans_cli_inst
                 };
+                METRICS.packet_received();
+                self.heartbeat(now, int).await;
                 int.dispatch(target, EV_CONTROLLER_RECEIVED, pkt)
                     .await
                     .unwrap();
@@ -143,6 +374,7 @@ impl Controller {
             }
             Ok(None) => {
                 debug!("Received datagram, but it did not contain a packet");
+                METRICS.packet_dropped();
                 self.recv_next(int);
                 Ok(())
             }
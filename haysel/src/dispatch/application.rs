@@ -1,26 +1,37 @@
 //! application-layer packet handling
 
-use std::{collections::HashMap, fmt::Write, net::SocketAddr};
+use std::{collections::HashMap, fmt::Write, net::SocketAddr, sync::Arc};
 
 use chrono::{DateTime, Utc};
-use mycelium::station::{
-    capabilities::{ChannelData, ChannelID, ChannelName},
-    identity::StationID,
+use mycelium::{
+    station::{
+        capabilities::{ChannelData, ChannelID, ChannelName},
+        identity::StationID,
+    },
+    StationDiagnostics,
 };
 use roundtable::{
     handler::{DispatchErr, HandlerInit, LocalInterface, MethodRegister},
     handler_decl_t, method_decl,
     msg::{self, HandlerInstance, Str},
 };
-use squirrel::api::{ChannelMappings, OnConnect, PacketKind, SomeData};
+use squirrel::{
+    api::{
+        compression, BeginOTA, ChannelMappings, Compression, Diagnostics, OnConnect, PacketKind,
+        SomeData,
+    },
+    clock::Clock,
+};
 
-use crate::registry;
+use crate::{registry, tsdb3};
 
-use super::{EV_TRANS_CLI_DATA_RECVD, EV_TRANS_CLI_QUEUE_DATA};
+use super::{
+    EV_CONTROLLER_CHECK_SEQ, EV_CONTROLLER_RESET_SEQ, EV_TRANS_CLI_DATA_RECVD, EV_TRANS_CLI_EVICT,
+    EV_TRANS_CLI_QUEUE_DATA, EV_TRANS_CLI_RESET,
+};
 
 pub struct AppClient {
     // controller instance
-    #[allow(unused)]
     ctrl: HandlerInstance,
     // associated transport client (used for sending packets to).
     transport: HandlerInstance,
@@ -30,9 +41,15 @@ pub struct AppClient {
     meta_station_build_rev: Option<String>,
     // chrono rfc3339 timestamp
     meta_station_build_date: Option<String>,
+    // drop received data instead of recording it
+    read_only: bool,
+    // source of the time data is recorded at
+    clock: Arc<dyn Clock>,
 }
 
 method_decl!(EV_WEATHER_DATA_RECEIVED, Record, ());
+// queue a firmware update for a station, returns true if sent to the station's client (false for other stations)
+method_decl!(EV_APP_BEGIN_OTA, (StationID, BeginOTA), bool);
 
 #[derive(Debug, Clone)]
 pub struct Record {
@@ -56,6 +73,8 @@ impl HandlerInit for AppClient {
     }
     fn methods(&self, reg: &mut MethodRegister<Self>) {
         reg.register(Self::received, EV_TRANS_CLI_DATA_RECVD);
+        reg.register(Self::evict, EV_TRANS_CLI_EVICT);
+        reg.register(Self::begin_ota, EV_APP_BEGIN_OTA);
     }
     async fn on_error(&mut self, error: DispatchErr, int: &LocalInterface) {
         error!(
@@ -72,6 +91,8 @@ impl AppClient {
         controller: HandlerInstance,
         transport: HandlerInstance,
         registry: HandlerInstance,
+        read_only: bool,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         Self {
             ctrl: controller,
@@ -81,16 +102,52 @@ impl AppClient {
             meta_station_id: None,
             meta_station_build_rev: None,
             meta_station_build_date: None,
+            read_only,
+            clock,
         }
     }
 
+    async fn evict(&mut self, _: &(), int: &LocalInterface) -> Result<(), DispatchErr> {
+        debug!("Evicting idle application client for {:?}", self.addr);
+        int.shutdown().await
+    }
+
+    async fn begin_ota(
+        &mut self,
+        (station, update): &(StationID, BeginOTA),
+        int: &LocalInterface,
+    ) -> Result<bool, DispatchErr> {
+        if self.meta_station_id != Some(*station) {
+            return Ok(false);
+        }
+        info!(
+            "Sending firmware update ({}) to station {station} at {:?}",
+            update.url, self.addr
+        );
+        let packet = rmp_serde::to_vec_named(&PacketKind::BeginOTA(update.clone())).unwrap();
+        int.dispatch(self.transport.clone(), EV_TRANS_CLI_QUEUE_DATA, packet)
+            .await?;
+        Ok(true)
+    }
+
     async fn received(&mut self, data: &Vec<u8>, int: &LocalInterface) -> Result<(), DispatchErr> {
-        match rmp_serde::from_slice::<PacketKind>(&*data) {
+        let data = match compression::decompress(data.as_slice()) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(
+                    "Failed to decompress packet from IP: {:?} - {e:#}",
+                    self.addr
+                );
+                return Ok(());
+            }
+        };
+        match rmp_serde::from_slice::<PacketKind>(&data) {
             Ok(pkt) => {
                 trace!("Received packet from IP: {:?} - {pkt:?}", self.addr);
                 match pkt {
                     PacketKind::Connect(data) => self.on_connect(data, int).await?,
                     PacketKind::Data(data) => self.on_data(data, int).await?,
+                    PacketKind::Diagnostics(data) => self.on_diagnostics(data, int).await?,
                     _ => warn!("Received unexpected packet kind"),
                 }
                 Ok(())
@@ -110,17 +167,49 @@ impl AppClient {
         data: OnConnect,
         int: &LocalInterface,
     ) -> Result<(), DispatchErr> {
-        let name_to_id_mappings = int
+        if let Some(prev_id) = self.meta_station_id {
+            // the station restarted (e.g. rebooted mid-transaction), anything queued for it is stale
+            if prev_id != data.station_id {
+                warn!(
+                    "Station at {:?} changed its id from {prev_id} to {}",
+                    self.addr, data.station_id
+                );
+            }
+            debug!(
+                "Station {} at {:?} re-sent Connect, re-registering",
+                data.station_id, self.addr
+            );
+            int.dispatch(self.transport.clone(), EV_TRANS_CLI_RESET, ())
+                .await?;
+        }
+        let mappings = match int
             .query(
                 self.registry.clone(),
                 registry::EV_REGISTRY_PROCESS_CONNECT,
                 (self.addr, data.clone()),
             )
+            .await
+        {
+            Ok(mappings) => mappings,
+            Err(DispatchErr::Failed(e)) => {
+                // (the station gets no mappings, so it will not send data)
+                warn!(
+                    "Station {} at {:?} was refused: {e}",
+                    data.station_id, self.addr
+                );
+                self.meta_station_id = None;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let mappings = ChannelMappings {
+            compression: Compression::negotiate(&data.compression),
+            ..mappings
+        };
+        // (a station's data numbering restarts if its storage was erased)
+        int.dispatch(self.ctrl.clone(), EV_CONTROLLER_RESET_SEQ, data.station_id)
             .await?;
-        let resp = rmp_serde::to_vec_named(&PacketKind::ChannelMappings(ChannelMappings {
-            map: name_to_id_mappings,
-        }))
-        .unwrap();
+        let resp = rmp_serde::to_vec_named(&PacketKind::ChannelMappings(mappings)).unwrap();
         int.dispatch(self.transport.clone(), EV_TRANS_CLI_QUEUE_DATA, resp)
             .await?;
         self.meta_station_id = Some(data.station_id);
@@ -129,8 +218,85 @@ impl AppClient {
         Ok(())
     }
 
-    async fn on_data(&mut self, data: SomeData, int: &LocalInterface) -> Result<(), DispatchErr> {
-        let received_at = chrono::Utc::now();
+    async fn on_diagnostics(
+        &mut self,
+        data: Diagnostics,
+        int: &LocalInterface,
+    ) -> Result<(), DispatchErr> {
+        let Some(station) = self.meta_station_id else {
+            debug!(
+                "Ignoring diagnostics from {:?}, which has not connected",
+                self.addr
+            );
+            return Ok(());
+        };
+        let stats = &data.transport;
+        debug!(
+            "Station {station} transport: {} packets sent ({} retransmitted), {} transactions ({} timed out), mean rtt {:?}",
+            stats.packets_sent,
+            stats.retransmits,
+            stats.transactions,
+            stats.timeouts,
+            stats.mean_rtt()
+        );
+        int.dispatch(
+            self.registry.clone(),
+            registry::EV_REGISTRY_RECORD_DIAGNOSTICS,
+            (
+                station,
+                StationDiagnostics {
+                    received_at: self.clock.now_utc(),
+                    transport: data.transport,
+                },
+            ),
+        )
+        .await
+    }
+
+    async fn on_data(
+        &mut self,
+        mut data: SomeData,
+        int: &LocalInterface,
+    ) -> Result<(), DispatchErr> {
+        let received_at = self.clock.now_utc();
+        if let Some(station) = self.meta_station_id {
+            if !int
+                .query(
+                    self.registry.clone(),
+                    registry::EV_REGISTRY_CHECK_SOURCE,
+                    (station, self.addr),
+                )
+                .await?
+            {
+                warn!(
+                    "Received data for station {station} from {:?}, which is not the address it last connected from - ignoring (it must re-send Connect)",
+                    self.addr
+                );
+                return Ok(());
+            }
+            if !int
+                .query(
+                    self.ctrl.clone(),
+                    EV_CONTROLLER_CHECK_SEQ,
+                    (station, data.seq),
+                )
+                .await?
+            {
+                debug!(
+                    "Dropping duplicate data (seq {}) from station {station}",
+                    data.seq
+                );
+                return Ok(());
+            }
+            let derived = int
+                .query(
+                    self.registry.clone(),
+                    registry::EV_REGISTRY_COMPUTE_DERIVED,
+                    (station, data.per_channel.clone()),
+                )
+                .await?;
+            data.per_channel.extend(derived);
+        }
         let mut buf = String::new();
         for (chid, dat) in data.per_channel.clone() {
             if let Some(ch) = int
@@ -156,12 +322,19 @@ impl AppClient {
             }
         }
         info!("Received data:\n{buf}");
+        if self.read_only {
+            warn!(
+                "Server is running read-only, data from {:?} will not be recorded",
+                self.addr
+            );
+            return Ok(());
+        }
         if let Some(recorded_by) = self.meta_station_id.clone() {
             int.announce(
                 msg::Target::Any,
                 EV_WEATHER_DATA_RECEIVED,
                 Record {
-                    recorded_at: received_at,
+                    recorded_at: recorded_at(data.recorded_at, received_at),
                     recorded_by,
                     data: data.per_channel,
                 },
@@ -171,3 +344,122 @@ impl AppClient {
         Ok(())
     }
 }
+
+/// Time to record data at: when the station says it was recorded if its clock is synced (and sane),
+/// otherwise when it was received
+fn recorded_at(reported: Option<i64>, received_at: DateTime<Utc>) -> DateTime<Utc> {
+    // allowed difference between the station's clock and ours
+    const MAX_SKEW: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+    let Some(reported) = reported else {
+        return received_at;
+    };
+    match DateTime::from_timestamp(reported, 0) {
+        Some(time) if tsdb3::is_storable(time) && time <= received_at + MAX_SKEW => time,
+        _ => {
+            warn!("Station reported an implausible time ({reported}), using the time the data was received instead");
+            received_at
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn recorded_at_falls_back_to_received() {
+    let received_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    assert_eq!(recorded_at(None, received_at), received_at);
+    // sent a bit later than it was recorded
+    let reported = received_at - chrono::TimeDelta::seconds(5);
+    assert_eq!(
+        recorded_at(Some(reported.timestamp()), received_at),
+        reported
+    );
+    // clock not synced (1970), would panic the database
+    assert_eq!(recorded_at(Some(0), received_at), received_at);
+    // far in the future
+    assert_eq!(
+        recorded_at(Some(received_at.timestamp() + 86400), received_at),
+        received_at
+    );
+    assert_eq!(recorded_at(Some(i64::MAX), received_at), received_at);
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, time::Duration};
+
+    use roundtable::{common::HDL_EXTERNAL, Bus};
+    use squirrel::clock::SystemClock;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// forwards the packets queued for its station
+    struct Transport(mpsc::UnboundedSender<Vec<u8>>);
+
+    #[async_trait]
+    impl HandlerInit for Transport {
+        const DECL: msg::HandlerType = handler_decl_t!("Test transport client");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Test transport client")
+        }
+        fn methods(&self, reg: &mut MethodRegister<Self>) {
+            reg.register(Self::queue, EV_TRANS_CLI_QUEUE_DATA);
+        }
+    }
+
+    impl Transport {
+        async fn queue(&mut self, data: &Vec<u8>, _int: &LocalInterface) -> Result<(), Infallible> {
+            let _ = self.0.send(Vec::clone(data));
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn begin_ota_sent_to_station() {
+        let bus = Bus::new(Default::default()).await;
+        let station = Uuid::new_v4();
+        // one client for the station, and one for another
+        let mut queued = vec![];
+        for id in [station, Uuid::new_v4()] {
+            let (send, recv) = mpsc::unbounded_channel();
+            let transport = bus.spawn(Transport(send));
+            let mut client = AppClient::new(
+                "127.0.0.1:1234".parse().unwrap(),
+                HDL_EXTERNAL,
+                transport,
+                HDL_EXTERNAL,
+                false,
+                Arc::new(SystemClock),
+            );
+            client.meta_station_id = Some(id);
+            bus.spawn(client);
+            queued.push(recv);
+        }
+        let update = BeginOTA {
+            url: "https://example.com/firmware.bin".to_string(),
+            sha256: [7; 32],
+            signature: vec![1, 2, 3],
+        };
+        let sent = bus
+            .dispatch_collect_as(
+                HDL_EXTERNAL,
+                msg::Target::Type(AppClient::DECL),
+                EV_APP_BEGIN_OTA,
+                (station, update.clone()),
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(sent.iter().filter(|sent| **sent).count(), 1);
+
+        let packet = queued[0].recv().await.unwrap();
+        let Ok(PacketKind::BeginOTA(received)) = rmp_serde::from_slice(&packet) else {
+            panic!("expected BeginOTA");
+        };
+        assert_eq!(received.url, update.url);
+        assert_eq!(received.sha256, update.sha256);
+        assert!(queued[1].try_recv().is_err());
+    }
+}
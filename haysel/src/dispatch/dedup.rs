@@ -0,0 +1,83 @@
+//! dropping duplicate data packets (UDP may deliver a packet twice, and stations re-send after a timeout)
+
+use std::collections::HashMap;
+
+use mycelium::station::identity::StationID;
+
+/// Tracks the last sequence number received from each station
+#[derive(Debug, Default)]
+pub struct SeqTracker {
+    last: HashMap<StationID, u64>,
+}
+
+impl SeqTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If data numbered `seq` from `station` is new (and should be recorded), as opposed to a duplicate or
+    /// an old packet arriving late.
+    ///
+    /// `seq` 0 means the station does not number its data (it predates sequence numbers), which is always accepted
+    pub fn accept(&mut self, station: StationID, seq: u64) -> bool {
+        if seq == 0 {
+            return true;
+        }
+        match self.last.get_mut(&station) {
+            Some(last) if seq <= *last => false,
+            Some(last) => {
+                *last = seq;
+                true
+            }
+            None => {
+                self.last.insert(station, seq);
+                true
+            }
+        }
+    }
+
+    /// Forget the last sequence number from `station`, because it (re-)connected.
+    ///
+    /// a station's numbering restarts when its storage is erased (e.g. when it is re-flashed),
+    /// and its new data would otherwise be rejected until the numbering caught up
+    pub fn reset(&mut self, station: StationID) {
+        self.last.remove(&station);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn duplicates_suppressed() {
+    let mut tracker = SeqTracker::new();
+    let (a, b) = (StationID::new_v4(), StationID::new_v4());
+    assert!(tracker.accept(a, 1));
+    assert!(!tracker.accept(a, 1));
+    assert!(tracker.accept(a, 2));
+    // old packet, delivered late
+    assert!(!tracker.accept(a, 1));
+    // stations are tracked separately
+    assert!(tracker.accept(b, 1));
+    // gaps are fine (packets lost, or the station rebooted and skipped ahead)
+    assert!(tracker.accept(a, 70));
+    assert!(!tracker.accept(a, 69));
+    // unsequenced
+    assert!(tracker.accept(a, 0));
+    assert!(tracker.accept(a, 0));
+}
+
+#[cfg(test)]
+#[test]
+fn numbering_restarts_on_connect() {
+    let mut tracker = SeqTracker::new();
+    let (a, b) = (StationID::new_v4(), StationID::new_v4());
+    assert!(tracker.accept(a, 500));
+    assert!(tracker.accept(b, 20));
+    // the station was re-flashed, and numbers its data from the start
+    assert!(!tracker.accept(a, 1));
+    tracker.reset(a);
+    assert!(tracker.accept(a, 1));
+    assert!(!tracker.accept(a, 1));
+    assert!(tracker.accept(a, 2));
+    // other stations are unaffected
+    assert!(!tracker.accept(b, 20));
+}
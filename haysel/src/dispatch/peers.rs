@@ -0,0 +1,365 @@
+//! bookkeeping for the per-peer client handlers owned by `Controller`
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use super::ratelimit::TokenBucket;
+use crate::core::config::RateLimit;
+
+#[derive(Debug)]
+struct Peer<I> {
+    // transport client for this peer
+    instance: I,
+    last_seen: Instant,
+    // last reported state of the peer's `ClientInterface`
+    resting: bool,
+    // last reported number of pings in a row the peer did not answer
+    missed_pings: u32,
+    bucket: TokenBucket,
+}
+
+/// Maps peer addresses to their transport client, and decides when a peer should be forgotten.
+///
+/// Any datagram creates a peer, so without eviction a flood of packets with spoofed source
+/// addresses would grow this without bound. idle peers are evicted periodically, and once there are
+/// `max_peers` the least recently seen peer is evicted to make room for a new one.
+///
+/// generic over the handle used for a peer (normally a `HandlerInstance`)
+#[derive(Debug)]
+pub struct PeerTable<I> {
+    peers: HashMap<SocketAddr, Peer<I>>,
+    inv: HashMap<I, SocketAddr>,
+    /// how long a `Resting` peer may be idle before it is evicted
+    idle_ttl: Duration,
+    /// how long a peer in the middle of a transaction may be idle before it is evicted
+    /// (the transaction would have timed out by then anyway)
+    max_trans_t: Duration,
+    rate_limit: RateLimit,
+    max_peers: usize,
+}
+
+impl<I: Clone + Eq + Hash> PeerTable<I> {
+    pub fn new(
+        idle_ttl: Duration,
+        max_trans_t: Duration,
+        rate_limit: RateLimit,
+        max_peers: usize,
+    ) -> Self {
+        Self {
+            peers: HashMap::new(),
+            inv: HashMap::new(),
+            idle_ttl,
+            max_trans_t,
+            rate_limit,
+            max_peers,
+        }
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&I> {
+        self.peers.get(addr).map(|peer| &peer.instance)
+    }
+
+    pub fn addr_of(&self, instance: &I) -> Option<SocketAddr> {
+        self.inv.get(instance).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// transport clients of all peers
+    pub fn instances(&self) -> impl Iterator<Item = &I> {
+        self.peers.values().map(|peer| &peer.instance)
+    }
+
+    /// Insert a new peer (the packet that caused it to be created counts against its rate limit).
+    ///
+    /// if the table is full, the least recently seen peer is removed to make room, and returned (its transport
+    /// client should be shut down)
+    pub fn insert(
+        &mut self,
+        addr: SocketAddr,
+        instance: I,
+        now: Instant,
+    ) -> Option<(SocketAddr, I)> {
+        let evicted = if self.peers.len() >= self.max_peers && !self.peers.contains_key(&addr) {
+            let oldest = self
+                .peers
+                .iter()
+                .min_by_key(|(_, peer)| peer.last_seen)
+                .map(|(addr, _)| *addr);
+            oldest.and_then(|oldest| self.remove_all(vec![oldest]).pop())
+        } else {
+            None
+        };
+        self.inv.insert(instance.clone(), addr);
+        let mut bucket = TokenBucket::new(self.rate_limit, now);
+        bucket.try_take(now);
+        if let Some(old) = self.peers.insert(
+            addr,
+            Peer {
+                instance,
+                last_seen: now,
+                resting: true,
+                missed_pings: 0,
+                bucket,
+            },
+        ) {
+            self.inv.remove(&old.instance);
+        }
+        evicted
+    }
+
+    /// record that a packet was received from `addr`, returning false if it is over its rate limit
+    /// (and the packet should be dropped)
+    pub fn admit(&mut self, addr: &SocketAddr, now: Instant) -> bool {
+        let Some(peer) = self.peers.get_mut(addr) else {
+            return false;
+        };
+        peer.last_seen = now;
+        // anything counts as an answer to a ping
+        peer.missed_pings = 0;
+        peer.bucket.try_take(now)
+    }
+
+    /// record the state of a peer's transport, as reported by its handler
+    pub fn set_resting(&mut self, instance: &I, resting: bool) {
+        if let Some(peer) = self.inv.get(instance).and_then(|a| self.peers.get_mut(a)) {
+            peer.resting = resting;
+        }
+    }
+
+    /// record the number of pings in a row a peer has missed, as reported by its handler
+    pub fn set_missed_pings(&mut self, instance: &I, missed: u32) {
+        if let Some(peer) = self.inv.get(instance).and_then(|a| self.peers.get_mut(a)) {
+            peer.missed_pings = missed;
+        }
+    }
+
+    fn remove_all(&mut self, addrs: Vec<SocketAddr>) -> Vec<(SocketAddr, I)> {
+        addrs
+            .into_iter()
+            .map(|addr| {
+                let peer = self.peers.remove(&addr).unwrap();
+                self.inv.remove(&peer.instance);
+                (addr, peer.instance)
+            })
+            .collect()
+    }
+
+    /// Remove all peers that have missed at least `max_missed` pings in a row, returning their transport clients
+    /// (which should be shut down)
+    pub fn evict_unresponsive(&mut self, max_missed: u32) -> Vec<(SocketAddr, I)> {
+        let unresponsive = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.missed_pings >= max_missed)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+        self.remove_all(unresponsive)
+    }
+
+    /// Remove all peers that have been idle for too long, returning their transport clients (which should be shut down)
+    ///
+    /// peers in the middle of a transaction are kept until the transaction could no longer complete
+    pub fn evict_idle(&mut self, now: Instant) -> Vec<(SocketAddr, I)> {
+        let (idle_ttl, max_trans_t) = (self.idle_ttl, self.max_trans_t);
+        let expired = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| {
+                let idle = now.saturating_duration_since(peer.last_seen);
+                idle >= idle_ttl && (peer.resting || idle >= max_trans_t)
+            })
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+        self.remove_all(expired)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn peer_created_and_evicted_when_idle() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(60),
+        Duration::from_secs(30),
+        RateLimit::default(),
+        1024,
+    );
+    let addr = SocketAddr::from(([10, 0, 0, 1], 4000));
+    assert!(table.get(&addr).is_none());
+    table.insert(addr, 1, start);
+    assert_eq!(table.get(&addr), Some(&1));
+    assert_eq!(table.addr_of(&1), Some(addr));
+
+    assert!(table.admit(&addr, start + Duration::from_secs(30)));
+    assert!(table.evict_idle(start + Duration::from_secs(60)).is_empty());
+    let evicted = table.evict_idle(start + Duration::from_secs(90));
+    assert_eq!(evicted, vec![(addr, 1)]);
+    assert_eq!(table.len(), 0);
+    assert!(table.addr_of(&1).is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn active_transaction_not_evicted() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(10),
+        Duration::from_secs(30),
+        RateLimit::default(),
+        1024,
+    );
+    let addr = SocketAddr::from(([10, 0, 0, 1], 4000));
+    table.insert(addr, 1, start);
+    table.set_resting(&1, false);
+    assert!(table.evict_idle(start + Duration::from_secs(20)).is_empty());
+    assert_eq!(table.len(), 1);
+    // the transaction can no longer complete
+    assert_eq!(table.evict_idle(start + Duration::from_secs(30)).len(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn unresponsive_peer_evicted() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(60),
+        Duration::from_secs(30),
+        RateLimit::default(),
+        1024,
+    );
+    let (a, b) = (
+        SocketAddr::from(([10, 0, 0, 1], 4000)),
+        SocketAddr::from(([10, 0, 0, 2], 4000)),
+    );
+    table.insert(a, 1, start);
+    table.insert(b, 2, start);
+    assert_eq!(table.instances().count(), 2);
+    table.set_missed_pings(&1, 2);
+    table.set_missed_pings(&2, 2);
+    assert!(table.evict_unresponsive(3).is_empty());
+    // hearing from a peer resets its count
+    assert!(table.admit(&b, start));
+    table.set_missed_pings(&1, 3);
+    assert_eq!(table.evict_unresponsive(3), vec![(a, 1)]);
+    assert_eq!(table.len(), 1);
+    assert!(table.addr_of(&1).is_none());
+    table.set_missed_pings(&2, 1);
+    assert!(table.evict_unresponsive(3).is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn flood_of_sources_is_bounded() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(10),
+        Duration::from_secs(30),
+        RateLimit::default(),
+        1024,
+    );
+    for i in 0..1000u16 {
+        let now = start + Duration::from_millis(i as u64 * 100);
+        table.insert(SocketAddr::from(([10, 0, 0, 1], i)), i, now);
+        table.evict_idle(now);
+    }
+    // only peers seen in the last 10s remain
+    assert!(table.len() <= 101);
+}
+
+#[cfg(test)]
+#[test]
+fn peer_rate_limited() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(60),
+        Duration::from_secs(30),
+        RateLimit {
+            packets_per_sec: 1,
+            burst: 3,
+        },
+        1024,
+    );
+    let (a, b) = (
+        SocketAddr::from(([10, 0, 0, 1], 4000)),
+        SocketAddr::from(([10, 0, 0, 2], 4000)),
+    );
+    assert!(!table.admit(&a, start), "unknown peers are never admitted");
+    table.insert(a, 1, start);
+    table.insert(b, 2, start);
+    assert!(table.admit(&a, start));
+    assert!(table.admit(&a, start));
+    assert!(!table.admit(&a, start));
+    // limits are per-address
+    assert!(table.admit(&b, start));
+    assert!(table.admit(&a, start + Duration::from_secs(1)));
+}
+
+#[cfg(test)]
+#[test]
+fn ipv6_peer() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(60),
+        Duration::from_secs(30),
+        RateLimit::default(),
+        1024,
+    );
+    let v6: SocketAddr = "[2001:db8::1]:4000".parse().unwrap();
+    let v4_mapped: SocketAddr = "[::ffff:10.0.0.1]:4000".parse().unwrap();
+    let v4: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+    table.insert(v6, 1, start);
+    table.insert(v4, 2, start);
+    assert_eq!(table.get(&v6), Some(&1));
+    assert_eq!(table.addr_of(&1), Some(v6));
+    assert!(table.admit(&v6, start));
+    // a v4-mapped v6 address is a different peer to the plain v4 one (they arrive on different sockets)
+    assert!(table.get(&v4_mapped).is_none());
+    assert_eq!(table.evict_idle(start + Duration::from_secs(60)).len(), 2);
+}
+
+#[cfg(test)]
+#[test]
+fn least_recently_seen_evicted_when_full() {
+    let start = Instant::now();
+    let mut table = PeerTable::<u16>::new(
+        Duration::from_secs(60),
+        Duration::from_secs(30),
+        RateLimit::default(),
+        3,
+    );
+    let addr = |port| SocketAddr::from(([10, 0, 0, 1], port));
+    for i in 0..3u16 {
+        assert!(table
+            .insert(addr(i), i, start + Duration::from_secs(i as u64))
+            .is_none());
+    }
+    // peer 0 was seen most recently, so 1 is the oldest
+    assert!(table.admit(&addr(0), start + Duration::from_secs(5)));
+    assert_eq!(
+        table.insert(addr(3), 3, start + Duration::from_secs(6)),
+        Some((addr(1), 1))
+    );
+    assert_eq!(table.len(), 3);
+    assert!(table.get(&addr(1)).is_none());
+    assert!(table.addr_of(&1).is_none());
+    // replacing an existing peer does not evict anything
+    assert!(table
+        .insert(addr(3), 4, start + Duration::from_secs(7))
+        .is_none());
+    assert_eq!(table.len(), 3);
+    assert_eq!(table.instances().count(), 3);
+
+    // a flood of new sources never grows the table past the limit
+    for i in 100..1100u16 {
+        table.insert(addr(i), i, start + Duration::from_secs(8));
+        assert!(table.len() <= 3);
+        assert!(table.inv.len() <= 3);
+    }
+}
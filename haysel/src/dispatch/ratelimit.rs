@@ -0,0 +1,68 @@
+//! per-address packet rate limiting
+
+use std::time::Instant;
+
+use crate::core::config::RateLimit;
+
+/// Token bucket, holding up to `burst` tokens and refilling at `packets_per_sec`
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// creates a full bucket
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            capacity: limit.burst as f64,
+            per_sec: limit.packets_per_sec as f64,
+            last_refill: now,
+        }
+    }
+
+    /// take a token, returning false if there are none left (the packet should be dropped)
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_sec).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn token_bucket() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(
+        RateLimit {
+            packets_per_sec: 10,
+            burst: 5,
+        },
+        start,
+    );
+    for _ in 0..5 {
+        assert!(bucket.try_take(start));
+    }
+    assert!(!bucket.try_take(start));
+    // refills at 10/s
+    assert!(!bucket.try_take(start + Duration::from_millis(50)));
+    assert!(bucket.try_take(start + Duration::from_millis(100)));
+    assert!(!bucket.try_take(start + Duration::from_millis(100)));
+    // but never holds more than `burst`
+    let later = start + Duration::from_secs(60);
+    for _ in 0..5 {
+        assert!(bucket.try_take(later));
+    }
+    assert!(!bucket.try_take(later));
+}
@@ -0,0 +1,417 @@
+//! replaying captured packets into a database (for debugging)
+//!
+//! a capture is a sequence of raw application packets (as sent by a station), each prefixed with its length
+//! (u64, big endian). packets are handled like the live dispatcher would: `Connect` registers the station,
+//! and `Data` (from the last station to connect) is deduplicated, has computed channels derived, and is recorded
+
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mycelium::station::{
+    capabilities::{ChannelData, ChannelID, KnownChannels},
+    identity::{KnownStations, StationID},
+};
+use squirrel::api::{
+    compression::{self, DecompressError},
+    PacketKind, SomeData,
+};
+
+use crate::{
+    registry::{self, ConnectError, Limits},
+    tsdb3::{self, DB},
+};
+
+use super::dedup::SeqTracker;
+
+/// Why a packet (or part of one) could not be replayed
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("The capture ends partway through a packet")]
+    Truncated,
+    #[error("Failed to decompress packet: {0}")]
+    Decompress(#[from] DecompressError),
+    #[error("Failed to deserialize packet: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+    #[error("Stations do not send {0} packets")]
+    Unexpected(&'static str),
+    #[error("Data was sent before any station connected")]
+    NotConnected,
+    #[error("The station was refused: {0}")]
+    Refused(#[from] ConnectError),
+    #[error(
+        "Data has no (valid) time it was recorded at, and the time it was received is not known"
+    )]
+    NoTimestamp,
+    #[error("Channel {0} is not supported by the station")]
+    UnknownChannel(ChannelID),
+    #[error("Event for channel {0} is older than events already recorded for it")]
+    OutOfOrder(ChannelID),
+    #[error("Database error: {0}")]
+    Database(#[from] tsdb3::Error),
+}
+
+/// Outcome of replaying a capture
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// packets in the capture
+    pub packets: usize,
+    /// readings and events recorded
+    pub recorded: usize,
+    /// data packets dropped as duplicates
+    pub duplicates: usize,
+    /// (index of the packet, what went wrong). a packet may fail more than once (e.g. for each channel)
+    pub failed: Vec<(usize, ReplayError)>,
+}
+
+/// State kept between packets (the parts of the registry, controller, and application handlers that matter)
+pub struct Replay<'a> {
+    db: &'a mut DB,
+    stations: KnownStations,
+    channels: KnownChannels,
+    limits: Limits,
+    /// the last station to connect
+    station: Option<StationID>,
+    seqs: SeqTracker,
+    /// newest event time recorded, for each (station, channel). readings can be inserted out of order, events can not
+    newest_event: HashMap<(StationID, ChannelID), DateTime<Utc>>,
+}
+
+impl<'a> Replay<'a> {
+    /// `db` must be newly initialized. `stations` and `channels` should be the registry the capture was made
+    /// with (stations send data using the channel ids they were given), or empty
+    pub fn new(
+        db: &'a mut DB,
+        stations: KnownStations,
+        channels: KnownChannels,
+        limits: Limits,
+    ) -> Self {
+        Self {
+            db,
+            stations,
+            channels,
+            limits,
+            station: None,
+            seqs: SeqTracker::new(),
+            newest_event: HashMap::new(),
+        }
+    }
+
+    /// Replays every packet in `capture`
+    pub fn replay(&mut self, mut capture: &[u8]) -> ReplayReport {
+        let mut report = ReplayReport::default();
+        while !capture.is_empty() {
+            let idx = report.packets;
+            report.packets += 1;
+            let Some(packet) = next_packet(&mut capture) else {
+                report.failed.push((idx, ReplayError::Truncated));
+                break;
+            };
+            let packet = match compression::decompress(packet) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    report.failed.push((idx, e.into()));
+                    continue;
+                }
+            };
+            let res = match rmp_serde::from_slice::<PacketKind>(&packet) {
+                Ok(PacketKind::Connect(data)) => self.connect(&data),
+                Ok(PacketKind::Data(data)) => {
+                    self.data(data, idx, &mut report);
+                    Ok(())
+                }
+                Ok(PacketKind::ChannelMappings(..)) => {
+                    Err(ReplayError::Unexpected("ChannelMappings"))
+                }
+                Ok(PacketKind::BeginOTA(..)) => Err(ReplayError::Unexpected("BeginOTA")),
+                // (nothing is stored in the database)
+                Ok(PacketKind::Diagnostics(..)) => Ok(()),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = res {
+                report.failed.push((idx, e));
+            }
+        }
+        report
+    }
+
+    fn connect(&mut self, data: &squirrel::api::OnConnect) -> Result<(), ReplayError> {
+        // (not connected, if refused)
+        self.station = None;
+        let outcome =
+            registry::apply_connect(&mut self.stations, &mut self.channels, data, self.limits)?;
+        if !outcome.conflicts().is_empty() {
+            // (the station was not given mappings for these, so it would not have sent data for them)
+            warn!(
+                "station {} declared channels {:?} differently than they are known",
+                data.station_id,
+                outcome.conflicts()
+            );
+        }
+        self.ensure_exists(data.station_id)?;
+        // (the station may have restarted its numbering)
+        self.seqs.reset(data.station_id);
+        self.station = Some(data.station_id);
+        Ok(())
+    }
+
+    /// adds the station, and all channels it supports, to the database if they are not already there
+    fn ensure_exists(&mut self, station: StationID) -> Result<(), ReplayError> {
+        if !self.db.get_stations().any(|id| *id == station) {
+            self.db.insert_station(station)?;
+        }
+        let known = self
+            .db
+            .get_channels_for(station)
+            .map(|chs| chs.copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let missing = self
+            .stations
+            .get_info(&station)
+            .unwrap()
+            .supports_channels
+            .iter()
+            .filter(|id| !known.contains(id))
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.db.insert_channels(station, missing)?;
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, mut data: SomeData, idx: usize, report: &mut ReplayReport) {
+        let Some(station) = self.station else {
+            report.failed.push((idx, ReplayError::NotConnected));
+            return;
+        };
+        if !self.seqs.accept(station, data.seq) {
+            report.duplicates += 1;
+            return;
+        }
+        let Some(time) = data
+            .recorded_at
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .filter(|t| tsdb3::is_storable(*t))
+        else {
+            report.failed.push((idx, ReplayError::NoTimestamp));
+            return;
+        };
+        let (derived, _) = registry::derive_for_station(
+            &mut self.stations,
+            &self.channels,
+            self.limits,
+            station,
+            &data.per_channel,
+        );
+        data.per_channel.extend(derived);
+        if let Err(e) = self.ensure_exists(station) {
+            report.failed.push((idx, e));
+            return;
+        }
+        let supported = &self.stations.get_info(&station).unwrap().supports_channels;
+        for (ch, val) in &data.per_channel {
+            if !supported.contains(ch) {
+                report.failed.push((idx, ReplayError::UnknownChannel(*ch)));
+                continue;
+            }
+            if let ChannelData::Event { .. } = val {
+                let newest = self.newest_event.entry((station, *ch)).or_insert(time);
+                if *newest > time {
+                    report.failed.push((idx, ReplayError::OutOfOrder(*ch)));
+                    continue;
+                }
+                *newest = time;
+            }
+            let res = match val {
+                ChannelData::Float(val) => self.db.insert_data(station, *ch, time, *val),
+                ChannelData::Event { sub, data } => {
+                    self.db.insert_event(station, *ch, time, sub, data)
+                }
+            };
+            match res {
+                Ok(()) => report.recorded += 1,
+                Err(e) => report.failed.push((idx, e.into())),
+            }
+        }
+    }
+}
+
+/// splits the next length-prefixed packet off of `capture`, or None if it is truncated
+fn next_packet<'b>(capture: &mut &'b [u8]) -> Option<&'b [u8]> {
+    let (len, rest) = capture.split_first_chunk::<8>()?;
+    let len = usize::try_from(u64::from_be_bytes(*len)).ok()?;
+    if rest.len() < len {
+        return None;
+    }
+    let (packet, rest) = rest.split_at(len);
+    *capture = rest;
+    Some(packet)
+}
+
+/// Replays the capture at `capture` into the (newly initialized) database at `db`.
+///
+/// if `registry` is given, the station and channel registry in it (`stations.json`, `channels.json`) is
+/// used as a starting point. it is not modified
+pub fn main(db: &Path, capture: &Path, registry: Option<&Path>) -> Result<()> {
+    let (stations, channels) = match registry {
+        Some(dir) => (
+            serde_json::from_str(&fs::read_to_string(dir.join("stations.json"))?)?,
+            serde_json::from_str(&fs::read_to_string(dir.join("channels.json"))?)?,
+        ),
+        None => {
+            warn!("No registry given, channel ids in the capture will not be recognized unless the station connects first");
+            (KnownStations::new(), KnownChannels::new())
+        }
+    };
+    let capture = fs::read(capture)?;
+    let file = fs::OpenOptions::new().read(true).write(true).open(db)?;
+    warn!("Initializing new database in {db:?}...");
+    // Saftey: the file is not otherwise in use
+    let mut db = unsafe { DB::new_uninit(file) }?;
+    db.init();
+    let limits = crate::core::config::Registry::default().into();
+    let report = Replay::new(&mut db, stations, channels, limits).replay(&capture);
+    for (idx, e) in &report.failed {
+        error!("Packet {idx}: {e}");
+    }
+    info!(
+        "Replayed {} packets: recorded {} values, dropped {} duplicates, {} failures",
+        report.packets,
+        report.recorded,
+        report.duplicates,
+        report.failed.len()
+    );
+    db.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn replay_capture() {
+    use mycelium::station::capabilities::{Channel, ChannelType, ChannelValue};
+    use squirrel::api::OnConnect;
+
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    // the registry the capture was made with
+    let mut channels = KnownChannels::new();
+    let channel = |name: &str| Channel {
+        name: name.into(),
+        value: ChannelValue::Float,
+        ty: ChannelType::Periodic,
+        unit: None,
+        description: None,
+    };
+    let temp = channels.insert_channel(channel("temperature")).unwrap();
+    let humid = channels.insert_channel(channel("humidity")).unwrap();
+
+    let station = StationID::new_v4();
+    let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    let at = |secs: i64| start + chrono::Duration::seconds(secs);
+    let data = |seq, secs, values: &[(ChannelID, f32)]| {
+        PacketKind::Data(SomeData {
+            per_channel: values
+                .iter()
+                .map(|(ch, v)| (*ch, ChannelData::Float(*v)))
+                .collect(),
+            recorded_at: Some(at(secs).timestamp()),
+            seq,
+        })
+    };
+    let packets = [
+        // 0: not connected yet
+        data(1, 0, &[(temp, 0.0)]),
+        // 1
+        PacketKind::Connect(OnConnect {
+            station_id: station,
+            station_build_rev: "abcdef".into(),
+            station_build_date: "2024-01-01T00:00:00Z".into(),
+            channels: vec![channel("temperature"), channel("humidity")],
+            degraded_channels: vec![],
+            provisioning_token: None,
+            compression: vec![],
+        }),
+        // 2
+        data(1, 10, &[(temp, 20.0), (humid, 50.0)]),
+        // 3: duplicate
+        data(1, 10, &[(temp, 20.0), (humid, 50.0)]),
+        // 4: unknown channel (the other is still recorded)
+        data(2, 20, &[(temp, 21.0), (ChannelID::nil(), 0.0)]),
+        // 5: older than what was already recorded (still recorded, in order)
+        data(3, 5, &[(temp, 19.0)]),
+        // 6: no timestamp
+        PacketKind::Data(SomeData {
+            per_channel: HashMap::new(),
+            recorded_at: None,
+            seq: 4,
+        }),
+        // 7
+        data(5, 30, &[(temp, 22.0), (humid, 55.0)]),
+    ];
+    let mut capture = vec![];
+    for packet in &packets {
+        let buf = rmp_serde::to_vec_named(packet).unwrap();
+        capture.extend((buf.len() as u64).to_be_bytes());
+        capture.extend(buf);
+    }
+    // 8: garbage (which looks like a compressed packet, with an unknown codec)
+    capture.extend(3u64.to_be_bytes());
+    capture.extend([0xc1, 0xc1, 0xc1]);
+    // 9: cut off
+    capture.extend(100u64.to_be_bytes());
+    capture.extend([0; 10]);
+
+    let report = Replay::new(
+        &mut db,
+        KnownStations::new(),
+        channels,
+        Limits {
+            max_stations: 16,
+            max_channels_per_station: 64,
+        },
+    )
+    .replay(&capture);
+    assert_eq!(report.packets, 10);
+    assert_eq!(report.recorded, 6);
+    assert_eq!(report.duplicates, 1);
+    let failed = report
+        .failed
+        .iter()
+        .map(|(idx, e)| (*idx, e.to_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        report
+            .failed
+            .iter()
+            .map(|(idx, _)| *idx)
+            .collect::<Vec<_>>(),
+        vec![0, 4, 6, 8, 9],
+        "{failed:#?}"
+    );
+    assert!(matches!(report.failed[0].1, ReplayError::NotConnected));
+    assert!(matches!(report.failed[1].1, ReplayError::UnknownChannel(ch) if ch.is_nil()));
+    assert!(matches!(report.failed[2].1, ReplayError::NoTimestamp));
+    assert!(matches!(
+        report.failed[3].1,
+        ReplayError::Decompress(DecompressError::UnknownCodec(0xc1))
+    ));
+    assert!(matches!(report.failed[4].1, ReplayError::Truncated));
+
+    let mut recorded = vec![];
+    db.for_each_entry(station, temp, |t, v| recorded.push((t, v)));
+    assert_eq!(
+        recorded,
+        vec![
+            (at(5), 19.0),
+            (at(10), 20.0),
+            (at(20), 21.0),
+            (at(30), 22.0)
+        ]
+    );
+    let mut recorded = vec![];
+    db.for_each_entry(station, humid, |t, v| recorded.push((t, v)));
+    assert_eq!(recorded, vec![(at(10), 50.0), (at(30), 55.0)]);
+}
@@ -1,8 +1,11 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use squirrel::transport::{
-    server::{ClientInterface, DispatchEvent},
-    Packet,
+use squirrel::{
+    clock::Clock,
+    transport::{
+        server::{ClientInterface, DispatchEvent},
+        Packet, MAX_FRAME_BUF_SIZE,
+    },
 };
 
 use roundtable::{
@@ -11,6 +14,8 @@ use roundtable::{
     msg::{self, HandlerInstance, Str},
 };
 
+use crate::metrics::METRICS;
+
 pub struct TransportClient {
     // controller instance
     ctrl: HandlerInstance,
@@ -26,6 +31,25 @@ pub struct TransportClient {
 // sent to its associated station
 method_decl!(EV_TRANS_CLI_QUEUE_DATA, Vec<u8>, ());
 
+// request by an external handler of `TransportClient` to abandon any in-progress transaction
+// and discard queued data (the station has restarted)
+method_decl!(EV_TRANS_CLI_RESET, (), ());
+
+// request by `Controller` for a `TransportClient` (and its application client) to shut down,
+// as its station has been idle for too long
+method_decl!(EV_TRANS_CLI_EVICT, (), ());
+
+// event sent by a `TransportClient` to `Controller` after handling a packet (true if the
+// transport is resting, i.e. it is not in the middle of a transaction)
+method_decl!(EV_TRANS_CLI_STATE, bool, ());
+
+// request by `Controller` for a `TransportClient` to ping its station (if it is not in the middle of a transaction)
+method_decl!(EV_TRANS_CLI_PING, (), ());
+
+// event sent by a `TransportClient` to `Controller` after pinging its station, with the number of
+// pings in a row that the station has not answered
+method_decl!(EV_TRANS_CLI_MISSED_PINGS, u32, ());
+
 // event sent by a `TransportClient` to an external handler when a full group of data is received.
 method_decl!(EV_TRANS_CLI_DATA_RECVD, Vec<u8>, ());
 
@@ -33,7 +57,7 @@ method_decl!(EV_TRANS_CLI_DATA_RECVD, Vec<u8>, ());
 // method_decl!(EV_TRANS_CLI_TIMED_OUT, (), ());
 
 // TransportClient requests Controller to send `Packet` to the address associeted
-// (through `active_clients_inv` with the sending handler)
+// (through `active_clients` with the sending handler)
 method_decl!(EV_TRANS_CLI_REQ_SEND_PKT, Packet, ());
 
 // Controller notifies TransportClient of the identity of its
@@ -55,8 +79,11 @@ impl HandlerInit for TransportClient {
     }
     fn methods(&self, reg: &mut MethodRegister<Self>) {
         reg.register(Self::queue_data, EV_TRANS_CLI_QUEUE_DATA);
+        reg.register(Self::reset, EV_TRANS_CLI_RESET);
         reg.register(Self::handle_pkt, super::EV_CONTROLLER_RECEIVED);
         reg.register(Self::ident_appl, EV_TRANS_CLI_IDENT_APP);
+        reg.register(Self::evict, EV_TRANS_CLI_EVICT);
+        reg.register(Self::ping, EV_TRANS_CLI_PING);
     }
     async fn on_error(&mut self, error: DispatchErr, int: &LocalInterface) {
         error!(
@@ -68,12 +95,21 @@ impl HandlerInit for TransportClient {
 }
 
 impl TransportClient {
-    pub fn new(addr: SocketAddr, max_trans_t: Duration, controller: HandlerInstance) -> Self {
+    pub fn new(
+        addr: SocketAddr,
+        max_trans_t: Duration,
+        controller: HandlerInstance,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         Self {
             ctrl: controller,
             ext: None,
             addr,
-            inter: ClientInterface::new(max_trans_t),
+            // stations with a smaller MTU will negotiate this down
+            inter: ClientInterface::new(max_trans_t)
+                .with_max_frame_size(MAX_FRAME_BUF_SIZE)
+                .with_peer(addr)
+                .with_clock(clock),
             missed_events: vec![],
         }
     }
@@ -104,12 +140,55 @@ impl TransportClient {
         Ok(())
     }
 
+    async fn reset(
+        &mut self,
+        _: &(),
+        _int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        debug!("Resetting transport state for {:?}", self.addr);
+        self.inter.reset();
+        Ok(())
+    }
+
+    async fn evict(
+        &mut self,
+        _: &(),
+        int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        debug!("Evicting idle transport client for {:?}", self.addr);
+        if let Some(ext) = self.ext.clone() {
+            int.announce(msg::Target::Instance(ext), EV_TRANS_CLI_EVICT, ())
+                .await?;
+        }
+        int.shutdown().await
+    }
+
+    async fn ping(
+        &mut self,
+        _: &(),
+        int: &LocalInterface,
+    ) -> Result<(), <Self as HandlerInit>::Error> {
+        if let Some(pkt) = self.inter.ping() {
+            int.dispatch(self.ctrl.clone(), EV_TRANS_CLI_REQ_SEND_PKT, pkt)
+                .await?;
+        }
+        // not verified, the controller may be waiting on us
+        int.announce(
+            msg::Target::Instance(self.ctrl.clone()),
+            EV_TRANS_CLI_MISSED_PINGS,
+            self.inter.missed_pings(),
+        )
+        .await?;
+        Ok(())
+    }
+
     async fn handle_pkt(
         &mut self,
         pkt: &Packet,
         int: &LocalInterface,
     ) -> Result<(), <Self as HandlerInit>::Error> {
-        for ev in self.inter.handle(*pkt) {
+        for ev in self.inter.handle(pkt.clone()) {
+            METRICS.dispatch_event(&ev);
             match ev {
                 DispatchEvent::TimedOut => {
                     warn!("Connection to weather station at {:?} timed out", self.addr,);
@@ -128,6 +207,13 @@ impl TransportClient {
                 }
             }
         }
+        // not verified, the controller may be waiting on us
+        int.announce(
+            msg::Target::Instance(self.ctrl.clone()),
+            EV_TRANS_CLI_STATE,
+            self.inter.is_resting(),
+        )
+        .await?;
         Ok(())
     }
 }
@@ -1,6 +1,11 @@
 //! IPC Bus integration
 
-use std::{convert::Infallible, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use chrono::Utc;
 use mycelium::{
@@ -11,7 +16,7 @@ use mycelium::{
     IPCError, IPCMsg,
 };
 use roundtable::{
-    handler::{DispatchErr, HandlerInit, LocalInterface, MethodRegister},
+    handler::{BgHandle, DispatchErr, HandlerInit, Interface, LocalInterface, MethodRegister},
     handler_decl_t, method_decl_owned,
     msg::{self, HandlerInstance, Str},
 };
@@ -24,16 +29,71 @@ use tokio::{
 };
 
 use crate::{
-    dispatch::application::{Record, EV_WEATHER_DATA_RECEIVED},
-    misc::Take,
+    alarm::{AlarmEvent, EV_ALARM},
+    core::{
+        autosave::EV_AUTOSAVE_FORCE,
+        config,
+        health::{Component, EV_HEALTH_ALIVE, EV_HEALTH_PING},
+    },
+    dispatch::application::{AppClient, Record, EV_APP_BEGIN_OTA, EV_WEATHER_DATA_RECEIVED},
+    metrics::METRICS,
+    misc::{make_private, Take},
     registry::{self, EV_META_NEW_CHANNEL, EV_META_NEW_STATION, EV_META_STATION_ASSOC_CHANNEL},
-    tsdb3::{bus::EV_DB_QUERY, query::QueryBuilder},
+    tsdb3::{
+        bus::{PageQuery, EV_DB_QUERY, EV_DB_QUERY_LATEST, EV_DB_QUERY_PAGE, EV_DB_STATS},
+        query::QueryBuilder,
+    },
 };
 
+/// Sets up the IPC socket at `path` (replacing any left over from a previous run), unless it is disabled in `cfg`
+pub async fn setup(
+    cfg: &config::Ipc,
+    path: PathBuf,
+    bus: &Interface,
+    registry: HandlerInstance,
+    database: HandlerInstance,
+    autosave: HandlerInstance,
+    health: HandlerInstance,
+) -> io::Result<()> {
+    if tokio::fs::try_exists(&path).await? {
+        tokio::fs::remove_file(&path).await?;
+    }
+    if !cfg.enabled {
+        info!("IPC is disabled, not creating a socket");
+        return Ok(());
+    }
+    debug!("Setting up IPC at {:?}", path);
+    if cfg.token.is_none() {
+        warn!(
+            "No IPC token is configured, any local user that can access the IPC socket may connect"
+        );
+    }
+    let ipc_stop = IPCNewConnections::new(
+        path,
+        registry,
+        database,
+        autosave,
+        health,
+        cfg.token.clone(),
+    )
+    .await?;
+    bus.spawn(ipc_stop);
+    info!("IPC configured");
+    Ok(())
+}
+
+/// maximum number of readings in one `QueryRangeResponse`
+const QUERY_RANGE_PAGE_SIZE: usize = 2000;
+/// how long station clients have to respond to a `BeginOTA`
+const BEGIN_OTA_DEADLINE: std::time::Duration = std::time::Duration::from_secs(1);
+
 pub struct IPCNewConnections {
     listener: Arc<UnixListener>,
     registry: HandlerInstance,
     database: HandlerInstance,
+    autosave: HandlerInstance,
+    health: HandlerInstance,
+    token: Option<String>,
 }
 
 impl IPCNewConnections {
@@ -41,11 +101,17 @@ impl IPCNewConnections {
         path: PathBuf,
         registry: HandlerInstance,
         database: HandlerInstance,
+        autosave: HandlerInstance,
+        health: HandlerInstance,
+        token: Option<String>,
     ) -> io::Result<Self> {
         Ok(Self {
-            listener: Arc::new(UnixListener::bind(path)?),
+            listener: Arc::new(bind_private(&path)?),
             registry,
             database,
+            autosave,
+            health,
+            token,
         })
     }
 
@@ -70,10 +136,15 @@ impl IPCNewConnections {
                 };
                 let conn = IPCConnection {
                     write,
-                    read: Take::new(read),
+                    read: Some(read),
+                    reader: None,
                     addr,
                     init_known: Take::new((stations, channels)),
+                    registry: self.registry.clone(),
                     database: self.database.clone(),
+                    autosave: self.autosave.clone(),
+                    health: self.health.clone(),
+                    token: self.token.clone(),
                 };
                 int.nonlocal.spawn(conn);
                 self.bg_handle_new_client(int);
@@ -86,6 +157,10 @@ impl IPCNewConnections {
         Ok(())
     }
 
+    async fn alive(&mut self, _: &(), _int: &LocalInterface) -> Result<Component, Infallible> {
+        Ok(Component::Ipc)
+    }
+
     fn bg_handle_new_client(&mut self, int: &LocalInterface) {
         let li = self.listener.clone();
         int.bg_spawn(EV_PRIV_NEW_CONNECTION, async move { li.accept().await });
@@ -108,9 +183,19 @@ impl HandlerInit for IPCNewConnections {
     // methods of this handler instance
     fn methods(&self, reg: &mut MethodRegister<Self>) {
         reg.register_owned(Self::handle_new_client, EV_PRIV_NEW_CONNECTION);
+        reg.register(Self::alive, EV_HEALTH_ALIVE);
     }
 }
 
+/// binds the IPC socket at `path`, only allowing its owner to connect (mode 0600)
+///
+/// (the socket briefly has default permissions, the directory containing it should also be private)
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let listener = UnixListener::bind(path)?;
+    make_private(path)?;
+    Ok(listener)
+}
+
 method_decl_owned!(
     EV_PRIV_NEW_CONNECTION,
     io::Result<(UnixStream, SocketAddr)>,
@@ -127,37 +212,90 @@ pub enum IPCConnectionErr {
 
 pub struct IPCConnection {
     write: OwnedWriteHalf,
-    read: Take<OwnedReadHalf>,
+    /// moved into the receive loop (see [`IPCConnection::bg_read`]) on init
+    read: Option<OwnedReadHalf>,
+    reader: Option<BgHandle>,
     addr: SocketAddr,
     init_known: Take<(KnownStations, KnownChannels)>,
+    registry: HandlerInstance,
     database: HandlerInstance,
+    autosave: HandlerInstance,
+    health: HandlerInstance,
+    /// token the client must send before anything else. `None` once authenticated (or if none is required)
+    token: Option<String>,
+}
+
+/// what to do with a message received from a client that has not yet authenticated
+#[derive(Debug, PartialEq, Eq)]
+enum AuthOutcome {
+    Accepted,
+    Rejected,
+}
+
+fn authenticate(expected: &str, msg: &mycelium::IPCMsgKind) -> AuthOutcome {
+    match msg {
+        mycelium::IPCMsgKind::Auth { token } if tokens_match(expected, token) => {
+            AuthOutcome::Accepted
+        }
+        _ => AuthOutcome::Rejected,
+    }
+}
+
+/// compares tokens in constant time (for tokens of the same length)
+pub(crate) fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
 }
 
 impl IPCConnection {
-    fn bg_read(&mut self, mut read: OwnedReadHalf, int: &LocalInterface) {
-        int.bg_spawn(EV_PRIV_READ, async move {
+    /// starts receiving messages from the client (until it disconnects, or an error occurs)
+    fn bg_read(&mut self, read: OwnedReadHalf, int: &LocalInterface) {
+        self.reader = Some(int.bg_loop(EV_PRIV_READ, read, |mut read| async move {
             let res = mycelium::ipc_recv::<IPCMsg>(&mut read).await;
             (read, res)
-        })
+        }));
     }
 
     async fn handle_read(
         &mut self,
-        (read, res): (OwnedReadHalf, Result<IPCMsg, IPCError>),
+        res: Result<IPCMsg, IPCError>,
         int: &LocalInterface,
     ) -> Result<(), IPCConnectionErr> {
-        self.read.put(read);
         let msg = res?;
+        if let Some(token) = &self.token {
+            if authenticate(token, &msg.kind) == AuthOutcome::Rejected {
+                warn!(
+                    "IPC Client {:?} failed to authenticate, disconnecting it",
+                    self.addr
+                );
+                let _ = self
+                    .send(&IPCMsg {
+                        kind: mycelium::IPCMsgKind::Bye,
+                    })
+                    .await;
+                return int.shutdown().await;
+            }
+            debug!("IPC Client {:?} authenticated", self.addr);
+            self.token = None;
+            self.send_init().await?;
+            return Ok(());
+        }
         trace!("IPC: Received {msg:?}");
         match msg.kind {
             mycelium::IPCMsgKind::ClientDisconnect => {
                 debug!("IPC Client {:?} disconnected", self.addr);
-                warn!("IPC Client disconnected, but the task will not close (TODO/unimplemented)");
+                if let Some(reader) = &self.reader {
+                    reader.cancel();
+                }
                 let _ = self
                     .send(&IPCMsg {
                         kind: mycelium::IPCMsgKind::Bye,
                     })
                     .await;
+                return int.shutdown().await;
             }
             mycelium::IPCMsgKind::QueryLastHourOf { station, channel } => {
                 let from_time = Utc::now();
@@ -177,13 +315,120 @@ impl IPCConnection {
                     kind: mycelium::IPCMsgKind::QueryLastHourResponse { data, from_time },
                 })
                 .await?;
-                let read = self.read.take();
-                self.bg_read(read, int);
             }
-            _other => {
-                let read = self.read.take();
-                self.bg_read(read, int);
+            mycelium::IPCMsgKind::QueryRange {
+                station,
+                channel,
+                from,
+                to,
+                cursor,
+            } => {
+                let (data, next_cursor) = int
+                    .query(
+                        self.database.clone(),
+                        EV_DB_QUERY_PAGE,
+                        PageQuery {
+                            station,
+                            channel,
+                            from,
+                            to,
+                            cursor,
+                            page_size: QUERY_RANGE_PAGE_SIZE,
+                        },
+                    )
+                    .await?;
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::QueryRangeResponse { data, next_cursor },
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::QueryLatest => {
+                let latest = int
+                    .query(self.database.clone(), EV_DB_QUERY_LATEST, ())
+                    .await?;
+                let mut by_station = HashMap::<_, HashMap<_, _>>::new();
+                for ((station, channel), value) in latest {
+                    by_station
+                        .entry(station)
+                        .or_default()
+                        .insert(channel, value);
+                }
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::LatestResponse(by_station),
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::QueryMetrics => {
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::MetricsResponse(
+                        METRICS.snapshot(int.nonlocal.metrics()),
+                    ),
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::QueryDBStats => {
+                let stats = int.query(self.database.clone(), EV_DB_STATS, ()).await?;
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::DBStatsResponse(stats),
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::QueryDiagnostics => {
+                let diagnostics = int
+                    .query(
+                        self.registry.clone(),
+                        registry::EV_REGISTRY_QUERY_DIAGNOSTICS,
+                        (),
+                    )
+                    .await?;
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::DiagnosticsResponse(diagnostics),
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::HealthCheck => {
+                let status = int.query(self.health.clone(), EV_HEALTH_PING, ()).await?;
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::HealthResponse(status),
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::ForceSave => {
+                info!("IPC Client {:?} requested a save", self.addr);
+                let handlers = int
+                    .query(self.autosave.clone(), EV_AUTOSAVE_FORCE, ())
+                    .await?;
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::SaveComplete {
+                        handlers: handlers as u32,
+                    },
+                })
+                .await?;
+            }
+            mycelium::IPCMsgKind::BeginOTA { station, update } => {
+                info!(
+                    "IPC Client {:?} requested a firmware update of station {station}",
+                    self.addr
+                );
+                let queued = int
+                    .dispatch_collect(
+                        msg::Target::Type(AppClient::DECL),
+                        EV_APP_BEGIN_OTA,
+                        (station, update),
+                        BEGIN_OTA_DEADLINE,
+                    )
+                    .await?
+                    .into_iter()
+                    .any(|queued| queued);
+                if !queued {
+                    warn!("Station {station} is not connected, the firmware update was not sent");
+                }
+                self.send(&IPCMsg {
+                    kind: mycelium::IPCMsgKind::OTAQueued { station, queued },
+                })
+                .await?;
             }
+            _other => {}
         }
         Ok(())
     }
@@ -192,11 +437,27 @@ impl IPCConnection {
         mycelium::ipc_send(&mut self.write, msg).await
     }
 
+    /// if the client may be sent data (it has authenticated, or no token is required)
+    fn authenticated(&self) -> bool {
+        self.token.is_none()
+    }
+
+    async fn send_init(&mut self) -> Result<(), IPCError> {
+        let (stations, channels) = self.init_known.take();
+        self.send(&IPCMsg {
+            kind: mycelium::IPCMsgKind::Haiii { stations, channels },
+        })
+        .await
+    }
+
     async fn new_station(
         &mut self,
         &id: &StationID,
         _int: &LocalInterface,
     ) -> Result<(), IPCConnectionErr> {
+        if !self.authenticated() {
+            return Ok(());
+        }
         self.send(&IPCMsg {
             kind: mycelium::IPCMsgKind::NewStation { id },
         })
@@ -209,6 +470,9 @@ impl IPCConnection {
         (id, ch): &(ChannelID, Channel),
         _int: &LocalInterface,
     ) -> Result<(), IPCConnectionErr> {
+        if !self.authenticated() {
+            return Ok(());
+        }
         self.send(&IPCMsg {
             kind: mycelium::IPCMsgKind::NewChannel {
                 id: *id,
@@ -224,6 +488,9 @@ impl IPCConnection {
         (station, channel, _channel_info): &(StationID, ChannelID, Channel),
         _int: &LocalInterface,
     ) -> Result<(), IPCConnectionErr> {
+        if !self.authenticated() {
+            return Ok(());
+        }
         self.send(&IPCMsg {
             kind: mycelium::IPCMsgKind::StationNewChannel {
                 station: *station,
@@ -248,6 +515,9 @@ impl IPCConnection {
         data: &Record,
         _int: &LocalInterface,
     ) -> Result<(), IPCConnectionErr> {
+        if !self.authenticated() {
+            return Ok(());
+        }
         self.send(&IPCMsg {
             kind: mycelium::IPCMsgKind::FreshHotData {
                 from: data.recorded_by,
@@ -258,6 +528,27 @@ impl IPCConnection {
         .await?;
         Ok(())
     }
+
+    async fn send_alarm(
+        &mut self,
+        alarm: &AlarmEvent,
+        _int: &LocalInterface,
+    ) -> Result<(), IPCConnectionErr> {
+        if !self.authenticated() {
+            return Ok(());
+        }
+        self.send(&IPCMsg {
+            kind: mycelium::IPCMsgKind::Alarm {
+                station: alarm.station,
+                channel: alarm.channel,
+                value: alarm.value,
+                rule: alarm.rule.clone(),
+                raised: alarm.raised,
+            },
+        })
+        .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -265,13 +556,12 @@ impl HandlerInit for IPCConnection {
     const DECL: msg::HandlerType = handler_decl_t!("IPC Connection Handler");
     type Error = IPCConnectionErr;
     async fn init(&mut self, int: &LocalInterface) -> Result<(), IPCConnectionErr> {
-        let read = self.read.take();
+        let read = self.read.take().expect("IPC connection initialized twice");
         self.bg_read(read, int);
-        let (stations, channels) = self.init_known.take();
-        self.send(&IPCMsg {
-            kind: mycelium::IPCMsgKind::Haiii { stations, channels },
-        })
-        .await?;
+        // (otherwise, sent once the client authenticates)
+        if self.authenticated() {
+            self.send_init().await?;
+        }
         Ok(())
     }
     // description of this handler instance
@@ -285,6 +575,7 @@ impl HandlerInit for IPCConnection {
         reg.register(Self::new_channel, EV_META_NEW_CHANNEL);
         reg.register(Self::station_new_channel, EV_META_STATION_ASSOC_CHANNEL);
         reg.register(Self::send_data, EV_WEATHER_DATA_RECEIVED);
+        reg.register(Self::send_alarm, EV_ALARM);
     }
     async fn on_error(&mut self, error: IPCConnectionErr, int: &LocalInterface) {
         error!(
@@ -295,4 +586,280 @@ impl HandlerInit for IPCConnection {
     }
 }
 
-method_decl_owned!(EV_PRIV_READ, (OwnedReadHalf, Result<IPCMsg, IPCError>), ());
+method_decl_owned!(EV_PRIV_READ, Result<IPCMsg, IPCError>, ());
+
+#[cfg(all(test, unix))]
+#[tokio::test]
+async fn socket_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = crate::misc::testing::temp_dir();
+    let path = dir.path().join("ipc.sock");
+    let _listener = bind_private(&path).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // (still usable by the owner)
+    UnixStream::connect(&path).await.unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn auth_tokens() {
+    use mycelium::IPCMsgKind;
+
+    let auth = |token: &str| IPCMsgKind::Auth {
+        token: token.into(),
+    };
+    assert_eq!(
+        authenticate("hunter2", &auth("hunter2")),
+        AuthOutcome::Accepted
+    );
+    assert_eq!(
+        authenticate("hunter2", &auth("hunter3")),
+        AuthOutcome::Rejected
+    );
+    assert_eq!(
+        authenticate("hunter2", &auth("hunter")),
+        AuthOutcome::Rejected
+    );
+    assert_eq!(authenticate("hunter2", &auth("")), AuthOutcome::Rejected);
+    // anything else sent first is rejected
+    assert_eq!(
+        authenticate("hunter2", &IPCMsgKind::QueryMetrics),
+        AuthOutcome::Rejected
+    );
+}
+
+#[cfg(test)]
+#[tokio::test(flavor = "multi_thread")]
+async fn disabled_ipc_creates_no_socket() {
+    use mycelium::station::capabilities::{
+        channel_id_for_name, ChannelData, ChannelType, ChannelValue,
+    };
+    use roundtable::{common::HDL_EXTERNAL, Bus};
+    use squirrel::api::{OnConnect, PacketKind, SomeData};
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        core::shutdown::Shutdown,
+        dispatch::{Controller, EV_TRANS_CLI_DATA_RECVD, EV_TRANS_CLI_QUEUE_DATA},
+        tsdb3::{bus::TStopDBus3, DB},
+    };
+
+    /// the station's transport client, which drops what is sent to the station
+    struct Transport;
+
+    #[async_trait]
+    impl HandlerInit for Transport {
+        const DECL: msg::HandlerType = handler_decl_t!("Test transport client");
+        type Error = Infallible;
+        fn describe(&self) -> Str {
+            Str::Borrowed("Test transport client")
+        }
+        fn methods(&self, reg: &mut MethodRegister<Self>) {
+            reg.register(Self::queue, EV_TRANS_CLI_QUEUE_DATA);
+        }
+    }
+
+    impl Transport {
+        async fn queue(&mut self, _: &Vec<u8>, _int: &LocalInterface) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    let dir = crate::misc::testing::temp_dir();
+    let path = dir.path().join("ipc.sock");
+    // left over from a previous run
+    std::fs::write(&path, []).unwrap();
+    let bus = Bus::new(Default::default()).await;
+    let shutdown = Shutdown::new();
+    let registry = test_registry(&bus, dir.path(), &shutdown).await;
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let db = bus.spawn_checked(TStopDBus3::new(db)).await.unwrap();
+    // (the other handlers are only used by connections)
+    let setup = |cfg: config::Ipc| {
+        let (path, bus, registry, db) = (path.clone(), bus.clone(), registry.clone(), db.clone());
+        async move { setup(&cfg, path, &bus, registry, db.clone(), db.clone(), db).await }
+    };
+
+    setup(config::Ipc {
+        enabled: false,
+        token: None,
+    })
+    .await
+    .unwrap();
+    assert!(!path.exists());
+
+    // data from a station is still recorded
+    let controller = bus
+        .spawn_checked(Controller::new(
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            std::time::Duration::from_secs(30),
+            registry.clone(),
+            false,
+            Default::default(),
+            16,
+            None,
+        ))
+        .await
+        .unwrap();
+    let transport = bus.spawn_checked(Transport).await.unwrap();
+    let client = bus
+        .spawn_checked(AppClient::new(
+            "127.0.0.1:1234".parse().unwrap(),
+            controller,
+            transport,
+            registry.clone(),
+            false,
+            Arc::new(squirrel::clock::SystemClock),
+        ))
+        .await
+        .unwrap();
+    let receive = |packet: PacketKind| {
+        bus.query_as(
+            HDL_EXTERNAL,
+            client.clone(),
+            EV_TRANS_CLI_DATA_RECVD,
+            rmp_serde::to_vec_named(&packet).unwrap(),
+        )
+    };
+    let sid = StationID::new_v4();
+    receive(PacketKind::Connect(OnConnect {
+        station_id: sid,
+        station_build_rev: "abcdef".into(),
+        station_build_date: "2024-01-01T00:00:00Z".into(),
+        channels: vec![Channel {
+            name: "temperature".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: None,
+            description: None,
+        }],
+        degraded_channels: vec![],
+        provisioning_token: None,
+        compression: vec![],
+    }))
+    .await
+    .unwrap();
+    let cid = channel_id_for_name(&"temperature".into());
+    receive(PacketKind::Data(SomeData {
+        per_channel: [(cid, ChannelData::Float(1.0))].into(),
+        recorded_at: None,
+        seq: 1,
+    }))
+    .await
+    .unwrap();
+    let latest = bus
+        .query_as(HDL_EXTERNAL, db.clone(), EV_DB_QUERY_LATEST, ())
+        .await
+        .unwrap();
+    assert!(matches!(latest[&(sid, cid)].1, ChannelData::Float(v) if v == 1.0));
+
+    setup(config::Ipc::default()).await.unwrap();
+    assert!(path.exists());
+}
+
+#[cfg(test)]
+#[tokio::test(flavor = "multi_thread")]
+async fn wrong_token_is_disconnected() {
+    use mycelium::{ipc_recv, ipc_send, IPCMsgKind};
+    use roundtable::Bus;
+    use tokio::io::AsyncReadExt;
+
+    use crate::{
+        core::shutdown::Shutdown,
+        tsdb3::{bus::TStopDBus3, DB},
+    };
+
+    let dir = crate::misc::testing::temp_dir();
+    let path = dir.path().join("ipc.sock");
+    let bus = Bus::new(Default::default()).await;
+    let shutdown = Shutdown::new();
+    let registry = test_registry(&bus, dir.path(), &shutdown).await;
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let db = bus.spawn_checked(TStopDBus3::new(db)).await.unwrap();
+    let cfg = config::Ipc {
+        enabled: true,
+        token: Some("hunter2".into()),
+    };
+    setup(
+        &cfg,
+        path.clone(),
+        &bus,
+        registry,
+        db.clone(),
+        db.clone(),
+        db,
+    )
+    .await
+    .unwrap();
+
+    let connect = |token: &'static str| {
+        let path = path.clone();
+        async move {
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            let auth = IPCMsg {
+                kind: IPCMsgKind::Auth {
+                    token: token.into(),
+                },
+            };
+            ipc_send(&mut stream, &auth).await.unwrap();
+            stream
+        }
+    };
+    let mut accepted = connect("hunter2").await;
+    let msg = ipc_recv::<IPCMsg>(&mut accepted).await.unwrap();
+    assert!(matches!(msg.kind, IPCMsgKind::Haiii { .. }));
+    // a client that disconnects is sent off, and its connection closed
+    let bye = IPCMsg {
+        kind: IPCMsgKind::ClientDisconnect,
+    };
+    ipc_send(&mut accepted, &bye).await.unwrap();
+    let msg = ipc_recv::<IPCMsg>(&mut accepted).await.unwrap();
+    assert!(matches!(msg.kind, IPCMsgKind::Bye));
+    let mut rest = vec![];
+    let read = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        accepted.read_to_end(&mut rest),
+    );
+    assert_eq!(read.await.unwrap().unwrap(), 0);
+
+    let mut rejected = connect("hunter3").await;
+    let msg = ipc_recv::<IPCMsg>(&mut rejected).await.unwrap();
+    assert!(matches!(msg.kind, IPCMsgKind::Bye));
+    // and the connection is closed
+    let mut rest = vec![];
+    let read = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        rejected.read_to_end(&mut rest),
+    );
+    assert_eq!(read.await.unwrap().unwrap(), 0);
+}
+
+/// a registry (for connections to query) stored in `dir`
+#[cfg(test)]
+async fn test_registry(
+    bus: &Interface,
+    dir: &Path,
+    shutdown: &crate::core::shutdown::Shutdown,
+) -> HandlerInstance {
+    use crate::registry::{audit::AuditLog, loader::JsonLoader, Registry};
+
+    bus.spawn_checked(Registry::new(
+        JsonLoader::open(dir.join("stations.json"), shutdown.handle(), false)
+            .await
+            .unwrap(),
+        JsonLoader::open(dir.join("channels.json"), shutdown.handle(), false)
+            .await
+            .unwrap(),
+        AuditLog::open(dir.join("audit.jsonl"), false)
+            .await
+            .unwrap(),
+        config::Registry::default().into(),
+        (&config::Registry::default()).into(),
+    ))
+    .await
+    .unwrap()
+}
@@ -11,15 +11,20 @@ extern crate tracing;
 #[macro_use]
 extern crate anyhow;
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use roundtable::{common::HDL_EXTERNAL, Bus};
-use squirrel::api::station::{capabilities::KnownChannels, identity::KnownStations};
+use squirrel::{
+    api::station::{capabilities::KnownChannels, identity::KnownStations},
+    clock::{Clock, SystemClock},
+};
 use tokio::net::UdpSocket;
 
+mod alarm;
 mod core;
 mod dispatch;
 mod ipc;
+mod metrics;
 mod misc;
 mod registry;
 pub mod tsdb3;
@@ -31,7 +36,11 @@ use core::{
 use misc::RecordsPath;
 use registry::JsonLoader;
 
-use crate::{core::AutosaveDispatch, registry::Registry};
+use crate::{
+    alarm::AlarmDispatch,
+    core::{health::HealthCheck, AutosaveDispatch, RetentionDispatch},
+    registry::Registry,
+};
 
 fn main() -> anyhow::Result<()> {
     core::rt::stage0_delegate()
@@ -49,19 +58,38 @@ async fn async_main(
         trap_ctrl_c(shutdown.handle()).await;
     }
 
-    let addrs = core::lookup_server_ip(cfg.server.url, cfg.server.port).await?;
-    let bus = Bus::new().await;
+    let addrs = match cfg.server.bind_addr() {
+        Some(addr) => {
+            info!("Using configured bind address {addr}");
+            vec![addr]
+        }
+        None => core::lookup_server_ip(cfg.server.url.clone(), cfg.server.port).await?,
+    };
+    let bus = Bus::new(cfg.bus.into()).await;
+    let _recorder = match &args.record_bus {
+        Some(path) => Some(core::bus_record::start(&bus, path)?),
+        None => None,
+    };
 
+    if args.read_only {
+        warn!("Running read-only, changes to the registry (new stations and channels) will NOT be saved");
+    }
     info!("Loading info for known stations");
-    let stations =
-        JsonLoader::<KnownStations>::open(records_dir.path("stations.json"), shutdown.handle())
-            .await?;
+    let stations = JsonLoader::<KnownStations>::open(
+        records_dir.path("stations.json"),
+        shutdown.handle(),
+        args.read_only,
+    )
+    .await?;
     debug!("Loaded known stations:");
 
     info!("Loading known channels");
-    let channels =
-        JsonLoader::<KnownChannels>::open(records_dir.path("channels.json"), shutdown.handle())
-            .await?;
+    let mut channels = JsonLoader::<KnownChannels>::open(
+        records_dir.path("channels.json"),
+        shutdown.handle(),
+        args.read_only,
+    )
+    .await?;
 
     debug!(
         "Loaded known channels: {:#?}",
@@ -71,6 +99,22 @@ async fn async_main(
             .collect::<Vec<_>>()
     );
 
+    let problems = registry::validate(&stations, &channels);
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{problem}");
+        }
+        error!("the station/channel registry is inconsistent (was it edited by hand?)");
+        bail!("Invalid registry");
+    }
+
+    for id in registry::define_computed(&mut channels, &cfg.computed) {
+        info!(
+            "Registered computed channel {id} ({:?})",
+            channels.get_channel(&id).unwrap().name
+        );
+    }
+
     for s in stations.stations() {
         // in the future, station info should be printed
         let info = stations.get_info(s).unwrap();
@@ -80,36 +124,37 @@ async fn async_main(
         );
     }
 
-    let registry = bus.spawn(Registry::new(stations, channels));
+    info!("Loading registry audit log");
+    let audit =
+        registry::audit::AuditLog::open(records_dir.path("registry_audit.jsonl"), args.read_only)
+            .await?;
+    debug!("Loaded {} audit records", audit.records().len());
+
+    let registry = bus
+        .spawn_checked(Registry::new(
+            stations,
+            channels,
+            audit,
+            cfg.registry.clone().into(),
+            (&cfg.registry).into(),
+        ))
+        .await?;
 
     debug!("Loading database [TSDB v3]");
     let db = {
         let path = match cfg.database.storage {
             core::config::StorageMode::default => records_dir.path("data.tsdb3"),
-            core::config::StorageMode::file => {
-                if cfg.database.files.is_empty() {
-                    error!(
-                        "storage mode: 'file' was selected, but no files were given for storage"
-                    );
-                    bail!("Invalid config");
-                } else if cfg.database.files.len() == 1 {
-                    cfg.database.files[0].path.clone()
-                } else {
-                    error!("storage mode: 'file' was selected, but multiple files were provided. TSDB v3 does not yet support this");
-                    bail!("Invalid config");
-                }
-            }
+            // (exactly one file, checked by `Config::validate`)
+            core::config::StorageMode::file => cfg.database.files[0].path.clone(),
         };
-        let file = tokio::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(path)
-            .await?
-            .into_std()
-            .await;
+        if args.read_only {
+            warn!("Opening the database read-only, data received from weather stations will NOT be recorded");
+        }
         // Saftey: YOLO
-        let mut db = unsafe { tsdb3::DB::new(file) }?;
-        db.open();
+        let mut db = unsafe {
+            tsdb3::DB::open_path(&path, cfg.database.initial_size_bytes, args.read_only)
+        }?;
+        db.set_compression(cfg.database.compress);
         let mut stop = tsdb3::bus::TStopDBus3::new(db);
         let (stations, channels) = bus
             .query_as(
@@ -120,28 +165,92 @@ async fn async_main(
             )
             .await?;
         stop.ensure_exists(&(stations, channels)).await;
-        bus.spawn(stop)
+        bus.spawn_checked(stop).await?
     };
 
-    let ipc_path = run_dir.path("ipc.sock");
-    debug!("Setting up IPC at {:?}", ipc_path);
-    if tokio::fs::try_exists(&ipc_path).await? {
-        tokio::fs::remove_file(&ipc_path).await?;
+    // (shared by the autosave dispatch and the station interfaces)
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let autosave_interval = Duration::from_secs(cfg.database.autosave_interval_secs);
+    info!("Autosaves will be triggered every {autosave_interval:?}");
+    let autosave = bus.spawn(AutosaveDispatch::new(autosave_interval, clock.clone()));
+    if let Some(policy) = cfg.retention {
+        if args.read_only {
+            warn!("The database is read-only, so the retention policy will not be applied");
+        } else {
+            info!(
+                "Old data will be freed every {}s (see the [retention] config)",
+                policy.interval_secs
+            );
+            bus.spawn(RetentionDispatch::new(policy, db.clone()));
+        }
+    }
+    // (stations normally send data every few minutes)
+    let health = bus.spawn(HealthCheck::new(Duration::from_secs(60 * 60)));
+
+    if !cfg.alarms.is_empty() {
+        let (_, channels) = bus
+            .query_as(
+                HDL_EXTERNAL,
+                registry.clone(),
+                registry::EV_REGISTRY_QUERY_ALL,
+                (),
+            )
+            .await?;
+        info!("Watching readings for {} alarm(s)", cfg.alarms.len());
+        bus.spawn(AlarmDispatch::new(cfg.alarms.clone(), &channels));
     }
-    let ipc_stop = ipc::IPCNewConnections::new(ipc_path, registry.clone(), db.clone()).await?;
-    bus.spawn(ipc_stop);
-    info!("IPC configured");
 
-    let autosave_interval = Duration::from_secs(30);
-    info!("Autosaves will be triggered every {autosave_interval:?}");
-    bus.spawn(AutosaveDispatch::new(autosave_interval));
+    ipc::setup(
+        &cfg.ipc,
+        run_dir.path("ipc.sock"),
+        &bus,
+        registry.clone(),
+        db.clone(),
+        autosave,
+        health,
+    )
+    .await?;
 
     info!("running -- press ctrl+c to exit");
-    let sock = UdpSocket::bind(addrs.as_slice()).await?;
     let max_transaction_time = Duration::from_secs(30);
-
-    let dispatch_ctrl = dispatch::Controller::new(sock, max_transaction_time, registry.clone());
-    bus.spawn(dispatch_ctrl);
+    let (v4_addrs, v6_addrs) = core::split_by_family(&addrs);
+    let mut bound = 0;
+    // one controller per address family
+    for family_addrs in [v4_addrs, v6_addrs] {
+        if family_addrs.is_empty() {
+            continue;
+        }
+        let sock = match UdpSocket::bind(family_addrs.as_slice()).await {
+            Ok(sock) => sock,
+            Err(e) => {
+                warn!("Failed to bind to any of {family_addrs:?}: {e:#}");
+                continue;
+            }
+        };
+        if let Some(iface) = &cfg.server.bind_interface {
+            if let Err(e) = core::bind_to_interface(&sock, iface) {
+                error!("Failed to bind to interface {iface:?}: {e:#}");
+                bail!("Invalid config");
+            }
+            info!("Bound to interface {iface:?}");
+        }
+        info!("Listening for weather stations on {:?}", sock.local_addr()?);
+        let dispatch_ctrl = dispatch::Controller::new(
+            sock,
+            max_transaction_time,
+            registry.clone(),
+            args.read_only,
+            cfg.server.rate_limit,
+            cfg.server.max_peers,
+            cfg.server.keepalive,
+        )
+        .with_clock(clock.clone());
+        bus.spawn(dispatch_ctrl);
+        bound += 1;
+    }
+    if bound == 0 {
+        bail!("Failed to bind to any of the server's addresses ({addrs:?})");
+    }
 
     shutdown.handle().wait_for_shutdown().await;
 
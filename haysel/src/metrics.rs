@@ -0,0 +1,100 @@
+//! Server activity counters, exposed over IPC
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use mycelium::ServerMetrics;
+use roundtable::metrics::BusMetrics;
+use squirrel::transport::server::DispatchEvent;
+
+/// counters for the whole server (bus counters are kept by the bus itself)
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    packets_received: AtomicU64,
+    packets_dropped: AtomicU64,
+    packets_sent: AtomicU64,
+    transactions_completed: AtomicU64,
+    transactions_timed_out: AtomicU64,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            packets_received: AtomicU64::new(0),
+            packets_dropped: AtomicU64::new(0),
+            packets_sent: AtomicU64::new(0),
+            transactions_completed: AtomicU64::new(0),
+            transactions_timed_out: AtomicU64::new(0),
+        }
+    }
+
+    pub fn packet_received(&self) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_dropped(&self) {
+        self.packets_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// record the outcome of a `ClientInterface` handling a packet
+    pub fn dispatch_event(&self, ev: &DispatchEvent) {
+        match ev {
+            DispatchEvent::Send(..) => &self.packets_sent,
+            DispatchEvent::Received(..) => &self.transactions_completed,
+            DispatchEvent::TimedOut => &self.transactions_timed_out,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, bus: &BusMetrics) -> ServerMetrics {
+        ServerMetrics {
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            packets_dropped: self.packets_dropped.load(Ordering::Relaxed),
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            transactions_completed: self.transactions_completed.load(Ordering::Relaxed),
+            transactions_timed_out: self.transactions_timed_out.load(Ordering::Relaxed),
+            bus_dispatched: bus.dispatched(),
+            bus_lagged: bus.lagged(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn counts_dispatch_events() {
+    use std::time::Duration;
+
+    use squirrel::transport::{server::ClientInterface, Cmd, CmdKind, Packet, PACKET_TYPE_COMMAND};
+
+    let metrics = Metrics::new();
+    let bus = BusMetrics::default();
+    assert_eq!(metrics.snapshot(&bus), ServerMetrics::default());
+    // a station starting a transaction, which the server confirms
+    let mut inter = ClientInterface::new(Duration::from_secs(30));
+    metrics.packet_received();
+    for ev in inter.handle(Packet::Cmd(Cmd {
+        packet: 1,
+        responding_to: 0,
+        packet_ty: PACKET_TYPE_COMMAND,
+        command: CmdKind::Tx as _,
+        frame_size: 0,
+    })) {
+        metrics.dispatch_event(&ev);
+    }
+    // .. which completes, followed by garbage and a transaction that never finishes
+    metrics.dispatch_event(&DispatchEvent::Received(vec![1, 2, 3]));
+    metrics.packet_dropped();
+    metrics.dispatch_event(&DispatchEvent::TimedOut);
+    assert_eq!(
+        metrics.snapshot(&bus),
+        ServerMetrics {
+            packets_received: 1,
+            packets_dropped: 1,
+            packets_sent: 1,
+            transactions_completed: 1,
+            transactions_timed_out: 1,
+            ..Default::default()
+        }
+    );
+}
@@ -2,6 +2,8 @@
 
 pub mod paths;
 pub mod take;
+#[cfg(test)]
+pub mod testing;
 
-pub use paths::RecordsPath;
+pub use paths::{make_private, RecordsPath};
 pub use take::Take;
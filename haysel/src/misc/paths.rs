@@ -26,6 +26,16 @@ impl RecordsPath {
         Ok(())
     }
 
+    /// Same as [`RecordsPath::ensure_exists_blocking`], but also makes the directory only accessible by its owner (mode 0700)
+    ///
+    /// for directories holding anything private (e.g. the IPC socket), so that nothing inside is exposed,
+    /// even before its own permissions are set
+    pub fn ensure_private_blocking(&self) -> Result<()> {
+        self.ensure_exists_blocking()?;
+        make_private(&self.records_dir)?;
+        Ok(())
+    }
+
     /// Returns the path with the requested file extension.
     /// does not allow for nesting in subdirectories
     ///
@@ -44,3 +54,35 @@ impl RecordsPath {
         }
     }
 }
+
+/// Makes `path` only accessible by its owner: mode 0700 for directories, and 0600 for anything else
+#[cfg(unix)]
+pub fn make_private(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if path.is_dir() { 0o700 } else { 0o600 };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn make_private(path: &Path) -> std::io::Result<()> {
+    warn!("Can not restrict permissions of {path:?} on this platform");
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn private_dir_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = super::testing::temp_dir();
+    // (created by `ensure_private_blocking`)
+    let dir = tmp.path().join("records");
+    let records = RecordsPath::new(dir.clone());
+    records.ensure_private_blocking().unwrap();
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&dir), 0o700);
+    // an existing (world readable) directory is fixed
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    records.ensure_private_blocking().unwrap();
+    assert_eq!(mode(&dir), 0o700);
+}
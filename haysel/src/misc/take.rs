@@ -1,7 +1,4 @@
-use std::{
-    mem::replace,
-    ops::{Deref, DerefMut},
-};
+use std::ops::{Deref, DerefMut};
 
 pub struct Take<T>(Option<T>);
 
@@ -14,10 +11,6 @@ impl<T> Take<T> {
     pub fn take(&mut self) -> T {
         self.0.take().unwrap()
     }
-
-    pub fn put(&mut self, val: T) -> Option<T> {
-        replace(&mut self.0, Some(val))
-    }
 }
 
 impl<T> Deref for Take<T> {
@@ -0,0 +1,11 @@
+//! helpers shared by tests
+
+use tempfile::TempDir;
+
+/// a new, empty directory for a test to use, which is removed (with everything in it) when dropped
+pub fn temp_dir() -> TempDir {
+    tempfile::Builder::new()
+        .prefix("haysel-test-")
+        .tempdir()
+        .expect("failed to create a temporary directory")
+}
@@ -1,33 +1,80 @@
+pub mod admission;
+pub mod audit;
 pub mod loader;
 
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::discriminant,
+    net::SocketAddr,
+};
 
+use admission::Admission;
+use audit::{AuditEvent, AuditLog, AuditRecord};
+use chrono::{DateTime, Utc};
 pub use loader::JsonLoader;
-use mycelium::station::{
-    capabilities::{Channel, ChannelID, ChannelName, KnownChannels},
-    identity::{KnownStations, StationID, StationInfo},
+use mycelium::{
+    station::{
+        capabilities::{Channel, ChannelData, ChannelID, ChannelName, ChannelType, KnownChannels},
+        identity::{KnownStations, StationID, StationInfo},
+    },
+    StationDiagnostics,
 };
 use roundtable::{
     common::EV_BUILTIN_AUTOSAVE,
-    handler::{DispatchErr, HandlerInit, LocalInterface, MethodRegister},
+    handler::{DispatchErr, HandlerError, HandlerInit, LocalInterface, MethodRegister},
     handler_decl_t, method_decl,
     msg::{self, Str},
 };
-use squirrel::api::OnConnect;
+use squirrel::api::{ChannelMappings, Compression, OnConnect};
 
-use crate::misc::Take;
+use crate::{core::config, misc::Take};
 
 pub struct Registry {
     stations: Take<JsonLoader<KnownStations>>,
     channels: Take<JsonLoader<KnownChannels>>,
+    // address each station last sent `Connect` from (not persisted)
+    sources: SourceBindings,
+    // latest `Diagnostics` from each station (not persisted)
+    diagnostics: HashMap<StationID, StationDiagnostics>,
+    audit: AuditLog,
+    limits: Limits,
+    admission: Admission,
+}
+
+/// Limits on what stations may register (see `config::Registry`)
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_stations: usize,
+    pub max_channels_per_station: usize,
 }
 
 method_decl!(EV_REGISTRY_QUERY_ALL, (), (KnownStations, KnownChannels));
 method_decl!(EV_REGISTRY_QUERY_CHANNEL, ChannelID, Option<Channel>);
+// changes to the registry caused by a station, oldest first
+method_decl!(EV_REGISTRY_QUERY_HISTORY, StationID, Vec<AuditRecord>);
+// fails if the station is not allowed to connect, or registering it would exceed the registry's limits
 method_decl!(
     EV_REGISTRY_PROCESS_CONNECT,
     (SocketAddr, OnConnect),
-    HashMap<ChannelName, ChannelID>
+    ChannelMappings
+);
+// if data from the given address should be accepted for the station
+// (it is the address the station last connected from)
+method_decl!(EV_REGISTRY_CHECK_SOURCE, (StationID, SocketAddr), bool);
+method_decl!(
+    EV_REGISTRY_COMPUTE_DERIVED,
+    (StationID, HashMap<ChannelID, ChannelData>),
+    HashMap<ChannelID, ChannelData>
+);
+method_decl!(
+    EV_REGISTRY_RECORD_DIAGNOSTICS,
+    (StationID, StationDiagnostics),
+    ()
+);
+method_decl!(
+    EV_REGISTRY_QUERY_DIAGNOSTICS,
+    (),
+    HashMap<StationID, StationDiagnostics>
 );
 method_decl!(EV_META_NEW_STATION, StationID, ());
 method_decl!(EV_META_NEW_CHANNEL, (ChannelID, Channel), ());
@@ -50,7 +97,12 @@ impl HandlerInit for Registry {
     fn methods(&self, reg: &mut MethodRegister<Self>) {
         reg.register(Self::query_all, EV_REGISTRY_QUERY_ALL);
         reg.register(Self::query_channel, EV_REGISTRY_QUERY_CHANNEL);
-        reg.register(Self::process_connect, EV_REGISTRY_PROCESS_CONNECT);
+        reg.register(Self::query_history, EV_REGISTRY_QUERY_HISTORY);
+        reg.register_fallible(Self::process_connect, EV_REGISTRY_PROCESS_CONNECT);
+        reg.register(Self::check_source, EV_REGISTRY_CHECK_SOURCE);
+        reg.register(Self::compute_derived, EV_REGISTRY_COMPUTE_DERIVED);
+        reg.register(Self::record_diagnostics, EV_REGISTRY_RECORD_DIAGNOSTICS);
+        reg.register(Self::query_diagnostics, EV_REGISTRY_QUERY_DIAGNOSTICS);
         reg.register(Self::sync, EV_BUILTIN_AUTOSAVE);
     }
     async fn on_error(&mut self, error: Self::Error, int: &LocalInterface) {
@@ -65,10 +117,29 @@ impl HandlerInit for Registry {
 }
 
 impl Registry {
-    pub fn new(stations: JsonLoader<KnownStations>, channels: JsonLoader<KnownChannels>) -> Self {
+    pub fn new(
+        stations: JsonLoader<KnownStations>,
+        channels: JsonLoader<KnownChannels>,
+        audit: AuditLog,
+        limits: Limits,
+        mut admission: Admission,
+    ) -> Self {
+        admission.restore(audit.records());
         Self {
             stations: Take::new(stations),
             channels: Take::new(channels),
+            sources: SourceBindings::default(),
+            diagnostics: HashMap::new(),
+            audit,
+            limits,
+            admission,
+        }
+    }
+
+    /// records changes in the audit log. failures are logged, but the changes are still made
+    async fn audit(&mut self, records: Vec<AuditRecord>) {
+        if let Err(e) = self.audit.append(records).await {
+            error!("Failed to write to the registry audit log: {e:#}");
         }
     }
 
@@ -95,85 +166,1155 @@ impl Registry {
         Ok(self.channels.get_channel(id).cloned())
     }
 
+    async fn query_history(
+        &mut self,
+        station: &StationID,
+        _int: &LocalInterface,
+    ) -> Result<Vec<AuditRecord>, DispatchErr> {
+        Ok(self.audit.station_history(station))
+    }
+
+    async fn record_diagnostics(
+        &mut self,
+        (station, diagnostics): &(StationID, StationDiagnostics),
+        _int: &LocalInterface,
+    ) -> Result<(), DispatchErr> {
+        self.diagnostics.insert(*station, diagnostics.clone());
+        Ok(())
+    }
+
+    async fn query_diagnostics(
+        &mut self,
+        _: &(),
+        _int: &LocalInterface,
+    ) -> Result<HashMap<StationID, StationDiagnostics>, DispatchErr> {
+        Ok(self.diagnostics.clone())
+    }
+
     async fn process_connect(
         &mut self,
         (ip, data): &(SocketAddr, OnConnect),
         int: &LocalInterface,
-    ) -> Result<HashMap<ChannelName, ChannelID>, DispatchErr> {
-        let (ip, data) = (ip.clone(), data.clone());
-        let name_to_id_mappings = data
-            .channels
-            .iter()
-            .map(|ch| {
-                (
-                    ch.name.clone(),
-                    self.channels
-                        .id_by_name(&ch.name)
-                        .map(|id| (id, false))
-                        .unwrap_or_else(|| {
-                            info!("creating new channel: {ch:?}");
-                            (self.channels.insert_channel(ch.clone()).unwrap(), true)
-                        }),
-                )
-            })
-            .collect::<HashMap<ChannelName, (ChannelID, bool)>>();
-        for (ch_id, _) in name_to_id_mappings.values().filter(|(_, is_new)| *is_new) {
-            let ch = self.channels.get_channel(&ch_id).unwrap();
-            int.announce(msg::Target::Any, EV_META_NEW_CHANNEL, (*ch_id, ch.clone()))
-                .await?;
+    ) -> Result<Result<ChannelMappings, HandlerError>, DispatchErr> {
+        let claim = match self
+            .admission
+            .check(data.station_id, data.provisioning_token.as_deref())
+        {
+            Ok(claim) => claim,
+            Err(e) => {
+                warn!(
+                    "refusing to connect station [{}] at IP {:?}: {e}",
+                    data.station_id, ip
+                );
+                return Ok(Err(HandlerError::new(e)));
+            }
+        };
+        let outcome = match apply_connect(&mut self.stations, &mut self.channels, data, self.limits)
+        {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                warn!(
+                    "refusing to register station [{}] at IP {:?}: {e}",
+                    data.station_id, ip
+                );
+                return Ok(Err(HandlerError::new(e)));
+            }
+        };
+        let mut records = audit_records(data.station_id, &outcome, &self.channels, Utc::now());
+        if let Some(token_hash) = claim {
+            info!("station [{}] claimed a provisioning token", data.station_id);
+            self.admission.claim(data.station_id, token_hash.clone());
+            records.insert(
+                0,
+                AuditRecord {
+                    time: Utc::now(),
+                    station: data.station_id,
+                    event: AuditEvent::ClaimedToken { token_hash },
+                },
+            );
         }
-        let name_to_id_mappings = name_to_id_mappings
-            .into_iter()
-            .map(|(k, (v, _))| (k, v))
-            .collect::<HashMap<ChannelName, ChannelID>>();
-        if let Some(pre_info) = self.stations.get_info(&data.station_id) {
+        self.audit(records).await;
+        if let Some(prev) = self.sources.bind(data.station_id, *ip) {
+            warn!(
+                "station [{}] moved from IP {:?} to {:?}, data from the old address will be rejected",
+                data.station_id, prev, ip
+            );
+        }
+        if outcome.new_station {
             info!(
-                "connecting to known station [{}] at IP {:?}\n    hayselnut rev {}\n    built on {}",
-                data.station_id,
-                ip,
-                data.station_build_rev,
-                data.station_build_date
+                "connected to new station [{}] at IP {:?}\n    hayselnut rev {}\n    built on {}",
+                data.station_id, ip, data.station_build_rev, data.station_build_date
             );
-            for new_channel in name_to_id_mappings
-                .values()
-                .filter(|&id| !pre_info.supports_channels.contains(id))
-            {
-                let ch = self.channels.get_channel(new_channel).unwrap();
-                int.announce(
-                    msg::Target::Any,
-                    EV_META_STATION_ASSOC_CHANNEL,
-                    (data.station_id, *new_channel, ch.clone()),
-                )
-                .await?;
-            }
-            self.stations.map_info(&data.station_id, |_id, info| {
-                info.supports_channels = name_to_id_mappings.values().copied().collect()
-            });
         } else {
             info!(
-                "connected to new station [{}] at IP {:?}\n    hayselnut rev {}\n    built on {}",
+                "connecting to known station [{}] at IP {:?}\n    hayselnut rev {}\n    built on {}",
                 data.station_id, ip, data.station_build_rev, data.station_build_date
             );
-            self.stations
-                .insert_station(
-                    data.station_id,
-                    StationInfo {
-                        supports_channels: name_to_id_mappings.values().copied().collect(),
-                    },
-                )
-                .unwrap();
-            int.announce(msg::Target::Any, EV_META_NEW_STATION, data.station_id)
+        }
+        for name in &outcome.conflicts {
+            let (_, known) = self.channels.find_by_name(name).unwrap();
+            let declared = data.channels.iter().find(|ch| ch.name == *name).unwrap();
+            warn!(
+                "station [{}] declared channel {name:?} as {:?} ({:?}), but it is known as {:?} ({:?}) -- it will not be mapped",
+                data.station_id, declared.value, declared.ty, known.value, known.ty
+            );
+        }
+        if !data.degraded_channels.is_empty() {
+            warn!(
+                "station [{}] failed its self test, no data will be sent for channels {:?}",
+                data.station_id, data.degraded_channels
+            );
+        }
+        for ch_id in &outcome.new_channels {
+            let ch = self.channels.get_channel(ch_id).unwrap();
+            info!("created new channel: {ch:?}");
+            int.announce(msg::Target::Any, EV_META_NEW_CHANNEL, (*ch_id, ch.clone()))
                 .await?;
-            for new_channel in name_to_id_mappings.values() {
-                let ch = self.channels.get_channel(new_channel).unwrap();
-                int.announce(
-                    msg::Target::Any,
-                    EV_META_STATION_ASSOC_CHANNEL,
-                    (data.station_id, *new_channel, ch.clone()),
-                )
+        }
+        if outcome.new_station {
+            int.announce(msg::Target::Any, EV_META_NEW_STATION, data.station_id)
                 .await?;
+        }
+        for new_channel in &outcome.new_assoc {
+            let ch = self.channels.get_channel(new_channel).unwrap();
+            int.announce(
+                msg::Target::Any,
+                EV_META_STATION_ASSOC_CHANNEL,
+                (data.station_id, *new_channel, ch.clone()),
+            )
+            .await?;
+        }
+        Ok(Ok(ChannelMappings {
+            map: outcome.mappings,
+            conflicts: outcome.conflicts,
+            // (negotiated by the station's `AppClient`)
+            compression: Compression::None,
+        }))
+    }
+
+    async fn check_source(
+        &mut self,
+        (station, ip): &(StationID, SocketAddr),
+        _int: &LocalInterface,
+    ) -> Result<bool, DispatchErr> {
+        Ok(self.sources.is_bound_to(station, ip))
+    }
+
+    /// computes the values of all computed channels that can be derived from data sent by a station,
+    /// associating the station with any computed channels it did not already have
+    async fn compute_derived(
+        &mut self,
+        (station, data): &(StationID, HashMap<ChannelID, ChannelData>),
+        int: &LocalInterface,
+    ) -> Result<HashMap<ChannelID, ChannelData>, DispatchErr> {
+        if self.stations.get_info(station).is_none() {
+            warn!("Received data from unknown station {station}, computed channels will not be derived");
+            return Ok(HashMap::new());
+        }
+        let (derived, new_channels) = derive_for_station(
+            &mut self.stations,
+            &self.channels,
+            self.limits,
+            *station,
+            data,
+        );
+        let now = Utc::now();
+        self.audit(
+            new_channels
+                .iter()
+                .map(|channel| AuditRecord {
+                    time: now,
+                    station: *station,
+                    event: AuditEvent::StationNewChannel { channel: *channel },
+                })
+                .collect(),
+        )
+        .await;
+        for new_channel in new_channels {
+            info!("associating computed channel {new_channel} with station {station}");
+            let ch = self.channels.get_channel(&new_channel).unwrap();
+            int.announce(
+                msg::Target::Any,
+                EV_META_STATION_ASSOC_CHANNEL,
+                (*station, new_channel, ch.clone()),
+            )
+            .await?;
+        }
+        Ok(derived)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum RegistryError {
+    #[error("Station {station} supports channel {channel}, which does not exist")]
+    UnknownSupportedChannel {
+        station: StationID,
+        channel: ChannelID,
+    },
+    #[error("Computed channel {channel} uses channel {input} as an input, which does not exist")]
+    UnknownInput {
+        channel: ChannelID,
+        input: ChannelID,
+    },
+}
+
+/// Cross-check the station and channel registries, returning every reference to a channel that does not exist
+///
+/// (these would otherwise only be noticed when the channel is used, e.g. by the database)
+pub fn validate(stations: &KnownStations, channels: &KnownChannels) -> Vec<RegistryError> {
+    let mut problems = vec![];
+    for station in stations.stations() {
+        let info = stations.get_info(station).unwrap();
+        for channel in &info.supports_channels {
+            if channels.get_channel(channel).is_none() {
+                problems.push(RegistryError::UnknownSupportedChannel {
+                    station: *station,
+                    channel: *channel,
+                });
+            }
+        }
+    }
+    for (id, _) in channels.channels() {
+        if let ChannelType::Computed { inputs, .. } = &channels.get_channel(id).unwrap().ty {
+            for input in inputs {
+                if channels.get_channel(input).is_none() {
+                    problems.push(RegistryError::UnknownInput {
+                        channel: *id,
+                        input: *input,
+                    });
+                }
             }
         }
-        Ok(name_to_id_mappings)
     }
+    problems
+}
+
+/// Registers the computed channels defined in the config (`[[computed]]`), returning the ids of the new ones.
+///
+/// channels are never modified once created, so a definition that differs from the existing channel of the same name
+/// is not applied. neither is one with an input that does not exist yet (no station has declared it), it is
+/// registered on a later startup. both are logged
+pub fn define_computed(
+    channels: &mut KnownChannels,
+    defs: &[config::ComputedChannel],
+) -> Vec<ChannelID> {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut new = vec![];
+    'defs: for def in defs {
+        let name = ChannelName::from(&def.name);
+        let mut inputs = vec![];
+        for input in &def.inputs {
+            match channels.find_by_name(&input.into()) {
+                Some((id, ch))
+                    if matches!(ch.value, ChannelValue::Float) && !channels.is_computed(&id) =>
+                {
+                    inputs.push(id)
+                }
+                Some(..) => {
+                    warn!("computed channel {name:?} uses channel {input:?}, which is not a (non-computed) float channel -- it will not be registered");
+                    continue 'defs;
+                }
+                None => {
+                    info!("computed channel {name:?} uses channel {input:?}, which does not exist yet -- it will be registered once it does (on a later startup)");
+                    continue 'defs;
+                }
+            }
+        }
+        match channels.find_by_name(&name) {
+            Some((_, known)) => match &known.ty {
+                ChannelType::Computed {
+                    inputs: known_inputs,
+                    formula,
+                } if *known_inputs == inputs && *formula == def.formula => {}
+                _ => warn!("computed channel {name:?} is defined differently than the existing channel of the same name, which will not be changed"),
+            },
+            None => {
+                let id = channels
+                    .insert_channel(Channel {
+                        name,
+                        value: ChannelValue::Float,
+                        ty: ChannelType::Computed {
+                            inputs,
+                            formula: def.formula.clone(),
+                        },
+                        unit: def.unit.clone(),
+                        description: def.description.clone(),
+                    })
+                    .unwrap();
+                new.push(id);
+            }
+        }
+    }
+    new
+}
+
+/// Computes the values of all computed channels that can be derived from data sent by `station`, associating
+/// it with any computed channels it did not already have (unless that would exceed `limits`).
+///
+/// returns the derived values, and the newly associated channels
+pub(crate) fn derive_for_station(
+    stations: &mut KnownStations,
+    channels: &KnownChannels,
+    limits: Limits,
+    station: StationID,
+    data: &HashMap<ChannelID, ChannelData>,
+) -> (HashMap<ChannelID, ChannelData>, Vec<ChannelID>) {
+    let mut derived = channels.compute_derived(data);
+    let Some(info) = stations.get_info(&station) else {
+        return (HashMap::new(), vec![]);
+    };
+    let mut new_channels = derived
+        .keys()
+        .filter(|id| !info.supports_channels.contains(id))
+        .copied()
+        .collect::<Vec<_>>();
+    let room = limits
+        .max_channels_per_station
+        .saturating_sub(info.supports_channels.len());
+    if new_channels.len() > room {
+        // (the database does not know about these channels, so their values can not be recorded)
+        for refused in new_channels.split_off(room) {
+            warn!(
+                "station {station} has reached its limit of {} channels, computed channel {refused} will not be associated with it",
+                limits.max_channels_per_station
+            );
+            derived.remove(&refused);
+        }
+    }
+    stations.map_info(&station, |_id, info| {
+        info.supports_channels.extend(&new_channels)
+    });
+    (derived, new_channels)
+}
+
+/// A station's `Connect` that was refused, because registering it would exceed the registry's [`Limits`] (or it
+/// declared something only the server may)
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ConnectError {
+    #[error("The station declared channel {0:?} as computed, only the server may define computed channels")]
+    DeclaredComputed(ChannelName),
+    #[error("The maximum number of stations ({max}) has been reached")]
+    TooManyStations { max: usize },
+    #[error("The station would have {channels} channels, more than the maximum ({max})")]
+    TooManyChannels { channels: usize, max: usize },
+}
+
+/// Ties each station to the address it connected from.
+///
+/// `Data` packets do not carry a station id, the sender is identified by its address. without this,
+/// anyone could send `Connect` claiming to be an existing station and then keep sending data for it
+/// after the real station reconnects
+#[derive(Debug, Default)]
+struct SourceBindings {
+    bound: HashMap<StationID, SocketAddr>,
+}
+
+impl SourceBindings {
+    /// bind `station` to `addr`, returning the previous address if it changed
+    fn bind(&mut self, station: StationID, addr: SocketAddr) -> Option<SocketAddr> {
+        self.bound
+            .insert(station, addr)
+            .filter(|prev| *prev != addr)
+    }
+
+    fn is_bound_to(&self, station: &StationID, addr: &SocketAddr) -> bool {
+        self.bound.get(station) == Some(addr)
+    }
+}
+
+/// changes made to the registry by a station connecting
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ConnectOutcome {
+    /// mappings to send back to the station
+    mappings: HashMap<ChannelName, ChannelID>,
+    /// channels that did not previously exist
+    new_channels: Vec<ChannelID>,
+    /// if the station did not previously exist
+    new_station: bool,
+    /// channels newly associated with the station
+    new_assoc: Vec<ChannelID>,
+    /// declared channels that conflict with the known channel of the same name (see [`conflicts`]), which are not
+    /// mapped
+    conflicts: Vec<ChannelName>,
+}
+
+impl ConnectOutcome {
+    /// declared channels that were not mapped, because they conflict with a known channel
+    pub(crate) fn conflicts(&self) -> &[ChannelName] {
+        &self.conflicts
+    }
+}
+
+/// if a station declaring `declared` would record data that does not fit `known`, a channel with the same name
+///
+/// (only the kind of value and type are compared, e.g. an event gaining a new sub-event is not a conflict)
+fn conflicts(known: &Channel, declared: &Channel) -> bool {
+    discriminant(&known.value) != discriminant(&declared.value)
+        || discriminant(&known.ty) != discriminant(&declared.ty)
+}
+
+/// Registers the station and channels described by `data`.
+///
+/// This is idempotent: a station sending the same `Connect` again (e.g. after rebooting) results in no changes.
+/// if the station would exceed `limits`, or declares a computed channel, nothing is changed
+pub(crate) fn apply_connect(
+    stations: &mut KnownStations,
+    channels: &mut KnownChannels,
+    data: &OnConnect,
+    limits: Limits,
+) -> Result<ConnectOutcome, ConnectError> {
+    if let Some(ch) = data
+        .channels
+        .iter()
+        .find(|ch| matches!(ch.ty, ChannelType::Computed { .. }))
+    {
+        return Err(ConnectError::DeclaredComputed(ch.name.clone()));
+    }
+    let pre_info = stations.get_info(&data.station_id);
+    if pre_info.is_none() && stations.stations().count() >= limits.max_stations {
+        return Err(ConnectError::TooManyStations {
+            max: limits.max_stations,
+        });
+    }
+    let declared = data
+        .channels
+        .iter()
+        .map(|ch| &ch.name)
+        .collect::<HashSet<_>>();
+    // computed channels are kept, see below
+    let kept = pre_info.map_or(0, |info| {
+        info.supports_channels
+            .iter()
+            .filter(|id| channels.is_computed(id))
+            .filter(|id| !declared.contains(&channels.get_channel(id).unwrap().name))
+            .count()
+    });
+    if declared.len() + kept > limits.max_channels_per_station {
+        return Err(ConnectError::TooManyChannels {
+            channels: declared.len() + kept,
+            max: limits.max_channels_per_station,
+        });
+    }
+    let mut outcome = ConnectOutcome::default();
+    // known channels the station declared differently. they are left as they are (and stay associated if they were)
+    let mut conflicting = vec![];
+    for ch in &data.channels {
+        let id = match channels.find_by_name(&ch.name) {
+            Some((id, known)) if conflicts(known, ch) => {
+                outcome.conflicts.push(ch.name.clone());
+                conflicting.push(id);
+                continue;
+            }
+            Some((id, _)) => id,
+            None => {
+                let id = channels.insert_channel(ch.clone()).unwrap();
+                outcome.new_channels.push(id);
+                id
+            }
+        };
+        outcome.mappings.insert(ch.name.clone(), id);
+    }
+    let declared = outcome.mappings.values().copied().collect::<Vec<_>>();
+    if let Some(pre_info) = stations.get_info(&data.station_id) {
+        outcome.new_assoc = declared
+            .iter()
+            .filter(|id| !pre_info.supports_channels.contains(id))
+            .copied()
+            .collect();
+        stations.map_info(&data.station_id, |_id, info| {
+            // computed channels are not declared by the station, so keep them
+            info.supports_channels.retain(|id| {
+                declared.contains(id) || channels.is_computed(id) || conflicting.contains(id)
+            });
+            for id in &declared {
+                if !info.supports_channels.contains(id) {
+                    info.supports_channels.push(*id);
+                }
+            }
+        });
+    } else {
+        stations
+            .insert_station(
+                data.station_id,
+                StationInfo {
+                    supports_channels: declared.clone(),
+                },
+            )
+            .unwrap();
+        outcome.new_station = true;
+        outcome.new_assoc = declared;
+    }
+    Ok(outcome)
+}
+
+/// the audit log entries for the changes made by a station connecting
+fn audit_records(
+    station: StationID,
+    outcome: &ConnectOutcome,
+    channels: &KnownChannels,
+    time: DateTime<Utc>,
+) -> Vec<AuditRecord> {
+    let record = |event| AuditRecord {
+        time,
+        station,
+        event,
+    };
+    let mut records = vec![];
+    if outcome.new_station {
+        records.push(record(AuditEvent::NewStation));
+    }
+    for channel in &outcome.new_channels {
+        let name = channels.get_channel(channel).unwrap().name.clone();
+        records.push(record(AuditEvent::NewChannel {
+            channel: *channel,
+            name,
+        }));
+    }
+    for channel in &outcome.new_assoc {
+        records.push(record(AuditEvent::StationNewChannel { channel: *channel }));
+    }
+    records
+}
+
+#[cfg(test)]
+const NO_LIMITS: Limits = Limits {
+    max_stations: usize::MAX,
+    max_channels_per_station: usize::MAX,
+};
+
+#[cfg(test)]
+#[test]
+fn duplicate_connect_is_idempotent() {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut stations = KnownStations::new();
+    let mut channels = KnownChannels::new();
+    let connect = OnConnect {
+        station_id: StationID::new_v4(),
+        station_build_rev: "abcdef".into(),
+        station_build_date: "2024-01-01T00:00:00Z".into(),
+        channels: ["temperature", "humidity"]
+            .into_iter()
+            .map(|name| Channel {
+                name: name.into(),
+                value: ChannelValue::Float,
+                ty: ChannelType::Periodic,
+                unit: None,
+                description: None,
+            })
+            .collect(),
+        degraded_channels: vec![],
+        provisioning_token: None,
+        compression: vec![],
+    };
+    let first = apply_connect(&mut stations, &mut channels, &connect, NO_LIMITS).unwrap();
+    assert!(first.new_station);
+    assert_eq!(first.new_channels.len(), 2);
+    assert_eq!(first.new_assoc.len(), 2);
+    let supported = stations
+        .get_info(&connect.station_id)
+        .unwrap()
+        .supports_channels
+        .clone();
+
+    // the station rebooted, and connects again (with a new build)
+    let connect = OnConnect {
+        station_build_rev: "fedcba".into(),
+        ..connect
+    };
+    let second = apply_connect(&mut stations, &mut channels, &connect, NO_LIMITS).unwrap();
+    assert_eq!(
+        second,
+        ConnectOutcome {
+            mappings: first.mappings,
+            ..Default::default()
+        }
+    );
+    assert_eq!(stations.stations().count(), 1);
+    assert_eq!(channels.channels().count(), 2);
+    assert_eq!(
+        stations
+            .get_info(&connect.station_id)
+            .unwrap()
+            .supports_channels,
+        supported
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn source_bindings() {
+    let mut sources = SourceBindings::default();
+    let station = StationID::new_v4();
+    let (a, b) = (
+        SocketAddr::from(([10, 0, 0, 1], 4000)),
+        SocketAddr::from(([10, 0, 0, 2], 4000)),
+    );
+    assert!(!sources.is_bound_to(&station, &a));
+    assert_eq!(sources.bind(station, a), None);
+    assert_eq!(sources.bind(station, a), None);
+    assert!(sources.is_bound_to(&station, &a));
+    assert!(!sources.is_bound_to(&station, &b));
+    // re-handshake from a new address
+    assert_eq!(sources.bind(station, b), Some(a));
+    assert!(!sources.is_bound_to(&station, &a));
+    assert!(sources.is_bound_to(&station, &b));
+}
+
+#[cfg(test)]
+#[test]
+fn validate_references() {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut stations = KnownStations::new();
+    let mut channels = KnownChannels::new();
+    let temp = channels
+        .insert_channel(Channel {
+            name: "temperature".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: None,
+            description: None,
+        })
+        .unwrap();
+    let station = StationID::new_v4();
+    stations
+        .insert_station(
+            station,
+            StationInfo {
+                supports_channels: vec![temp],
+            },
+        )
+        .unwrap();
+    assert_eq!(validate(&stations, &channels), vec![]);
+
+    // e.g. a channel deleted by hand from channels.json
+    let dangling = ChannelID::new_v4();
+    stations.map_info(&station, |_, info| info.supports_channels.push(dangling));
+    let computed = channels
+        .insert_channel(Channel {
+            name: "computed".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Computed {
+                inputs: vec![temp, dangling],
+                formula: mycelium::station::formula::Formula::Input(1),
+            },
+            unit: None,
+            description: None,
+        })
+        .unwrap();
+    assert_eq!(
+        validate(&stations, &channels),
+        vec![
+            RegistryError::UnknownSupportedChannel {
+                station,
+                channel: dangling
+            },
+            RegistryError::UnknownInput {
+                channel: computed,
+                input: dangling
+            },
+        ]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn connect_audit_records() {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut stations = KnownStations::new();
+    let mut channels = KnownChannels::new();
+    let channel = |name: &str| Channel {
+        name: name.into(),
+        value: ChannelValue::Float,
+        ty: ChannelType::Periodic,
+        unit: None,
+        description: None,
+    };
+    let connect = OnConnect {
+        station_id: StationID::new_v4(),
+        station_build_rev: "abcdef".into(),
+        station_build_date: "2024-01-01T00:00:00Z".into(),
+        channels: vec![channel("temperature")],
+        degraded_channels: vec![],
+        provisioning_token: None,
+        compression: vec![],
+    };
+    let time = Utc::now();
+    let outcome = apply_connect(&mut stations, &mut channels, &connect, NO_LIMITS).unwrap();
+    let temp = outcome.mappings[&ChannelName::from("temperature")];
+    let record = |station, event| AuditRecord {
+        time,
+        station,
+        event,
+    };
+    assert_eq!(
+        audit_records(connect.station_id, &outcome, &channels, time),
+        vec![
+            record(connect.station_id, AuditEvent::NewStation),
+            record(
+                connect.station_id,
+                AuditEvent::NewChannel {
+                    channel: temp,
+                    name: "temperature".into()
+                }
+            ),
+            record(
+                connect.station_id,
+                AuditEvent::StationNewChannel { channel: temp }
+            ),
+        ]
+    );
+
+    // reconnecting changes nothing
+    let outcome = apply_connect(&mut stations, &mut channels, &connect, NO_LIMITS).unwrap();
+    assert_eq!(
+        audit_records(connect.station_id, &outcome, &channels, time),
+        vec![]
+    );
+
+    // a second station, using an existing channel and a new one
+    let other = OnConnect {
+        station_id: StationID::new_v4(),
+        channels: vec![channel("temperature"), channel("humidity")],
+        ..connect
+    };
+    let outcome = apply_connect(&mut stations, &mut channels, &other, NO_LIMITS).unwrap();
+    let humidity = outcome.mappings[&ChannelName::from("humidity")];
+    let records = audit_records(other.station_id, &outcome, &channels, time);
+    assert_eq!(
+        records[..2],
+        [
+            record(other.station_id, AuditEvent::NewStation),
+            record(
+                other.station_id,
+                AuditEvent::NewChannel {
+                    channel: humidity,
+                    name: "humidity".into()
+                }
+            ),
+        ]
+    );
+    // (association order follows the order of `mappings`, which is not preserved)
+    assert_eq!(records.len(), 4);
+    for channel in [temp, humidity] {
+        assert!(records[2..].contains(&record(
+            other.station_id,
+            AuditEvent::StationNewChannel { channel }
+        )));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn connect_limits() {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut stations = KnownStations::new();
+    let mut channels = KnownChannels::new();
+    let limits = Limits {
+        max_stations: 2,
+        max_channels_per_station: 2,
+    };
+    let connect = |names: &[&str]| OnConnect {
+        station_id: StationID::new_v4(),
+        station_build_rev: "abcdef".into(),
+        station_build_date: "2024-01-01T00:00:00Z".into(),
+        channels: names
+            .iter()
+            .map(|name| Channel {
+                name: (*name).into(),
+                value: ChannelValue::Float,
+                ty: ChannelType::Periodic,
+                unit: None,
+                description: None,
+            })
+            .collect(),
+        degraded_channels: vec![],
+        provisioning_token: None,
+        compression: vec![],
+    };
+    let a = connect(&["temperature", "humidity"]);
+    let b = connect(&["temperature"]);
+    apply_connect(&mut stations, &mut channels, &a, limits).unwrap();
+    apply_connect(&mut stations, &mut channels, &b, limits).unwrap();
+
+    // a third station is refused, and nothing is registered for it
+    let c = connect(&["pressure"]);
+    assert_eq!(
+        apply_connect(&mut stations, &mut channels, &c, limits),
+        Err(ConnectError::TooManyStations { max: 2 })
+    );
+    assert_eq!(stations.stations().count(), 2);
+    assert_eq!(channels.id_by_name(&"pressure".into()), None);
+
+    // too many channels for an existing station are refused, and it keeps its old ones
+    let b_more = OnConnect {
+        station_id: b.station_id,
+        ..connect(&["temperature", "humidity", "pressure"])
+    };
+    assert_eq!(
+        apply_connect(&mut stations, &mut channels, &b_more, limits),
+        Err(ConnectError::TooManyChannels {
+            channels: 3,
+            max: 2
+        })
+    );
+    assert_eq!(
+        stations.get_info(&b.station_id).unwrap().supports_channels,
+        vec![channels.id_by_name(&"temperature".into()).unwrap()]
+    );
+    assert_eq!(channels.id_by_name(&"pressure".into()), None);
+
+    // existing stations can still reconnect (and change channels, within the limit)
+    let outcome = apply_connect(&mut stations, &mut channels, &a, limits).unwrap();
+    assert!(!outcome.new_station);
+    assert!(outcome.new_assoc.is_empty());
+    let b_changed = OnConnect {
+        station_id: b.station_id,
+        ..connect(&["temperature", "pressure"])
+    };
+    let outcome = apply_connect(&mut stations, &mut channels, &b_changed, limits).unwrap();
+    assert_eq!(outcome.new_channels.len(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn connect_type_conflicts() {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut stations = KnownStations::new();
+    let mut channels = KnownChannels::new();
+    let channel = |name: &str, value| Channel {
+        name: name.into(),
+        value,
+        ty: ChannelType::Periodic,
+        unit: None,
+        description: None,
+    };
+    let connect = OnConnect {
+        station_id: StationID::new_v4(),
+        station_build_rev: "abcdef".into(),
+        station_build_date: "2024-01-01T00:00:00Z".into(),
+        channels: vec![
+            channel("temperature", ChannelValue::Float),
+            channel("rain", ChannelValue::Float),
+        ],
+        degraded_channels: vec![],
+        provisioning_token: None,
+        compression: vec![],
+    };
+    let first = apply_connect(&mut stations, &mut channels, &connect, NO_LIMITS).unwrap();
+    assert!(first.conflicts.is_empty());
+    let rain = first.mappings[&ChannelName::from("rain")];
+
+    // the same station, after a firmware update that made `rain` an event
+    let changed = OnConnect {
+        channels: vec![
+            channel("temperature", ChannelValue::Float),
+            channel("rain", ChannelValue::Event(HashMap::new())),
+            Channel {
+                name: "lightning".into(),
+                value: ChannelValue::Event(HashMap::new()),
+                ty: ChannelType::Triggered,
+                unit: None,
+                description: None,
+            },
+        ],
+        ..connect.clone()
+    };
+    let outcome = apply_connect(&mut stations, &mut channels, &changed, NO_LIMITS).unwrap();
+    assert_eq!(outcome.conflicts, vec![ChannelName::from("rain")]);
+    assert!(!outcome.mappings.contains_key(&ChannelName::from("rain")));
+    assert_eq!(outcome.mappings.len(), 2);
+    // the known definition is unchanged, and the station keeps it
+    assert!(matches!(
+        channels.get_channel(&rain).unwrap().value,
+        ChannelValue::Float
+    ));
+    assert!(stations
+        .get_info(&connect.station_id)
+        .unwrap()
+        .supports_channels
+        .contains(&rain));
+
+    // a new station declaring a known channel with a different type
+    let other = OnConnect {
+        station_id: StationID::new_v4(),
+        channels: vec![Channel {
+            name: "temperature".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Triggered,
+            unit: None,
+            description: None,
+        }],
+        ..connect
+    };
+    let outcome = apply_connect(&mut stations, &mut channels, &other, NO_LIMITS).unwrap();
+    assert_eq!(outcome.conflicts, vec![ChannelName::from("temperature")]);
+    assert!(outcome.mappings.is_empty());
+    assert!(stations
+        .get_info(&other.station_id)
+        .unwrap()
+        .supports_channels
+        .is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn channel_metadata_persisted() {
+    use mycelium::station::capabilities::ChannelValue;
+
+    let mut channels = KnownChannels::new();
+    let bare = channels
+        .insert_channel(Channel {
+            name: "rainfall".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: None,
+            description: None,
+        })
+        .unwrap();
+    let labeled = channels
+        .insert_channel(Channel {
+            name: "battery".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: Some("V".into()),
+            description: Some("battery voltage".into()),
+        })
+        .unwrap();
+    // (as saved to `channels.json`)
+    let json = serde_json::to_string(&channels).unwrap();
+    let loaded = serde_json::from_str::<KnownChannels>(&json).unwrap();
+    let bare = loaded.get_channel(&bare).unwrap();
+    assert_eq!(
+        (bare.unit.as_deref(), bare.description.as_deref()),
+        (None, None)
+    );
+    let labeled = loaded.get_channel(&labeled).unwrap();
+    assert_eq!(
+        (labeled.unit.as_deref(), labeled.description.as_deref()),
+        (Some("V"), Some("battery voltage"))
+    );
+
+    // registries saved before the fields existed still load
+    let old = r#"{"channels":{"5b1f0c9e-3d2a-4c8b-a7e6-0f4d9c2b1e83":{"name":{"name":"temperature"},"value":{"type":"Float"},"ty":{"type":"Periodic"}}}}"#;
+    let loaded = serde_json::from_str::<KnownChannels>(old).unwrap();
+    let (id, _) = loaded.channels().next().unwrap();
+    assert_eq!(loaded.get_channel(id).unwrap().unit, None);
+}
+
+#[cfg(test)]
+#[test]
+fn computed_channels_from_config() {
+    use mycelium::station::{capabilities::ChannelValue, formula::Formula};
+
+    let mut stations = KnownStations::new();
+    let mut channels = KnownChannels::new();
+    let def = |name: &str, inputs: &[&str], formula| config::ComputedChannel {
+        name: name.into(),
+        inputs: inputs.iter().map(|input| input.to_string()).collect(),
+        formula,
+        unit: Some("°C".into()),
+        description: None,
+    };
+    let dew_point = def(
+        "dew_point",
+        &["temperature", "humidity"],
+        Formula::dew_point(),
+    );
+    // (the inputs do not exist yet)
+    assert!(define_computed(&mut channels, std::slice::from_ref(&dew_point)).is_empty());
+    assert_eq!(channels.channels().count(), 0);
+
+    let connect = |channels: Vec<Channel>| OnConnect {
+        station_id: StationID::new_v4(),
+        station_build_rev: "abcdef".into(),
+        station_build_date: "2024-01-01T00:00:00Z".into(),
+        channels,
+        degraded_channels: vec![],
+        provisioning_token: None,
+        compression: vec![],
+    };
+    let periodic = |name: &str| Channel {
+        name: name.into(),
+        value: ChannelValue::Float,
+        ty: ChannelType::Periodic,
+        unit: None,
+        description: None,
+    };
+    let station = connect(vec![periodic("temperature"), periodic("humidity")]);
+    apply_connect(&mut stations, &mut channels, &station, NO_LIMITS).unwrap();
+    let new = define_computed(&mut channels, std::slice::from_ref(&dew_point));
+    assert_eq!(new.len(), 1);
+    assert!(channels.is_computed(&new[0]));
+    assert_eq!(
+        channels.get_channel(&new[0]).unwrap().unit.as_deref(),
+        Some("°C")
+    );
+    let data = HashMap::from([
+        (
+            channels.id_by_name(&"temperature".into()).unwrap(),
+            ChannelData::Float(20.0),
+        ),
+        (
+            channels.id_by_name(&"humidity".into()).unwrap(),
+            ChannelData::Float(50.0),
+        ),
+    ]);
+    let (derived, assoc) = derive_for_station(
+        &mut stations,
+        &channels,
+        NO_LIMITS,
+        station.station_id,
+        &data,
+    );
+    assert_eq!((derived.len(), assoc), (1, new.clone()));
+
+    // defining it again does nothing, and a different definition does not change it
+    assert!(define_computed(&mut channels, &[dew_point]).is_empty());
+    let changed = def("dew_point", &["temperature"], Formula::Input(0));
+    assert!(define_computed(&mut channels, &[changed]).is_empty());
+    let ChannelType::Computed { inputs, .. } = &channels.get_channel(&new[0]).unwrap().ty else {
+        panic!("dew point is no longer computed");
+    };
+    assert_eq!(inputs.len(), 2);
+    // and neither do ones that clash with, or use, other kinds of channel
+    let clash = def("humidity", &["temperature"], Formula::Input(0));
+    let uses_computed = def("dew_point_f", &["dew_point"], Formula::Input(0));
+    assert!(define_computed(&mut channels, &[clash, uses_computed]).is_empty());
+    assert!(!channels.is_computed(&channels.id_by_name(&"humidity".into()).unwrap()));
+    assert_eq!(channels.id_by_name(&"dew_point_f".into()), None);
+
+    // stations may not declare computed channels
+    let declares = connect(vec![
+        periodic("pressure"),
+        Channel {
+            ty: ChannelType::Computed {
+                inputs: vec![],
+                formula: Formula::Const(1.0),
+            },
+            ..periodic("constant")
+        },
+    ]);
+    assert_eq!(
+        apply_connect(&mut stations, &mut channels, &declares, NO_LIMITS),
+        Err(ConnectError::DeclaredComputed("constant".into()))
+    );
+    assert!(stations.get_info(&declares.station_id).is_none());
+    assert_eq!(channels.id_by_name(&"pressure".into()), None);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn read_only_changes_no_files() {
+    use std::path::Path;
+
+    use mycelium::station::capabilities::ChannelValue;
+    use roundtable::{common::HDL_EXTERNAL, Bus};
+
+    use crate::core::{config, shutdown::Shutdown};
+
+    let dir = crate::misc::testing::temp_dir();
+    let shutdown = Shutdown::new();
+    let open = |read_only| {
+        let (dir, handles) = (
+            dir.path().to_owned(),
+            [shutdown.handle(), shutdown.handle()],
+        );
+        async move {
+            let [stations, channels] = handles;
+            Registry::new(
+                JsonLoader::open(dir.join("stations.json"), stations, read_only)
+                    .await
+                    .unwrap(),
+                JsonLoader::open(dir.join("channels.json"), channels, read_only)
+                    .await
+                    .unwrap(),
+                AuditLog::open(dir.join("audit.jsonl"), read_only)
+                    .await
+                    .unwrap(),
+                config::Registry::default().into(),
+                (&config::Registry::default()).into(),
+            )
+        }
+    };
+    let files = |dir: &Path| {
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let contents = std::fs::read(&path).unwrap();
+                (path, contents)
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    let bus = Bus::new(Default::default()).await;
+    let connect = |registry: msg::HandlerInstance, channel: &str| {
+        let data = OnConnect {
+            station_id: StationID::new_v4(),
+            station_build_rev: "abcdef".into(),
+            station_build_date: "2024-01-01T00:00:00Z".into(),
+            channels: vec![Channel {
+                name: channel.into(),
+                value: ChannelValue::Float,
+                ty: ChannelType::Periodic,
+                unit: None,
+                description: None,
+            }],
+            degraded_channels: vec![],
+            provisioning_token: None,
+            compression: vec![],
+        };
+        let bus = bus.clone();
+        async move {
+            let mappings = bus
+                .query_as(
+                    HDL_EXTERNAL,
+                    registry.clone(),
+                    EV_REGISTRY_PROCESS_CONNECT,
+                    ("127.0.0.1:1234".parse().unwrap(), data),
+                )
+                .await
+                .unwrap();
+            assert_eq!(mappings.map.len(), 1);
+            bus.query_as(HDL_EXTERNAL, registry, EV_BUILTIN_AUTOSAVE, ())
+                .await
+                .unwrap();
+        }
+    };
+
+    // (written normally first, so that there is something to change)
+    let writable = bus.spawn_checked(open(false).await).await.unwrap();
+    connect(writable, "temperature").await;
+    let before = files(dir.path());
+    assert_eq!(before.len(), 3);
+
+    let read_only = bus.spawn_checked(open(true).await).await.unwrap();
+    connect(read_only.clone(), "pressure").await;
+    // (changes are still made in memory)
+    let (stations, channels) = bus
+        .query_as(HDL_EXTERNAL, read_only, EV_REGISTRY_QUERY_ALL, ())
+        .await
+        .unwrap();
+    assert_eq!(stations.stations().count(), 2);
+    assert!(channels.id_by_name(&"pressure".into()).is_some());
+    // and not when a loader is dropped
+    let mut drop_shutdown = Shutdown::new();
+    let mut loader = JsonLoader::<KnownChannels>::open(
+        dir.path().join("channels.json"),
+        drop_shutdown.handle(),
+        true,
+    )
+    .await
+    .unwrap();
+    *loader = channels;
+    drop(loader);
+    drop_shutdown.wait_for_completion().await;
+    assert_eq!(files(dir.path()), before);
 }
@@ -0,0 +1,182 @@
+//! Which stations may connect
+//!
+//! by default any station may connect (and is registered). in allowlist mode, only stations listed in the config,
+//! or that have claimed one of the configured provisioning tokens, are accepted. a token may only be claimed once,
+//! and the station that claimed it must keep presenting it (it is bound to that station, so a station's ID alone is not
+//! enough to connect). claims are recorded in the audit log (by the token's hash), so they survive restarts
+
+use std::collections::{HashMap, HashSet};
+
+use mycelium::station::identity::StationID;
+use sha2::{Digest, Sha256};
+
+use super::audit::{AuditEvent, AuditRecord};
+use crate::{core::config, ipc::tokens_match};
+
+#[derive(Debug, Clone, Default)]
+pub struct Admission {
+    /// stations that may connect, or None if any station may
+    allowed: Option<HashSet<StationID>>,
+    /// tokens that may be claimed
+    tokens: Vec<String>,
+    /// token hash (see [`hash_token`]) -> the station that claimed it
+    claimed: HashMap<String, StationID>,
+}
+
+/// hash of a provisioning token, as stored in the audit log (hex encoded SHA-256)
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// A station's `Connect` that was refused, because it is not allowed to connect
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AdmissionError {
+    #[error("The station is not on the allowlist, and did not present a provisioning token")]
+    NotAllowed,
+    #[error("The station presented an unknown provisioning token")]
+    UnknownToken,
+    #[error("The station presented a provisioning token that was already claimed by station {0}")]
+    AlreadyClaimed(StationID),
+}
+
+impl Admission {
+    /// any station may connect
+    pub fn open() -> Self {
+        Self::default()
+    }
+
+    /// only stations in `allowed`, or that claim one of `tokens`, may connect
+    pub fn allowlist(
+        allowed: impl IntoIterator<Item = StationID>,
+        tokens: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            allowed: Some(allowed.into_iter().collect()),
+            tokens: tokens.into_iter().collect(),
+            claimed: HashMap::new(),
+        }
+    }
+
+    /// restores the tokens claimed in `records` (from the audit log)
+    pub fn restore(&mut self, records: &[AuditRecord]) {
+        for record in records {
+            if let AuditEvent::ClaimedToken { token_hash } = &record.event {
+                self.claimed.insert(token_hash.clone(), record.station);
+            }
+        }
+    }
+
+    /// Checks if `station` may connect, presenting `token` (if any).
+    ///
+    /// returns the token's hash if the station is accepted by claiming it (see [`claim`][Self::claim])
+    pub fn check(
+        &self,
+        station: StationID,
+        token: Option<&str>,
+    ) -> Result<Option<String>, AdmissionError> {
+        let Some(allowed) = &self.allowed else {
+            return Ok(None);
+        };
+        if allowed.contains(&station) {
+            return Ok(None);
+        }
+        let token = token.ok_or(AdmissionError::NotAllowed)?;
+        let token_hash = hash_token(token);
+        if let Some(by) = self.claimed.get(&token_hash) {
+            return if *by == station {
+                Ok(None)
+            } else {
+                Err(AdmissionError::AlreadyClaimed(*by))
+            };
+        }
+        self.tokens
+            .iter()
+            .find(|t| tokens_match(t, token))
+            .map(|_| Some(token_hash))
+            .ok_or(AdmissionError::UnknownToken)
+    }
+
+    /// records that `station` claimed the token with `token_hash` (as returned by [`check`][Self::check])
+    pub fn claim(&mut self, station: StationID, token_hash: String) {
+        self.claimed.insert(token_hash, station);
+    }
+}
+
+impl From<&config::Registry> for Admission {
+    fn from(registry: &config::Registry) -> Self {
+        match registry.admission {
+            config::AdmissionMode::open => Self::open(),
+            config::AdmissionMode::allowlist => Self::allowlist(
+                registry.allowed_stations.iter().copied(),
+                registry.provisioning_tokens.iter().cloned(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn allowlist() {
+    let (listed, other) = (StationID::new_v4(), StationID::new_v4());
+    let open = Admission::open();
+    assert_eq!(open.check(other, None), Ok(None));
+    assert_eq!(open.check(other, Some("whatever")), Ok(None));
+
+    let admission = Admission::allowlist([listed], []);
+    assert_eq!(admission.check(listed, None), Ok(None));
+    assert_eq!(
+        admission.check(other, None),
+        Err(AdmissionError::NotAllowed)
+    );
+    assert_eq!(
+        admission.check(other, Some("guess")),
+        Err(AdmissionError::UnknownToken)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn claim_token() {
+    use chrono::Utc;
+
+    let (a, b) = (StationID::new_v4(), StationID::new_v4());
+    let mut admission = Admission::allowlist([], ["hunter2".to_string(), "swordfish".to_string()]);
+    assert_eq!(admission.check(a, None), Err(AdmissionError::NotAllowed));
+    let token_hash = admission.check(a, Some("hunter2")).unwrap().unwrap();
+    assert_eq!(token_hash, hash_token("hunter2"));
+    assert_ne!(token_hash, "hunter2");
+    admission.claim(a, token_hash.clone());
+    // once claimed, the station must keep presenting the token
+    assert_eq!(admission.check(a, Some("hunter2")), Ok(None));
+    assert_eq!(admission.check(a, None), Err(AdmissionError::NotAllowed));
+    assert_eq!(
+        admission.check(a, Some("guess")),
+        Err(AdmissionError::UnknownToken)
+    );
+    // and the token can not be used again
+    assert_eq!(
+        admission.check(b, Some("hunter2")),
+        Err(AdmissionError::AlreadyClaimed(a))
+    );
+    assert_eq!(
+        admission.check(b, Some("swordfish")),
+        Ok(Some(hash_token("swordfish")))
+    );
+
+    // claims are restored from the audit log
+    let mut restored = Admission::allowlist([], ["hunter2".to_string()]);
+    restored.restore(&[AuditRecord {
+        time: Utc::now(),
+        station: a,
+        event: AuditEvent::ClaimedToken { token_hash },
+    }]);
+    assert_eq!(restored.check(a, Some("hunter2")), Ok(None));
+    assert_eq!(restored.check(a, None), Err(AdmissionError::NotAllowed));
+    assert_eq!(
+        restored.check(b, Some("hunter2")),
+        Err(AdmissionError::AlreadyClaimed(a))
+    );
+}
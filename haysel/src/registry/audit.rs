@@ -0,0 +1,185 @@
+//! Append-only log of changes made to the registry
+//!
+//! `KnownStations` / `KnownChannels` only hold the current state, this records *when* each station
+//! and channel appeared (one JSON record per line)
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mycelium::station::{
+    capabilities::{ChannelID, ChannelName},
+    identity::StationID,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub time: DateTime<Utc>,
+    /// the station whose connection (or data) caused the change
+    pub station: StationID,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    NewStation,
+    NewChannel {
+        channel: ChannelID,
+        name: ChannelName,
+    },
+    /// an (existing or new) channel was associated with the station
+    StationNewChannel {
+        channel: ChannelID,
+    },
+    /// the station was accepted by claiming a provisioning token (see [`Admission`][super::admission::Admission]).
+    /// (only the token's hash is recorded, as the station must keep presenting the token)
+    ClaimedToken {
+        token_hash: String,
+    },
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    records: Vec<AuditRecord>,
+    /// if new records are kept in memory only (never written)
+    read_only: bool,
+    /// if the file ends in a partially written record, which the next record must not be appended to
+    partial_line: bool,
+}
+
+impl AuditLog {
+    /// Loads the existing records at `path` (if any). new records are appended to it, unless `read_only`
+    #[instrument]
+    pub async fn open(path: PathBuf, read_only: bool) -> Result<Self> {
+        if path.exists() && !path.is_file() {
+            error!("Could not open `{path:?}` -- directory exists here");
+            bail!("AuditLog::open failed - invalid path");
+        }
+        let (records, partial_line) = load(&path).await?;
+        Ok(Self {
+            path,
+            records,
+            read_only,
+            partial_line,
+        })
+    }
+
+    /// Appends `records`, flushing them to disk before returning (if read-only, they are only kept in memory)
+    pub async fn append(&mut self, records: Vec<AuditRecord>) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if self.read_only {
+            self.records.extend(records);
+            return Ok(());
+        }
+        let mut buf = String::new();
+        if self.partial_line {
+            buf.push('\n');
+        }
+        for record in &records {
+            buf += &serde_json::to_string(record)?;
+            buf.push('\n');
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(buf.as_bytes()).await?;
+        file.sync_data().await?;
+        self.partial_line = false;
+        self.records.extend(records);
+        Ok(())
+    }
+
+    /// all records, oldest first
+    pub fn records(&self) -> &[AuditRecord] {
+        &self.records
+    }
+
+    /// all records caused by `station`, oldest first
+    pub fn station_history(&self, station: &StationID) -> Vec<AuditRecord> {
+        self.records
+            .iter()
+            .filter(|r| r.station == *station)
+            .cloned()
+            .collect()
+    }
+}
+
+/// returns the records in `path`, and if it ends in a partial line
+async fn load(path: &Path) -> Result<(Vec<AuditRecord>, bool)> {
+    if !fs::try_exists(path).await? {
+        return Ok((vec![], false));
+    }
+    let buf = fs::read_to_string(path).await?;
+    let mut records = vec![];
+    for (i, line) in buf.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            // (most likely the last line, from a crash mid-write. the rest is still usable)
+            Err(e) => warn!("Skipping invalid audit record at {path:?}:{}: {e}", i + 1),
+        }
+    }
+    Ok((records, !buf.is_empty() && !buf.ends_with('\n')))
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn append_and_reload() {
+    let dir = crate::misc::testing::temp_dir();
+    let path = dir.path().join("audit.jsonl");
+    let mut log = AuditLog::open(path.clone(), false).await.unwrap();
+    assert!(log.records().is_empty());
+
+    let (a, b) = (StationID::new_v4(), StationID::new_v4());
+    let channel = ChannelID::new_v4();
+    let record = |station, event| AuditRecord {
+        time: Utc::now(),
+        station,
+        event,
+    };
+    let first = vec![
+        record(a, AuditEvent::NewStation),
+        record(
+            a,
+            AuditEvent::NewChannel {
+                channel,
+                name: "temperature".into(),
+            },
+        ),
+        record(a, AuditEvent::StationNewChannel { channel }),
+    ];
+    log.append(first.clone()).await.unwrap();
+    let second = vec![record(b, AuditEvent::NewStation)];
+    log.append(second.clone()).await.unwrap();
+    log.append(vec![]).await.unwrap();
+    assert_eq!(log.station_history(&a), first);
+    assert_eq!(log.station_history(&b), second);
+
+    // a crash mid-write leaves a partial line, which is skipped
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .await
+        .unwrap();
+    file.write_all(b"{\"time\":\"20").await.unwrap();
+    drop(file);
+    let mut reloaded = AuditLog::open(path.clone(), false).await.unwrap();
+    assert_eq!(reloaded.records(), log.records());
+    // and does not corrupt the next one
+    reloaded.append(second.clone()).await.unwrap();
+    let reloaded = AuditLog::open(path, false).await.unwrap();
+    assert_eq!(
+        reloaded.station_history(&b),
+        [second.clone(), second].concat()
+    );
+}
@@ -1,74 +1,125 @@
 //! Utility for loading the registry types (`KnownStations`, `KnownChannels`, etc) from disk
 
 use std::{
+    ffi::OsString,
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use anyhow::Result;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::{
-    fs::{File, OpenOptions},
-    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
-};
+use tokio::{fs, io::AsyncWriteExt};
 
-use crate::{
-    core::shutdown::{async_drop::AsyncDrop, ShutdownHandle},
-    misc::Take,
-};
+use crate::core::shutdown::{async_drop::AsyncDrop, ShutdownHandle};
 
+/// A value persisted as JSON.
+///
+/// Saving writes to a temporary file and renames it over the original (which is first copied to a
+/// `.bak` file), so a crash mid-write can not leave a truncated file behind. If the file still fails
+/// to load, the backup is used instead.
 pub struct JsonLoader<R: Serialize + DeserializeOwned> {
-    file: Take<File>,
+    path: PathBuf,
     value: R,
+    /// if changes are kept in memory only (never saved)
+    read_only: bool,
     drop: AsyncDrop,
 }
 
 impl<R: Serialize + DeserializeOwned> JsonLoader<R> {
-    /// Loads the json at `path`, using `R::default` if it does not exist
+    /// Loads the json at `path` (or its backup), using `R::default` if neither exists.
+    /// if `read_only`, the file is never written (changes are kept in memory only)
     #[instrument(skip(sh_handle))]
-    pub async fn open(path: PathBuf, sh_handle: ShutdownHandle) -> Result<Self>
+    pub async fn open(path: PathBuf, sh_handle: ShutdownHandle, read_only: bool) -> Result<Self>
     where
         R: Default,
     {
-        let new = !path.exists();
         if path.exists() && !path.is_file() {
             error!("Could not open `{path:?}` -- directory exists here");
             bail!("JsonLoader::open failed - invalid path");
         }
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)
-            .await?;
-        let value = if new {
-            R::default()
-        } else {
-            let mut buf = String::new();
-            file.read_to_string(&mut buf).await?;
-            if buf.trim().is_empty() {
-                R::default()
-            } else {
-                serde_json::from_str(&buf)?
-            }
-        };
+        let value = load(&path).await?.unwrap_or_default();
         Ok(Self {
-            file: Take::new(file),
+            path,
             value,
+            read_only,
             drop: AsyncDrop::new(sh_handle).await,
         })
     }
 
     #[instrument(skip(self))]
     pub async fn sync(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
         let serialized = serde_json::to_string_pretty(&self.value)?;
-        self.file.set_len(0).await?;
-        self.file.seek(std::io::SeekFrom::Start(0)).await?;
-        self.file.write_all(serialized.as_bytes()).await?;
-        Ok(())
+        save(&self.path, serialized.as_bytes()).await
     }
 }
 
+fn with_extension(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(ext);
+    path.with_file_name(name)
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    with_extension(path, ".bak")
+}
+
+async fn load_one<R: DeserializeOwned>(path: &Path) -> Result<Option<R>> {
+    if !fs::try_exists(path).await? {
+        return Ok(None);
+    }
+    let buf = fs::read_to_string(path).await?;
+    if buf.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&buf)?))
+}
+
+/// Loads `path`, falling back to its backup if it is missing or corrupt.
+///
+/// returns `None` if neither exist (or they are empty)
+async fn load<R: DeserializeOwned>(path: &Path) -> Result<Option<R>> {
+    let err = match load_one(path).await {
+        Ok(Some(value)) => return Ok(Some(value)),
+        Ok(None) => None,
+        Err(e) => {
+            error!("Failed to load {path:?} ({e:#}), trying the backup");
+            Some(e)
+        }
+    };
+    let bak = backup_path(path);
+    match load_one(&bak).await {
+        Ok(Some(value)) => {
+            warn!("Loaded {path:?} from backup {bak:?}, changes since the last save may be lost");
+            Ok(Some(value))
+        }
+        Ok(None) => match err {
+            Some(e) => Err(e),
+            None => Ok(None),
+        },
+        Err(bak_err) => {
+            error!("Failed to load backup {bak:?}: {bak_err:#}");
+            Err(err.unwrap_or(bak_err))
+        }
+    }
+}
+
+/// Replace the contents of `path` with `contents`, such that it is never left partially written
+async fn save(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = with_extension(path, ".tmp");
+    let mut file = fs::File::create(&tmp).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    drop(file);
+    if fs::try_exists(path).await? {
+        fs::copy(path, backup_path(path)).await?;
+    }
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
 impl<R: Serialize + DeserializeOwned> Deref for JsonLoader<R> {
     type Target = R;
     fn deref(&self) -> &Self::Target {
@@ -84,28 +135,57 @@ impl<R: Serialize + DeserializeOwned> DerefMut for JsonLoader<R> {
 
 impl<R: Serialize + DeserializeOwned> Drop for JsonLoader<R> {
     fn drop(&mut self) {
+        if self.read_only {
+            return;
+        }
         let Ok(serialized) = serde_json::to_string_pretty(&self.value) else {
             error!("JsonLoader sync failed - could not serialize");
             return;
         };
-        let mut file: File = self.file.take();
+        let path = self.path.clone();
         self.drop.run(async move {
-            if let Err(e) = file.set_len(0).await {
-                error!("JsonLoader sync failed - could not truncate: {e:#?}");
-                return;
-            }
-            if let Err(e) = file.seek(std::io::SeekFrom::Start(0)).await {
-                error!("JsonLoader sync failed - could not truncate: {e:#?}");
-                return;
-            }
-            if let Err(e) = file.write_all(serialized.as_bytes()).await {
-                error!("JsonLoader sync failed - could not write: {e:#?}");
-                return;
-            }
-            if let Err(e) = file.shutdown().await {
-                error!("JsonLoader drop failed - could not shutdown file stream: {e:#?}");
-                return;
+            if let Err(e) = save(&path, serialized.as_bytes()).await {
+                error!("JsonLoader sync failed - could not save {path:?}: {e:#?}");
             }
         });
     }
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn recover_from_backup() {
+    use mycelium::station::identity::{KnownStations, StationID, StationInfo};
+
+    let dir = crate::misc::testing::temp_dir();
+    let path = dir.path().join("stations.json");
+    assert!(load::<KnownStations>(&path).await.unwrap().is_none());
+
+    let mut stations = KnownStations::new();
+    let id = StationID::new_v4();
+    stations
+        .insert_station(
+            id,
+            StationInfo {
+                supports_channels: vec![],
+            },
+        )
+        .unwrap();
+    let serialized = serde_json::to_string_pretty(&stations).unwrap();
+    save(&path, serialized.as_bytes()).await.unwrap();
+    // a second save creates the backup
+    save(&path, serialized.as_bytes()).await.unwrap();
+    assert!(!fs::try_exists(with_extension(&path, ".tmp")).await.unwrap());
+    let loaded = load::<KnownStations>(&path).await.unwrap().unwrap();
+    assert!(loaded.get_info(&id).is_some());
+
+    // crash mid-write (without atomic saves)
+    fs::write(&path, &serialized.as_bytes()[..serialized.len() / 2])
+        .await
+        .unwrap();
+    let loaded = load::<KnownStations>(&path).await.unwrap().unwrap();
+    assert!(loaded.get_info(&id).is_some());
+
+    // nothing to recover from
+    fs::write(backup_path(&path), "{").await.unwrap();
+    assert!(load::<KnownStations>(&path).await.is_err());
+}
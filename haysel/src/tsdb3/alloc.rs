@@ -9,7 +9,7 @@ use static_assertions::const_assert;
 use zerocopy::{AsBytes, FromBytes, FromZeroes, Ref};
 
 use self::{
-    access::{access_memmap, BaseOffset, MultipleAccess},
+    access::{access_memmap, BaseOffset, MultipleAccess, SlicePtr},
     registry::alignment_pad_size,
 };
 
@@ -21,9 +21,49 @@ pub mod ptr;
 mod registry;
 mod repr;
 
+#[derive(Debug, thiserror::Error)]
+pub enum AllocError {
+    #[error("Pointer to {addr:#x} (+{size}B) is outside of the usable store (data starts at {start:#x}, store is {store_size}B)")]
+    PointerOutOfBounds {
+        addr: u64,
+        size: u64,
+        start: u64,
+        store_size: u64,
+    },
+    #[error("Pointer to {addr:#x} refers to data that is already being accessed (aliasing is not allowed)")]
+    AlreadyAccessed { addr: u64 },
+}
+
+/// A chunk found by [`AllocAccess::scan_chunks`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScannedChunk {
+    /// address of the chunk's header
+    pub addr: u64,
+    /// length of the chunk (after the header, including alignment padding)
+    pub len: u32,
+    pub free: bool,
+}
+
+impl ScannedChunk {
+    /// pointer to the `T` in this chunk, if it is in use and has the length a `T` is allocated with
+    pub fn value<T>(&self) -> Option<Ptr<T>> {
+        let pad = alignment_pad_size::<T>();
+        (!self.free && self.len as usize == pad + size_of::<T>())
+            .then(|| Ptr::with(self.addr + (size_of::<repr::ChunkHeader>() + pad) as u64))
+    }
+
+    /// the addresses of the space in this chunk (after the header)
+    pub fn body(&self) -> std::ops::Range<u64> {
+        let start = self.addr + size_of::<repr::ChunkHeader>() as u64;
+        start..start + self.len as u64
+    }
+}
+
 pub struct AllocAccess<'a> {
     alloc_t_reg: &'a TypeRegistry,
     base: BaseOffset<'a>,
+    /// total size of the backing store (including the header)
+    store_size: u64,
     header: &'a mut repr::AllocHeader,
     free_lists: &'a mut [repr::AllocCategoryHeader],
     dat: MultipleAccess<'a>,
@@ -36,6 +76,7 @@ impl<'a> AllocAccess<'a> {
         const_assert!(align_of::<repr::AllocCategoryHeader>() <= align_of::<repr::ChunkHeader>());
         // -- get memmap content --
         let (base, dat): (BaseOffset, &mut [u8]) = access_memmap(map, &alloc_t_reg);
+        let store_size = dat.len() as u64;
         // -- get header --
         let (mut header, dat) = Ref::<_, repr::AllocHeader>::new_from_prefix(dat).unwrap();
         if write_header {
@@ -51,6 +92,7 @@ impl<'a> AllocAccess<'a> {
         Self {
             alloc_t_reg,
             base,
+            store_size,
             header: header.into_mut(),
             free_lists: free_lists.into_mut_slice(),
             dat: MultipleAccess::new(dat),
@@ -61,6 +103,31 @@ impl<'a> AllocAccess<'a> {
         self.header.used
     }
 
+    /// total size of the backing store, including the alloc header
+    pub fn get_store_size(&self) -> u64 {
+        self.store_size
+    }
+
+    /// checks if `map` starts with a valid alloc header (without panicking if it does not, unlike [`AllocAccess::new`])
+    pub fn header_is_valid(map: &MmapMut) -> bool {
+        Ref::<_, repr::AllocHeader>::new_from_prefix(&map[..])
+            .is_some_and(|(header, _)| header.verify())
+    }
+
+    /// hash of the layout of what is stored (see [`repr::AllocHeader::schema_hash`])
+    pub fn schema_hash(&mut self) -> &mut u32 {
+        &mut self.header.schema_hash
+    }
+
+    /// number of types the store was written with (`(stored, expected)`), which differs if it is from a version with
+    /// a different set of types (the free lists are not read correctly otherwise)
+    pub fn num_types(&self) -> (u64, u64) {
+        (
+            self.header.free_list_size,
+            self.alloc_t_reg.num_types() as u64,
+        )
+    }
+
     pub fn entrypoint_pointer(&mut self) -> &mut Ptr<ptr::Void> {
         &mut self.header.entrypoint
     }
@@ -112,28 +179,35 @@ impl<'a> AllocAccess<'a> {
     pub fn alloc<T: AsBytes + FromBytes + FromZeroes>(&mut self) -> (Ptr<T>, &'a mut T) {
         assert!(self.alloc_t_reg.contains_similar::<T>());
         if let Some(free_spot) = self.get_free_for::<T>() {
-            let dat = self
+            let header_dat = self
                 .dat
                 .get(free_spot.localize_to(self.base, &self.dat).to_range_usize());
-            let (mut header, dat) = Ref::<_, repr::ChunkHeader>::new_from_prefix(dat).unwrap();
+            let mut header = Ref::<_, repr::ChunkHeader>::new(&mut *header_dat).unwrap();
             let mut flags = repr::ChunkFlags::from_bits(header.flags).unwrap();
             flags.remove(repr::ChunkFlags::FREE);
             header.flags = flags.bits();
-            // remove alignment padding
-            let ref0 = Ref::<_, T>::new_zeroed(&mut dat[alignment_pad_size::<T>()..])
-                .unwrap()
-                .into_mut();
-            (
-                free_spot
-                    .offset((size_of::<repr::ChunkHeader>() + alignment_pad_size::<T>()) as _)
-                    .cast::<T>(),
-                ref0,
-            )
+            header.next = Ptr::with(1);
+            self.dat.put(header_dat);
+            // skip alignment padding
+            let ptr_t = free_spot
+                .offset((size_of::<repr::ChunkHeader>() + alignment_pad_size::<T>()) as _)
+                .cast::<T>();
+            let dat = self
+                .dat
+                .get(ptr_t.localize_to(self.base, &self.dat).to_range_usize());
+            (ptr_t, Ref::<_, T>::new_zeroed(dat).unwrap().into_mut())
         } else {
             let global_ptr = Ptr::<repr::ChunkHeader>::with(self.header.used);
-            self.header.used += (size_of::<repr::ChunkHeader>()
-                + alignment_pad_size::<T>()
-                + size_of::<T>()) as u64;
+            let size = (size_of::<repr::ChunkHeader>() + alignment_pad_size::<T>() + size_of::<T>())
+                as u64;
+            // (checked before anything is written, so that the store is not left inconsistent)
+            assert!(
+                self.header.used + size <= self.store_size,
+                "Out of space: allocating {size}B with {}B of {}B used (the store must be grown before this)",
+                self.header.used,
+                self.store_size
+            );
+            self.header.used += size;
             // -- write the new header --
             let header_dat = self.dat.get(
                 global_ptr
@@ -159,14 +233,145 @@ impl<'a> AllocAccess<'a> {
         }
     }
 
+    /// Frees the `T` at `ptr` (from [`AllocAccess::alloc`]), so that its space is reused by a later allocation of a `T`.
+    ///
+    /// it must not be used after this, and panics if it is currently being accessed (or was already freed).
+    /// returns false if the space can not be reused, because the store has no free list left for `T`
+    /// (there is one for each type registered when it was created)
+    pub fn free<T: AsBytes + FromBytes + FromZeroes>(&mut self, ptr: Ptr<T>) -> bool {
+        assert!(self.alloc_t_reg.contains_similar::<T>());
+        if let Err(e) = self.check_bounds(ptr) {
+            panic!("{e}");
+        }
+        assert!(
+            !self
+                .dat
+                .is_accessed(ptr.localize_to(self.base, &self.dat).to_range_usize()),
+            "Attempted to free data that is being accessed"
+        );
+        let (size, align) = (
+            (size_of::<T>() + alignment_pad_size::<T>()) as u64,
+            align_of::<T>() as u64,
+        );
+        // (free lists are claimed the first time a type is freed, an unused one is all zeros)
+        let Some(list) = self
+            .free_lists
+            .iter()
+            .position(|list| list.size == size && list.align == align)
+            .or_else(|| self.free_lists.iter().position(|list| list.size == 0))
+        else {
+            return false;
+        };
+        let header_ptr = ptr
+            .offset(-((size_of::<repr::ChunkHeader>() + alignment_pad_size::<T>()) as i64))
+            .cast::<repr::ChunkHeader>();
+        let header_dat = self.dat.get(
+            header_ptr
+                .localize_to(self.base, &self.dat)
+                .to_range_usize(),
+        );
+        let mut header = Ref::<_, repr::ChunkHeader>::new(&mut *header_dat).unwrap();
+        let mut flags = repr::ChunkFlags::from_bits_retain(header.flags);
+        assert!(
+            !flags.contains(repr::ChunkFlags::FREE),
+            "Pointer to {:#x} was already freed",
+            ptr.addr
+        );
+        assert_eq!(
+            header.len as u64,
+            size,
+            "Pointer to {:#x} does not refer to an allocated {}",
+            ptr.addr,
+            std::any::type_name::<T>()
+        );
+        flags.insert(repr::ChunkFlags::FREE);
+        header.flags = flags.bits();
+        let list = &mut self.free_lists[list];
+        (list.size, list.align) = (size, align);
+        header.next = list.head;
+        list.head = header_ptr;
+        self.dat.put(header_dat);
+        true
+    }
+
     /// returns a ref to an already allocated value
+    ///
+    /// panics if `ptr` does not point to within the store, see [`AllocAccess::try_read`]
     pub fn read<T: AsBytes + FromBytes + FromZeroes>(&mut self, ptr: Ptr<T>) -> &'a mut T {
+        match self.try_read(ptr) {
+            Ok(v) => v,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// returns a ref to an already allocated value, or an error if `ptr` (or the T it points to)
+    /// lies outside of the data section of the store
+    pub fn try_read<T: AsBytes + FromBytes + FromZeroes>(
+        &mut self,
+        ptr: Ptr<T>,
+    ) -> Result<&'a mut T, AllocError> {
         assert!(self.alloc_t_reg.contains_similar::<T>());
+        self.check_bounds(ptr)?;
+        let range = ptr.localize_to(self.base, &self.dat).to_range_usize();
+        if self.dat.is_accessed(range.clone()) {
+            return Err(AllocError::AlreadyAccessed { addr: ptr.addr });
+        }
         // -- get and return the body --
-        let dat = self
-            .dat
-            .get(ptr.localize_to(self.base, &self.dat).to_range_usize());
-        Ref::<_, T>::new(dat).unwrap().into_mut()
+        let dat = self.dat.get(range);
+        Ok(Ref::<_, T>::new(dat).unwrap().into_mut())
+    }
+
+    /// Every chunk in the used space, in the order they were allocated.
+    ///
+    /// chunks do not record what type they hold, only their length. stops at the first chunk header that is not
+    /// within the used space (or is misaligned), as nothing after it can be found
+    pub fn scan_chunks(&mut self) -> Vec<ScannedChunk> {
+        let header_size = size_of::<repr::ChunkHeader>() as u64;
+        let end = self.header.used.min(self.store_size);
+        let mut addr = (self.dat.ptr() as usize - self.base.ptr() as usize) as u64;
+        let mut chunks = vec![];
+        while addr + header_size <= end {
+            let range = Ptr::<repr::ChunkHeader>::with(addr)
+                .localize_to(self.base, &self.dat)
+                .to_range_usize();
+            if self.dat.is_accessed(range.clone()) {
+                break;
+            }
+            let dat = self.dat.get(range);
+            let header = Ref::<_, repr::ChunkHeader>::new(&mut *dat).map(|header| *header);
+            self.dat.put(dat);
+            let Some(header) = header else {
+                break;
+            };
+            chunks.push(ScannedChunk {
+                addr,
+                len: header.len,
+                free: repr::ChunkFlags::from_bits_retain(header.flags)
+                    .contains(repr::ChunkFlags::FREE),
+            });
+            addr += header_size + header.len as u64;
+        }
+        chunks
+    }
+
+    /// checks that `size_of::<T>()` bytes at `ptr` are contained within the data section of the store
+    /// (after the alloc header and free lists, and before the end of the store)
+    pub fn check_bounds<T>(&self, ptr: Ptr<T>) -> Result<(), AllocError> {
+        let start = (self.dat.ptr() as usize - self.base.ptr() as usize) as u64;
+        let size = size_of::<T>() as u64;
+        let err = AllocError::PointerOutOfBounds {
+            addr: ptr.addr,
+            size,
+            start,
+            store_size: self.store_size,
+        };
+        if ptr.addr < start {
+            return Err(err);
+        }
+        match ptr.addr.checked_add(size) {
+            Some(end) if end <= self.store_size => Ok(()),
+            _ => Err(err),
+        }
     }
 }
 
@@ -203,6 +408,21 @@ fn test_alloc_twice() {
     let _ = alloc.alloc::<u64>();
 }
 
+#[test]
+#[should_panic(expected = "Out of space")]
+fn test_alloc_out_of_space() {
+    let mut map = MmapMut::map_anon(256).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<[u8; 128]>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    let _ = alloc.alloc::<[u8; 128]>();
+    // no space for the second
+    let _ = alloc.alloc::<[u8; 128]>();
+}
+
 #[test]
 #[should_panic]
 fn test_new_map_not_enough_space() {
@@ -252,6 +472,151 @@ fn test_alloc_access_again() {
     assert_eq!(v, &b"Hello, World!"[..]);
 }
 
+#[test]
+fn test_read_out_of_bounds() {
+    let mut map = MmapMut::map_anon(4096).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    // past the end of the store
+    assert!(matches!(
+        alloc.try_read(Ptr::<u64>::with(4096)),
+        Err(AllocError::PointerOutOfBounds { .. })
+    ));
+    // straddling the end of the store
+    assert!(matches!(
+        alloc.try_read(Ptr::<u64>::with(4096 - 4)),
+        Err(AllocError::PointerOutOfBounds { .. })
+    ));
+    // overflowing
+    assert!(matches!(
+        alloc.try_read(Ptr::<u64>::with(u64::MAX - 2)),
+        Err(AllocError::PointerOutOfBounds { .. })
+    ));
+    // into the alloc header
+    assert!(matches!(
+        alloc.try_read(Ptr::<u64>::with(8)),
+        Err(AllocError::PointerOutOfBounds { .. })
+    ));
+    // valid pointers still work
+    let (ptr_v, v) = alloc.alloc::<u64>();
+    *v = 42;
+    drop(alloc);
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, false);
+    assert_eq!(*alloc.try_read(ptr_v).unwrap(), 42);
+    // the very last position in the store
+    assert!(alloc.try_read(Ptr::<u64>::with(4096 - 8)).is_ok());
+}
+
+#[test]
+#[should_panic]
+fn test_read_out_of_bounds_panics() {
+    let mut map = MmapMut::map_anon(4096).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    let _ = alloc.read(Ptr::<u64>::with(4096));
+}
+
+#[test]
+fn test_free_reused() {
+    let mut map = MmapMut::map_anon(4096).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg.register::<[u8; 13]>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    let (a, v) = alloc.alloc::<u64>();
+    *v = 1;
+    let (b, v) = alloc.alloc::<u64>();
+    *v = 2;
+    let (s, _) = alloc.alloc::<[u8; 13]>();
+    drop(alloc);
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, false);
+    let used = alloc.get_size_used();
+    assert!(alloc.free(a));
+    assert!(alloc.free(b));
+    assert!(alloc.free(s));
+    assert_eq!(alloc.scan_chunks().iter().filter(|c| c.free).count(), 3);
+    // the space is reused (most recently freed first), and zeroed
+    let (ptr, v) = alloc.alloc::<u64>();
+    assert_eq!((ptr, *v), (b, 0));
+    let (ptr, _) = alloc.alloc::<u64>();
+    assert_eq!(ptr, a);
+    let (ptr, _) = alloc.alloc::<[u8; 13]>();
+    assert_eq!(ptr, s);
+    assert_eq!(alloc.get_size_used(), used);
+    assert!(alloc.scan_chunks().iter().all(|c| !c.free));
+    // nothing left to reuse
+    let (ptr, _) = alloc.alloc::<u64>();
+    assert!(ptr.addr >= used);
+}
+
+#[test]
+fn test_free_without_free_list() {
+    let mut map = MmapMut::map_anon(4096).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    let (a, _) = alloc.alloc::<u64>();
+    drop(alloc);
+    // a store created with fewer types than are registered now
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg.register::<[u8; 13]>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, false);
+    let (s, _) = alloc.alloc::<[u8; 13]>();
+    drop(alloc);
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, false);
+    assert!(alloc.free(a));
+    assert!(!alloc.free(s));
+}
+
+#[test]
+#[should_panic(expected = "already freed")]
+fn test_double_free() {
+    let mut map = MmapMut::map_anon(4096).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    let (a, _) = alloc.alloc::<u64>();
+    drop(alloc);
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, false);
+    alloc.free(a);
+    alloc.free(a);
+}
+
+#[test]
+#[should_panic(expected = "being accessed")]
+fn test_free_while_accessed() {
+    let mut map = MmapMut::map_anon(4096).unwrap();
+    let alloc_t_reg = {
+        let mut alloc_t_reg = TypeRegistry::new();
+        alloc_t_reg.register::<u64>();
+        alloc_t_reg
+    };
+    let mut alloc = AllocAccess::new(&mut map, &alloc_t_reg, true);
+    let (a, _v) = alloc.alloc::<u64>();
+    alloc.free(a);
+}
+
 #[test]
 fn test_alloc_tricky_types() {
     let mut map = MmapMut::map_anon(4096).unwrap();
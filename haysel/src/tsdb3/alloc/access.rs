@@ -79,7 +79,7 @@ impl<'a> MultipleAccess<'a> {
     pub fn get<'b>(&'b mut self, range: Range<usize>) -> &'a mut [u8] {
         let Range { start, end } = range;
         assert!(start < end);
-        assert!(end < self.len);
+        assert!(end <= self.len);
         // saftey preconditions
         assert!(range.end < isize::MAX as _);
         assert!(range.end.checked_add(self.ptr as usize).is_some());
@@ -103,6 +103,21 @@ impl<'a> MultipleAccess<'a> {
         }
     }
 
+    /// checks if any part of `range` is currently accessed (if it is, [`MultipleAccess::get`] would panic)
+    pub fn is_accessed(&self, range: Range<usize>) -> bool {
+        let Range { start, end } = range;
+        assert!(start < end);
+        assert!(end <= self.len);
+        // Saftey: see asserts (and [`MultipleAccess::get`])
+        let ptr_range = unsafe {
+            Range {
+                start: self.ptr.add(start),
+                end: self.ptr.add(end),
+            }
+        };
+        self.is_overlapping(ptr_range)
+    }
+
     /// the returned slice must be from a current access
     pub fn put<'b>(&'b mut self, slice: &'a mut [u8]) {
         let range = slice.as_mut_ptr_range();
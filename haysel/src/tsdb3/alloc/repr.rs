@@ -31,7 +31,8 @@ pub struct ChunkHeader {
 #[repr(C)]
 pub struct AllocHeader {
     pub magic_bytes: [u8; 12],
-    pub _padding: [u8; 4],
+    /// hash of the layout of what is stored (set by the user of the allocator), 0 if it was never set
+    pub schema_hash: u32,
     /// entrypoint pointer - pointer to something that can be used to get a frame of
     /// reference to the content stored in the allocator
     pub entrypoint: Ptr<Void>,
@@ -47,7 +48,7 @@ impl AllocHeader {
     pub fn new(entrypoint: Ptr<Void>, free_list_size: u64) -> Self {
         Self {
             magic_bytes: MAGIC_BYTES,
-            _padding: [0u8; 4],
+            schema_hash: 0,
             entrypoint,
             used: (size_of::<Self>() + size_of::<AllocCategoryHeader>() * free_list_size as usize)
                 as _,
@@ -1,12 +1,18 @@
 //! bus integration for TSBD2
 
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use flume::Sender;
-use mycelium::station::{
-    capabilities::{Channel, KnownChannels},
-    identity::KnownStations,
+use mycelium::{
+    station::{
+        capabilities::{Channel, ChannelData, ChannelID, KnownChannels},
+        identity::{KnownStations, StationID},
+    },
+    DBStats, QueryCursor,
 };
 use roundtable::{
+    common::EV_BUILTIN_AUTOSAVE,
     handler::{HandlerInit, LocalInterface},
     handler_decl_t, method_decl,
     msg::{HandlerType, Str},
@@ -15,23 +21,27 @@ use tokio::sync::oneshot;
 use uuid::Uuid;
 
 use crate::{
+    core::health::{Component, EV_HEALTH_ALIVE},
     dispatch::application::{Record, EV_WEATHER_DATA_RECEIVED},
     registry::{EV_META_NEW_STATION, EV_META_STATION_ASSOC_CHANNEL},
 };
 
-use super::{query::QueryParams, DB};
+use super::{query::QueryParams, Page, Reclaimed, Tier, DB};
 
 mod rt;
 
 /// The handler
 pub struct TStopDBus3 {
     comm: Sender<rt::Msg>,
+    /// newest value received for each channel (filled from the database on startup, see [`EV_DB_QUERY_LATEST`])
+    latest: HashMap<(StationID, ChannelID), (DateTime<Utc>, ChannelData)>,
 }
 
 impl TStopDBus3 {
-    pub fn new(db: DB) -> Self {
+    pub fn new(mut db: DB) -> Self {
+        let latest = db.latest_values();
         let comm = rt::launch(db);
-        Self { comm }
+        Self { comm, latest }
     }
 
     async fn query(
@@ -47,6 +57,76 @@ impl TStopDBus3 {
         recv.await.map_err(|_| RuntimeTaskClosed)
     }
 
+    async fn query_page(
+        &mut self,
+        &query: &PageQuery,
+        _int: &LocalInterface,
+    ) -> Result<Page, RuntimeTaskClosed> {
+        let (response, recv) = oneshot::channel();
+        self.comm
+            .send_async(rt::Msg::QueryPage { query, response })
+            .await
+            .map_err(|_| RuntimeTaskClosed)?;
+        recv.await.map_err(|_| RuntimeTaskClosed)
+    }
+
+    async fn query_latest(
+        &mut self,
+        _: &(),
+        _int: &LocalInterface,
+    ) -> Result<HashMap<(StationID, ChannelID), (DateTime<Utc>, ChannelData)>, RuntimeTaskClosed>
+    {
+        Ok(self.latest.clone())
+    }
+
+    async fn stats(&mut self, _: &(), _int: &LocalInterface) -> Result<DBStats, RuntimeTaskClosed> {
+        let (response, recv) = oneshot::channel();
+        self.comm
+            .send_async(rt::Msg::Stats { response })
+            .await
+            .map_err(|_| RuntimeTaskClosed)?;
+        recv.await.map_err(|_| RuntimeTaskClosed)
+    }
+
+    async fn channels(
+        &mut self,
+        _: &(),
+        _int: &LocalInterface,
+    ) -> Result<Vec<(StationID, ChannelID)>, RuntimeTaskClosed> {
+        let (response, recv) = oneshot::channel();
+        self.comm
+            .send_async(rt::Msg::Channels { response })
+            .await
+            .map_err(|_| RuntimeTaskClosed)?;
+        recv.await.map_err(|_| RuntimeTaskClosed)
+    }
+
+    async fn roll_up(
+        &mut self,
+        &args: &RollUp,
+        _int: &LocalInterface,
+    ) -> Result<Reclaimed, RuntimeTaskClosed> {
+        let (response, recv) = oneshot::channel();
+        self.comm
+            .send_async(rt::Msg::RollUp { args, response })
+            .await
+            .map_err(|_| RuntimeTaskClosed)?;
+        recv.await.map_err(|_| RuntimeTaskClosed)
+    }
+
+    async fn delete_before(
+        &mut self,
+        &args: &DeleteBefore,
+        _int: &LocalInterface,
+    ) -> Result<Reclaimed, RuntimeTaskClosed> {
+        let (response, recv) = oneshot::channel();
+        self.comm
+            .send_async(rt::Msg::DeleteBefore { args, response })
+            .await
+            .map_err(|_| RuntimeTaskClosed)?;
+        recv.await.map_err(|_| RuntimeTaskClosed)
+    }
+
     pub async fn ensure_exists(&mut self, (stations, channels): &(KnownStations, KnownChannels)) {
         self.comm
             .send_async(rt::Msg::EnsureExists {
@@ -90,6 +170,17 @@ impl TStopDBus3 {
         record: &Record,
         _int: &LocalInterface,
     ) -> Result<(), RuntimeTaskClosed> {
+        for (&channel, value) in &record.data {
+            let key = (record.recorded_by, channel);
+            // (delayed readings are stored, but are not the latest)
+            if self
+                .latest
+                .get(&key)
+                .is_none_or(|(time, _)| *time <= record.recorded_at)
+            {
+                self.latest.insert(key, (record.recorded_at, value.clone()));
+            }
+        }
         self.comm
             .send_async(rt::Msg::Record {
                 record: record.clone(),
@@ -98,6 +189,30 @@ impl TStopDBus3 {
             .map_err(|_| RuntimeTaskClosed)?;
         Ok(())
     }
+
+    async fn alive(
+        &mut self,
+        _: &(),
+        _int: &LocalInterface,
+    ) -> Result<Component, RuntimeTaskClosed> {
+        if self.comm.is_disconnected() {
+            return Err(RuntimeTaskClosed);
+        }
+        Ok(Component::Database)
+    }
+
+    #[instrument(skip(self, _int))]
+    async fn sync(&mut self, _: &(), _int: &LocalInterface) -> Result<(), RuntimeTaskClosed> {
+        let (response, recv) = oneshot::channel();
+        self.comm
+            .send_async(rt::Msg::Flush { response })
+            .await
+            .map_err(|_| RuntimeTaskClosed)?;
+        if let Err(e) = recv.await.map_err(|_| RuntimeTaskClosed)? {
+            error!("Failed to flush the database: {e}");
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -112,10 +227,117 @@ impl HandlerInit for TStopDBus3 {
     }
     fn methods(&self, r: &mut roundtable::handler::MethodRegister<Self>) {
         r.register(Self::query, EV_DB_QUERY);
+        r.register(Self::query_page, EV_DB_QUERY_PAGE);
+        r.register(Self::query_latest, EV_DB_QUERY_LATEST);
+        r.register(Self::stats, EV_DB_STATS);
+        r.register(Self::channels, EV_DB_CHANNELS);
+        r.register(Self::roll_up, EV_DB_ROLL_UP);
+        r.register(Self::delete_before, EV_DB_DELETE_BEFORE);
         r.register(Self::new_station, EV_META_NEW_STATION);
         r.register(Self::station_new_channel, EV_META_STATION_ASSOC_CHANNEL);
         r.register(Self::record_data, EV_WEATHER_DATA_RECEIVED);
+        r.register(Self::sync, EV_BUILTIN_AUTOSAVE);
+        r.register(Self::alive, EV_HEALTH_ALIVE);
     }
 }
 
+/// A page of a range query (see [`DB::query_page`])
+#[derive(Debug, Clone, Copy)]
+pub struct PageQuery {
+    pub station: Uuid,
+    pub channel: Uuid,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub cursor: Option<QueryCursor>,
+    pub page_size: usize,
+}
+
+/// Arguments of [`DB::roll_up`]
+#[derive(Debug, Clone, Copy)]
+pub struct RollUp {
+    pub station: StationID,
+    pub channel: ChannelID,
+    pub older_than: DateTime<Utc>,
+    pub bucket: chrono::Duration,
+    pub max_chunks: usize,
+}
+
+/// Arguments of [`DB::delete_before`]
+#[derive(Debug, Clone, Copy)]
+pub struct DeleteBefore {
+    pub station: StationID,
+    pub channel: ChannelID,
+    pub tier: Tier,
+    pub before: DateTime<Utc>,
+    pub max_chunks: usize,
+}
+
 method_decl!(EV_DB_QUERY, QueryParams, Vec<(DateTime<Utc>, f32)>);
+method_decl!(EV_DB_QUERY_PAGE, PageQuery, Page);
+// newest value of every channel (kept in memory, so this does not read the database)
+method_decl!(
+    EV_DB_QUERY_LATEST,
+    (),
+    HashMap<(StationID, ChannelID), (DateTime<Utc>, ChannelData)>
+);
+method_decl!(EV_DB_STATS, (), DBStats);
+// every (station, channel) pair in the database
+method_decl!(EV_DB_CHANNELS, (), Vec<(StationID, ChannelID)>);
+// (failures are logged, and free nothing)
+method_decl!(EV_DB_ROLL_UP, RollUp, Reclaimed);
+method_decl!(EV_DB_DELETE_BEFORE, DeleteBefore, Reclaimed);
+
+#[cfg(test)]
+mod test {
+    use roundtable::{common::HDL_EXTERNAL, msg, Bus};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn latest_values_cache() {
+        let bus = Bus::new(Default::default()).await;
+        let mut db = DB::new_in_ram(100_000).unwrap();
+        db.init();
+        let (sid, stored, fresh) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        db.insert_station(sid).unwrap();
+        db.insert_channels(sid, [stored, fresh]).unwrap();
+        let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        db.insert_data(sid, stored, at(0), 1.0).unwrap();
+        let handler = bus.spawn_checked(TStopDBus3::new(db)).await.unwrap();
+        let latest = || bus.query_as(HDL_EXTERNAL, handler.clone(), EV_DB_QUERY_LATEST, ());
+        let record = |secs, data: &[(Uuid, f32)]| {
+            bus.announce_as(
+                HDL_EXTERNAL,
+                msg::Target::Any,
+                EV_WEATHER_DATA_RECEIVED,
+                Record {
+                    recorded_at: at(secs),
+                    recorded_by: sid,
+                    data: data
+                        .iter()
+                        .map(|&(ch, v)| (ch, ChannelData::Float(v)))
+                        .collect(),
+                },
+            )
+        };
+        let value =
+            |latest: &HashMap<_, (DateTime<Utc>, ChannelData)>, ch| match latest.get(&(sid, ch)) {
+                Some(&(t, ChannelData::Float(v))) => Some((t, v)),
+                _ => None,
+            };
+
+        // filled from the database
+        let cache = latest().await.unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(value(&cache, stored), Some((at(0), 1.0)));
+
+        record(10, &[(stored, 2.0), (fresh, 3.0)]).await.unwrap();
+        record(20, &[(fresh, 4.0)]).await.unwrap();
+        // delayed
+        record(5, &[(stored, 5.0), (fresh, 6.0)]).await.unwrap();
+        let cache = latest().await.unwrap();
+        assert_eq!(value(&cache, stored), Some((at(10), 2.0)));
+        assert_eq!(value(&cache, fresh), Some((at(20), 4.0)));
+    }
+}
@@ -2,16 +2,20 @@
 
 use chrono::{DateTime, Utc};
 use flume::{Receiver, Sender};
-use mycelium::station::{
-    capabilities::{Channel, ChannelData, KnownChannels},
-    identity::KnownStations,
+use mycelium::{
+    station::{
+        capabilities::{Channel, ChannelData, KnownChannels},
+        identity::KnownStations,
+    },
+    DBStats,
 };
 use tokio::sync::oneshot;
 use uuid::Uuid;
 
+use super::{DeleteBefore, PageQuery, RollUp};
 use crate::{
     dispatch::application::Record,
-    tsdb3::{query::QueryParams, DB},
+    tsdb3::{query::QueryParams, Error, Page, Reclaimed, DB},
 };
 
 pub enum Msg {
@@ -19,6 +23,24 @@ pub enum Msg {
         params: QueryParams,
         response: oneshot::Sender<Vec<(DateTime<Utc>, f32)>>,
     },
+    QueryPage {
+        query: PageQuery,
+        response: oneshot::Sender<Page>,
+    },
+    Stats {
+        response: oneshot::Sender<DBStats>,
+    },
+    Channels {
+        response: oneshot::Sender<Vec<(Uuid, Uuid)>>,
+    },
+    RollUp {
+        args: RollUp,
+        response: oneshot::Sender<Reclaimed>,
+    },
+    DeleteBefore {
+        args: DeleteBefore,
+        response: oneshot::Sender<Reclaimed>,
+    },
     EnsureExists {
         stations: KnownStations,
         channels: KnownChannels,
@@ -34,6 +56,9 @@ pub enum Msg {
     Record {
         record: Record,
     },
+    Flush {
+        response: oneshot::Sender<Result<(), Error>>,
+    },
 }
 
 pub fn launch(db: DB) -> Sender<Msg> {
@@ -56,30 +81,107 @@ pub fn runner(mut db: DB, queue: Receiver<Msg>) {
                 let resp = db.query_data(params);
                 let _ = response.send(resp);
             }
+            Msg::QueryPage { query, response } => {
+                let PageQuery {
+                    station,
+                    channel,
+                    from,
+                    to,
+                    cursor,
+                    page_size,
+                } = query;
+                let _ = response.send(db.query_page(station, channel, from, to, cursor, page_size));
+            }
+            Msg::Stats { response } => {
+                let _ = response.send(db.stats());
+            }
+            Msg::Channels { response } => {
+                let _ = response.send(db.iter_channels().collect());
+            }
+            Msg::RollUp { args, response } => {
+                let RollUp {
+                    station,
+                    channel,
+                    older_than,
+                    bucket,
+                    max_chunks,
+                } = args;
+                let reclaimed = db
+                    .roll_up(station, channel, older_than, bucket, max_chunks)
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to roll up channel {channel} (for station {station}): {e}");
+                        Reclaimed::default()
+                    });
+                let _ = response.send(reclaimed);
+            }
+            Msg::DeleteBefore { args, response } => {
+                let DeleteBefore {
+                    station,
+                    channel,
+                    tier,
+                    before,
+                    max_chunks,
+                } = args;
+                let reclaimed = db
+                    .delete_before(station, channel, tier, before, max_chunks)
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to delete old data of channel {channel} (for station {station}): {e}");
+                        Reclaimed::default()
+                    });
+                let _ = response.send(reclaimed);
+            }
             Msg::EnsureExists { stations, channels } => {
+                // anything already in the database must not be inserted again
+                let known_stations = db.get_stations().copied().collect::<Vec<_>>();
+                let known_channels = db.iter_channels().collect::<Vec<_>>();
                 for &id in stations.stations() {
-                    db.insert_station(id);
-                    db.insert_channels(id, channels.channels().map(|(id, _)| *id));
+                    if !known_stations.contains(&id) {
+                        if let Err(e) = db.insert_station(id) {
+                            warn!("Failed to add station {id} to the database: {e}");
+                            continue;
+                        }
+                    }
+                    let missing = channels
+                        .channels()
+                        .map(|(cid, _)| *cid)
+                        .filter(|cid| !known_channels.contains(&(id, *cid)))
+                        .collect::<Vec<_>>();
+                    if missing.is_empty() {
+                        continue;
+                    }
+                    if let Err(e) = db.insert_channels(id, missing) {
+                        warn!("Failed to add channels for station {id} to the database: {e}");
+                    }
+                }
+            }
+            Msg::NewStation { sid } => {
+                if let Err(e) = db.insert_station(sid) {
+                    warn!("Failed to add station {sid} to the database: {e}");
+                }
+            }
+            Msg::NewChannel { sid, cid, .. } => {
+                if let Err(e) = db.insert_channels(sid, [cid]) {
+                    warn!("Failed to add channel {cid} (for station {sid}) to the database: {e}");
                 }
             }
-            Msg::NewStation { sid } => db.insert_station(sid),
-            Msg::NewChannel { sid, cid, .. } => db.insert_channels(sid, [cid]),
             Msg::Record { record } => {
                 for (ch, val) in &record.data {
-                    db.insert_data(
-                        record.recorded_by,
-                        *ch,
-                        record.recorded_at,
-                        match val {
-                            ChannelData::Float(val) => *val,
-                            ChannelData::Event { .. } => {
-                                error!("Database does not support recording `event` type data yet");
-                                continue;
-                            }
-                        },
-                    );
+                    let res = match val {
+                        ChannelData::Float(val) => {
+                            db.insert_data(record.recorded_by, *ch, record.recorded_at, *val)
+                        }
+                        ChannelData::Event { sub, data } => {
+                            db.insert_event(record.recorded_by, *ch, record.recorded_at, sub, data)
+                        }
+                    };
+                    if let Err(e) = res {
+                        warn!("Failed to record data for channel {ch}: {e}");
+                    }
                 }
             }
+            Msg::Flush { response } => {
+                let _ = response.send(db.flush());
+            }
         }
     }
 }
@@ -47,13 +47,13 @@ pub fn main(args: args::DBCmdArgs) -> Result<()> {
         DBSubcommand::Init { path } => {
             let file = OpenOptions::new().read(true).write(true).open(&path)?;
             warn!("Initializing new database in {path:?}...");
-            unsafe { DB::new(file) }?.init();
+            unsafe { DB::new_uninit(file) }?.init();
             info!("Initialization complete");
         }
         DBSubcommand::Usage { path } => {
             let file = OpenOptions::new().read(true).write(true).open(&path)?;
             warn!("Opening database {path:?}...");
-            let mut db = unsafe { DB::new(file) }?;
+            let mut db = unsafe { DB::new_uninit(file) }?;
             info!("Opened database");
             let size = db.store.map.len() as u64;
             let access = db.store.access(false);
@@ -61,6 +61,50 @@ pub fn main(args: args::DBCmdArgs) -> Result<()> {
             let percentage = used as f64 / size as f64;
             info!("Current usage of {path:?} is {used}B / {size}B ({percentage:.4}% full)");
         }
+        DBSubcommand::Check { path } => {
+            let file = OpenOptions::new().read(true).open(&path)?;
+            warn!("Opening database {path:?} (read-only)...");
+            let mut db = unsafe { DB::new_read_only_uninit(file) }?;
+            info!("Opened database, checking integrity");
+            let report = db.check_integrity();
+            if report.is_ok() {
+                info!("No problems found in {path:?}: {report}");
+            } else {
+                error!("Problems found in {path:?}: {report}");
+                bail!("Database failed integrity check");
+            }
+        }
+        DBSubcommand::Repair { path } => {
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            warn!("Opening database {path:?}...");
+            let mut db = unsafe { DB::new_uninit(file) }?;
+            warn!("Repairing {path:?}, this modifies the database");
+            let report = db.repair()?;
+            info!("Repair complete: {report}");
+            let check = db.check_integrity();
+            if !check.is_ok() {
+                error!("Problems remain in {path:?}: {check}");
+                bail!("Database failed integrity check after repairing");
+            }
+        }
+        DBSubcommand::Migrate { from, to } => {
+            let file = OpenOptions::new().read(true).open(&from)?;
+            warn!("Opening database {from:?} (read-only)...");
+            let mut old = unsafe { DB::new_read_only_uninit(file) }?;
+            let file = OpenOptions::new().read(true).write(true).open(&to)?;
+            warn!("Initializing new database in {to:?}...");
+            let mut new = unsafe { DB::new_uninit(file) }?;
+            new.init();
+            // (it is at most as large as the old one, as it is not compressed)
+            new.set_growth(Some(old.store.map.len() as u64));
+            let report = old.migrate_legacy(&mut new)?;
+            info!("Migration complete: {report}");
+        }
+        DBSubcommand::Replay {
+            path,
+            capture,
+            registry,
+        } => crate::dispatch::replay::main(&path, &capture, registry.as_deref())?,
     }
     Ok(())
 }
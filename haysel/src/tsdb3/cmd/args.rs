@@ -30,6 +30,40 @@ pub enum DBSubcommand {
         #[arg(help = "path of the database to investigate")]
         path: PathBuf,
     },
+    /// Check the consistency of the database, reporting all problems found (does not modify the database)
+    Check {
+        #[arg(help = "path of the database to check")]
+        path: PathBuf,
+    },
+    /// Rebuild the database's index (the station and channel maps) from the data it contains.
+    /// A last resort, for when `check` finds that the index is damaged. Stations that can not be identified are
+    /// given new ids, which are printed
+    Repair {
+        #[arg(help = "path of the database to repair")]
+        path: PathBuf,
+    },
+    /// Copy a database written before its layout was recorded (which this version refuses to open) into a new one.
+    /// The old database is not modified. The new one is initialized first, so IT WILL BE OVERWRITTEN (it grows as needed)
+    Migrate {
+        #[arg(help = "path of the database to migrate")]
+        from: PathBuf,
+        #[arg(help = "path of the new database (must already exist)")]
+        to: PathBuf,
+    },
+    /// Replay a capture of packets sent by weather stations into a new database (for debugging).
+    /// The capture is a sequence of application packets, each prefixed with its length (u64, big endian).
+    /// The database is initialized first, so IT WILL BE OVERWRITTEN
+    Replay {
+        #[arg(help = "path of the database to replay into (must already exist)")]
+        path: PathBuf,
+        #[arg(help = "path of the capture to replay")]
+        capture: PathBuf,
+        #[arg(
+            long,
+            help = "data directory containing the registry (stations.json, channels.json) the capture was made with. it is not modified"
+        )]
+        registry: Option<PathBuf>,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -0,0 +1,368 @@
+//! compression of full data chunks (see [`repr::CompressedData`])
+//!
+//! the entries are bitpacked oldest -> newest. times are stored as the change in the time between readings
+//! (so that readings taken at a fixed interval take one bit), and readings are XORed with the previous one, only
+//! storing the bits that changed (like Facebook's Gorilla), since most channels change slowly.
+//!
+//! - the first entry is stored as-is (32 bit htime, 32 bit reading)
+//! - times (delta of delta, two's complement):
+//!   - `0` - same interval as before
+//!   - `10` + 7 bits, `110` + 9 bits, `1110` + 12 bits, `1111` + 36 bits
+//! - readings (XOR with the previous reading):
+//!   - `0` - same as before
+//!   - `10` + the changed bits, if they fit in the window (leading and trailing zeros) of the previous one
+//!   - `11` + 5 bits leading zeros + 5 bits (length - 1) + the changed bits, starting a new window
+
+use std::borrow::Cow;
+
+use super::{alloc::AllocAccess, repr};
+
+/// (bits of the value, prefix, length of the prefix)
+const TIME_CLASSES: [(u32, u64, u32); 4] = [
+    (7, 0b10, 2),
+    (9, 0b110, 3),
+    (12, 0b1110, 4),
+    (36, 0b1111, 4),
+];
+
+struct BitWriter<'a> {
+    buf: &'a mut [u8],
+    /// number of bits written
+    pos: usize,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        buf.fill(0);
+        Self { buf, pos: 0 }
+    }
+
+    /// writes the low `bits` bits of `value`, most significant first. None if it does not fit
+    fn write(&mut self, value: u64, bits: u32) -> Option<()> {
+        if self.pos + bits as usize > self.buf.len() * 8 {
+            return None;
+        }
+        for i in (0..bits).rev() {
+            if value >> i & 1 == 1 {
+                self.buf[self.pos / 8] |= 0x80 >> (self.pos % 8);
+            }
+            self.pos += 1;
+        }
+        Some(())
+    }
+
+    /// bytes used
+    fn len(&self) -> usize {
+        self.pos.div_ceil(8)
+    }
+}
+
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: u32) -> Option<u64> {
+        if self.pos + bits as usize > self.buf.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..bits {
+            value = value << 1 | (self.buf[self.pos / 8] >> (7 - self.pos % 8) & 1) as u64;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    fn read_signed(&mut self, bits: u32) -> Option<i64> {
+        let value = self.read(bits)?;
+        // sign-extend
+        Some(((value << (64 - bits)) as i64) >> (64 - bits))
+    }
+}
+
+/// Compresses `entries` into `buf`, returning the number of bytes used, or None if they do not fit
+pub fn encode(entries: &[repr::DataEntry], buf: &mut [u8]) -> Option<usize> {
+    let mut w = BitWriter::new(buf);
+    let Some((first, rest)) = entries.split_first() else {
+        return Some(0);
+    };
+    w.write(first.htime as u64, 32)?;
+    w.write(first.data.to_bits() as u64, 32)?;
+    let (mut prev_time, mut prev_delta) = (first.htime, 0i64);
+    let (mut prev_bits, mut window) = (first.data.to_bits(), None::<(u32, u32)>);
+    for entry in rest {
+        let delta = entry.htime as i64 - prev_time as i64;
+        let dod = delta - prev_delta;
+        if dod == 0 {
+            w.write(0, 1)?;
+        } else {
+            let &(bits, prefix, prefix_len) = TIME_CLASSES
+                .iter()
+                .find(|(bits, ..)| (-(1 << (bits - 1))..(1 << (bits - 1))).contains(&dod))
+                .unwrap();
+            w.write(prefix, prefix_len)?;
+            w.write(dod as u64 & ((1 << bits) - 1), bits)?;
+        }
+        (prev_time, prev_delta) = (entry.htime, delta);
+
+        let bits = entry.data.to_bits();
+        let xor = bits ^ prev_bits;
+        prev_bits = bits;
+        if xor == 0 {
+            w.write(0, 1)?;
+            continue;
+        }
+        let (leading, trailing) = (xor.leading_zeros(), xor.trailing_zeros());
+        match window {
+            Some((w_leading, w_trailing)) if leading >= w_leading && trailing >= w_trailing => {
+                w.write(0b10, 2)?;
+                w.write((xor >> w_trailing) as u64, 32 - w_leading - w_trailing)?;
+            }
+            _ => {
+                let len = 32 - leading - trailing;
+                w.write(0b11, 2)?;
+                w.write(leading as u64, 5)?;
+                w.write(len as u64 - 1, 5)?;
+                w.write((xor >> trailing) as u64, len)?;
+                window = Some((leading, trailing));
+            }
+        }
+    }
+    Some(w.len())
+}
+
+/// Decompresses `count` entries from `buf`, or None if it is not valid
+pub fn decode(buf: &[u8], count: usize) -> Option<Vec<repr::DataEntry>> {
+    let mut r = BitReader { buf, pos: 0 };
+    let mut entries = Vec::with_capacity(count);
+    if count == 0 {
+        return Some(entries);
+    }
+    let mut time = r.read(32)? as u32;
+    let mut bits = r.read(32)? as u32;
+    entries.push(repr::DataEntry {
+        htime: time,
+        data: f32::from_bits(bits),
+    });
+    let (mut delta, mut window) = (0i64, None);
+    for _ in 1..count {
+        let mut class = 0;
+        while class < 4 && r.read(1)? == 1 {
+            class += 1;
+        }
+        let dod = match class {
+            0 => 0,
+            // (the prefix for the largest class has no terminating zero)
+            n => r.read_signed(TIME_CLASSES[n - 1].0)?,
+        };
+        delta += dod;
+        time = u32::try_from(time as i64 + delta).ok()?;
+
+        if r.read(1)? == 1 {
+            if r.read(1)? == 1 {
+                let leading = r.read(5)? as u32;
+                let len = r.read(5)? as u32 + 1;
+                if leading + len > 32 {
+                    return None;
+                }
+                window = Some((leading, 32 - leading - len));
+            }
+            // (None if no window has been started yet)
+            let (leading, trailing) = window?;
+            bits ^= (r.read(32 - leading - trailing)? as u32) << trailing;
+        }
+        entries.push(repr::DataEntry {
+            htime: time,
+            data: f32::from_bits(bits),
+        });
+    }
+    Some(entries)
+}
+
+/// A data chunk other than the head (which is stored inline in the channel, and never compressed)
+pub enum OlderChunk<'a> {
+    Raw(&'a mut repr::ChannelData),
+    Compressed(&'a mut repr::CompressedData),
+}
+
+impl<'a> OlderChunk<'a> {
+    /// panics if `link` is null, or has an unknown codec
+    pub fn read(access: &mut AllocAccess<'a>, link: repr::ChunkLink) -> Self {
+        assert!(!link.is_null());
+        match link.codec {
+            repr::CODEC_RAW => Self::Raw(access.read(link.ptr.cast())),
+            repr::CODEC_GORILLA => Self::Compressed(access.read(link.ptr.cast())),
+            codec => panic!(
+                "Data chunk at {:#x} has unknown codec {codec}",
+                link.ptr.addr
+            ),
+        }
+    }
+
+    /// the next older chunk
+    pub fn next(&self) -> repr::ChunkLink {
+        match self {
+            Self::Raw(chunk) => chunk.next,
+            Self::Compressed(chunk) => chunk.next,
+        }
+    }
+
+    pub fn set_next(&mut self, next: repr::ChunkLink) {
+        match self {
+            Self::Raw(chunk) => chunk.next = next,
+            Self::Compressed(chunk) => chunk.next = next,
+        }
+    }
+
+    /// number of entries (older chunks are always full)
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Raw(chunk) => chunk.chunk.len(),
+            Self::Compressed(chunk) => chunk.count as usize,
+        }
+    }
+
+    /// time of the oldest entry (without decoding the chunk)
+    pub fn first_time(&self) -> u32 {
+        match self {
+            Self::Raw(chunk) => chunk.chunk[0].htime,
+            Self::Compressed(chunk) => chunk.first_time,
+        }
+    }
+
+    /// time of the newest entry (without decoding the chunk)
+    pub fn last_time(&self) -> u32 {
+        match self {
+            Self::Raw(chunk) => chunk.chunk[chunk.chunk.len() - 1].htime,
+            Self::Compressed(chunk) => chunk.last_time,
+        }
+    }
+
+    /// the entries, oldest -> newest (decoding compressed chunks)
+    pub fn entries(&self) -> Cow<'_, [repr::DataEntry]> {
+        match self {
+            Self::Raw(chunk) => Cow::Borrowed(&chunk.chunk),
+            Self::Compressed(chunk) => Cow::Owned(
+                decode(&chunk.buf[..chunk.used as usize], chunk.count as usize)
+                    .expect("Compressed data chunk is corrupt"),
+            ),
+        }
+    }
+
+    /// Like [`shift_in`][super::shift_in] on the chunk's entries, returning the entry that no longer fits.
+    ///
+    /// compressed chunks are re-encoded. if one no longer fits (compressed), the chunk must be replaced with an
+    /// uncompressed one, and the new entries are returned (and the chunk is not modified)
+    pub fn shift_in(
+        &mut self,
+        idx: impl FnOnce(&[repr::DataEntry]) -> usize,
+        new: repr::DataEntry,
+    ) -> (repr::DataEntry, Option<Vec<repr::DataEntry>>) {
+        match self {
+            Self::Raw(chunk) => {
+                let idx = idx(&chunk.chunk);
+                (super::shift_in(&mut chunk.chunk, idx, new), None)
+            }
+            Self::Compressed(chunk) => {
+                let mut entries = decode(&chunk.buf[..chunk.used as usize], chunk.count as usize)
+                    .expect("Compressed data chunk is corrupt");
+                let idx = idx(&entries);
+                let pushed_out = super::shift_in(&mut entries, idx, new);
+                let mut buf = [0u8; repr::COMPRESSED_BUF_SIZE];
+                match encode(&entries, &mut buf) {
+                    Some(used) => {
+                        chunk.buf = buf;
+                        chunk.used = used as u32;
+                        chunk.first_time = entries[0].htime;
+                        chunk.last_time = entries[entries.len() - 1].htime;
+                        (pushed_out, None)
+                    }
+                    None => (pushed_out, Some(entries)),
+                }
+            }
+        }
+    }
+}
+
+/// Stores a full chunk of `entries` (with `next` as its next older chunk), compressed if `compress` and they
+/// compress small enough, returning the link to it
+pub fn store_chunk(
+    access: &mut AllocAccess,
+    entries: &[repr::DataEntry],
+    next: repr::ChunkLink,
+    compress: bool,
+) -> repr::ChunkLink {
+    let mut buf = [0u8; repr::COMPRESSED_BUF_SIZE];
+    if let Some(used) = compress.then(|| encode(entries, &mut buf)).flatten() {
+        let (ptr, chunk) = access.alloc::<repr::CompressedData>();
+        *chunk = repr::CompressedData {
+            next,
+            count: entries.len() as u32,
+            used: used as u32,
+            first_time: entries[0].htime,
+            last_time: entries[entries.len() - 1].htime,
+            buf,
+        };
+        return repr::ChunkLink::new(ptr.cast(), repr::CODEC_GORILLA);
+    }
+    let (ptr, chunk) = access.alloc::<repr::ChannelData>();
+    chunk.chunk.copy_from_slice(entries);
+    chunk.next = next;
+    repr::ChunkLink::new(ptr.cast(), repr::CODEC_RAW)
+}
+
+#[test]
+fn round_trip() {
+    let series = |f: &dyn Fn(u32) -> (u32, f32)| {
+        (0..512)
+            .map(f)
+            .map(|(htime, data)| repr::DataEntry { htime, data })
+            .collect::<Vec<_>>()
+    };
+    let cases = [
+        // slowly changing, at a fixed interval
+        series(&|i| (100_000 + i * 60, 20.0 + (i / 16) as f32 * 0.5)),
+        // noisy readings, with jitter in the interval
+        series(&|i| {
+            (
+                100_000 + i * 60 + (i * 7919) % 13,
+                (i.wrapping_mul(2654435761) as f32).sin() * 1000.0,
+            )
+        }),
+        // large gaps, repeated times, and special values
+        series(&|i| match i % 4 {
+            0 => (i * 1_000_000, f32::NAN),
+            1 => (i * 1_000_000, -0.0),
+            2 => (i * 1_000_000 + 86400 * 365, f32::INFINITY),
+            _ => (i * 1_000_000 + 86400 * 365, f32::MIN_POSITIVE),
+        }),
+        vec![],
+    ];
+    for entries in cases {
+        let mut buf = vec![0u8; 8192];
+        let used = encode(&entries, &mut buf).unwrap();
+        let decoded = decode(&buf[..used], entries.len()).unwrap();
+        assert_eq!(decoded.len(), entries.len());
+        for (a, b) in entries.iter().zip(&decoded) {
+            assert_eq!((a.htime, a.data.to_bits()), (b.htime, b.data.to_bits()));
+        }
+    }
+}
+
+#[test]
+fn smaller_than_raw() {
+    let entries = (0..512)
+        .map(|i| repr::DataEntry {
+            htime: 100_000 + i * 60,
+            data: 20.0 + (i / 16) as f32 * 0.5,
+        })
+        .collect::<Vec<_>>();
+    let mut buf = [0u8; repr::COMPRESSED_BUF_SIZE];
+    let used = encode(&entries, &mut buf).unwrap();
+    assert!(used < std::mem::size_of_val(&entries[..]) / 8, "{used}B");
+    // does not fit, instead of writing past the end
+    assert_eq!(encode(&entries, &mut buf[..used - 1]), None);
+    assert!(decode(&buf[..used - 1], entries.len()).is_none());
+}
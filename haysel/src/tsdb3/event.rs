@@ -0,0 +1,108 @@
+//! encoding of `ChannelData::Event`s in [`repr::EventChunk`]s
+//!
+//! each event is (all little endian)
+//! - htime: u32
+//! - sub: u8 length, then utf8 bytes
+//! - number of fields: u8
+//! - for each field: u8 length, then utf8 bytes of the key, then the value (f32)
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use super::repr;
+
+/// An event read back from the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEvent {
+    pub time: DateTime<Utc>,
+    pub sub: String,
+    pub data: HashMap<String, f32>,
+}
+
+/// encode an event, or None if it can not be stored (a string or the number of fields is longer than 255,
+/// or the whole event does not fit in a chunk)
+pub fn encode(htime: u32, sub: &str, data: &HashMap<String, f32>) -> Option<Vec<u8>> {
+    fn push_str(buf: &mut Vec<u8>, s: &str) -> Option<()> {
+        buf.push(u8::try_from(s.len()).ok()?);
+        buf.extend_from_slice(s.as_bytes());
+        Some(())
+    }
+    let mut buf = htime.to_le_bytes().to_vec();
+    push_str(&mut buf, sub)?;
+    buf.push(u8::try_from(data.len()).ok()?);
+    // sorted, so that the same event is always encoded the same way
+    let mut fields = data.iter().collect::<Vec<_>>();
+    fields.sort_by_key(|(key, _)| *key);
+    for (key, value) in fields {
+        push_str(&mut buf, key)?;
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+    (buf.len() <= repr::EVENT_CHUNK_BUF_SIZE).then_some(buf)
+}
+
+/// splits `buf` (the used part of a chunk) into its encoded events, with their times, oldest to newest
+pub fn split(mut buf: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut events = vec![];
+    while !buf.is_empty() {
+        let htime = u32::from_le_bytes(buf[..4].try_into().unwrap());
+        let mut len = 4;
+        let skip_str = |len: &mut usize| *len += 1 + buf[*len] as usize;
+        skip_str(&mut len);
+        let num_fields = buf[len];
+        len += 1;
+        for _ in 0..num_fields {
+            skip_str(&mut len);
+            len += 4;
+        }
+        let (event, rest) = buf.split_at(len);
+        events.push((htime, event));
+        buf = rest;
+    }
+    events
+}
+
+/// decodes the events in `buf` (the used part of a chunk), oldest to newest
+pub fn decode(buf: &[u8]) -> Vec<(u32, String, HashMap<String, f32>)> {
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> &'a [u8] {
+        let (taken, rest) = buf.split_at(n);
+        *buf = rest;
+        taken
+    }
+    fn take_str(buf: &mut &[u8]) -> String {
+        let len = take(buf, 1)[0] as usize;
+        String::from_utf8_lossy(take(buf, len)).into_owned()
+    }
+    split(buf)
+        .into_iter()
+        .map(|(htime, mut buf)| {
+            take(&mut buf, 4);
+            let sub = take_str(&mut buf);
+            let num_fields = take(&mut buf, 1)[0];
+            let data = (0..num_fields)
+                .map(|_| {
+                    let key = take_str(&mut buf);
+                    let value = f32::from_le_bytes(take(&mut buf, 4).try_into().unwrap());
+                    (key, value)
+                })
+                .collect();
+            (htime, sub, data)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn encode_roundtrip() {
+    let data = HashMap::from([("distance".to_string(), 12.5), ("energy".to_string(), 3.0)]);
+    let mut buf = encode(100, "strike", &data).unwrap();
+    buf.extend(encode(101, "disturber", &HashMap::new()).unwrap());
+    assert_eq!(
+        decode(&buf),
+        vec![
+            (100, "strike".to_string(), data),
+            (101, "disturber".to_string(), HashMap::new())
+        ]
+    );
+    assert_eq!(encode(0, &"x".repeat(256), &HashMap::new()), None);
+}
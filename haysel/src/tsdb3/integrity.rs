@@ -0,0 +1,418 @@
+//! offline consistency checking (fsck) for TSDB v3
+//!
+//! unlike the rest of the database, this does not panic on bad data - every problem found is collected into an [`IntegrityReport`]
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use uuid::Uuid;
+use zerocopy::FromZeroes;
+
+use super::{
+    alloc::{AllocAccess, AllocError, Ptr},
+    codec, repr, DB,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityProblem {
+    #[error("The allocator header is missing or invalid (wrong magic bytes)")]
+    BadHeader,
+    #[error("The allocator claims to use {used}B, which is outside of the store ({store_size}B)")]
+    UsedOutOfRange { used: u64, store_size: u64 },
+    #[error("The database has no entrypoint (was it initialized?)")]
+    NullEntrypoint,
+    #[error("The database was written with a different layout (hash {stored:#x}, expected {expected:#x})")]
+    SchemaMismatch { stored: u32, expected: u32 },
+    #[error("The database was written with a different set of types ({stored} types of chunk, expected {expected})")]
+    TypesMismatch { stored: u64, expected: u64 },
+    #[error("Tuning parameter `{param}` is {found}, expected {expected}")]
+    TuningMismatch {
+        param: &'static str,
+        found: u64,
+        expected: u64,
+    },
+    #[error("{at}: invalid pointer: {err}")]
+    BadPointer { at: String, err: AllocError },
+    #[error("{at}: pointer to {addr:#x} is past the end of the used space ({used}B)")]
+    PointerPastUsed { at: String, addr: u64, used: u64 },
+    #[error("{at}: pointer to {addr:#x} refers to space that has been freed")]
+    PointerToFreed { at: String, addr: u64 },
+    #[error("{at}: data at {addr:#x} is referenced more than once")]
+    MultiplyReferenced { at: String, addr: u64 },
+    #[error("{at}: entry {idx} is in use, but comes after an empty entry (the map must not be sparse)")]
+    SparseMap { at: String, idx: usize },
+    #[error("{at}: entry {idx} has a null id")]
+    NullId { at: String, idx: usize },
+    #[error("{at}: id {id} appears more than once")]
+    DuplicateId { at: String, id: Uuid },
+    #[error("{at}: {num_used} entries are in use, but there is only space for {capacity}")]
+    NumUsedTooLarge {
+        at: String,
+        num_used: u32,
+        capacity: u32,
+    },
+    #[error("{at}: entry {idx} is older than the entry before it (data must be in chronological order)")]
+    OutOfOrder { at: String, idx: usize },
+    #[error("{at}: pointer to {addr:#x} has unknown codec {codec}")]
+    UnknownCodec { at: String, addr: u64, codec: u32 },
+    #[error("{at}: compressed data at {addr:#x} could not be decoded, or does not match its header")]
+    BadCompressedChunk { at: String, addr: u64 },
+    #[error("{at}: last recorded time is {last_time}, but the newest entry has time {newest}")]
+    LastTimeMismatch {
+        at: String,
+        last_time: u32,
+        newest: u32,
+    },
+}
+
+/// Result of [`DB::check_integrity`]
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub problems: Vec<IntegrityProblem>,
+    pub stations_checked: usize,
+    pub channels_checked: usize,
+    pub chunks_checked: usize,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "checked {} stations, {} channels, {} data chunks: {} problem(s) found",
+            self.stations_checked,
+            self.channels_checked,
+            self.chunks_checked,
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "- {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+/// shared state for walking the database
+struct Checker<'a> {
+    access: AllocAccess<'a>,
+    /// addresses of everything that has been read so far
+    visited: HashSet<u64>,
+    /// start -> end of the space in each freed chunk
+    freed: BTreeMap<u64, u64>,
+    report: IntegrityReport,
+}
+
+impl<'a> Checker<'a> {
+    fn problem(&mut self, problem: IntegrityProblem) {
+        self.report.problems.push(problem);
+    }
+
+    /// reads the value at `ptr`, recording a problem (and returning None) if it is invalid or has already been seen
+    fn read<T: zerocopy::AsBytes + zerocopy::FromBytes + FromZeroes>(
+        &mut self,
+        ptr: Ptr<T>,
+        at: impl Fn() -> String,
+    ) -> Option<&'a mut T> {
+        let used = self.access.get_size_used();
+        if let Err(err) = self.access.check_bounds(ptr) {
+            self.problem(IntegrityProblem::BadPointer { at: at(), err });
+            return None;
+        }
+        if ptr.addr >= used {
+            self.problem(IntegrityProblem::PointerPastUsed {
+                at: at(),
+                addr: ptr.addr,
+                used,
+            });
+            return None;
+        }
+        if self
+            .freed
+            .range(..=ptr.addr)
+            .next_back()
+            .is_some_and(|(_, &end)| ptr.addr < end)
+        {
+            self.problem(IntegrityProblem::PointerToFreed {
+                at: at(),
+                addr: ptr.addr,
+            });
+            return None;
+        }
+        if !self.visited.insert(ptr.addr) {
+            self.problem(IntegrityProblem::MultiplyReferenced {
+                at: at(),
+                addr: ptr.addr,
+            });
+            return None;
+        }
+        match self.access.try_read(ptr) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                self.problem(IntegrityProblem::BadPointer { at: at(), err });
+                None
+            }
+        }
+    }
+
+    /// reads the (older, full) data chunk at `link`, decoding it if it is compressed.
+    ///
+    /// returns its entries and the link to the next chunk
+    fn read_chunk(
+        &mut self,
+        link: repr::ChunkLink,
+        capacity: usize,
+        at: &str,
+    ) -> Option<(Vec<repr::DataEntry>, repr::ChunkLink)> {
+        let addr = link.ptr.addr;
+        match link.codec {
+            repr::CODEC_RAW => {
+                let chunk = self.read(link.ptr.cast::<repr::ChannelData>(), || at.to_string())?;
+                Some((chunk.chunk.to_vec(), chunk.next))
+            }
+            repr::CODEC_GORILLA => {
+                let chunk =
+                    self.read(link.ptr.cast::<repr::CompressedData>(), || at.to_string())?;
+                let entries = chunk
+                    .buf
+                    .get(..chunk.used as usize)
+                    .and_then(|buf| codec::decode(buf, chunk.count as usize))
+                    .filter(|entries| {
+                        entries.len() == capacity
+                            && entries[0].htime == chunk.first_time
+                            && entries[capacity - 1].htime == chunk.last_time
+                    });
+                let Some(entries) = entries else {
+                    self.problem(IntegrityProblem::BadCompressedChunk {
+                        at: at.to_string(),
+                        addr,
+                    });
+                    return None;
+                };
+                Some((entries, chunk.next))
+            }
+            codec => {
+                self.problem(IntegrityProblem::UnknownCodec {
+                    at: at.to_string(),
+                    addr,
+                    codec,
+                });
+                None
+            }
+        }
+    }
+
+    /// checks that the in-use entries (non-null ptr) of a map are dense and have unique, non-null ids.
+    ///
+    /// returns the (id, ptr) pairs of entries that are in use
+    fn check_map<T>(&mut self, at: &str, entries: &[(uuid::Bytes, Ptr<T>)]) -> Vec<(Uuid, Ptr<T>)> {
+        let mut found = vec![];
+        let mut ids = HashSet::new();
+        let mut seen_empty = false;
+        for (idx, &(id, ptr)) in entries.iter().enumerate() {
+            if ptr.is_null() {
+                seen_empty = true;
+                continue;
+            }
+            if seen_empty {
+                self.problem(IntegrityProblem::SparseMap {
+                    at: at.to_string(),
+                    idx,
+                });
+            }
+            let id = Uuid::from_bytes(id);
+            if id.is_nil() {
+                self.problem(IntegrityProblem::NullId {
+                    at: at.to_string(),
+                    idx,
+                });
+            } else if !ids.insert(id) {
+                self.problem(IntegrityProblem::DuplicateId {
+                    at: at.to_string(),
+                    id,
+                });
+            }
+            found.push((id, ptr));
+        }
+        found
+    }
+
+    fn check_entrypoint(&mut self) {
+        let used = self.access.get_size_used();
+        let store_size = self.access.get_store_size();
+        if used > store_size {
+            self.problem(IntegrityProblem::UsedOutOfRange { used, store_size });
+        }
+        let (stored, expected) = self.access.num_types();
+        if stored != expected {
+            self.problem(IntegrityProblem::TypesMismatch { stored, expected });
+            return;
+        }
+        // (0 if written before the hash was stored, with a different layout)
+        let (stored, expected) = (*self.access.schema_hash(), repr::schema_hash());
+        if stored != expected {
+            self.problem(IntegrityProblem::SchemaMismatch { stored, expected });
+            return;
+        }
+        let entry_ptr = *self.access.entrypoint_pointer();
+        if entry_ptr.is_null() {
+            self.problem(IntegrityProblem::NullEntrypoint);
+            return;
+        }
+        let Some(entry) = self.read(entry_ptr.cast::<repr::DBEntrypoint>(), || {
+            "entrypoint".to_string()
+        }) else {
+            return;
+        };
+        for (param, found, expected) in [
+            (
+                "station_map_chunk_size",
+                entry.tuning_params.station_map_chunk_size,
+                repr::MapStations::new_zeroed().stations.len() as u64,
+            ),
+            (
+                "channel_map_chunk_size",
+                entry.tuning_params.channel_map_chunk_size,
+                repr::Station::new_zeroed().channels.len() as u64,
+            ),
+        ] {
+            if found != expected {
+                self.problem(IntegrityProblem::TuningMismatch {
+                    param,
+                    found,
+                    expected,
+                });
+            }
+        }
+        let stations = entry
+            .stations
+            .stations
+            .iter()
+            .map(|elem| (elem.id, elem.ptr))
+            .collect::<Vec<_>>();
+        for (sid, ptr) in self.check_map("station map", &stations) {
+            self.report.stations_checked += 1;
+            self.check_station(sid, ptr);
+        }
+    }
+
+    fn check_station(&mut self, sid: Uuid, ptr: Ptr<repr::Station>) {
+        let Some(station) = self.read(ptr, || format!("station {sid}")) else {
+            return;
+        };
+        let channels = station
+            .channels
+            .iter()
+            .map(|elem| (elem.id, elem.ptr))
+            .collect::<Vec<_>>();
+        for (cid, ptr) in self.check_map(&format!("channel map of station {sid}"), &channels) {
+            self.report.channels_checked += 1;
+            self.check_channel(sid, cid, ptr);
+        }
+    }
+
+    fn check_channel(&mut self, sid: Uuid, cid: Uuid, ptr: Ptr<repr::Channel>) {
+        let at = format!("channel {cid} of station {sid}");
+        let Some(channel) = self.read(ptr, || at.clone()) else {
+            return;
+        };
+        let capacity = channel.data.chunk.len() as u32;
+        let mut num_valid = channel.num_used;
+        if num_valid > capacity {
+            self.problem(IntegrityProblem::NumUsedTooLarge {
+                at: at.clone(),
+                num_used: num_valid,
+                capacity,
+            });
+            num_valid = capacity;
+        }
+        // the chunks are walked newest -> oldest, and the entries in each chunk are oldest -> newest
+        let mut newest = None;
+        // oldest entry of the previous (newer) chunk
+        let mut newer_oldest = None;
+        let mut entries = Cow::Borrowed(&channel.data.chunk[..num_valid as usize]);
+        let mut next = channel.data.next;
+        let mut chunk_n = 0usize;
+        loop {
+            self.report.chunks_checked += 1;
+            let chunk_at = format!("data chunk {chunk_n} of {at}");
+            for (idx, pair) in entries.windows(2).enumerate() {
+                if pair[1].htime < pair[0].htime {
+                    self.problem(IntegrityProblem::OutOfOrder {
+                        at: chunk_at.clone(),
+                        idx: idx + 1,
+                    });
+                }
+            }
+            if let (Some(last), Some(newer_oldest)) = (entries.last(), newer_oldest) {
+                if last.htime > newer_oldest {
+                    self.problem(IntegrityProblem::OutOfOrder {
+                        at: chunk_at.clone(),
+                        idx: entries.len() - 1,
+                    });
+                }
+            }
+            if newest.is_none() {
+                newest = entries.last().map(|entry| entry.htime);
+            }
+            if let Some(first) = entries.first() {
+                newer_oldest = Some(first.htime);
+            }
+            if next.is_null() {
+                break;
+            }
+            let Some((older, older_next)) = self.read_chunk(next, capacity as usize, &chunk_at)
+            else {
+                break;
+            };
+            (entries, next) = (Cow::Owned(older), older_next);
+            chunk_n += 1;
+        }
+        if let Some(newest) = newest {
+            if newest != channel.last_time {
+                self.problem(IntegrityProblem::LastTimeMismatch {
+                    at,
+                    last_time: channel.last_time,
+                    newest,
+                });
+            }
+        }
+    }
+}
+
+impl DB {
+    /// Checks the consistency of the entire database, collecting every problem found instead of stopping at the first one.
+    ///
+    /// This does not require the database to be opened with [`DB::open`] (or even to be writable, see
+    /// [`DB::new_read_only_uninit`]), and does not modify it.
+    pub fn check_integrity(&mut self) -> IntegrityReport {
+        if !AllocAccess::header_is_valid(&self.store.map) {
+            return IntegrityReport {
+                problems: vec![IntegrityProblem::BadHeader],
+                ..Default::default()
+            };
+        }
+        let mut access = self.store.access(false);
+        let freed = access
+            .scan_chunks()
+            .into_iter()
+            .filter(|chunk| chunk.free)
+            .map(|chunk| (chunk.body().start, chunk.body().end))
+            .collect();
+        let mut checker = Checker {
+            access,
+            visited: HashSet::new(),
+            freed,
+            report: IntegrityReport::default(),
+        };
+        checker.check_entrypoint();
+        checker.report
+    }
+}
@@ -0,0 +1,187 @@
+//! the layout of databases written before the layout was recorded (see [`repr::schema_hash`]), which have a stored
+//! hash of 0. it differs from the current one in [`Channel`] (which has no events) and [`ChannelData`] (which links
+//! chunks with a plain pointer, as they are never compressed). such databases are converted by [`DB::migrate_legacy`]
+
+use std::fmt;
+
+use chrono::DateTime;
+use mycelium::station::{capabilities::ChannelID, identity::StationID};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use super::{
+    alloc::{AllocAccess, Ptr, TypeRegistry},
+    repr::{self, DataEntry},
+    Error, DB,
+};
+
+#[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
+#[repr(C)]
+pub struct Channel {
+    pub num_used: u32,
+    pub last_time: u32,
+    pub data: ChannelData,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
+#[repr(C)]
+pub struct ChannelData {
+    pub chunk: [DataEntry; 512],
+    pub next: Ptr<ChannelData>,
+}
+
+/// the types registered with the allocator (the store has a free list for each)
+pub fn type_registry() -> TypeRegistry {
+    let mut alloc_t_reg = TypeRegistry::new();
+    alloc_t_reg.register::<repr::DBEntrypoint>();
+    alloc_t_reg.register::<repr::Station>();
+    alloc_t_reg.register::<Channel>();
+    alloc_t_reg.register::<ChannelData>();
+    alloc_t_reg
+}
+
+/// Result of [`DB::migrate_legacy`]
+#[derive(Debug, Default)]
+pub struct MigrateReport {
+    pub stations: usize,
+    pub channels: usize,
+    pub readings: usize,
+}
+
+impl fmt::Display for MigrateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "copied {} stations, {} channels, and {} readings",
+            self.stations, self.channels, self.readings
+        )
+    }
+}
+
+impl DB {
+    /// Copies the stations, channels, and readings of a database with the legacy layout into `to`, which must be
+    /// opened (it should be empty, so that no station already exists). this database is not modified, and must not be
+    /// opened (it can not be, see [`DB::open`]), so it may be read-only (see [`DB::new_read_only_uninit`])
+    ///
+    /// ## Errors
+    /// - [`Error::NotADatabase`] if this does not contain a database
+    /// - [`Error::IncompatibleTypes`] if it is not of the legacy layout (so does not need to be migrated)
+    /// - any error inserting into `to`
+    pub fn migrate_legacy(&mut self, to: &mut DB) -> Result<MigrateReport, Error> {
+        if !AllocAccess::header_is_valid(&self.store.map) {
+            return Err(Error::NotADatabase);
+        }
+        let alloc_t_reg = type_registry();
+        let mut access = AllocAccess::new(&mut self.store.map, &alloc_t_reg, false);
+        let (stored, expected) = access.num_types();
+        if stored != expected {
+            return Err(Error::IncompatibleTypes { stored, expected });
+        }
+        let entry = access
+            .entrypoint::<repr::DBEntrypoint>()
+            .ok_or(Error::NotADatabase)?;
+        let mut stations = vec![];
+        for elem in entry
+            .stations
+            .stations
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+        {
+            let station = access.read(elem.ptr);
+            let mut channels = vec![];
+            for elem in station.channels.iter().take_while(|ch| !ch.ptr.is_null()) {
+                let channel = access.read(elem.ptr.cast::<Channel>());
+                // older chunks are linked from the head, newest first (and are always full)
+                let mut older = vec![];
+                let mut next = channel.data.next;
+                while !next.is_null() {
+                    let chunk = access.read(next);
+                    older.push(&chunk.chunk[..]);
+                    next = chunk.next;
+                }
+                let readings = older
+                    .into_iter()
+                    .rev()
+                    .chain([&channel.data.chunk[..channel.num_used as usize]])
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<_>>();
+                channels.push((ChannelID::from_bytes(elem.id), readings));
+            }
+            stations.push((StationID::from_bytes(elem.id), channels));
+        }
+
+        let mut report = MigrateReport::default();
+        for (station, channels) in stations {
+            to.insert_station(station)?;
+            to.insert_channels(station, channels.iter().map(|(id, _)| *id))?;
+            for (channel, readings) in channels {
+                for reading in &readings {
+                    let time =
+                        DateTime::from_timestamp(repr::htime_to_unix(reading.htime), 0).unwrap();
+                    to.insert_data(station, channel, time, reading.data)?;
+                }
+                report.channels += 1;
+                report.readings += readings.len();
+            }
+            report.stations += 1;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+impl DB {
+    /// Writes a new database with the legacy layout, containing `channels` ((station, channel, readings oldest to
+    /// newest), stations in order of first appearance), as a version from before the layout was recorded would
+    pub(super) fn write_legacy(&mut self, channels: &[(uuid::Uuid, uuid::Uuid, Vec<DataEntry>)]) {
+        let alloc_t_reg = type_registry();
+        let mut access = AllocAccess::new(&mut self.store.map, &alloc_t_reg, true);
+        let (entry_ptr, entry) = access.alloc::<repr::DBEntrypoint>();
+        *access.entrypoint_pointer() = entry_ptr.cast();
+        entry.tuning_params.station_map_chunk_size =
+            repr::MapStations::new_zeroed().stations.len() as u64;
+        entry.tuning_params.channel_map_chunk_size =
+            repr::Station::new_zeroed().channels.len() as u64;
+        for (station_id, channel_id, readings) in channels {
+            // (a new access for each, so that the same station can be read again)
+            let mut access = AllocAccess::new(&mut self.store.map, &alloc_t_reg, false);
+            let stations = &mut access
+                .entrypoint::<repr::DBEntrypoint>()
+                .unwrap()
+                .stations
+                .stations;
+            let station = match stations.iter().position(|s| &s.id == station_id.as_bytes()) {
+                Some(idx) => access.read(stations[idx].ptr),
+                None => {
+                    let idx = stations.iter().position(|s| s.ptr.is_null()).unwrap();
+                    let (ptr, station) = access.alloc::<repr::Station>();
+                    stations[idx] = repr::MapStationsElem {
+                        id: station_id.into_bytes(),
+                        ptr,
+                    };
+                    station
+                }
+            };
+            let elem = station
+                .channels
+                .iter_mut()
+                .find(|ch| ch.ptr.is_null())
+                .unwrap();
+            let (channel_ptr, channel) = access.alloc::<Channel>();
+            elem.id = channel_id.into_bytes();
+            elem.ptr = channel_ptr.cast();
+            // full chunks are moved out of the head, which links to them (newest to oldest)
+            let mut chunks = readings.chunks(512).collect::<Vec<_>>();
+            let head = chunks.pop().unwrap_or(&[]);
+            for chunk in chunks {
+                let (ptr, older) = access.alloc::<ChannelData>();
+                older.chunk.copy_from_slice(chunk);
+                older.next = channel.data.next;
+                channel.data.next = ptr;
+            }
+            channel.data.chunk[..head.len()].copy_from_slice(head);
+            channel.num_used = head.len() as u32;
+            channel.last_time = readings.last().map_or(0, |entry| entry.htime);
+        }
+    }
+}
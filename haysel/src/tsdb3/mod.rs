@@ -1,25 +1,41 @@
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fs::{self, OpenOptions},
     io,
     mem::ManuallyDrop,
+    path::Path,
     ptr,
 };
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use memmap2::MmapMut;
-use mycelium::station::{capabilities::ChannelID, identity::StationID};
+use memmap2::{MmapMut, MmapOptions};
+use mycelium::{
+    station::{
+        capabilities::{ChannelData, ChannelID},
+        identity::StationID,
+    },
+    DBStats, QueryCursor,
+};
 use zerocopy::FromZeroes;
 
 use self::{
-    alloc::{AllocAccess, TypeRegistry},
+    alloc::{AllocAccess, Ptr, TypeRegistry},
+    codec::OlderChunk,
+    event::StoredEvent,
     query::QueryParams,
 };
 
 mod alloc;
 pub mod bus;
 pub mod cmd;
+mod codec;
+pub mod event;
+pub mod integrity;
+pub mod legacy;
 pub mod query;
+pub mod repair;
 mod repr;
 mod test;
 
@@ -27,6 +43,252 @@ mod test;
 pub enum Error {
     #[error("I/O Error: {0:#}")]
     Mmap(#[from] io::Error),
+    #[error("The database was opened read-only, and may not be modified")]
+    ReadOnly,
+    #[error("The event is too large to be stored (names must be at most 255 bytes, with at most 255 fields)")]
+    EventTooLarge,
+    #[error("The database was written by an incompatible version (its layout hash is {stored:#010x}, this version uses {expected:#010x}, 0 is from before the layout was recorded)")]
+    Incompatible { stored: u32, expected: u32 },
+    #[error("The database was written by an incompatible version (it has {stored} types of chunk, this version uses {expected}, databases from before the layout was recorded can be converted with `db migrate`)")]
+    IncompatibleTypes { stored: u64, expected: u64 },
+    #[error(
+        "The file does not contain a database (and is not empty, so a new one was not created)"
+    )]
+    NotADatabase,
+    #[error("The database has already been opened or initialized")]
+    AlreadyInitialized,
+    #[error(
+        "The station's channel map is full (there is no space for the channels of a rollup tier)"
+    )]
+    ChannelMapFull,
+}
+
+/// The most stations the database can hold
+pub const MAX_STATIONS: usize =
+    std::mem::size_of::<repr::MapStations>() / std::mem::size_of::<repr::MapStationsElem>();
+/// The most channels a single station can have in the database
+pub const MAX_CHANNELS_PER_STATION: usize =
+    std::mem::size_of::<repr::Station>() / std::mem::size_of::<repr::MapChannelsElem>();
+
+/// Free space kept available in a database that can grow (see [`DB::set_growth`]), so that no single operation runs out
+/// of space part way through. the most any operation allocates is when every channel of a station is added at once
+pub const MIN_FREE_SPACE: u64 = 2
+    * (std::mem::size_of::<repr::DBEntrypoint>()
+        + std::mem::size_of::<repr::Station>()
+        + MAX_CHANNELS_PER_STATION * std::mem::size_of::<repr::Channel>()) as u64;
+
+/// If `time` can be stored in the database (it is between 2020 and 2156)
+pub fn is_storable(time: DateTime<Utc>) -> bool {
+    repr::unix_to_htime(time.timestamp()).is_some()
+}
+
+/// Which of a channel's readings to query (see [`DB::roll_up`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tier {
+    /// the readings as they were recorded
+    #[default]
+    Raw,
+    /// the mean of each bucket (of this length) of the readings that have been rolled up
+    Rollup(chrono::Duration),
+}
+
+/// Space freed by [`DB::roll_up`] or [`DB::delete_before`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reclaimed {
+    /// number of data chunks freed
+    pub chunks: usize,
+    pub bytes: u64,
+    /// if there are more chunks that could be freed (past `max_chunks`)
+    pub more: bool,
+}
+
+/// totals of successive calls (`more` is from the later one)
+impl std::ops::AddAssign for Reclaimed {
+    fn add_assign(&mut self, rhs: Self) {
+        self.chunks += rhs.chunks;
+        self.bytes += rhs.bytes;
+        self.more = rhs.more;
+    }
+}
+
+/// One bucket of a rollup tier (see [`DB::query_rollup`])
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollupBucket {
+    /// the start of the bucket
+    pub time: DateTime<Utc>,
+    pub mean: f32,
+    /// number of readings in the bucket
+    pub count: u32,
+}
+
+/// length of a rollup bucket in seconds (panics if it is not between 1s and `u32::MAX`s)
+fn bucket_secs(bucket: chrono::Duration) -> u32 {
+    u32::try_from(bucket.num_seconds())
+        .ok()
+        .filter(|secs| *secs > 0)
+        .expect("Rollup buckets must be at least one second long")
+}
+
+/// The hidden channels that the rollup tier of `channel` with `bucket_secs` long buckets is stored in:
+/// (means, number of readings).
+///
+/// they are stored in the station's channel map like any other channel, but are not listed (see [`is_rollup`]).
+/// the two always have entries at the same times (so their chunks line up)
+fn rollup_channels(channel: ChannelID, bucket_secs: u32) -> (ChannelID, ChannelID) {
+    let id = |kind: u8| {
+        // FNV-1a (128 bit), like the ids of channels themselves
+        let mut hash = 0x6c62272e07bb014262b821756295c58du128;
+        for &byte in channel
+            .as_bytes()
+            .iter()
+            .chain(&bucket_secs.to_le_bytes())
+            .chain(&[kind])
+        {
+            hash ^= byte as u128;
+            hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+        }
+        let mut bytes = hash.to_be_bytes();
+        // version 15, which the ids of channels never are (they are version 4 or 8)
+        bytes[6] |= 0xf0;
+        bytes[8] = bytes[8] & 0x3f | 0x80;
+        ChannelID::from_bytes(bytes)
+    };
+    (id(0), id(1))
+}
+
+/// If `channel` is one of the hidden channels of a rollup tier (see [`rollup_channels`])
+fn is_rollup(channel: &ChannelID) -> bool {
+    channel.get_version_num() == 15
+}
+
+/// the channel `id` of `station` (including hidden ones)
+fn channel_in(station: &repr::Station, id: ChannelID) -> Option<Ptr<repr::Channel>> {
+    station
+        .channels
+        .iter()
+        .take_while(|elem| !elem.ptr.is_null())
+        .find(|elem| &elem.id == id.as_bytes())
+        .map(|elem| elem.ptr)
+}
+
+/// Inserts `new` into `channel`, in chronological order (after any entries at the same time)
+fn insert_into<'a>(
+    access: &mut AllocAccess<'a>,
+    channel: &'a mut repr::Channel,
+    new: repr::DataEntry,
+    compress: bool,
+) {
+    let timestamp = new.htime;
+    // chunks older than the head, newest -> oldest, up to the one the reading belongs in
+    // (only walked for readings older than the head's oldest, e.g. from a delayed packet)
+    let mut older = vec![];
+    if channel.data.chunk[..channel.num_used as usize]
+        .first()
+        .is_some_and(|oldest| oldest.htime > timestamp)
+    {
+        let mut next = channel.data.next;
+        while !next.is_null() {
+            let chunk = OlderChunk::read(access, next);
+            next = chunk.next();
+            let found = chunk.first_time() <= timestamp;
+            older.push(chunk);
+            if found {
+                break;
+            }
+        }
+    }
+    // insert into the chunk it belongs in, and carry the newest entry of each (full) chunk into the start of
+    // the next newer one, until the head is reached
+    let mut carry = new;
+    let oldest = older.len().wrapping_sub(1);
+    for i in (0..older.len()).rev() {
+        let (pushed_out, replace) = older[i].shift_in(
+            |entries| {
+                if i == oldest {
+                    insertion_idx(entries, timestamp)
+                } else {
+                    0
+                }
+            },
+            carry,
+        );
+        carry = pushed_out;
+        if let Some(entries) = replace {
+            // (a compressed chunk that no longer fits, the old one is left unused)
+            let link = codec::store_chunk(access, &entries, older[i].next(), false);
+            match i {
+                0 => channel.data.next = link,
+                _ => older[i - 1].set_next(link),
+            }
+        }
+    }
+    let idx = if older.is_empty() {
+        insertion_idx(&channel.data.chunk[..channel.num_used as usize], timestamp)
+    } else {
+        0
+    };
+    if channel.is_full() {
+        let pushed_out = shift_in(&mut channel.data.chunk, idx, carry);
+        channel.data.next =
+            codec::store_chunk(access, &channel.data.chunk, channel.data.next, compress);
+        channel.num_used = 1;
+        channel.data.chunk[0] = pushed_out;
+    } else {
+        let used = channel.num_used as usize;
+        shift_in(&mut channel.data.chunk[..=used], idx, carry);
+        channel.num_used += 1;
+    }
+    channel.last_time = channel.last_time.max(timestamp);
+}
+
+/// Unlinks the oldest data chunks of `channel` where every entry is older than `before` (at most `max_chunks`).
+///
+/// returns them oldest -> newest (they must be freed once they are no longer accessed), and if there are more
+/// that could have been unlinked
+fn detach_older<'a>(
+    access: &mut AllocAccess<'a>,
+    channel: &mut repr::Channel,
+    before: u32,
+    max_chunks: usize,
+) -> (Vec<(repr::ChunkLink, OlderChunk<'a>)>, bool) {
+    // the list goes newest -> oldest, and the chunks to remove are at the end of it
+    let mut chunks = vec![];
+    let mut next = channel.data.next;
+    while !next.is_null() {
+        let chunk = OlderChunk::read(access, next);
+        chunks.push((next, chunk));
+        next = chunks.last().unwrap().1.next();
+    }
+    let expired = chunks
+        .iter()
+        .rev()
+        .take_while(|(_, chunk)| chunk.last_time() < before)
+        .count();
+    let keep = chunks.len() - expired.min(max_chunks);
+    let mut removed = chunks.split_off(keep);
+    match chunks.last_mut() {
+        Some((_, tail)) => tail.set_next(repr::ChunkLink::new_zeroed()),
+        None => channel.data.next = repr::ChunkLink::new_zeroed(),
+    }
+    removed.reverse();
+    (removed, expired > max_chunks)
+}
+
+/// where a reading at `htime` goes in `entries` (after any readings at the same time)
+fn insertion_idx(entries: &[repr::DataEntry], htime: u32) -> usize {
+    entries.partition_point(|entry| entry.htime <= htime)
+}
+
+/// Inserts `new` at `idx`, moving the entries after it along by one. returns the entry that no longer fits
+/// (the last one, or `new` itself if `idx` is the end)
+fn shift_in(entries: &mut [repr::DataEntry], idx: usize, new: repr::DataEntry) -> repr::DataEntry {
+    if idx == entries.len() {
+        return new;
+    }
+    let last = entries[entries.len() - 1];
+    entries.copy_within(idx..entries.len() - 1, idx + 1);
+    entries[idx] = new;
+    last
 }
 
 struct DBStore {
@@ -38,12 +300,41 @@ impl DBStore {
     pub fn access<'a>(&'a mut self, write_header: bool) -> AllocAccess<'a> {
         AllocAccess::new(&mut self.map, &self.alloc_t_reg, write_header)
     }
+
+    /// Replaces the mapping with a larger one (of `new_size` bytes), with the same contents.
+    ///
+    /// `file` is the mapped file, or None if the mapping is anonymous (in ram). nothing from [`DBStore::access`] can be
+    /// borrowed while this is called, so nothing refers to the old mapping after it is dropped
+    fn grow(&mut self, file: Option<&fs::File>, new_size: u64) -> io::Result<()> {
+        assert!(new_size >= self.map.len() as u64);
+        self.map = match file {
+            Some(file) => {
+                file.set_len(new_size)?;
+                // Saftey: the same file as the existing mapping (see `DB::new`)
+                unsafe { MmapMut::map_mut(file) }?
+            }
+            None => {
+                let mut map = MmapMut::map_anon(new_size as usize)?;
+                map[..self.map.len()].copy_from_slice(&self.map);
+                map
+            }
+        };
+        Ok(())
+    }
 }
 
+/// One page of readings, and the cursor for the next (see [`DB::query_page`])
+pub type Page = (Vec<(DateTime<Utc>, f32)>, Option<QueryCursor>);
+
 pub struct DB {
     file: *const fs::File,
     store: ManuallyDrop<DBStore>,
     init: bool,
+    read_only: bool,
+    /// if full data chunks are compressed (see [`DB::set_compression`])
+    compress: bool,
+    /// how much the store is grown by when it fills up (None if it can not grow, see [`DB::set_growth`])
+    grow_by: Option<u64>,
 }
 
 // `file` (which is what breaks the auto-impl) is effectively owned
@@ -59,12 +350,42 @@ const _: () = {
     }
 };
 
+fn alloc_type_registry() -> TypeRegistry {
+    let mut alloc_t_reg = TypeRegistry::new();
+    // only types that HAVE POINTERS TO THEM need to go here
+    alloc_t_reg.register::<repr::DBEntrypoint>();
+    alloc_t_reg.register::<repr::Station>();
+    alloc_t_reg.register::<repr::Channel>();
+    alloc_t_reg.register::<repr::ChannelData>();
+    alloc_t_reg.register::<repr::EventChunk>();
+    alloc_t_reg.register::<repr::CompressedData>();
+    alloc_t_reg
+}
+
 impl DB {
-    /// Creates an interaface to the database stored in `file`
+    /// Creates an interaface to the database stored in `file`, opening it if it contains one, or initializing a new one
+    /// if it is empty (all zeros, as it is after [`fs::File::set_len`])
+    ///
+    /// ## Errors
+    /// if memory mapping fails, if opening fails (see [`DB::open`]), or [`Error::NotADatabase`] if the file is neither
+    /// a database nor empty (it is never overwritten)
+    ///
+    /// ## Saftey
+    /// see memmap2::MmapMut::map_mut (file must be appropreatly protected, and it is UB if it is changed externally)
+    #[forbid(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new(file: fs::File) -> Result<Self, Error> {
+        // Saftey: forwarded to consumer of this function
+        let mut db = unsafe { Self::new_uninit(file) }?;
+        db.open_or_init()?;
+        Ok(db)
+    }
+
+    /// Creates an interaface to the database stored in `file`, without opening or initializing it
     ///
     /// ## Initialization
     /// this function does not rely on `file` containing anything in perticular.
-    /// before the database may be used, you must initialize it using [`DB::open`] (to open an existing datbase) or [`DB::init`] to initialize a new one
+    /// before the database may be used, you must initialize it using [`DB::open`] (to open an existing datbase) or [`DB::init`] to initialize a new one.
+    /// this is for tests, and for tools that must not touch the database before they look at it (like [`DB::repair`])
     ///
     /// ## Errors
     /// if memory mapping fails
@@ -73,48 +394,214 @@ impl DB {
     /// see memmap2::MmapMut::map_mut (file must be appropreatly protected, and it is UB if it is changed externally)
     #[must_use]
     #[forbid(unsafe_op_in_unsafe_fn)]
-    pub unsafe fn new(file: fs::File) -> Result<Self, Error> {
+    pub unsafe fn new_uninit(file: fs::File) -> Result<Self, Error> {
         let file = &*Box::leak(Box::new(file));
         // Saftey: forwarded to consumer of this function
         let map = unsafe { MmapMut::map_mut(file) }?;
-        let mut alloc_t_reg = TypeRegistry::new();
-        // only types that HAVE POINTERS TO THEM need to go here
-        alloc_t_reg.register::<repr::DBEntrypoint>();
-        alloc_t_reg.register::<repr::Station>();
-        alloc_t_reg.register::<repr::Channel>();
-        alloc_t_reg.register::<repr::ChannelData>();
         Ok(Self {
             file: file as *const _,
-            store: ManuallyDrop::new(DBStore { map, alloc_t_reg }),
+            store: ManuallyDrop::new(DBStore {
+                map,
+                alloc_t_reg: alloc_type_registry(),
+            }),
             init: false,
+            read_only: false,
+            compress: false,
+            grow_by: None,
         })
     }
 
+    /// Creates a read-only interface to the database stored in `file` (which may be opened read-only), and opens it.
+    ///
+    /// The file is mapped copy-on-write, so nothing is ever written back to it, and all operations that would modify
+    /// the database return [`Error::ReadOnly`]
+    ///
+    /// ## Errors
+    /// if memory mapping fails, if opening fails (see [`DB::open`]), or [`Error::NotADatabase`] if the file does not
+    /// contain a database (a new one can not be created read-only)
+    ///
+    /// ## Saftey
+    /// see memmap2::MmapOptions::map_copy (it is UB if the file is changed externally)
+    #[must_use]
+    #[forbid(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new_read_only(file: fs::File) -> Result<Self, Error> {
+        // Saftey: forwarded to consumer of this function
+        let mut db = unsafe { Self::new_read_only_uninit(file) }?;
+        db.open()?;
+        Ok(db)
+    }
+
+    /// Creates a read-only interface to the database stored in `file` (see [`DB::new_read_only`]), without opening it.
+    /// for tools that only look at the database, and must work even if it can not be opened (like
+    /// [`DB::check_integrity`])
+    ///
+    /// ## Errors
+    /// if memory mapping fails
+    ///
+    /// ## Saftey
+    /// see memmap2::MmapOptions::map_copy (it is UB if the file is changed externally)
+    #[must_use]
+    #[forbid(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new_read_only_uninit(file: fs::File) -> Result<Self, Error> {
+        let file = &*Box::leak(Box::new(file));
+        // Saftey: forwarded to consumer of this function
+        let map = unsafe { MmapOptions::new().map_copy(file) }?;
+        Ok(Self {
+            file: file as *const _,
+            store: ManuallyDrop::new(DBStore {
+                map,
+                alloc_t_reg: alloc_type_registry(),
+            }),
+            init: false,
+            read_only: true,
+            compress: false,
+            grow_by: None,
+        })
+    }
+
+    /// Opens the database stored at `path`, creating it if it does not exist (or is empty).
+    ///
+    /// a new file is made `initial_size` bytes long and initialized, otherwise it is opened (see [`DB::new`]).
+    /// unless `read_only`, the file grows by `initial_size` bytes whenever it is nearly full (see [`DB::set_growth`])
+    ///
+    /// ## Saftey
+    /// see [`DB::new`] (or [`DB::new_read_only`])
+    #[forbid(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn open_path(
+        path: &Path,
+        initial_size: u64,
+        read_only: bool,
+    ) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .create(!read_only)
+            .truncate(false)
+            .open(path)?;
+        let created = !read_only && file.metadata()?.len() == 0;
+        if created {
+            file.set_len(initial_size)?;
+        }
+        // Saftey: forwarded to consumer of this function
+        let mut db = if read_only {
+            unsafe { Self::new_read_only(file) }?
+        } else {
+            unsafe { Self::new(file) }?
+        };
+        if created {
+            info!("Created a new database in {path:?} ({initial_size}B)");
+        }
+        if !read_only {
+            db.set_growth(Some(initial_size));
+        }
+        Ok(db)
+    }
+
     #[must_use]
     #[cfg(test)]
-    pub(in crate::tsdb3) fn new_in_ram(size: usize) -> Result<Self, Error> {
+    pub(crate) fn new_in_ram(size: usize) -> Result<Self, Error> {
         let map = MmapMut::map_anon(size)?;
-        let mut alloc_t_reg = TypeRegistry::new();
-        // only types that HAVE POINTERS TO THEM need to go here
-        alloc_t_reg.register::<repr::DBEntrypoint>();
-        alloc_t_reg.register::<repr::Station>();
-        alloc_t_reg.register::<repr::Channel>();
-        alloc_t_reg.register::<repr::ChannelData>();
         Ok(Self {
             file: ptr::null(),
-            store: ManuallyDrop::new(DBStore { map, alloc_t_reg }),
+            store: ManuallyDrop::new(DBStore {
+                map,
+                alloc_t_reg: alloc_type_registry(),
+            }),
             init: false,
+            read_only: false,
+            compress: false,
+            grow_by: None,
         })
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// If the database has been opened or initialized (and may be used)
+    pub fn is_initialized(&self) -> bool {
+        self.init
+    }
+
+    /// Sets if data chunks are compressed once they fill up (off by default).
+    ///
+    /// this only affects chunks filled after it is set, existing chunks are read either way.
+    /// chunks that do not compress well enough are stored uncompressed
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.compress = enabled;
+    }
+
+    /// Writes all changes to disk (this also happens when the database is dropped).
+    ///
+    /// does nothing if the database is read-only
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.store.map.flush()?;
+        Ok(())
+    }
+
+    /// Sets how much the database grows by when it is nearly full, or None to keep it the same size (running out of space
+    /// is then a panic).
+    ///
+    /// before every change, the database is grown if there is less than [`MIN_FREE_SPACE`] left.
+    /// databases do not grow unless this is set (except from [`DB::open_path`]), read-only databases can not grow
+    pub fn set_growth(&mut self, grow_by: Option<u64>) {
+        assert!(
+            !self.read_only || grow_by.is_none(),
+            "Cannot grow a read-only database"
+        );
+        self.grow_by = grow_by;
+    }
+
+    /// Grows the store if the database can grow, and has less than [`MIN_FREE_SPACE`] free
+    fn reserve(&mut self) -> Result<(), Error> {
+        let Some(grow_by) = self.grow_by else {
+            return Ok(());
+        };
+        let size = self.store.map.len() as u64;
+        let used = self.store.access(false).get_size_used();
+        if size.saturating_sub(used) >= MIN_FREE_SPACE {
+            return Ok(());
+        }
+        let new_size = (size + grow_by.max(1)).max(used + MIN_FREE_SPACE);
+        // Saftey: `file` is valid until `self` is dropped (see `DB::new`), and only null for in-ram databases
+        let file = (!self.file.is_null()).then(|| unsafe { &*self.file });
+        self.store.grow(file, new_size)?;
+        info!("Grew the database from {size}B to {new_size}B ({used}B used)");
+        Ok(())
+    }
+
+    /// Opens the database if the store contains one, or initializes a new one if it is empty (all zeros).
+    /// anything else is left as is, and is [`Error::NotADatabase`]
+    fn open_or_init(&mut self) -> Result<(), Error> {
+        if AllocAccess::header_is_valid(&self.store.map) {
+            self.open()
+        } else if !self.read_only && self.store.map.iter().all(|b| *b == 0) {
+            self.init();
+            Ok(())
+        } else {
+            Err(Error::NotADatabase)
+        }
+    }
+
     /// Initialize a new database, discarding any previous content.
     ///
-    /// This function must only be called once, before any other usage of the db and is the alternative to [`DB::open`]
+    /// This function must only be called once, before any other usage of the db and is the alternative to [`DB::open`].
+    /// it is only needed for databases from [`DB::new_uninit`]
+    ///
+    /// panics if the database is read-only, or has already been opened or initialized
     pub fn init(&mut self) {
-        assert!(!self.init);
+        assert!(
+            !self.init,
+            "The database has already been opened or initialized"
+        );
+        assert!(!self.read_only, "Cannot initialize a read-only database");
         let mut access = self.store.access(true);
         let (entry_ptr, entry) = access.alloc::<repr::DBEntrypoint>();
         *access.entrypoint_pointer() = entry_ptr.cast::<alloc::ptr::Void>();
+        *access.schema_hash() = repr::schema_hash();
         entry.tuning_params.station_map_chunk_size =
             repr::MapStations::new_zeroed().stations.len() as u64;
         entry.tuning_params.channel_map_chunk_size =
@@ -124,21 +611,45 @@ impl DB {
 
     /// Open an existing database, under the assumption that there is one.
     ///
-    /// This function must only be called once, before any other usage of the db and is the alternative to [`DB::init`]
-    pub fn open(&mut self) {
-        assert!(!self.init);
-        // will error if the alloc header is invalid
+    /// This function must only be called once, before any other usage of the db and is the alternative to [`DB::init`].
+    /// it is only needed for databases from [`DB::new_uninit`]
+    ///
+    /// ## Errors
+    /// - [`Error::Incompatible`] or [`Error::IncompatibleTypes`] if the database was written with a different layout
+    ///   (by an older or newer version)
+    /// - [`Error::NotADatabase`] if the store does not contain a database
+    /// - [`Error::AlreadyInitialized`] if it has already been opened or initialized
+    pub fn open(&mut self) -> Result<(), Error> {
+        if self.init {
+            return Err(Error::AlreadyInitialized);
+        }
+        if !AllocAccess::header_is_valid(&self.store.map) {
+            return Err(Error::NotADatabase);
+        }
         let mut access = self.store.access(false);
-        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
-        assert!(
-            entry.tuning_params.station_map_chunk_size
-                == repr::MapStations::new_zeroed().stations.len() as u64
-        );
-        assert!(
-            entry.tuning_params.channel_map_chunk_size
-                == repr::Station::new_zeroed().channels.len() as u64
-        );
+        // (checked separately from the hash, which is not reliable for files stamped by older versions)
+        let (stored, expected) = access.num_types();
+        if stored != expected {
+            return Err(Error::IncompatibleTypes { stored, expected });
+        }
+        let expected = repr::schema_hash();
+        let stored = *access.schema_hash();
+        // (0 if written before the hash was stored, with a different layout, see `legacy`)
+        if stored != expected {
+            return Err(Error::Incompatible { stored, expected });
+        }
+        let entry = access
+            .entrypoint::<repr::DBEntrypoint>()
+            .ok_or(Error::NotADatabase)?;
+        if entry.tuning_params.station_map_chunk_size
+            != repr::MapStations::new_zeroed().stations.len() as u64
+            || entry.tuning_params.channel_map_chunk_size
+                != repr::Station::new_zeroed().channels.len() as u64
+        {
+            return Err(Error::Incompatible { stored, expected });
+        }
         self.init = true;
+        Ok(())
     }
 
     /// Get all stations currently known to the database
@@ -176,12 +687,56 @@ impl DB {
                 .channels
                 .iter()
                 .take_while(|ch| !ch.ptr.is_null())
-                .map(|ch| ChannelID::from_bytes_ref(&ch.id)),
+                .map(|ch| ChannelID::from_bytes_ref(&ch.id))
+                .filter(|id| !is_rollup(id)),
         )
     }
 
-    pub fn insert_station(&mut self, id: StationID) {
+    /// Every (station, channel) pair in the database. the station and channel maps are only walked once, unlike
+    /// calling [`DB::get_channels_for`] for every station
+    pub fn iter_channels(&mut self) -> impl Iterator<Item = (StationID, ChannelID)> {
+        assert!(self.init);
+        Self::channels_in(&mut self.store.access(false))
+            .into_iter()
+            .map(|(sid, cid, _)| (sid, cid))
+    }
+
+    /// (station, channel, pointer to the channel) for every channel in the database (not including the hidden
+    /// channels of rollup tiers)
+    fn channels_in(access: &mut AllocAccess) -> Vec<(StationID, ChannelID, Ptr<repr::Channel>)> {
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let mut channels = vec![];
+        for station_elem in entry
+            .stations
+            .stations
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+        {
+            let station = access.read(station_elem.ptr);
+            channels.extend(
+                station
+                    .channels
+                    .iter()
+                    .take_while(|elem| !elem.ptr.is_null())
+                    .filter(|elem| !is_rollup(ChannelID::from_bytes_ref(&elem.id)))
+                    .map(|elem| {
+                        (
+                            StationID::from_bytes(station_elem.id),
+                            ChannelID::from_bytes(elem.id),
+                            elem.ptr,
+                        )
+                    }),
+            );
+        }
+        channels
+    }
+
+    pub fn insert_station(&mut self, id: StationID) -> Result<(), Error> {
         assert!(self.init);
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.reserve()?;
         assert!(!id.is_nil());
         assert!(self
             .get_stations()
@@ -199,14 +754,19 @@ impl DB {
         // we don't need to add any channel info to the station map, only allocate and set a reference to it
         let (station_ptr, _station) = access.alloc::<repr::Station>();
         first_empty.ptr = station_ptr;
+        Ok(())
     }
 
     pub fn insert_channels(
         &mut self,
         station: StationID,
         channels: impl IntoIterator<Item = ChannelID>,
-    ) {
+    ) -> Result<(), Error> {
         assert!(self.init);
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.reserve()?;
         assert!(!station.is_nil());
         let mut access = self.store.access(false);
         let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
@@ -226,6 +786,10 @@ impl DB {
             .count();
         for ch in channels {
             assert!(!ch.is_nil());
+            assert!(
+                !is_rollup(&ch),
+                "Channel id {ch} is reserved for rollup tiers"
+            );
             let elem = station
                 .channels
                 .get_mut(ins_idx)
@@ -235,22 +799,49 @@ impl DB {
             elem.ptr = data_ptr;
             ins_idx += 1;
         }
+        Ok(())
     }
 
+    /// Records a reading.
+    ///
+    /// readings do not have to be inserted in chronological order (packets can be delayed), but inserting one
+    /// older than the newest chunk moves every newer reading along, so it gets slower the further back it goes
     pub fn insert_data(
         &mut self,
         station_id: StationID,
         channel_id: ChannelID,
         time: DateTime<Utc>,
         reading: f32,
-    ) {
+    ) -> Result<(), Error> {
         assert!(self.init);
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
         assert!(self.get_stations().find(|st| *st == &station_id).is_some());
         assert!(self
             .get_channels_for(station_id)
             .is_some_and(|mut chs| chs.find(|ch| *ch == &channel_id).is_some()));
         let timestamp = repr::unix_to_htime(time.timestamp())
             .expect("Cannot create timestamp (date is not between 2020 and 2156)");
+        self.insert_entry(
+            station_id,
+            channel_id,
+            repr::DataEntry {
+                htime: timestamp,
+                data: reading,
+            },
+        )
+    }
+
+    /// Inserts `new` into a channel (which may be hidden), see [`DB::insert_data`]
+    fn insert_entry(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        new: repr::DataEntry,
+    ) -> Result<(), Error> {
+        self.reserve()?;
+        let compress = self.compress;
         let mut access = self.store.access(false);
         let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
         let ptr = entry
@@ -262,42 +853,471 @@ impl DB {
             .expect("Requested station [for insert_data] does not exist!")
             .ptr;
         let station = access.read(ptr);
+        let ptr = channel_in(station, channel_id)
+            .expect("Requested channel [for insert_data] does not exist!");
+        let channel = access.read(ptr);
+        insert_into(&mut access, channel, new, compress);
+        Ok(())
+    }
+
+    /// Records an event (`ChannelData::Event`), which is stored separately from the channel's readings.
+    ///
+    /// like readings, events may be inserted out of order (they are put into place, which is slower than appending)
+    pub fn insert_event(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        time: DateTime<Utc>,
+        sub: &str,
+        data: &HashMap<String, f32>,
+    ) -> Result<(), Error> {
+        assert!(self.init);
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.reserve()?;
+        assert!(self.get_stations().find(|st| *st == &station_id).is_some());
+        assert!(self
+            .get_channels_for(station_id)
+            .is_some_and(|mut chs| chs.find(|ch| *ch == &channel_id).is_some()));
+        let timestamp = repr::unix_to_htime(time.timestamp())
+            .expect("Cannot create timestamp (date is not between 2020 and 2156)");
+        let encoded = event::encode(timestamp, sub, data).ok_or(Error::EventTooLarge)?;
+        let mut access = self.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let ptr = entry
+            .stations
+            .stations
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+            .find(|elem| &elem.id == station_id.as_bytes())
+            .expect("Requested station [for insert_event] does not exist!")
+            .ptr;
+        let station = access.read(ptr);
         let ptr = station
             .channels
             .iter()
             .take_while(|elem| !elem.ptr.is_null())
             .find(|elem| &elem.id == channel_id.as_bytes())
-            .expect("Requested channel [for insert_data] does not exist!")
+            .expect("Requested channel [for insert_event] does not exist!")
             .ptr;
         let channel = access.read(ptr);
-        assert!(channel.last_time <= timestamp);
-        channel.last_time = timestamp;
-        if channel.is_full() {
-            let (new_chunk_ptr, new_chunk) = access.alloc::<repr::ChannelData>();
-            *new_chunk = channel.data;
-            channel.data.next = new_chunk_ptr;
-            channel.num_used = 1;
-            let entry = &mut channel.data.chunk[0];
-            entry.htime = timestamp;
-            entry.data = reading;
+        let mut chunk = if channel.events.is_null() {
+            let (head_ptr, head) = access.alloc::<repr::EventChunk>();
+            channel.events = head_ptr;
+            head
         } else {
-            let entry = &mut channel.data.chunk[channel.num_used as usize];
-            entry.htime = timestamp;
-            entry.data = reading;
-            channel.num_used += 1;
+            access.read(channel.events)
+        };
+        // events may arrive out of order (stations supply the time), so find the chunk this one belongs in:
+        // the newest chunk whose next (older) chunk ends at or before it
+        while !chunk.next.is_null() {
+            let next = access.read(chunk.next);
+            if next.last_time <= timestamp {
+                break;
+            }
+            chunk = next;
+        }
+        let mut events = event::split(&chunk.buf[..chunk.used as usize])
+            .into_iter()
+            .map(|(htime, bytes)| (htime, bytes.to_vec()))
+            .collect::<Vec<_>>();
+        // (after any events at the same time)
+        let idx = events.partition_point(|(htime, _)| *htime <= timestamp);
+        events.insert(idx, (timestamp, encoded));
+
+        // pack the events into chunks, oldest first. the newest stay in this chunk, and any older ones that no
+        // longer fit go in new chunks between it and the next
+        let mut packed = vec![(0, vec![])];
+        for (htime, bytes) in events {
+            let (last_time, buf) = packed.last_mut().unwrap();
+            if buf.len() + bytes.len() > repr::EVENT_CHUNK_BUF_SIZE {
+                packed.push((htime, bytes));
+            } else {
+                *last_time = htime;
+                buf.extend_from_slice(&bytes);
+            }
+        }
+        let fill = |chunk: &mut repr::EventChunk, (last_time, buf): (u32, Vec<u8>)| {
+            chunk.buf[..buf.len()].copy_from_slice(&buf);
+            chunk.used = buf.len() as u32;
+            chunk.last_time = last_time;
+        };
+        let newest = packed.pop().unwrap();
+        let mut next = chunk.next;
+        for older in packed {
+            let (older_ptr, older_chunk) = access.alloc::<repr::EventChunk>();
+            fill(older_chunk, older);
+            older_chunk.next = next;
+            next = older_ptr;
+        }
+        fill(chunk, newest);
+        chunk.next = next;
+        Ok(())
+    }
+
+    /// Replaces the oldest readings of a channel with the mean of each `bucket` long period (its rollup tier, see
+    /// [`Tier::Rollup`]), freeing the space they used for newer data. this is meant to be called in the background,
+    /// `max_chunks` at a time, until there is nothing [`more`][Reclaimed::more].
+    ///
+    /// readings are stored (and freed) in chunks, so only the chunks where every reading is older than
+    /// `older_than` are rolled up, oldest first. the rest of the bucket they end in is rolled up by a later call,
+    /// and the parts are combined when it is queried (the same goes for delayed readings that arrive after their
+    /// bucket was rolled up)
+    pub fn roll_up(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        older_than: DateTime<Utc>,
+        bucket: chrono::Duration,
+        max_chunks: usize,
+    ) -> Result<Reclaimed, Error> {
+        assert!(self.init);
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.reserve()?;
+        assert!(!is_rollup(&channel_id));
+        let bucket_secs = bucket_secs(bucket);
+        // (nothing is older than the start of the database's time)
+        let Some(before) = repr::unix_to_htime(older_than.timestamp()) else {
+            return Ok(Reclaimed::default());
+        };
+        let (means_id, counts_id) = rollup_channels(channel_id, bucket_secs);
+        // -- unlink the chunks, and find the buckets they cover (bucket start, sum, count) --
+        let (freed, buckets, more) = {
+            let mut access = self.store.access(false);
+            let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+            let ptr = entry
+                .stations
+                .stations
+                .iter()
+                .take_while(|elem| !elem.ptr.is_null())
+                .find(|elem| &elem.id == station_id.as_bytes())
+                .expect("Requested station [for roll_up] does not exist!")
+                .ptr;
+            let station = access.read(ptr);
+            let ptr = channel_in(station, channel_id)
+                .expect("Requested channel [for roll_up] does not exist!");
+            let used = station
+                .channels
+                .iter()
+                .take_while(|elem| !elem.ptr.is_null())
+                .count();
+            let missing = [means_id, counts_id]
+                .into_iter()
+                .filter(|id| channel_in(station, *id).is_none())
+                .collect::<Vec<_>>();
+            if used + missing.len() > MAX_CHANNELS_PER_STATION {
+                return Err(Error::ChannelMapFull);
+            }
+            let channel = access.read(ptr);
+            let (removed, more) = detach_older(&mut access, channel, before, max_chunks);
+            if removed.is_empty() {
+                return Ok(Reclaimed {
+                    more,
+                    ..Default::default()
+                });
+            }
+            for (elem, id) in station.channels[used..].iter_mut().zip(missing) {
+                elem.id = id.into_bytes();
+                (elem.ptr, _) = access.alloc::<repr::Channel>();
+            }
+            let mut buckets: Vec<(u32, f64, u32)> = vec![];
+            for (_, chunk) in &removed {
+                for entry in chunk.entries().iter() {
+                    let start = entry.htime - entry.htime % bucket_secs;
+                    match buckets.last_mut() {
+                        Some((time, sum, count)) if *time == start => {
+                            *sum += entry.data as f64;
+                            *count += 1;
+                        }
+                        _ => buckets.push((start, entry.data as f64, 1)),
+                    }
+                }
+            }
+            let freed = removed.iter().map(|(link, _)| *link).collect::<Vec<_>>();
+            (freed, buckets, more)
+        };
+        // -- free them (now that they are no longer being accessed) --
+        let reclaimed = self.free_chunks(&freed, &[], more);
+        // -- and record the buckets --
+        for (htime, sum, count) in buckets {
+            for (id, data) in [
+                (means_id, (sum / count as f64) as f32),
+                (counts_id, count as f32),
+            ] {
+                self.insert_entry(station_id, id, repr::DataEntry { htime, data })?;
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    /// Deletes the oldest data of a channel (or of one of its rollup tiers), freeing the space it used for newer
+    /// data. like [`DB::roll_up`], this is meant to be called `max_chunks` at a time.
+    ///
+    /// only chunks where everything is older than `before` are deleted (readings, then events, oldest first), so
+    /// some older data is kept until the rest of its chunk is too
+    pub fn delete_before(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        tier: Tier,
+        before: DateTime<Utc>,
+        max_chunks: usize,
+    ) -> Result<Reclaimed, Error> {
+        assert!(self.init);
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        assert!(!is_rollup(&channel_id));
+        let Some(before) = repr::unix_to_htime(before.timestamp()) else {
+            return Ok(Reclaimed::default());
+        };
+        let (data, events, more) = {
+            let mut access = self.store.access(false);
+            let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+            let ptr = entry
+                .stations
+                .stations
+                .iter()
+                .take_while(|elem| !elem.ptr.is_null())
+                .find(|elem| &elem.id == station_id.as_bytes())
+                .expect("Requested station [for delete_before] does not exist!")
+                .ptr;
+            let station = access.read(ptr);
+            let ptr = channel_in(station, channel_id)
+                .expect("Requested channel [for delete_before] does not exist!");
+            match tier {
+                Tier::Raw => {
+                    let channel = access.read(ptr);
+                    let (removed, mut more) =
+                        detach_older(&mut access, channel, before, max_chunks);
+                    let data = removed.iter().map(|(link, _)| *link).collect::<Vec<_>>();
+                    // -- events, with what is left of `max_chunks` --
+                    let mut chunks = vec![];
+                    let mut next = channel.events;
+                    while !next.is_null() {
+                        let chunk = access.read(next);
+                        chunks.push((next, chunk));
+                        next = chunks.last().unwrap().1.next;
+                    }
+                    let expired = chunks
+                        .iter()
+                        .rev()
+                        .take_while(|(_, chunk)| chunk.last_time < before)
+                        .count();
+                    let max_events = max_chunks - data.len();
+                    let keep = chunks.len() - expired.min(max_events);
+                    let events = chunks[keep..]
+                        .iter()
+                        .rev()
+                        .map(|(ptr, _)| *ptr)
+                        .collect::<Vec<_>>();
+                    match chunks[..keep].last_mut() {
+                        Some((_, tail)) => tail.next = Ptr::null(),
+                        None => channel.events = Ptr::null(),
+                    }
+                    more |= expired > max_events;
+                    (data, events, more)
+                }
+                Tier::Rollup(bucket) => {
+                    // (both channels of the tier have the same chunks)
+                    let (means_id, counts_id) = rollup_channels(channel_id, bucket_secs(bucket));
+                    let mut data = vec![];
+                    let mut more = false;
+                    for id in [means_id, counts_id] {
+                        let Some(ptr) = channel_in(station, id) else {
+                            continue;
+                        };
+                        let channel = access.read(ptr);
+                        let (removed, more_in) =
+                            detach_older(&mut access, channel, before, max_chunks);
+                        data.extend(removed.iter().map(|(link, _)| *link));
+                        more |= more_in;
+                    }
+                    (data, vec![], more)
+                }
+            }
+        };
+        Ok(self.free_chunks(&data, &events, more))
+    }
+
+    /// Frees data and event chunks (that have been unlinked, and are no longer accessed)
+    fn free_chunks(
+        &mut self,
+        data: &[repr::ChunkLink],
+        events: &[Ptr<repr::EventChunk>],
+        more: bool,
+    ) -> Reclaimed {
+        let size = |freed: bool, size: usize| if freed { size as u64 } else { 0 };
+        let mut access = self.store.access(false);
+        let mut reclaimed = Reclaimed {
+            chunks: data.len() + events.len(),
+            more,
+            ..Default::default()
+        };
+        for link in data {
+            reclaimed.bytes += match link.codec {
+                repr::CODEC_RAW => size(
+                    access.free(link.ptr.cast::<repr::ChannelData>()),
+                    std::mem::size_of::<repr::ChannelData>(),
+                ),
+                _ => size(
+                    access.free(link.ptr.cast::<repr::CompressedData>()),
+                    std::mem::size_of::<repr::CompressedData>(),
+                ),
+            };
+        }
+        for &ptr in events {
+            reclaimed.bytes += size(access.free(ptr), std::mem::size_of::<repr::EventChunk>());
         }
+        reclaimed
     }
 
+    /// Calls `f` with every entry stored for a channel, oldest to newest.
+    ///
+    /// this walks all of the channel's data chunks (following `next`, and only reading the used part of the head)
+    pub fn for_each_entry(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        mut f: impl FnMut(DateTime<Utc>, f32),
+    ) {
+        assert!(self.init);
+        let mut access = self.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let ptr = entry
+            .stations
+            .stations
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+            .find(|elem| &elem.id == station_id.as_bytes())
+            .expect("Requested station [for for_each_entry] does not exist!")
+            .ptr;
+        let station = access.read(ptr);
+        let ptr = station
+            .channels
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+            .find(|elem| &elem.id == channel_id.as_bytes())
+            .expect("Requested channel [for for_each_entry] does not exist!")
+            .ptr;
+        let channel = access.read(ptr);
+        let num_used = channel.num_used as usize;
+        let head = &channel.data;
+        // the list goes newest -> oldest, so find all chunks before visiting any
+        let mut chunks = vec![];
+        let mut next = head.next;
+        while !next.is_null() {
+            let chunk = OlderChunk::read(&mut access, next);
+            next = chunk.next();
+            chunks.push(chunk);
+        }
+        let mut visit = |entries: &[repr::DataEntry]| {
+            for entry in entries {
+                f(
+                    DateTime::from_timestamp(repr::htime_to_unix(entry.htime), 0).unwrap(),
+                    entry.data,
+                );
+            }
+        };
+        for chunk in chunks.into_iter().rev() {
+            visit(&chunk.entries());
+        }
+        visit(&head.chunk[..num_used]);
+    }
+
+    /// Summary of what is stored in the database (see [`DBStats`])
+    ///
+    /// this walks the data chunks of every channel, but does not read their contents
+    pub fn stats(&mut self) -> DBStats {
+        assert!(self.init);
+        let station_count = self.get_stations().count() as u64;
+        let mut access = self.store.access(false);
+        let mut stats = DBStats {
+            station_count,
+            bytes_used: access.get_size_used(),
+            bytes_capacity: access.get_store_size(),
+            ..Default::default()
+        };
+        for (_, _, ptr) in Self::channels_in(&mut access) {
+            stats.channel_count += 1;
+            let channel = access.read(ptr);
+            stats.approx_reading_count += channel.num_used as u64;
+            // every chunk after the head is full
+            let mut next = channel.data.next;
+            while !next.is_null() {
+                let chunk = OlderChunk::read(&mut access, next);
+                stats.approx_reading_count += chunk.len() as u64;
+                next = chunk.next();
+            }
+        }
+        stats
+    }
+
+    /// The newest reading (or event) of every channel that has any, for filling a cache of the latest values.
+    ///
+    /// only the newest chunk of each channel is read
+    pub fn latest_values(
+        &mut self,
+    ) -> HashMap<(StationID, ChannelID), (DateTime<Utc>, ChannelData)> {
+        assert!(self.init);
+        let to_time = |htime| DateTime::from_timestamp(repr::htime_to_unix(htime), 0).unwrap();
+        let mut latest = HashMap::new();
+        let mut access = self.store.access(false);
+        for (sid, cid, ptr) in Self::channels_in(&mut access) {
+            let channel = access.read(ptr);
+            // (the head is empty right after it is moved out)
+            let reading = match channel.num_used {
+                0 if channel.data.next.is_null() => None,
+                0 => OlderChunk::read(&mut access, channel.data.next)
+                    .entries()
+                    .last()
+                    .copied(),
+                n => Some(channel.data.chunk[n as usize - 1]),
+            }
+            .map(|entry| (entry.htime, ChannelData::Float(entry.data)));
+            let event = (!channel.events.is_null())
+                .then(|| {
+                    let chunk = access.read(channel.events);
+                    event::decode(&chunk.buf[..chunk.used as usize]).pop()
+                })
+                .flatten()
+                .map(|(htime, sub, data)| (htime, ChannelData::Event { sub, data }));
+            let newest = match (reading, event) {
+                (Some(reading), Some(event)) if event.0 > reading.0 => Some(event),
+                (Some(reading), _) => Some(reading),
+                (None, event) => event,
+            };
+            if let Some((htime, value)) = newest {
+                latest.insert((sid, cid), (to_time(htime), value));
+            }
+        }
+        latest
+    }
+
+    /// Readings (or the buckets of a rollup tier, see [`QueryBuilder::with_tier`][query::QueryBuilder::with_tier])
+    /// matching `query`, oldest to newest
     pub fn query_data(&mut self, query: QueryParams) -> Vec<(DateTime<Utc>, f32)> {
+        let tier = query.tier();
         let (sid, cid, max, after, before) = query.to_raw();
         let (max, after, before) = (
             max.unwrap_or(usize::MAX),
             after.unwrap_or(DateTime::from_timestamp(repr::EPOCH, 0).unwrap()),
             before.unwrap_or(DateTime::from_timestamp(repr::htime_to_unix(u32::MAX), 0).unwrap()),
         );
-        self.qery_data_raw(sid, cid, after, before, max)
+        match tier {
+            Tier::Raw => self.qery_data_raw(sid, cid, after, before, max),
+            Tier::Rollup(bucket) => self
+                .query_rollup(sid, cid, bucket, after, before, max)
+                .into_iter()
+                .map(|bucket| (bucket.time, bucket.mean))
+                .collect(),
+        }
     }
 
+    /// Readings between `after_time` and `before_time` (exclusive), oldest to newest
     pub fn qery_data_raw(
         &mut self,
         station_id: StationID,
@@ -314,13 +1334,285 @@ impl DB {
         assert!(self
             .get_channels_for(station_id)
             .is_some_and(|mut chs| chs.find(|ch| *ch == &channel_id).is_some()));
+        self.entries_between(station_id, channel_id, after_time, before_time, max_results)
+    }
 
+    /// The buckets of a rollup tier (see [`DB::roll_up`]) that start between `after_time` and `before_time`
+    /// (exclusive), oldest to newest. a bucket that was rolled up in parts is returned as one.
+    ///
+    /// a channel that has not been rolled up has no buckets
+    pub fn query_rollup(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        bucket: chrono::Duration,
+        after_time: DateTime<Utc>,
+        before_time: DateTime<Utc>,
+        // (unlike `qery_data_raw` this is exact, the newest buckets are kept)
+        max_results: usize,
+    ) -> Vec<RollupBucket> {
+        assert!(self.init);
+        assert!(self
+            .get_channels_for(station_id)
+            .is_some_and(|mut chs| chs.find(|ch| *ch == &channel_id).is_some()));
+        let (means_id, counts_id) = rollup_channels(channel_id, bucket_secs(bucket));
+        // (a bucket may be split over several entries, so limit the merged buckets rather than the entries)
+        let means = self.entries_between(station_id, means_id, after_time, before_time, usize::MAX);
+        let counts =
+            self.entries_between(station_id, counts_id, after_time, before_time, usize::MAX);
+        assert_eq!(
+            means.len(),
+            counts.len(),
+            "The channels of a rollup tier are out of step"
+        );
+        let mut buckets: Vec<RollupBucket> = vec![];
+        for ((time, mean), (_, count)) in means.into_iter().zip(counts) {
+            let count = count as u32;
+            match buckets.last_mut() {
+                Some(last) if last.time == time => {
+                    let total = last.count + count;
+                    last.mean = ((last.mean as f64 * last.count as f64
+                        + mean as f64 * count as f64)
+                        / total as f64) as f32;
+                    last.count = total;
+                }
+                _ => buckets.push(RollupBucket { time, mean, count }),
+            }
+        }
+        buckets.drain(..buckets.len().saturating_sub(max_results));
+        buckets
+    }
+
+    /// Entries of a channel (which may be hidden) between `after_time` and `before_time` (exclusive), oldest to
+    /// newest. a channel that does not exist has none
+    fn entries_between(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        after_time: DateTime<Utc>,
+        before_time: DateTime<Utc>,
+        max_results: usize,
+    ) -> Vec<(DateTime<Utc>, f32)> {
         let t_lower = repr::unix_to_htime(after_time.timestamp())
             .expect("Cannot create timestamp (date is not between 2020 and 2156)");
         let t_upper = repr::unix_to_htime(before_time.timestamp())
             .expect("Cannot create timestamp (date is not between 2020 and 2156)");
         assert!(t_lower <= t_upper);
 
+        let mut access = self.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let Some(ptr) = entry
+            .stations
+            .stations
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+            .find(|elem| &elem.id == station_id.as_bytes())
+            .map(|elem| elem.ptr)
+        else {
+            return vec![];
+        };
+        let station = access.read(ptr);
+        let Some(ptr) = channel_in(station, channel_id) else {
+            return vec![];
+        };
+        let channel = access.read(ptr);
+        // the list goes newest -> oldest, so collect the matching entries of each chunk before ordering them
+        let mut chunks = vec![];
+        let mut num_found = 0;
+        let mut collect = |entries: &[repr::DataEntry]| {
+            let matching = entries
+                .iter()
+                .filter(|entry| entry.htime > t_lower && entry.htime < t_upper)
+                .map(|entry| {
+                    (
+                        DateTime::from_timestamp(repr::htime_to_unix(entry.htime), 0).unwrap(),
+                        entry.data,
+                    )
+                })
+                .collect::<Vec<_>>();
+            num_found += matching.len();
+            chunks.push(matching);
+            num_found
+        };
+        let head = &channel.data.chunk[..channel.num_used as usize];
+        let mut next = channel.data.next;
+        let (mut oldest, mut newest) = match (head.first(), head.last()) {
+            (Some(oldest), Some(newest)) => (oldest.htime, newest.htime),
+            _ => return vec![],
+        };
+        let mut chunk: Option<OlderChunk> = None;
+        loop {
+            if newest <= t_lower {
+                // everything from here on is too old
+                break;
+            }
+            // (chunks entirely newer than the range are skipped (without decoding them), older ones may still match)
+            if oldest < t_upper {
+                let num_found = match &chunk {
+                    None => collect(head),
+                    Some(chunk) => collect(&chunk.entries()),
+                };
+                if num_found > max_results {
+                    break;
+                }
+            }
+            if next.is_null() {
+                break;
+            }
+            let older = OlderChunk::read(&mut access, next);
+            (oldest, newest, next) = (older.first_time(), older.last_time(), older.next());
+            chunk = Some(older);
+        }
+        chunks.into_iter().rev().flatten().collect()
+    }
+
+    /// One page (at most `page_size` readings) of the readings from `from` (inclusive) to `to` (exclusive), oldest to
+    /// newest, continuing from `cursor`. returns the page, and the cursor for the next one (None if there is no more).
+    ///
+    /// the cursor is a time (not a position in the database), so it stays valid as readings are inserted.
+    /// an unknown station or channel has no readings
+    pub fn query_page(
+        &mut self,
+        station_id: StationID,
+        channel_id: ChannelID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        cursor: Option<QueryCursor>,
+        page_size: usize,
+    ) -> Page {
+        assert!(self.init);
+        assert!(page_size > 0);
+        if !self
+            .get_channels_for(station_id)
+            .is_some_and(|mut chs| chs.any(|ch| ch == &channel_id))
+        {
+            return (vec![], None);
+        }
+        // (times outside of what can be stored are clamped)
+        let htime = |time: DateTime<Utc>| {
+            repr::unix_to_htime(time.timestamp()).unwrap_or(if time.timestamp() <= repr::EPOCH {
+                0
+            } else {
+                u32::MAX
+            })
+        };
+        let t_upper = htime(to);
+        // readings at `t_lower` are included, after skipping `skip` of them
+        let (t_lower, mut skip) = match cursor {
+            Some(cursor) => (htime(cursor.time), cursor.skip),
+            None => (htime(from), 0),
+        };
+
+        let mut access = self.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let ptr = entry
+            .stations
+            .stations
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+            .find(|elem| &elem.id == station_id.as_bytes())
+            .unwrap()
+            .ptr;
+        let station = access.read(ptr);
+        let ptr = station
+            .channels
+            .iter()
+            .take_while(|elem| !elem.ptr.is_null())
+            .find(|elem| &elem.id == channel_id.as_bytes())
+            .unwrap()
+            .ptr;
+        let channel = access.read(ptr);
+        let head = &channel.data.chunk[..channel.num_used as usize];
+        let (mut oldest, mut newest) = match (head.first(), head.last()) {
+            (Some(oldest), Some(newest)) => (oldest.htime, newest.htime),
+            _ => return (vec![], None),
+        };
+        // chunks that may have readings in the range, newest -> oldest (None is the head).
+        // only their times are read, so that only the ones the page comes from are decoded
+        let mut chunks = vec![];
+        let mut chunk: Option<OlderChunk> = None;
+        let mut next = channel.data.next;
+        loop {
+            if newest < t_lower {
+                break;
+            }
+            let older = (!next.is_null()).then(|| OlderChunk::read(&mut access, next));
+            if oldest < t_upper {
+                chunks.push(chunk);
+            }
+            let Some(older) = older else {
+                break;
+            };
+            (oldest, newest, next) = (older.first_time(), older.last_time(), older.next());
+            chunk = Some(older);
+        }
+        let mut page = vec![];
+        let mut more = false;
+        'chunks: for chunk in chunks.iter().rev() {
+            let entries = match chunk {
+                None => Cow::Borrowed(head),
+                Some(chunk) => chunk.entries(),
+            };
+            for entry in entries.iter() {
+                if entry.htime < t_lower || entry.htime >= t_upper {
+                    continue;
+                }
+                if entry.htime == t_lower && skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                if page.len() == page_size {
+                    more = true;
+                    break 'chunks;
+                }
+                page.push(*entry);
+            }
+        }
+        let next_cursor = match page.last() {
+            Some(last) if more => {
+                let mut skip = page.iter().filter(|e| e.htime == last.htime).count() as u32;
+                if last.htime == t_lower {
+                    // (the ones skipped to get here)
+                    skip += cursor.map_or(0, |cursor| cursor.skip);
+                }
+                Some(QueryCursor {
+                    time: DateTime::from_timestamp(repr::htime_to_unix(last.htime), 0).unwrap(),
+                    skip,
+                })
+            }
+            _ => None,
+        };
+        let page = page
+            .into_iter()
+            .map(|entry| {
+                (
+                    DateTime::from_timestamp(repr::htime_to_unix(entry.htime), 0).unwrap(),
+                    entry.data,
+                )
+            })
+            .collect();
+        (page, next_cursor)
+    }
+
+    /// Events (see [`DB::insert_event`]) matching `query`, oldest to newest
+    pub fn query_events(&mut self, query: QueryParams) -> Vec<StoredEvent> {
+        let (station_id, channel_id, max, after, before) = query.to_raw();
+        assert!(self.init);
+        assert!(self.get_stations().find(|st| *st == &station_id).is_some());
+        assert!(self
+            .get_channels_for(station_id)
+            .is_some_and(|mut chs| chs.find(|ch| *ch == &channel_id).is_some()));
+        let max = max.unwrap_or(usize::MAX);
+        let t_lower = after.map_or(0, |t| {
+            repr::unix_to_htime(t.timestamp())
+                .expect("Cannot create timestamp (date is not between 2020 and 2156)")
+        });
+        let t_upper = before.map_or(u32::MAX, |t| {
+            repr::unix_to_htime(t.timestamp())
+                .expect("Cannot create timestamp (date is not between 2020 and 2156)")
+        });
+        assert!(t_lower <= t_upper);
+
         let mut access = self.store.access(false);
         let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
         let ptr = entry
@@ -329,7 +1621,7 @@ impl DB {
             .iter()
             .take_while(|elem| !elem.ptr.is_null())
             .find(|elem| &elem.id == station_id.as_bytes())
-            .expect("Requested station [for insert_data] does not exist!")
+            .expect("Requested station [for query_events] does not exist!")
             .ptr;
         let station = access.read(ptr);
         let ptr = station
@@ -337,38 +1629,33 @@ impl DB {
             .iter()
             .take_while(|elem| !elem.ptr.is_null())
             .find(|elem| &elem.id == channel_id.as_bytes())
-            .expect("Requested channel [for insert_data] does not exist!")
+            .expect("Requested channel [for query_events] does not exist!")
             .ptr;
         let channel = access.read(ptr);
-        let mut results = vec![];
-
-        let mut num_vaild = channel.num_used;
-        let mut t_newest = channel.last_time;
-        let mut t_oldest = channel.data.chunk[0].htime;
-        let mut data = &mut channel.data;
-        // if not(newest is older than oldest requested || oldest is newer than newest requested || current results < max results)
-        while !(t_newest < t_lower || t_oldest > t_upper || results.len() > max_results) {
-            results.extend(
-                data.chunk[0..num_vaild as usize]
-                    .iter()
-                    .filter(|entry| entry.htime > t_lower && entry.htime < t_upper)
-                    .map(|entry| {
-                        (
-                            DateTime::from_timestamp(repr::htime_to_unix(entry.htime), 0).unwrap(),
-                            entry.data,
-                        )
-                    }),
-            );
-            if !data.next.is_null() {
-                num_vaild = data.chunk.len() as u32;
-                data = access.read(data.next);
-                t_newest = data.chunk[data.chunk.len() - 1].htime;
-                t_oldest = data.chunk[0].htime
-            } else {
+        // the list goes newest -> oldest, so collect the matching events of each chunk before ordering them
+        let mut chunks = vec![];
+        let mut num_found = 0;
+        let mut next = channel.events;
+        while !next.is_null() && num_found < max {
+            let chunk = access.read(next);
+            next = chunk.next;
+            if chunk.last_time <= t_lower {
+                // everything from here on is too old
                 break;
             }
+            let events = event::decode(&chunk.buf[..chunk.used as usize])
+                .into_iter()
+                .filter(|(htime, ..)| *htime > t_lower && *htime < t_upper)
+                .map(|(htime, sub, data)| StoredEvent {
+                    time: DateTime::from_timestamp(repr::htime_to_unix(htime), 0).unwrap(),
+                    sub,
+                    data,
+                })
+                .collect::<Vec<_>>();
+            num_found += events.len();
+            chunks.push(events);
         }
-        results
+        chunks.into_iter().rev().flatten().collect()
     }
 }
 
@@ -379,7 +1666,9 @@ impl Drop for DB {
         if !self.file.is_null() {
             // Saftey: self.file not used after this, no longer referenced by self.store
             let file = unsafe { ptr::read(self.file as *const fs::File) };
-            let _ = file.sync_all();
+            if !self.read_only {
+                let _ = file.sync_all();
+            }
             drop(file)
         }
     }
@@ -5,6 +5,8 @@ use mycelium::station::{capabilities::ChannelID, identity::StationID};
 
 use comptime_hacks::*;
 
+use super::Tier;
+
 // base stages (2 LSB)
 const INITIAL: usize = 0b00; // 0
 const WITH_STATION: usize = 0b01; // 1
@@ -34,6 +36,7 @@ pub struct QueryBuilder<const STEP: usize = INITIAL> {
     pub(super) max_results: Option<usize>,
     pub(super) after_time: Option<DateTime<Utc>>,
     pub(super) before_time: Option<DateTime<Utc>>,
+    pub(super) tier: Tier,
 }
 
 pub type QueryParams = QueryBuilder<VERIFIED>;
@@ -56,6 +59,10 @@ impl QueryParams {
             self.before_time,
         )
     }
+
+    pub(super) fn tier(&self) -> Tier {
+        self.tier
+    }
 }
 
 impl QueryBuilder<INITIAL> {
@@ -66,6 +73,7 @@ impl QueryBuilder<INITIAL> {
             max_results: None,
             after_time: None,
             before_time: None,
+            tier: Tier::Raw,
         }
     }
 }
@@ -85,6 +93,12 @@ where
             ..self.private_into()
         })
     }
+
+    /// queries the buckets of a rollup tier instead of the readings (see [`DB::roll_up`][super::DB::roll_up]).
+    /// events do not have tiers
+    pub fn with_tier(self, tier: Tier) -> Self {
+        Self { tier, ..self }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -101,6 +115,7 @@ impl<const STEP: usize> QueryBuilder<STEP> {
             max_results,
             before_time,
             after_time,
+            tier,
         } = self;
         QueryBuilder {
             station,
@@ -108,6 +123,7 @@ impl<const STEP: usize> QueryBuilder<STEP> {
             max_results,
             before_time,
             after_time,
+            tier,
         }
     }
 }
@@ -0,0 +1,179 @@
+//! last-resort recovery for TSDB v3, when the index (entrypoint and station map) is damaged but the data is not
+//!
+//! the allocator does not record what type each chunk holds, so chunks are identified by their length (every type
+//! stored has a different one). channel ids are stored in the channel maps of [`repr::Station`]s, but station ids
+//! are only stored in the station map, so stations that the (damaged) station map no longer refers to can not be
+//! identified. they are added back under new ids, so that their data is not lost
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use mycelium::station::identity::StationID;
+use uuid::Uuid;
+use zerocopy::FromZeroes;
+
+use super::{
+    alloc::{AllocAccess, Ptr, ScannedChunk},
+    repr, DB,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepairError {
+    #[error("The allocator header is missing or invalid, so the data can not be found")]
+    BadHeader,
+    #[error("The database was opened read-only, and may not be modified")]
+    ReadOnly,
+    #[error("The database was written with a different layout (hash {stored:#x}, expected {expected:#x}), so its chunks can not be identified")]
+    Incompatible { stored: u32, expected: u32 },
+    #[error("The database was written with a different set of types ({stored} types of chunk, expected {expected}), so its chunks can not be identified")]
+    IncompatibleTypes { stored: u64, expected: u64 },
+}
+
+/// Result of [`DB::repair`]
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// stations found in the station map, with their original ids
+    pub stations_kept: Vec<StationID>,
+    /// stations that the station map did not refer to, and the new ids they were given
+    pub stations_renamed: Vec<StationID>,
+    /// stations that did not fit in the station map (there were more than [`super::MAX_STATIONS`])
+    pub stations_dropped: usize,
+    /// channel map entries that were removed, because they did not point to a channel
+    pub channel_entries_removed: usize,
+    /// channels that no station refers to (their ids are not known, so they are not added back)
+    pub orphaned_channels: usize,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "kept {} stations, renamed {}, dropped {}. removed {} invalid channel entries, {} channels are orphaned",
+            self.stations_kept.len(),
+            self.stations_renamed.len(),
+            self.stations_dropped,
+            self.channel_entries_removed,
+            self.orphaned_channels
+        )?;
+        for id in &self.stations_renamed {
+            writeln!(f, "- recovered a station as {id}")?;
+        }
+        Ok(())
+    }
+}
+
+impl DB {
+    /// Rebuilds the index (entrypoint, station map, and channel maps) from the stations and channels found by scanning
+    /// the allocator's chunks. this is a last resort, run [`DB::check_integrity`] first (and after).
+    ///
+    /// stations keep their ids if the station map still refers to them, and are given new ones otherwise.
+    /// the data of each channel is not checked or changed. like [`DB::check_integrity`], this does not require the
+    /// database to be opened (and it is usable afterwards, without [`DB::open`]), but it must have the current layout
+    pub fn repair(&mut self) -> Result<RepairReport, RepairError> {
+        if self.read_only {
+            return Err(RepairError::ReadOnly);
+        }
+        if !AllocAccess::header_is_valid(&self.store.map) {
+            return Err(RepairError::BadHeader);
+        }
+        let mut report = RepairReport::default();
+        let mut access = self.store.access(false);
+        let (stored, expected) = access.num_types();
+        if stored != expected {
+            return Err(RepairError::IncompatibleTypes { stored, expected });
+        }
+        let (stored, expected) = (*access.schema_hash(), repr::schema_hash());
+        if stored != expected {
+            return Err(RepairError::Incompatible { stored, expected });
+        }
+        let chunks = access.scan_chunks();
+        let find = |value: fn(&ScannedChunk) -> Option<u64>| {
+            chunks.iter().filter_map(value).collect::<Vec<_>>()
+        };
+        let entrypoints = find(|c| c.value::<repr::DBEntrypoint>().map(|p| p.addr));
+        let stations = find(|c| c.value::<repr::Station>().map(|p| p.addr));
+        let channels = find(|c| c.value::<repr::Channel>().map(|p| p.addr))
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        // the entrypoint the header points to, or the first one found
+        let entry_ptr = Some(access.entrypoint_pointer().addr)
+            .filter(|addr| entrypoints.contains(addr))
+            .or(entrypoints.first().copied())
+            .map(Ptr::<repr::DBEntrypoint>::with);
+        let entry = entry_ptr.map(|ptr| access.read(ptr));
+        // ids of the stations the station map still refers to
+        let mut known_ids = HashMap::new();
+        if let Some(entry) = &entry {
+            for elem in &entry.stations.stations {
+                let id = Uuid::from_bytes(elem.id);
+                if stations.contains(&elem.ptr.addr)
+                    && !id.is_nil()
+                    && !known_ids.values().any(|known| *known == id)
+                {
+                    known_ids.entry(elem.ptr.addr).or_insert(id);
+                }
+            }
+        }
+
+        let mut map = repr::MapStations::new_zeroed();
+        let mut referenced = HashSet::new();
+        for addr in stations {
+            let idx = report.stations_kept.len() + report.stations_renamed.len();
+            let Some(elem) = map.stations.get_mut(idx) else {
+                report.stations_dropped += 1;
+                continue;
+            };
+            let station = access.read(Ptr::<repr::Station>::with(addr));
+            let valid = station
+                .channels
+                .iter()
+                .filter(|elem| !elem.ptr.is_null() || elem.id != [0; 16])
+                .filter(|elem| {
+                    let ok = channels.contains(&elem.ptr.addr)
+                        && !Uuid::from_bytes(elem.id).is_nil()
+                        && referenced.insert(elem.ptr.addr);
+                    if !ok {
+                        report.channel_entries_removed += 1;
+                    }
+                    ok
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            station.channels = FromZeroes::new_zeroed();
+            station.channels[..valid.len()].copy_from_slice(&valid);
+            let id = match known_ids.get(&addr) {
+                Some(&id) => {
+                    report.stations_kept.push(id);
+                    id
+                }
+                None => {
+                    let id = Uuid::new_v4();
+                    report.stations_renamed.push(id);
+                    id
+                }
+            };
+            *elem = repr::MapStationsElem {
+                id: id.into_bytes(),
+                ptr: Ptr::with(addr),
+            };
+        }
+        report.orphaned_channels = channels.len() - referenced.len();
+
+        let (entry_ptr, entry) = match entry_ptr.zip(entry) {
+            Some(found) => found,
+            None => access.alloc::<repr::DBEntrypoint>(),
+        };
+        entry.stations = map;
+        entry.tuning_params.station_map_chunk_size =
+            repr::MapStations::new_zeroed().stations.len() as u64;
+        entry.tuning_params.channel_map_chunk_size =
+            repr::Station::new_zeroed().channels.len() as u64;
+        *access.entrypoint_pointer() = entry_ptr.cast();
+        *access.schema_hash() = repr::schema_hash();
+        self.init = true;
+        Ok(report)
+    }
+}
@@ -1,6 +1,6 @@
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
-use super::alloc::Ptr;
+use super::alloc::{ptr::Void, Ptr};
 
 /// Midnight, Jan 1 2020 (unix timestamp, seconds)
 pub const EPOCH: i64 = 1577836800;
@@ -21,6 +21,60 @@ pub const fn htime_to_unix(htime: u32) -> i64 {
     htime as i64 + EPOCH
 }
 
+/// Hash of the layout of everything stored in the database (the size of each type, and the name, offset, and size
+/// of each of its fields). stored when a database is initialized, so that a file written with a different layout
+/// is not misread. never 0 (which is stored by files from before this existed)
+pub fn schema_hash() -> u32 {
+    /// (name, size, [(field, offset, size)])
+    macro_rules! layout {
+        ($($ty:ident { $($field:ident),* $(,)? }),* $(,)?) => {
+            [$((
+                stringify!($ty),
+                std::mem::size_of::<$ty>(),
+                vec![$((
+                    stringify!($field),
+                    std::mem::offset_of!($ty, $field),
+                    std::mem::size_of_val(&$ty::new_zeroed().$field),
+                )),*],
+            )),*]
+        };
+    }
+    let types = layout! {
+        DBEntrypoint { stations, tuning_params },
+        TuningParams { station_map_chunk_size, channel_map_chunk_size },
+        MapStations { stations },
+        MapStationsElem { id, ptr },
+        Station { channels },
+        MapChannelsElem { id, ptr },
+        Channel { num_used, last_time, data, events },
+        ChannelData { chunk, next },
+        ChunkLink { ptr, codec, _padding },
+        CompressedData { next, count, used, first_time, last_time, buf },
+        DataEntry { htime, data },
+        EventChunk { used, last_time, next, buf },
+    };
+    // FNV-1a (std's hashers are not guaranteed to be stable between releases)
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut write = |bytes: &[u8]| {
+        for b in bytes {
+            hash = (hash ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+    };
+    for (name, size, fields) in types {
+        write(name.as_bytes());
+        write(&(size as u64).to_le_bytes());
+        for (field, offset, size) in fields {
+            write(field.as_bytes());
+            write(&(offset as u64).to_le_bytes());
+            write(&(size as u64).to_le_bytes());
+        }
+    }
+    match (hash ^ (hash >> 32)) as u32 {
+        0 => 1,
+        hash => hash,
+    }
+}
+
 #[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
 #[repr(C)]
 pub struct DBEntrypoint {
@@ -74,6 +128,8 @@ pub struct Channel {
     /// previous data entry time. (htime fmt)
     pub last_time: u32,
     pub data: ChannelData,
+    /// newest chunk of events (null if no events have been recorded)
+    pub events: Ptr<EventChunk>,
 }
 
 impl Channel {
@@ -89,12 +145,66 @@ impl Channel {
 /// - time is in seconds since 2020 (when this breaks in 2156, I'll be dead)
 /// - idx 0->len is oldest->newest (0=old, len=new)
 /// - only the head can have empty elements, the number of non-empty elements is stored in MapChannelsElem
-/// - once the head fills up, a new empty head is created, with its `next` pointing to the previous head
+/// - once the head fills up, it is moved out (and possibly compressed, see [`CompressedData`]) and emptied,
+///   with its `next` pointing to the previous head
 #[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
 #[repr(C)]
 pub struct ChannelData {
     pub chunk: [DataEntry; 512],
-    pub next: Ptr<ChannelData>,
+    pub next: ChunkLink,
+}
+
+/// [`ChunkLink::codec`] of a [`ChannelData`] chunk
+pub const CODEC_RAW: u32 = 0;
+/// [`ChunkLink::codec`] of a [`CompressedData`] chunk
+pub const CODEC_GORILLA: u32 = 1;
+
+/// pointer to the next (older) data chunk, and how that chunk is stored.
+///
+/// the head (in [`Channel`]) is always a [`ChannelData`], older chunks may be either.
+/// (zeroed is a null [`CODEC_RAW`] link)
+#[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
+#[repr(C)]
+pub struct ChunkLink {
+    /// `Ptr<ChannelData>` or `Ptr<CompressedData>`, depending on `codec`
+    pub ptr: Ptr<Void>,
+    pub codec: u32,
+    pub _padding: [u8; 4],
+}
+
+impl ChunkLink {
+    pub fn new(ptr: Ptr<Void>, codec: u32) -> Self {
+        Self {
+            ptr,
+            codec,
+            _padding: [0u8; 4],
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+}
+
+/// size of the buffer in a [`CompressedData`] (so that the whole chunk is 2KiB)
+pub const COMPRESSED_BUF_SIZE: usize = 2016;
+static_assertions::const_assert_eq!(std::mem::size_of::<CompressedData>(), 2048);
+
+/// a full [`ChannelData`] chunk, compressed (see `tsdb3::codec` for the encoding).
+///
+/// chunks are only compressed once they are no longer the head, and only if they fit in `buf`
+#[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
+#[repr(C)]
+pub struct CompressedData {
+    pub next: ChunkLink,
+    /// number of entries
+    pub count: u32,
+    /// number of bytes of `buf` in use
+    pub used: u32,
+    /// time of the oldest and newest entries (htime fmt), so that chunks can be skipped without decoding them
+    pub first_time: u32,
+    pub last_time: u32,
+    pub buf: [u8; 2016],
 }
 
 #[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
@@ -103,3 +213,23 @@ pub struct DataEntry {
     pub htime: u32,
     pub data: f32,
 }
+
+/// size of the event buffer in an [`EventChunk`] (so that the whole chunk is 4KiB)
+pub const EVENT_CHUNK_BUF_SIZE: usize = 4080;
+// (the const can not be used as the array length, because of generic_const_exprs)
+static_assertions::const_assert_eq!(std::mem::size_of::<EventChunk>(), 4096);
+
+/// entry in a linked list of events (going from most recent to oldest, like [`ChannelData`])
+/// - events are variable length, see `tsdb3::event` for the encoding
+/// - the first `used` bytes of `buf` are events, oldest->newest
+/// - once an event does not fit in the head, a new head is created, with its `next` pointing to the previous head
+#[derive(Debug, Clone, Copy, FromBytes, AsBytes, FromZeroes)]
+#[repr(C)]
+pub struct EventChunk {
+    /// number of bytes of `buf` in use
+    pub used: u32,
+    /// time of the newest event in this chunk (htime fmt)
+    pub last_time: u32,
+    pub next: Ptr<EventChunk>,
+    pub buf: [u8; 4080],
+}
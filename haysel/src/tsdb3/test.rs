@@ -1,12 +1,14 @@
 #[cfg(test)]
 use ::{
     chrono::{DateTime, Utc},
-    std::collections::HashSet,
+    std::collections::{HashMap, HashSet},
     uuid::Uuid,
 };
 
 #[cfg(test)]
-use super::DB;
+use super::{
+    alloc::Ptr, event::StoredEvent, integrity::IntegrityProblem, query::QueryBuilder, repr, DB,
+};
 
 #[test]
 fn create_new_db() {
@@ -20,7 +22,7 @@ fn create_new_db() {
 fn op_without_init() {
     let mut db = DB::new_in_ram(4096).unwrap();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
 }
 
 #[test]
@@ -28,7 +30,7 @@ fn create_new_station() {
     let mut db = DB::new_in_ram(4096).unwrap();
     db.init();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
     println!("Station created, verifying");
     let stations = db.get_stations().collect::<Vec<_>>();
     assert_eq!(stations, vec![&sid]);
@@ -42,7 +44,7 @@ fn create_16_new_stations() {
     for _ in 0..16 {
         let sid = Uuid::new_v4();
         set.insert(sid);
-        db.insert_station(sid);
+        db.insert_station(sid).unwrap();
     }
     println!("Station created, verifying");
     let stations = db.get_stations().copied().collect::<HashSet<_>>();
@@ -55,9 +57,9 @@ fn create_new_channel() {
     let mut db = DB::new_in_ram(10_000).unwrap();
     db.init();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
     let cid = Uuid::new_v4();
-    db.insert_channels(sid, [cid]);
+    db.insert_channels(sid, [cid]).unwrap();
     println!("Channel created, verifying");
     let channels = db.get_channels_for(sid).map(|x| x.collect::<Vec<_>>());
     assert_eq!(channels, Some(vec![&cid]));
@@ -69,12 +71,12 @@ fn insert_data() {
     let mut db = DB::new_in_ram(30_000).unwrap();
     db.init();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
     let cid = Uuid::new_v4();
-    db.insert_channels(sid, [cid]);
+    db.insert_channels(sid, [cid]).unwrap();
     let time = Utc::now();
     let reading = 5f32;
-    db.insert_data(sid, cid, time, reading);
+    db.insert_data(sid, cid, time, reading).unwrap();
 }
 
 #[test]
@@ -83,32 +85,74 @@ fn insert_data_in_order() {
     let mut db = DB::new_in_ram(30_000).unwrap();
     db.init();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
     let cid = Uuid::new_v4();
-    db.insert_channels(sid, [cid]);
+    db.insert_channels(sid, [cid]).unwrap();
     let time = Utc::now();
     let prev_time = time.checked_sub_days(chrono::Days::new(1)).unwrap();
     let reading = 5f32;
-    db.insert_data(sid, cid, prev_time, reading);
-    db.insert_data(sid, cid, time, reading);
+    db.insert_data(sid, cid, prev_time, reading).unwrap();
+    db.insert_data(sid, cid, time, reading).unwrap();
 }
 
 #[test]
-#[should_panic]
 fn insert_data_backwards() {
     // note: need moar bigger
     let mut db = DB::new_in_ram(30_000).unwrap();
     db.init();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
     let cid = Uuid::new_v4();
-    db.insert_channels(sid, [cid]);
-    let time = Utc::now();
+    db.insert_channels(sid, [cid]).unwrap();
+    let time = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
     let prev_time = time.checked_sub_days(chrono::Days::new(1)).unwrap();
-    let reading = 5f32;
-    db.insert_data(sid, cid, time, reading);
-    // ERROR: data must be in chronological order
-    db.insert_data(sid, cid, prev_time, reading);
+    let between = time - chrono::Duration::hours(1);
+    // (a delayed packet)
+    db.insert_data(sid, cid, time, 3.0).unwrap();
+    db.insert_data(sid, cid, prev_time, 1.0).unwrap();
+    db.insert_data(sid, cid, between, 2.0).unwrap();
+    let mut seen = vec![];
+    db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+    assert_eq!(seen, vec![(prev_time, 1.0), (between, 2.0), (time, 3.0)]);
+    assert!(db.check_integrity().is_ok());
+}
+
+#[test]
+fn insert_data_backwards_across_chunks() {
+    let mut db = DB::new_in_ram(30_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    let at = |secs: i64| start + chrono::Duration::seconds(secs);
+    // 2 full chunks, and a head with one free entry (at even times)
+    let num = 512 * 3 - 1;
+    for i in 0..num {
+        db.insert_data(sid, cid, at(i * 2), i as f32).unwrap();
+    }
+    // at odd times: in the head, before everything, in the oldest chunk, and on the boundary between the chunks
+    // (the first fills the head, the rest each push a reading out of it into a new one)
+    let late = [(num * 2 - 3), -1, 101, 511 * 2 + 1, 1023 * 2 + 1];
+    for secs in late {
+        db.insert_data(sid, cid, at(secs), -1.0).unwrap();
+    }
+    let mut expected = (0..num)
+        .map(|i| (at(i * 2), i as f32))
+        .chain(late.iter().map(|&secs| (at(secs), -1.0)))
+        .collect::<Vec<_>>();
+    expected.sort_by_key(|&(t, _)| t);
+    let mut seen = vec![];
+    db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+    assert_eq!(seen, expected);
+    assert!(db.check_integrity().is_ok());
+    assert_eq!(
+        db.qery_data_raw(sid, cid, at(100), at(103), 10),
+        vec![(at(101), -1.0), (at(102), 51.0)]
+    );
 }
 
 #[test]
@@ -117,12 +161,12 @@ fn query_data() {
     let mut db = DB::new_in_ram(30_000).unwrap();
     db.init();
     let sid = Uuid::new_v4();
-    db.insert_station(sid);
+    db.insert_station(sid).unwrap();
     let cid = Uuid::new_v4();
-    db.insert_channels(sid, [cid]);
+    db.insert_channels(sid, [cid]).unwrap();
     let time = Utc::now();
     let reading = 5f32;
-    db.insert_data(sid, cid, time, reading);
+    db.insert_data(sid, cid, time, reading).unwrap();
     let before = time.checked_add_days(chrono::Days::new(1)).unwrap();
     let after = time.checked_sub_days(chrono::Days::new(1)).unwrap();
     let res = db.qery_data_raw(sid, cid, after, before, 10);
@@ -134,3 +178,1272 @@ fn query_data() {
         )]
     );
 }
+
+#[test]
+fn for_each_entry_multi_chunk() {
+    let mut db = DB::new_in_ram(30_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    // 2 full chunks, and part of the head
+    let num = 512 * 2 + 100;
+    for i in 0..num {
+        let t = start + chrono::Duration::seconds(i as i64);
+        db.insert_data(sid, cid, t, i as f32).unwrap();
+    }
+    let mut seen = vec![];
+    db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+    assert_eq!(seen.len(), num);
+    for (i, (t, v)) in seen.into_iter().enumerate() {
+        assert_eq!(t, start + chrono::Duration::seconds(i as i64));
+        assert_eq!(v, i as f32);
+    }
+}
+
+#[test]
+fn stats() {
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let empty = db.stats();
+    assert_eq!(
+        (
+            empty.station_count,
+            empty.channel_count,
+            empty.approx_reading_count
+        ),
+        (0, 0, 0)
+    );
+    assert_eq!(empty.bytes_capacity, 100_000);
+    let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+    db.insert_station(a).unwrap();
+    db.insert_station(b).unwrap();
+    let (ca1, ca2, cb) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    db.insert_channels(a, [ca1, ca2]).unwrap();
+    db.insert_channels(b, [cb]).unwrap();
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    // 2 full chunks, and part of the head
+    for i in 0..512 * 2 + 100 {
+        db.insert_data(a, ca1, start + chrono::Duration::seconds(i), 0.0)
+            .unwrap();
+    }
+    for i in 0..10 {
+        db.insert_data(b, cb, start + chrono::Duration::seconds(i), 0.0)
+            .unwrap();
+    }
+    let stats = db.stats();
+    assert_eq!(stats.station_count, 2);
+    assert_eq!(stats.channel_count, 3);
+    assert_eq!(stats.approx_reading_count, 512 * 2 + 100 + 10);
+    assert!(stats.bytes_used > empty.bytes_used);
+    assert!(stats.bytes_used <= stats.bytes_capacity);
+}
+
+#[test]
+fn iter_channels() {
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    assert_eq!(db.iter_channels().count(), 0);
+    let (a, b, empty) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let (ca1, ca2, cb) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    db.insert_station(a).unwrap();
+    db.insert_station(empty).unwrap();
+    db.insert_station(b).unwrap();
+    db.insert_channels(a, [ca1, ca2]).unwrap();
+    db.insert_channels(b, [cb]).unwrap();
+    let pairs = db.iter_channels().collect::<Vec<_>>();
+    assert_eq!(pairs.len(), 3);
+    assert_eq!(
+        pairs.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([(a, ca1), (a, ca2), (b, cb)])
+    );
+}
+
+#[test]
+fn compressed_chunks() {
+    let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    // a slowly changing temperature, every minute
+    let readings = (0..512 * 4 + 10)
+        .map(|i| (i * 60, 15.0 + ((i / 30) % 20) as f32 * 0.1))
+        .collect::<Vec<_>>();
+    let (mut raw, ..) = db_with_readings(&readings, true, false);
+    let (mut db, sid, cid, sorted) = db_with_readings(&readings, true, true);
+    let mut seen = vec![];
+    db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+    assert_eq!(seen, sorted);
+    let (after, before) = (
+        start + chrono::Duration::minutes(700),
+        start + chrono::Duration::minutes(1400),
+    );
+    assert_eq!(
+        db.qery_data_raw(sid, cid, after, before, usize::MAX),
+        sorted
+            .iter()
+            .copied()
+            .filter(|&(t, _)| t > after && t < before)
+            .collect::<Vec<_>>()
+    );
+    assert!(db.check_integrity().is_ok());
+    let (stats, raw_stats) = (db.stats(), raw.stats());
+    assert_eq!(stats.approx_reading_count, raw_stats.approx_reading_count);
+    // (each of the 4 full chunks takes at most half the space)
+    assert!(
+        raw_stats.bytes_used - stats.bytes_used
+            >= 4 * std::mem::size_of::<repr::ChannelData>() as u64 / 2,
+        "{} vs {}",
+        stats.bytes_used,
+        raw_stats.bytes_used
+    );
+
+    // chunks stored before compression was enabled are still read
+    let (mut mixed, sid, cid, _) = db_with_readings(&readings[..512 * 2], true, false);
+    mixed.set_compression(true);
+    for &(offset, reading) in &readings[512 * 2..] {
+        let time = start + chrono::Duration::seconds(offset as i64);
+        mixed.insert_data(sid, cid, time, reading).unwrap();
+    }
+    let mut seen = vec![];
+    mixed.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+    assert_eq!(seen, sorted);
+    assert!(mixed.check_integrity().is_ok());
+
+    // a corrupt compressed chunk is reported
+    {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let station = access.read(entry.stations.stations[0].ptr);
+        let channel = access.read(station.channels[0].ptr);
+        assert_eq!(channel.data.next.codec, repr::CODEC_GORILLA);
+        let chunk = access.read(channel.data.next.ptr.cast::<repr::CompressedData>());
+        chunk.used /= 2;
+    }
+    let report = db.check_integrity();
+    assert!(matches!(
+        report.problems[..],
+        [IntegrityProblem::BadCompressedChunk { .. }]
+    ));
+}
+
+#[test]
+fn insert_and_query_events() {
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let query = || QueryBuilder::new().with_station(sid).with_channel(cid);
+    assert_eq!(db.query_events(query().verify().unwrap()), vec![]);
+
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    // enough to need more than one chunk
+    let events = (0..500)
+        .map(|i| StoredEvent {
+            time: start + chrono::Duration::seconds(i),
+            sub: if i % 10 == 0 { "disturber" } else { "strike" }.to_string(),
+            data: HashMap::from([("distance".to_string(), i as f32)]),
+        })
+        .collect::<Vec<_>>();
+    for ev in &events {
+        db.insert_event(sid, cid, ev.time, &ev.sub, &ev.data)
+            .unwrap();
+    }
+    // readings are independent of events
+    db.insert_data(sid, cid, start, 1.0).unwrap();
+
+    assert_eq!(db.query_events(query().verify().unwrap()), events);
+    let after = events[200].time;
+    let res = db.query_events(query().with_after(after).verify().unwrap());
+    assert_eq!(res, events[201..]);
+    assert_eq!(res[0].sub, "strike");
+    assert_eq!(res[0].data["distance"], 201.0);
+    let res = db.query_events(query().with_before(after).verify().unwrap());
+    assert_eq!(res, events[..200]);
+    assert_eq!(res[100].sub, "disturber");
+    assert_eq!(
+        db.qery_data_raw(
+            sid,
+            cid,
+            start - chrono::Duration::seconds(1),
+            start + chrono::Duration::seconds(1),
+            10
+        ),
+        vec![(start, 1.0)]
+    );
+    assert!(db.check_integrity().is_ok());
+}
+
+#[test]
+fn latest_values() {
+    use mycelium::station::capabilities::ChannelData;
+
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let (full, delayed, events, empty) = (
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+    );
+    db.insert_channels(sid, [full, delayed, events, empty])
+        .unwrap();
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    let at = |secs| start + chrono::Duration::seconds(secs);
+    // exactly one chunk, so the head has just been moved out
+    for i in 0..512 {
+        db.insert_data(sid, full, at(i), i as f32).unwrap();
+    }
+    db.insert_data(sid, delayed, at(10), 1.0).unwrap();
+    db.insert_data(sid, delayed, at(5), 2.0).unwrap();
+    db.insert_data(sid, events, at(1), 3.0).unwrap();
+    let data = HashMap::from([("distance".to_string(), 12.0)]);
+    db.insert_event(sid, events, at(0), "disturber", &data)
+        .unwrap();
+    db.insert_event(sid, events, at(2), "strike", &data)
+        .unwrap();
+
+    let latest = db.latest_values();
+    assert_eq!(latest.len(), 3);
+    assert!(
+        matches!(latest[&(sid, full)], (t, ChannelData::Float(v)) if t == at(511) && v == 511.0)
+    );
+    assert!(
+        matches!(latest[&(sid, delayed)], (t, ChannelData::Float(v)) if t == at(10) && v == 1.0)
+    );
+    assert!(
+        matches!(&latest[&(sid, events)], (t, ChannelData::Event { sub, .. }) if *t == at(2) && sub == "strike")
+    );
+}
+
+#[test]
+fn insert_events_out_of_order() {
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let query = || QueryBuilder::new().with_station(sid).with_channel(cid);
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    let event = |i: i64| StoredEvent {
+        time: start + chrono::Duration::seconds(i),
+        sub: "strike".to_string(),
+        data: HashMap::from([("distance".to_string(), i as f32)]),
+    };
+    let insert = |db: &mut DB, ev: &StoredEvent| {
+        db.insert_event(sid, cid, ev.time, &ev.sub, &ev.data)
+            .unwrap()
+    };
+
+    // an older event after a newer one
+    insert(&mut db, &event(10));
+    insert(&mut db, &event(5));
+    assert_eq!(
+        db.query_events(query().verify().unwrap()),
+        vec![event(5), event(10)]
+    );
+
+    // enough to need several chunks, then older events into each of them (and before all of them)
+    for i in 11..500 {
+        insert(&mut db, &event(i * 2));
+    }
+    for i in [999, 501, 401, 11, 1] {
+        insert(&mut db, &event(i));
+    }
+    let mut expected = [1, 5, 10, 11]
+        .into_iter()
+        .chain((11..500).map(|i| i * 2))
+        .chain([401, 501, 999])
+        .map(event)
+        .collect::<Vec<_>>();
+    expected.sort_by_key(|ev| ev.time);
+    assert_eq!(db.query_events(query().verify().unwrap()), expected);
+    let after = event(400).time;
+    assert_eq!(
+        db.query_events(query().with_after(after).verify().unwrap()),
+        expected
+            .iter()
+            .filter(|ev| ev.time > after)
+            .cloned()
+            .collect::<Vec<_>>()
+    );
+    assert!(db.check_integrity().is_ok());
+}
+
+#[test]
+fn event_too_large() {
+    use super::Error;
+
+    let mut db = DB::new_in_ram(30_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let data = (0..300)
+        .map(|i| (i.to_string(), 0.0))
+        .collect::<HashMap<_, _>>();
+    assert!(matches!(
+        db.insert_event(sid, cid, Utc::now(), "strike", &data),
+        Err(Error::EventTooLarge)
+    ));
+}
+
+#[cfg(test)]
+fn db_with_data() -> (DB, Uuid, Uuid) {
+    let mut db = DB::new_in_ram(30_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let time = Utc::now();
+    for i in 0..10 {
+        let t = time.checked_sub_days(chrono::Days::new(10 - i)).unwrap();
+        db.insert_data(sid, cid, t, i as f32).unwrap();
+    }
+    (db, sid, cid)
+}
+
+#[test]
+fn check_integrity_ok() {
+    let (mut db, ..) = db_with_data();
+    let report = db.check_integrity();
+    assert!(report.is_ok(), "{report}");
+    assert_eq!(report.stations_checked, 1);
+    assert_eq!(report.channels_checked, 1);
+    assert_eq!(report.chunks_checked, 1);
+}
+
+#[test]
+fn check_integrity_reports_all_problems() {
+    let (mut db, ..) = db_with_data();
+    {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let station = access.read(entry.stations.stations[0].ptr);
+        let channel = access.read(station.channels[0].ptr);
+        // corrupt the chunk
+        channel.num_used = 1000;
+        channel.data.chunk.swap(2, 3);
+        // and add a bogus station
+        entry.stations.stations[1].id = Uuid::new_v4().into_bytes();
+        entry.stations.stations[1].ptr = Ptr::with(u64::MAX - 10);
+    }
+    let report = db.check_integrity();
+    assert!(!report.is_ok());
+    assert_eq!(report.problems.len(), 5, "{report}");
+    assert!(report
+        .problems
+        .iter()
+        .any(|p| matches!(p, IntegrityProblem::NumUsedTooLarge { num_used: 1000, .. })));
+    assert!(report
+        .problems
+        .iter()
+        .any(|p| matches!(p, IntegrityProblem::OutOfOrder { idx: 3, .. })));
+    assert!(report
+        .problems
+        .iter()
+        .any(|p| matches!(p, IntegrityProblem::BadPointer { .. })));
+    // num_used is clamped to the chunk size, so the (zeroed) entries after the real data are out of order as well
+    assert!(report
+        .problems
+        .iter()
+        .any(|p| matches!(p, IntegrityProblem::OutOfOrder { idx: 10, .. })));
+    assert!(report
+        .problems
+        .iter()
+        .any(|p| matches!(p, IntegrityProblem::LastTimeMismatch { newest: 0, .. })));
+}
+
+#[test]
+fn check_integrity_multiply_referenced() {
+    let (mut db, ..) = db_with_data();
+    {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        entry.stations.stations[1].id = Uuid::new_v4().into_bytes();
+        entry.stations.stations[1].ptr = entry.stations.stations[0].ptr;
+    }
+    let report = db.check_integrity();
+    assert_eq!(report.problems.len(), 1, "{report}");
+    assert!(matches!(
+        report.problems[0],
+        IntegrityProblem::MultiplyReferenced { .. }
+    ));
+}
+
+#[test]
+fn chunk_lengths_are_distinct() {
+    use super::alloc::ScannedChunk;
+
+    // (repair identifies chunks by their length)
+    fn len<T>() -> u32 {
+        (0..u16::MAX as u32)
+            .find(|&len| {
+                ScannedChunk {
+                    addr: 0,
+                    len,
+                    free: false,
+                }
+                .value::<T>()
+                .is_some()
+            })
+            .unwrap()
+    }
+    let lens = [
+        len::<repr::DBEntrypoint>(),
+        len::<repr::Station>(),
+        len::<repr::Channel>(),
+        len::<repr::ChannelData>(),
+        len::<repr::EventChunk>(),
+        len::<repr::CompressedData>(),
+    ];
+    assert_eq!(lens.iter().collect::<HashSet<_>>().len(), lens.len());
+}
+
+/// (station, channel, readings)
+#[cfg(test)]
+type ChannelReadings = (Uuid, Uuid, Vec<(DateTime<Utc>, f32)>);
+
+/// two stations with data, and the readings of each channel
+#[cfg(test)]
+fn db_for_repair() -> (DB, [ChannelReadings; 3]) {
+    let mut db = DB::new_in_ram(200_000).unwrap();
+    db.init();
+    let (s0, s1) = (Uuid::new_v4(), Uuid::new_v4());
+    let (c0, c1) = (Uuid::new_v4(), Uuid::new_v4());
+    db.insert_station(s0).unwrap();
+    db.insert_station(s1).unwrap();
+    db.insert_channels(s0, [c0, c1]).unwrap();
+    db.insert_channels(s1, [c0]).unwrap();
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    let mut channels = [(s0, c0, vec![]), (s0, c1, vec![]), (s1, c0, vec![])];
+    // more than one chunk
+    for i in 0..600 {
+        for (n, (sid, cid, readings)) in channels.iter_mut().enumerate() {
+            let reading = (
+                start + chrono::Duration::seconds(i),
+                (i * 3 + n as i64) as f32,
+            );
+            db.insert_data(*sid, *cid, reading.0, reading.1).unwrap();
+            readings.push(reading);
+        }
+    }
+    (db, channels)
+}
+
+#[cfg(test)]
+fn all_readings(db: &mut DB, sid: Uuid, cid: Uuid) -> Vec<(DateTime<Utc>, f32)> {
+    let mut readings = vec![];
+    db.for_each_entry(sid, cid, |t, v| readings.push((t, v)));
+    readings
+}
+
+#[test]
+fn repair_station_map() {
+    let (mut db, channels) = db_for_repair();
+    {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        // the first station's entry is overwritten
+        entry.stations.stations[0].ptr = Ptr::with(u64::MAX - 10);
+    }
+    assert!(!db.check_integrity().is_ok());
+    let report = db.repair().unwrap();
+    assert_eq!(report.stations_kept, [channels[2].0]);
+    assert_eq!(report.stations_renamed.len(), 1);
+    assert_eq!(
+        (report.channel_entries_removed, report.orphaned_channels),
+        (0, 0)
+    );
+    assert!(db.check_integrity().is_ok());
+    // the second station is unchanged, and the first is found under its new id
+    let renamed = report.stations_renamed[0];
+    assert_eq!(
+        db.get_stations().copied().collect::<Vec<_>>(),
+        [renamed, channels[2].0]
+    );
+    for (sid, cid, readings) in &channels {
+        let sid = if *sid == channels[0].0 { renamed } else { *sid };
+        assert_eq!(&all_readings(&mut db, sid, *cid), readings);
+    }
+    // and it is usable
+    db.insert_data(renamed, channels[0].1, Utc::now(), 1.0)
+        .unwrap();
+}
+
+#[test]
+fn repair_lost_entrypoint() {
+    let (mut db, channels) = db_for_repair();
+    {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let station = access.read(entry.stations.stations[1].ptr);
+        // a channel of the second station points at garbage
+        station.channels[0].ptr = Ptr::with(8);
+        *access.entrypoint_pointer() = Ptr::null();
+    }
+    let report = db.repair().unwrap();
+    // the entrypoint was found, so both stations keep their ids
+    assert_eq!(report.stations_kept, [channels[0].0, channels[2].0]);
+    assert!(report.stations_renamed.is_empty());
+    assert_eq!(
+        (report.channel_entries_removed, report.orphaned_channels),
+        (1, 1)
+    );
+    assert!(db.check_integrity().is_ok());
+    assert_eq!(
+        &all_readings(&mut db, channels[0].0, channels[0].1),
+        &channels[0].2
+    );
+    assert_eq!(
+        &all_readings(&mut db, channels[1].0, channels[1].1),
+        &channels[1].2
+    );
+    assert!(db.get_channels_for(channels[2].0).unwrap().next().is_none());
+}
+
+#[test]
+fn check_integrity_uninitialized() {
+    let mut db = DB::new_in_ram(4096).unwrap();
+    let report = db.check_integrity();
+    assert!(matches!(report.problems[..], [IntegrityProblem::BadHeader]));
+}
+
+#[test]
+fn grow_in_ram() {
+    use super::MIN_FREE_SPACE;
+
+    let mut db = DB::new_in_ram(MIN_FREE_SPACE as usize).unwrap();
+    db.init();
+    db.set_growth(Some(MIN_FREE_SPACE));
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    let num = 512 * 300;
+    let data = HashMap::from([("distance".to_string(), 1.0)]);
+    for i in 0..num {
+        let time = start + chrono::Duration::seconds(i);
+        db.insert_data(sid, cid, time, i as f32).unwrap();
+        if i % 100 == 0 {
+            db.insert_event(sid, cid, time, "strike", &data).unwrap();
+        }
+    }
+    let stats = db.stats();
+    assert!(stats.bytes_capacity > 2 * MIN_FREE_SPACE);
+    assert!(db.check_integrity().is_ok());
+    let mut seen = 0;
+    db.for_each_entry(sid, cid, |t, v| {
+        assert_eq!(t, start + chrono::Duration::seconds(seen));
+        assert_eq!(v, seen as f32);
+        seen += 1;
+    });
+    assert_eq!(seen, num);
+    let events = db.query_events(
+        QueryBuilder::new()
+            .with_station(sid)
+            .with_channel(cid)
+            .verify()
+            .unwrap(),
+    );
+    assert_eq!(events.len(), num as usize / 100);
+}
+
+#[test]
+fn new_opens_or_initializes() {
+    use std::fs::{self, OpenOptions};
+
+    use super::Error;
+
+    let path = std::env::temp_dir().join(format!("haysel-test-{}.tsdb3", Uuid::new_v4()));
+    let file = || {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap()
+    };
+    file().set_len(30_000).unwrap();
+    // nothing to open read-only
+    assert!(matches!(
+        unsafe { DB::new_read_only(file()) },
+        Err(Error::NotADatabase)
+    ));
+    // a fresh (empty) file is initialized
+    let mut db = unsafe { DB::new(file()) }.unwrap();
+    assert!(db.is_initialized());
+    assert!(matches!(db.open(), Err(Error::AlreadyInitialized)));
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    drop(db);
+    // and an existing one is opened
+    let mut db = unsafe { DB::new(file()) }.unwrap();
+    assert!(db.is_initialized());
+    assert_eq!(db.get_stations().collect::<Vec<_>>(), vec![&sid]);
+    drop(db);
+    let mut db = unsafe { DB::new_read_only(file()) }.unwrap();
+    assert_eq!(db.get_stations().collect::<Vec<_>>(), vec![&sid]);
+    drop(db);
+    // explicit opening still works
+    let mut db = unsafe { DB::new_uninit(file()) }.unwrap();
+    assert!(!db.is_initialized());
+    db.open().unwrap();
+    assert_eq!(db.get_stations().collect::<Vec<_>>(), vec![&sid]);
+    drop(db);
+
+    // something else is not overwritten
+    fs::write(&path, [0xAB; 30_000]).unwrap();
+    assert!(matches!(
+        unsafe { DB::new(file()) },
+        Err(Error::NotADatabase)
+    ));
+    assert_eq!(fs::read(&path).unwrap(), [0xAB; 30_000]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn open_path_creates_and_grows() {
+    use std::fs;
+
+    use super::MIN_FREE_SPACE;
+
+    let path = std::env::temp_dir().join(format!("haysel-test-{}.tsdb3", Uuid::new_v4()));
+    // nothing to open read-only
+    assert!(unsafe { DB::open_path(&path, MIN_FREE_SPACE, true) }.is_err());
+    let mut db = unsafe { DB::open_path(&path, MIN_FREE_SPACE, false) }.unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), MIN_FREE_SPACE);
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    // several times what fits in the initial size
+    let num = 512 * 300;
+    for i in 0..num {
+        db.insert_data(sid, cid, start + chrono::Duration::seconds(i), i as f32)
+            .unwrap();
+    }
+    assert!(db.check_integrity().is_ok());
+    drop(db);
+    let size = fs::metadata(&path).unwrap().len();
+    assert!(size > MIN_FREE_SPACE);
+    assert_eq!(size % MIN_FREE_SPACE, 0);
+
+    // opened (not initialized) the second time
+    let mut db = unsafe { DB::open_path(&path, MIN_FREE_SPACE, true) }.unwrap();
+    let mut seen = 0;
+    db.for_each_entry(sid, cid, |t, v| {
+        assert_eq!(t, start + chrono::Duration::seconds(seen));
+        assert_eq!(v, seen as f32);
+        seen += 1;
+    });
+    assert_eq!(seen, num);
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn read_only_rejects_writes() {
+    use std::fs::{self, OpenOptions};
+
+    use super::Error;
+
+    let path = std::env::temp_dir().join(format!("haysel-test-{}.tsdb3", Uuid::new_v4()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+    file.set_len(30_000).unwrap();
+    let mut db = unsafe { DB::new(file) }.unwrap();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let time = Utc::now();
+    db.insert_data(sid, cid, time, 5f32).unwrap();
+    drop(db);
+    let before = fs::read(&path).unwrap();
+
+    let file = OpenOptions::new().read(true).open(&path).unwrap();
+    let mut db = unsafe { DB::new_read_only(file) }.unwrap();
+    assert!(db.is_read_only());
+    // reads work
+    assert_eq!(db.get_stations().collect::<Vec<_>>(), vec![&sid]);
+    let res = db.qery_data_raw(
+        sid,
+        cid,
+        time.checked_sub_days(chrono::Days::new(1)).unwrap(),
+        time.checked_add_days(chrono::Days::new(1)).unwrap(),
+        10,
+    );
+    assert_eq!(
+        res,
+        vec![(DateTime::from_timestamp(time.timestamp(), 0).unwrap(), 5f32)]
+    );
+    // writes do not
+    assert!(matches!(
+        db.insert_station(Uuid::new_v4()),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        db.insert_channels(sid, [Uuid::new_v4()]),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        db.insert_data(sid, cid, Utc::now(), 6f32),
+        Err(Error::ReadOnly)
+    ));
+    drop(db);
+    assert!(fs::read(&path).unwrap() == before);
+    fs::remove_file(&path).unwrap();
+}
+
+/// a channel filled with `readings` (offsets in seconds from a fixed start time), inserted oldest to newest, or in
+/// the given order. also returns the readings in the order they should be read back
+#[cfg(test)]
+fn db_with_readings(
+    readings: &[(u32, f32)],
+    in_order: bool,
+    compress: bool,
+) -> (DB, Uuid, Uuid, Vec<(DateTime<Utc>, f32)>) {
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    db.set_compression(compress);
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    let readings = readings
+        .iter()
+        .map(|&(offset, reading)| (start + chrono::Duration::seconds(offset as i64), reading))
+        .collect::<Vec<_>>();
+    // a stable sort, readings at the same time are read back in the order they were inserted
+    let mut sorted = readings.clone();
+    sorted.sort_by_key(|&(time, _)| time);
+    for &(time, reading) in if in_order { &sorted } else { &readings } {
+        db.insert_data(sid, cid, time, reading).unwrap();
+    }
+    (db, sid, cid, sorted)
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+    /// everything inserted is read back, in order (across several chunks, with repeated times)
+    #[test]
+    fn prop_insert_then_read_back(
+        readings in proptest::collection::vec((0u32..20_000, -1000f32..1000f32), 0..1400)
+    ) {
+        let (mut db, sid, cid, sorted) = db_with_readings(&readings, true, false);
+        let mut seen = vec![];
+        db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+        proptest::prop_assert_eq!(seen, sorted);
+    }
+
+    /// readings inserted in any order are read back in chronological order, and the database stays consistent
+    #[test]
+    fn prop_insert_out_of_order(
+        readings in proptest::collection::vec((0u32..20_000, -1000f32..1000f32), 0..1400)
+    ) {
+        let (mut db, sid, cid, sorted) = db_with_readings(&readings, false, false);
+        let mut seen = vec![];
+        db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+        proptest::prop_assert_eq!(seen, sorted);
+        proptest::prop_assert!(db.check_integrity().is_ok());
+    }
+
+    /// the same, with compressed chunks (the readings are quantized, like real sensor data, so that most compress)
+    #[test]
+    fn prop_insert_out_of_order_compressed(
+        readings in proptest::collection::vec((0u32..20_000, -20i8..20), 0..1400)
+    ) {
+        let readings = readings
+            .into_iter()
+            .map(|(offset, reading)| (offset, reading as f32 * 0.25))
+            .collect::<Vec<_>>();
+        let (mut db, sid, cid, sorted) = db_with_readings(&readings, false, true);
+        let mut seen = vec![];
+        db.for_each_entry(sid, cid, |t, v| seen.push((t, v)));
+        proptest::prop_assert_eq!(seen, sorted);
+        proptest::prop_assert!(db.check_integrity().is_ok());
+    }
+
+    /// a query returns exactly the readings strictly between its bounds, oldest to newest
+    #[test]
+    fn prop_query_range(
+        readings in proptest::collection::vec((0u32..20_000, -1000f32..1000f32), 0..1400),
+        bounds in (0u32..20_000, 0u32..20_000),
+    ) {
+        let (mut db, sid, cid, sorted) = db_with_readings(&readings, true, false);
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let (after, before) = (
+            start + chrono::Duration::seconds(bounds.0.min(bounds.1) as i64),
+            start + chrono::Duration::seconds(bounds.0.max(bounds.1) as i64),
+        );
+        let expected = sorted
+            .into_iter()
+            .filter(|&(t, _)| t > after && t < before)
+            .collect::<Vec<_>>();
+        let res = db.qery_data_raw(sid, cid, after, before, usize::MAX);
+        proptest::prop_assert_eq!(res, expected);
+    }
+
+    /// reading every page of a range query returns the readings from `from` to `to` (exclusive) exactly once, even
+    /// when a page ends part way through readings with the same time
+    #[test]
+    fn prop_query_pages(
+        readings in proptest::collection::vec((0u32..2_000, -1000f32..1000f32), 0..1400),
+        bounds in (0u32..2_000, 0u32..2_000),
+        page_size in 1usize..300,
+    ) {
+        let (mut db, sid, cid, sorted) = db_with_readings(&readings, false, false);
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let (from, to) = (
+            start + chrono::Duration::seconds(bounds.0.min(bounds.1) as i64),
+            start + chrono::Duration::seconds(bounds.0.max(bounds.1) as i64),
+        );
+        let expected = sorted
+            .into_iter()
+            .filter(|&(t, _)| t >= from && t < to)
+            .collect::<Vec<_>>();
+        let mut res = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = db.query_page(sid, cid, from, to, cursor, page_size);
+            proptest::prop_assert!(page.len() <= page_size);
+            res.extend(page);
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        proptest::prop_assert_eq!(res, expected);
+    }
+}
+
+#[test]
+fn query_pages_with_delayed_readings() {
+    let readings = (0..1200).map(|i| (i, i as f32)).collect::<Vec<_>>();
+    let (mut db, sid, cid, sorted) = db_with_readings(&readings, true, false);
+    let (from, to) = (sorted[0].0, sorted[1199].0 + chrono::Duration::seconds(1));
+    let (first, cursor) = db.query_page(sid, cid, from, to, None, 700);
+    assert_eq!(first, sorted[..700]);
+    let cursor = cursor.unwrap();
+    assert_eq!((cursor.time, cursor.skip), (sorted[699].0, 1));
+    // a delayed reading from before the cursor moves every later reading to a different chunk,
+    // but the next page still starts after the last one read
+    db.insert_data(sid, cid, sorted[10].0, -1.0).unwrap();
+    let (second, cursor) = db.query_page(sid, cid, from, to, Some(cursor), 700);
+    assert_eq!(second, sorted[700..]);
+    assert_eq!(cursor, None);
+    // unknown channels have no readings
+    assert_eq!(
+        db.query_page(sid, Uuid::new_v4(), from, to, None, 700),
+        (vec![], None)
+    );
+}
+
+#[test]
+fn open_checks_layout() {
+    use std::fs::{self, OpenOptions};
+
+    use super::Error;
+
+    let path = std::env::temp_dir().join(format!("haysel-test-{}.tsdb3", Uuid::new_v4()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+    file.set_len(30_000).unwrap();
+    let mut db = unsafe { DB::new(file) }.unwrap();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    drop(db);
+    let open = || {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        unsafe { DB::new(file) }
+    };
+    // (the hash is stored after the alloc header's 12 magic bytes)
+    let set_stored_hash = |hash: u32| {
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[12..16].copy_from_slice(&hash.to_ne_bytes());
+        fs::write(&path, bytes).unwrap();
+    };
+    let stored_hash = || u32::from_ne_bytes(fs::read(&path).unwrap()[12..16].try_into().unwrap());
+
+    assert_eq!(stored_hash(), repr::schema_hash());
+    assert!(open().is_ok());
+
+    // written by a version with a different layout
+    set_stored_hash(repr::schema_hash() ^ 0x5a5a);
+    match open() {
+        Err(Error::Incompatible { stored, expected }) => {
+            assert_eq!(stored, repr::schema_hash() ^ 0x5a5a);
+            assert_eq!(expected, repr::schema_hash());
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(..) => panic!("opened a database with a different layout"),
+    }
+    // which is reported by checking it (read-only, without changing it)
+    let before = fs::read(&path).unwrap();
+    let file = OpenOptions::new().read(true).open(&path).unwrap();
+    let mut db = unsafe { DB::new_read_only_uninit(file) }.unwrap();
+    assert!(matches!(
+        db.check_integrity().problems[..],
+        [IntegrityProblem::SchemaMismatch { stored, .. }] if stored == repr::schema_hash() ^ 0x5a5a
+    ));
+    drop(db);
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn legacy_layout_is_refused() {
+    use super::{legacy, repair::RepairError, Error};
+
+    let (sid, cid) = (Uuid::new_v4(), Uuid::new_v4());
+    let start = repr::unix_to_htime(Utc::now().timestamp()).unwrap();
+    let readings = (0..1000)
+        .map(|i| repr::DataEntry {
+            htime: start + i,
+            data: i as f32,
+        })
+        .collect::<Vec<_>>();
+    // (either as written, or stamped with the current hash, as opening it with an earlier version of this one did)
+    for hash in [0, repr::schema_hash()] {
+        let mut db = DB::new_in_ram(100_000).unwrap();
+        db.write_legacy(&[(sid, cid, readings.clone())]);
+        *db.store.access(false).schema_hash() = hash;
+        let before = db.store.map.to_vec();
+        let is_legacy = |stored: u64, expected: u64| {
+            stored == legacy::type_registry().num_types() as u64 && stored != expected
+        };
+
+        assert!(matches!(
+            db.open(),
+            Err(Error::IncompatibleTypes { stored, expected }) if is_legacy(stored, expected)
+        ));
+        assert!(matches!(
+            db.check_integrity().problems[..],
+            [IntegrityProblem::TypesMismatch { stored, expected }] if is_legacy(stored, expected)
+        ));
+        assert!(matches!(
+            db.repair(),
+            Err(RepairError::IncompatibleTypes { stored, expected }) if is_legacy(stored, expected)
+        ));
+        // (and it is not marked as having the current layout)
+        assert!(db.store.map[..] == before[..]);
+    }
+}
+
+#[test]
+fn legacy_layout_is_migrated() {
+    let sid = Uuid::new_v4();
+    let start = repr::unix_to_htime(Utc::now().timestamp()).unwrap();
+    // (a partial head with 2 older chunks, a full head, and an empty one, in 2 stations)
+    let channels = [(sid, 512 * 2 + 100), (sid, 512), (Uuid::new_v4(), 0)].map(|(sid, num)| {
+        let readings = (0..num)
+            .map(|i| repr::DataEntry {
+                htime: start + i,
+                data: i as f32,
+            })
+            .collect::<Vec<_>>();
+        (sid, Uuid::new_v4(), readings)
+    });
+    let mut old = DB::new_in_ram(100_000).unwrap();
+    old.write_legacy(&channels);
+    let before = old.store.map.to_vec();
+
+    let mut db = DB::new_in_ram(100_000).unwrap();
+    db.init();
+    let report = old.migrate_legacy(&mut db).unwrap();
+    assert_eq!(
+        (report.stations, report.channels, report.readings),
+        (2, 3, 512 * 3 + 100)
+    );
+    for (sid, cid, readings) in &channels {
+        let mut seen = vec![];
+        db.for_each_entry(*sid, *cid, |t, v| seen.push((t, v)));
+        let expected = readings
+            .iter()
+            .map(|entry| {
+                let unix = repr::htime_to_unix(entry.htime);
+                (DateTime::from_timestamp(unix, 0).unwrap(), entry.data)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(seen, expected);
+    }
+    assert!(db.check_integrity().is_ok());
+    // (the old database is not changed)
+    assert!(old.store.map[..] == before[..]);
+    // and a current one is not migrated
+    assert!(matches!(
+        db.migrate_legacy(&mut DB::new_in_ram(4096).unwrap()),
+        Err(super::Error::IncompatibleTypes { .. })
+    ));
+}
+
+#[test]
+fn schema_hash_is_stable() {
+    // if this fails, the layout of the database changed, and databases written by earlier versions will no longer
+    // open. update the value only if that is intended
+    assert_eq!(repr::schema_hash(), 0x9e3cc7fd);
+}
+
+#[test]
+fn roll_up_hourly() {
+    use super::{RollupBucket, Tier};
+    let mut db = DB::new_in_ram(1_000_000).unwrap();
+    db.init();
+    let sid = Uuid::new_v4();
+    db.insert_station(sid).unwrap();
+    let cid = Uuid::new_v4();
+    db.insert_channels(sid, [cid]).unwrap();
+    let day = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    // a reading every minute, from 22:24 the day before (so that the day ends where a chunk does) until 10:00 the
+    // day after, each the number of minutes since the first
+    let first = day - chrono::Duration::minutes(96);
+    for i in 0..96 + 1440 + 600 {
+        db.insert_data(sid, cid, first + chrono::Duration::minutes(i), i as f32)
+            .unwrap();
+    }
+    let next_day = day + chrono::Duration::days(1);
+    let hour = chrono::Duration::hours(1);
+    let reclaimed = db.roll_up(sid, cid, next_day, hour, 2).unwrap();
+    assert_eq!(reclaimed.chunks, 2);
+    assert!(reclaimed.more);
+    let reclaimed = db.roll_up(sid, cid, next_day, hour, 16).unwrap();
+    assert_eq!(reclaimed.chunks, 1);
+    assert_eq!(
+        reclaimed.bytes,
+        std::mem::size_of::<repr::ChannelData>() as u64
+    );
+    assert!(!reclaimed.more);
+    // nothing else is entirely older
+    assert_eq!(db.roll_up(sid, cid, next_day, hour, 16).unwrap().chunks, 0);
+
+    // the readings rolled up are gone, the rest are untouched
+    let mut raw = vec![];
+    db.for_each_entry(sid, cid, |t, v| raw.push((t, v)));
+    assert_eq!(raw.len(), 600);
+    assert_eq!(raw[0], (next_day, 1536.0));
+    // (the mean of minutes `from..to`)
+    let bucket = |time, from: u32, to: u32| RollupBucket {
+        time,
+        mean: (from + to - 1) as f32 / 2.0,
+        count: to - from,
+    };
+    let mut expected = vec![
+        bucket(day - chrono::Duration::hours(2), 0, 36),
+        bucket(day - hour, 36, 96),
+    ];
+    // (hour 15 is split between the two calls, and combined)
+    expected.extend((0..24).map(|h| bucket(day + hour * h as i32, 96 + h * 60, 156 + h * 60)));
+    let before = next_day + chrono::Duration::days(1);
+    assert_eq!(
+        db.query_rollup(sid, cid, hour, first - hour, before, usize::MAX),
+        expected
+    );
+    // the limit applies to the combined buckets (the newest are kept)
+    assert_eq!(
+        db.query_rollup(sid, cid, hour, first - hour, before, 9),
+        expected[expected.len() - 9..]
+    );
+    let query = QueryBuilder::new()
+        .with_station(sid)
+        .with_channel(cid)
+        .with_after(day - chrono::Duration::seconds(1))
+        .with_tier(Tier::Rollup(hour))
+        .verify()
+        .unwrap();
+    assert_eq!(
+        db.query_data(query),
+        expected[2..]
+            .iter()
+            .map(|b| (b.time, b.mean))
+            .collect::<Vec<_>>()
+    );
+    // other bucket lengths are separate tiers
+    assert!(db
+        .query_rollup(sid, cid, hour * 24, first - hour, before, usize::MAX)
+        .is_empty());
+
+    // the tier's channels are hidden, and the freed chunks are reused by new readings
+    assert_eq!(
+        db.get_channels_for(sid).unwrap().collect::<Vec<_>>(),
+        vec![&cid]
+    );
+    assert_eq!(db.iter_channels().count(), 1);
+    assert_eq!(db.stats().channel_count, 1);
+    let used = db.stats().bytes_used;
+    for i in 0..3 * 512 {
+        db.insert_data(sid, cid, before + chrono::Duration::minutes(i), 0.0)
+            .unwrap();
+    }
+    assert_eq!(db.stats().bytes_used, used);
+    let report = db.check_integrity();
+    assert!(report.is_ok(), "{report}");
+}
+
+#[test]
+fn roll_up_compressed() {
+    // quantized readings every 10s, most chunks compress
+    let readings = (0..3000)
+        .map(|i| (i * 10, (i / 7 % 40) as f32 * 0.25))
+        .collect::<Vec<_>>();
+    let (mut db, sid, cid, sorted) = db_with_readings(&readings, true, true);
+    let minute = chrono::Duration::minutes(1);
+    let end = sorted.last().unwrap().0 + minute;
+    let mut reclaimed = super::Reclaimed::default();
+    loop {
+        reclaimed += db.roll_up(sid, cid, end, minute, 2).unwrap();
+        if !reclaimed.more {
+            break;
+        }
+    }
+    // everything but the head
+    assert_eq!(reclaimed.chunks, 5);
+    let mut raw = vec![];
+    db.for_each_entry(sid, cid, |t, v| raw.push((t, v)));
+    assert_eq!(raw, sorted[5 * 512..]);
+    let buckets = db.query_rollup(sid, cid, minute, sorted[0].0 - minute, end, usize::MAX);
+    assert_eq!(buckets.iter().map(|b| b.count).sum::<u32>(), 5 * 512);
+    assert!(buckets.iter().all(|b| b.count <= 6));
+    let report = db.check_integrity();
+    assert!(report.is_ok(), "{report}");
+}
+
+#[test]
+fn delete_before() {
+    use super::Tier;
+    let (mut db, sid, cid, sorted) = db_with_readings(
+        &(0..2100).map(|i| (i * 60, i as f32)).collect::<Vec<_>>(),
+        true,
+        false,
+    );
+    let start = sorted[0].0;
+    // enough events to need a few chunks, all older than the cutoff but the last
+    let events = (0..400)
+        .map(|i| StoredEvent {
+            time: start + chrono::Duration::minutes(i * 3),
+            sub: "strike".to_string(),
+            data: HashMap::from([("distance".to_string(), i as f32)]),
+        })
+        .collect::<Vec<_>>();
+    for ev in &events {
+        db.insert_event(sid, cid, ev.time, &ev.sub, &ev.data)
+            .unwrap();
+    }
+    let cutoff = start + chrono::Duration::minutes(1100);
+    let reclaimed = db.delete_before(sid, cid, Tier::Raw, cutoff, 1).unwrap();
+    assert_eq!(reclaimed.chunks, 1);
+    assert!(reclaimed.more);
+    let mut reclaimed = super::Reclaimed::default();
+    loop {
+        reclaimed += db.delete_before(sid, cid, Tier::Raw, cutoff, 16).unwrap();
+        if !reclaimed.more {
+            break;
+        }
+    }
+    // (the first chunk of readings ends at 511, the second at 1023)
+    let mut raw = vec![];
+    db.for_each_entry(sid, cid, |t, v| raw.push((t, v)));
+    assert_eq!(raw, sorted[1024..]);
+    // whole chunks of events are deleted, so a few older events are left with the rest
+    let query = || QueryBuilder::new().with_station(sid).with_channel(cid);
+    let left = db.query_events(query().with_after(start).verify().unwrap());
+    assert!(left.len() < events.len());
+    assert!(left[0].time < cutoff);
+    assert_eq!(left, events[events.len() - left.len()..]);
+    assert!(reclaimed.chunks > 2);
+
+    // there is no rollup tier to delete from
+    let minute = chrono::Duration::minutes(1);
+    assert_eq!(
+        db.delete_before(sid, cid, Tier::Rollup(minute), cutoff, 16)
+            .unwrap(),
+        super::Reclaimed::default()
+    );
+    // and a tier is deleted from like readings (the chunks with the newest rollups are kept)
+    let end = sorted.last().unwrap().0 + minute;
+    while db.roll_up(sid, cid, end, minute, 16).unwrap().more {}
+    let rolled = db.query_rollup(sid, cid, minute, start, end, usize::MAX);
+    assert_eq!(rolled.len(), 2 * 512);
+    let reclaimed = db
+        .delete_before(sid, cid, Tier::Rollup(minute), end, 16)
+        .unwrap();
+    assert!(reclaimed.chunks > 0);
+    assert!(!reclaimed.more);
+    let left = db.query_rollup(sid, cid, minute, start, end, usize::MAX);
+    assert!(!left.is_empty());
+    assert_eq!(left, rolled[rolled.len() - left.len()..]);
+    let report = db.check_integrity();
+    assert!(report.is_ok(), "{report}");
+}
+
+#[test]
+fn freed_chunk_referenced() {
+    let (mut db, sid, cid, sorted) = db_with_readings(
+        &(0..1100).map(|i| (i, i as f32)).collect::<Vec<_>>(),
+        true,
+        false,
+    );
+    let end = sorted.last().unwrap().0;
+    // keep a link to the oldest chunk, and put it back after it is freed
+    let link = {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let station = access.read(entry.stations.stations[0].ptr);
+        let channel = access.read(station.channels[0].ptr);
+        let newer = access.read(channel.data.next.ptr.cast::<repr::ChannelData>());
+        newer.next
+    };
+    assert_eq!(
+        db.roll_up(sid, cid, end, chrono::Duration::hours(1), 1)
+            .unwrap()
+            .chunks,
+        1
+    );
+    {
+        let mut access = db.store.access(false);
+        let entry = access.entrypoint::<repr::DBEntrypoint>().unwrap();
+        let station = access.read(entry.stations.stations[0].ptr);
+        let channel = access.read(station.channels[0].ptr);
+        let newer = access.read(channel.data.next.ptr.cast::<repr::ChannelData>());
+        newer.next = link;
+    }
+    assert!(matches!(
+        db.check_integrity().problems[..],
+        [IntegrityProblem::PointerToFreed { .. }]
+    ));
+}
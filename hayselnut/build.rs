@@ -17,6 +17,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .expect("`git describe` output not valid utf-8")
             .trim()
     );
+    // (read with `option_env!`, but checked here so that an invalid value fails the build rather than the station)
+    println!("cargo:rerun-if-env-changed=STATION_ALTITUDE");
+    if let Ok(altitude) = std::env::var("STATION_ALTITUDE") {
+        altitude
+            .parse::<f32>()
+            .expect("STATION_ALTITUDE must be a number (meters above sea level)");
+    }
     let now = chrono::Local::now();
     println!("cargo:rustc-env=BUILD_DATETIME_PRETTY={}", now.to_rfc2822());
     println!("cargo:rustc-env=BUILD_DATETIME={}", now.to_rfc3339());
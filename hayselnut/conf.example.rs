@@ -0,0 +1,18 @@
+//! station configuration, copy this to `src/conf.rs` (which is not tracked) and fill it in
+
+/// address of the haysel server (`host:port`)
+pub const SERVER: &str = "example.com:8998";
+
+/// networks the station may connect to, and their passwords
+pub const WIFI_CFG: &[(&str, &str)] = &[("network name", "password")];
+
+/// if networks with no password (that are not in `WIFI_CFG`) may be used
+pub const INCLUDE_OPEN_NETWORKS: bool = false;
+
+/// key that firmware updates must be signed with (ECDSA P-256, PEM encoded and nul terminated).
+/// only needed with the `ota` feature
+#[cfg(feature = "ota")]
+pub const OTA_PUBLIC_KEY: &[u8] = b"-----BEGIN PUBLIC KEY-----
+...
+-----END PUBLIC KEY-----
+\0";
@@ -0,0 +1,76 @@
+//! Waiting between attempts to reach the server
+//!
+//! the wait doubles after every failed attempt (up to a limit), so that a server that is down is not flooded with
+//! retries. half of it is random, so that a fleet of stations that lost the server at the same time does not
+//! reconnect all at once
+
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// failed attempts since the last success
+    failures: u32,
+}
+
+impl Backoff {
+    /// waits start at `base`, and are at most `max`
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            failures: 0,
+        }
+    }
+
+    /// An attempt failed, returns how long to wait before the next one.
+    ///
+    /// `random` is a random number (e.g. from `esp_random`), choosing a wait between half of the limit and the limit
+    pub fn failed(&mut self, random: u32) -> Duration {
+        let limit = self
+            .base
+            .saturating_mul(1 << self.failures.min(31))
+            .min(self.max);
+        self.failures = self.failures.saturating_add(1);
+        let half = limit / 2;
+        half + half.mul_f64(random as f64 / u32::MAX as f64)
+    }
+
+    /// An attempt succeeded, the next failure waits for the shortest time again
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn backoff_sequence() {
+    let secs = Duration::from_secs;
+    let mut backoff = Backoff::new(secs(2), secs(60));
+    // the longest possible waits double, until the limit
+    let longest = (0..8).map(|_| backoff.failed(u32::MAX)).collect::<Vec<_>>();
+    assert_eq!(longest, [2, 4, 8, 16, 32, 60, 60, 60].map(secs));
+    backoff.succeeded();
+    // the shortest possible waits are half as long
+    let shortest = (0..7).map(|_| backoff.failed(0)).collect::<Vec<_>>();
+    assert_eq!(shortest, [1, 2, 4, 8, 16, 30, 30].map(secs));
+
+    // and start over after a success
+    backoff.succeeded();
+    assert_eq!(backoff.failed(0), secs(1));
+
+    // waits are always within the bounds
+    let mut backoff = Backoff::new(secs(2), secs(60));
+    let mut random = 12345u32;
+    for attempt in 0..100u32 {
+        random = random.wrapping_mul(1664525).wrapping_add(1013904223);
+        let limit = secs(2 << attempt.min(5)).min(secs(60));
+        let wait = backoff.failed(random);
+        assert!(wait >= limit / 2 && wait <= limit, "{attempt}: {wait:?}");
+    }
+    // (does not overflow after many failures)
+    for _ in 0..1000 {
+        assert!(backoff.failed(u32::MAX) <= secs(60));
+    }
+}
@@ -0,0 +1,62 @@
+//! Choosing which of the server's addresses to use
+
+use std::net::SocketAddr;
+
+/// Cycles through the addresses the server resolved to (round-robin DNS, A + AAAA, etc), moving on to
+/// the next one whenever communication with the current one times out.
+///
+/// the addresses are passed in on every call (rather than stored) because DNS is re-done every time
+/// the server is retried
+#[derive(Debug, Default)]
+pub struct ServerCandidates {
+    failures: usize,
+}
+
+impl ServerCandidates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the address to try next, or None if there are no addresses
+    pub fn current(&self, ips: &[SocketAddr]) -> Option<SocketAddr> {
+        if ips.is_empty() {
+            None
+        } else {
+            Some(ips[self.failures % ips.len()])
+        }
+    }
+
+    /// the current address did not respond, move on to the next
+    pub fn failed(&mut self) {
+        self.failures = self.failures.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn tries_each_candidate() {
+    let ips: Vec<SocketAddr> = vec![
+        "10.0.0.1:8998".parse().unwrap(),
+        "[fd00::1]:8998".parse().unwrap(),
+        "10.0.0.2:8998".parse().unwrap(),
+    ];
+    let reachable = ips[2];
+    let mut candidates = ServerCandidates::new();
+    assert_eq!(candidates.current(&[]), None);
+    let mut tried = vec![];
+    let connected = loop {
+        let ip = candidates.current(&ips).unwrap();
+        tried.push(ip);
+        if ip == reachable {
+            break ip;
+        }
+        candidates.failed();
+    };
+    assert_eq!(connected, reachable);
+    assert_eq!(tried, ips);
+    // sticks with the address that worked
+    assert_eq!(candidates.current(&ips), Some(reachable));
+    // and wraps around if it stops responding
+    candidates.failed();
+    assert_eq!(candidates.current(&ips), Some(ips[0]));
+}
@@ -0,0 +1,219 @@
+//! Local log of readings, kept in flash (the `storage` SPIFFS partition) so that a station can be checked without
+//! the server, e.g. when it seems to work but the server receives nothing.
+//!
+//! one row is written per channel per reading (`time,seq,channel,value`). the log is split across two files, once
+//! the current one would grow past half of the limit it replaces the previous one, so the newest readings are kept
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::CStr,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use esp_idf_sys::{esp, esp_vfs_spiffs_conf_t, esp_vfs_spiffs_register, EspError};
+use squirrel::api::station::capabilities::{ChannelData, ChannelID};
+
+/// where the partition is mounted
+pub const MOUNT_POINT: &str = "/log";
+const MOUNT_POINT_C: &CStr = c"/log";
+const PARTITION: &CStr = c"storage";
+/// the most space the log may use (both files), leaving some of the partition free for SPIFFS
+pub const MAX_BYTES: u64 = 96 * 1024;
+
+const HEADER: &str = "time,seq,channel,value\n";
+
+/// mount the log partition at [`MOUNT_POINT`], formatting it if it has not been used yet
+pub fn mount() -> Result<(), EspError> {
+    let conf = esp_vfs_spiffs_conf_t {
+        base_path: MOUNT_POINT_C.as_ptr(),
+        partition_label: PARTITION.as_ptr(),
+        max_files: 2,
+        format_if_mount_failed: true,
+    };
+    esp!(unsafe { esp_vfs_spiffs_register(&conf) })
+}
+
+pub struct CsvLog {
+    path: PathBuf,
+    /// the previous file (replaced on rotation)
+    old_path: PathBuf,
+    max_bytes: u64,
+}
+
+impl CsvLog {
+    /// log to `path` (and `<path>.old`), using at most about `max_bytes`
+    pub fn new(path: PathBuf, max_bytes: u64) -> Self {
+        let mut old_path = path.clone().into_os_string();
+        old_path.push(".old");
+        Self {
+            path,
+            old_path: old_path.into(),
+            max_bytes,
+        }
+    }
+
+    /// append `rows` (from [`format_rows`]), rotating first if the current file would grow too large
+    pub fn append(&mut self, rows: &str) -> io::Result<()> {
+        let size = fs::metadata(&self.path).map_or(0, |m| m.len());
+        let size = if needs_rotation(size, rows.len() as u64, self.max_bytes) {
+            // (SPIFFS can not rename over an existing file)
+            if let Err(e) = fs::remove_file(&self.old_path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+            fs::rename(&self.path, &self.old_path)?;
+            0
+        } else {
+            size
+        };
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if size == 0 {
+            file.write_all(HEADER.as_bytes())?;
+        }
+        file.write_all(rows.as_bytes())
+    }
+
+    /// write the whole log (oldest first) to `out`
+    pub fn dump(&self, out: &mut impl Write) -> io::Result<()> {
+        for path in [&self.old_path, &self.path] {
+            match fs::File::open(path) {
+                Ok(mut file) => {
+                    io::copy(&mut file, out)?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// if a file of `size` bytes must be rotated before `len` more bytes are written, to keep both files within `max`
+fn needs_rotation(size: u64, len: u64, max: u64) -> bool {
+    // (a row larger than the limit is still written, to an empty file)
+    size > 0 && size + len > max / 2
+}
+
+/// Rows for one reading. `time` is a unix timestamp (left empty if the clock is not synced), and channels are
+/// named with `name_of` (in order of name)
+pub fn format_rows(
+    time: Option<i64>,
+    seq: u64,
+    readings: &HashMap<ChannelID, ChannelData>,
+    name_of: impl Fn(&ChannelID) -> String,
+) -> String {
+    let time = time.map(|t| t.to_string()).unwrap_or_default();
+    let mut rows = readings
+        .iter()
+        .map(|(id, data)| (name_of(id), format_value(data)))
+        .collect::<Vec<_>>();
+    rows.sort();
+    rows.into_iter()
+        .map(|(name, value)| format!("{time},{seq},{},{}\n", field(&name), field(&value)))
+        .collect()
+}
+
+/// floats as-is, events as `sub` followed by their data (`sub;key=value;...`, in order of key)
+fn format_value(data: &ChannelData) -> String {
+    match data {
+        ChannelData::Float(v) => v.to_string(),
+        ChannelData::Event { sub, data } => {
+            let mut data = data.iter().collect::<Vec<_>>();
+            data.sort_by(|a, b| a.0.cmp(b.0));
+            data.into_iter()
+                .fold(sub.clone(), |acc, (k, v)| format!("{acc};{k}={v}"))
+        }
+    }
+}
+
+/// quotes `s` if it contains anything that would break the row
+fn field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn row_formatting() {
+    let (temp, strike, odd) = (
+        ChannelID::new_v4(),
+        ChannelID::new_v4(),
+        ChannelID::new_v4(),
+    );
+    let readings = HashMap::from([
+        (temp, ChannelData::Float(21.5)),
+        (
+            strike,
+            ChannelData::Event {
+                sub: "strike".into(),
+                data: HashMap::from([("energy".into(), 3.0), ("distance".into(), 12.0)]),
+            },
+        ),
+        (odd, ChannelData::Float(-1.0)),
+    ]);
+    let names = HashMap::from([
+        (temp, "temperature"),
+        (strike, "lightning"),
+        (odd, "a \"quoted\", name"),
+    ]);
+    let name_of = |id: &ChannelID| names[id].to_string();
+    assert_eq!(
+        format_rows(Some(1_711_281_600), 42, &readings, name_of),
+        "1711281600,42,\"a \"\"quoted\"\", name\",-1\n\
+         1711281600,42,lightning,strike;distance=12;energy=3\n\
+         1711281600,42,temperature,21.5\n"
+    );
+    // no timestamp
+    assert_eq!(
+        format_rows(
+            None,
+            7,
+            &HashMap::from([(temp, ChannelData::Float(0.25))]),
+            name_of
+        ),
+        ",7,temperature,0.25\n"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn size_bounded_rotation() {
+    // rotation is decided up front, so that no file is ever written past half the limit
+    assert!(!needs_rotation(0, 10, 100));
+    assert!(!needs_rotation(40, 10, 100));
+    assert!(needs_rotation(41, 10, 100));
+    // an oversized row still goes to an (empty) file
+    assert!(!needs_rotation(0, 500, 100));
+
+    let dir = std::env::temp_dir().join(format!("hayselnut-test-{}", uuid::Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+    let mut log = CsvLog::new(dir.join("readings.csv"), 256);
+    let row = |i: u64| format!("{},{i},temperature,20\n", 1_711_281_600 + i);
+    for i in 0..50 {
+        log.append(&row(i)).unwrap();
+        let size = |p| fs::metadata(p).map_or(0, |m| m.len());
+        assert!(size(&log.path) <= 128 && size(&log.old_path) <= 128);
+    }
+    let mut dumped = vec![];
+    log.dump(&mut dumped).unwrap();
+    let dumped = String::from_utf8(dumped).unwrap();
+    // the newest rows are kept, in order
+    let kept = dumped
+        .lines()
+        .filter(|l| *l != HEADER.trim_end())
+        .collect::<Vec<_>>();
+    assert!(kept.len() >= 2);
+    assert_eq!(kept.last(), Some(&row(49).trim_end()));
+    assert!(kept.windows(2).all(|w| w[0] < w[1]));
+    fs::remove_dir_all(&dir).unwrap();
+}
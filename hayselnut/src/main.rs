@@ -4,22 +4,36 @@
 #[macro_use]
 extern crate log;
 
+pub mod backoff;
+pub mod candidates;
 pub mod conf;
+#[cfg(feature = "csv-log")]
+pub mod csvlog;
 pub mod error;
 pub mod flag;
 pub mod lightning;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "ota")]
+pub mod ota;
 pub mod periph;
+pub mod selftest;
+pub mod seq;
+pub mod serialcmd;
+pub mod sleep;
 pub mod store;
+pub mod timesync;
 pub mod wifictl;
 
 use std::{
-    cell::SyncUnsafeCell,
+    cell::RefCell,
     collections::HashMap,
-    io,
+    net::Ipv4Addr,
     str::FromStr,
     time::{Duration, Instant},
 };
 
+use embedded_hal_bus::i2c::RefCellDevice;
 use embedded_svc::wifi;
 use esp_idf_hal::{
     adc::{self, AdcDriver},
@@ -35,7 +49,7 @@ use esp_idf_svc::{
     timer::EspTaskTimerService,
     wifi::{AsyncWifi, EspWifi},
 };
-use esp_idf_sys::{self as _, esp_app_desc, esp_deep_sleep_start, esp_sleep_disable_wakeup_source}; // allways should be imported if `binstart` feature is enabled.
+use esp_idf_sys::{self as _, esp_app_desc, EspError}; // allways should be imported if `binstart` feature is enabled.
 use futures::{select_biased, FutureExt};
 use serde::{Deserialize, Serialize};
 use tokio::{
@@ -48,28 +62,60 @@ use squirrel::{
         station::capabilities::{
             Channel, ChannelData, ChannelID, ChannelName, ChannelType, ChannelValue,
         },
-        PacketKind, SomeData,
+        Compression, Diagnostics, PacketKind, SomeData,
     },
     transport::{
-        client::{mvp_recv, mvp_send},
-        shared::SendError,
-        UidGenerator,
+        client::{mvp_recv, mvp_send, Keepalive},
+        read_packet,
+        shared::{SendError, TransportStats},
+        UidGenerator, UDP_MAX_SIZE_NEGOTIATED,
     },
 };
 
-use store::{StationStore, StationStoreCached};
+use backoff::Backoff;
+use candidates::ServerCandidates;
+use selftest::SelfTest;
+use serialcmd::{Command, SerialCommands};
+use store::{SeqStore, StationStore, StationStoreCached};
 
 use crate::{
     error::{ErrExt as _, _panic_hwerr},
-    periph::{battery::BatteryMonitor, bme280::PeriphBME280, Peripheral, SensorPeripheral},
+    periph::{
+        battery::BatteryMonitor,
+        bme280::{Bme280Settings, PeriphBME280},
+        display::{self, StatusDisplay},
+        rain::{PeriphRain, RainCalibration},
+        wind::{PeriphWind, WindCalibration},
+        wind_direction::{PeriphWindDirection, WindVaneCalibration},
+        PeriphHealth, Peripheral, SensorPeripheral,
+    },
 };
 
 const NO_WIFI_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// time to wait before retrying if the server's address could not be found
+const NO_SERVER_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// shortest and longest wait before retrying after the server did not respond (see `backoff`)
+const SERVER_BACKOFF_MIN: Duration = Duration::from_secs(2);
+const SERVER_BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+/// time between pings to the server while waiting for the next reading
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+/// number of pings in a row the server may miss before reconnecting
+const KEEPALIVE_MAX_MISSED: u32 = 3;
+/// number of readings sent between sending transport diagnostics to the server
+const DIAGNOSTICS_EVERY: u32 = 10;
 /// metadata on the build (passed using `build.rs`)
 mod build {
     pub const GIT_REV: &str = env!("BUILD_GIT_REV");
     pub const DATETIME_PRETTY: &str = env!("BUILD_DATETIME_PRETTY");
     pub const DATETIME: &str = env!("BUILD_DATETIME");
+    /// one-time token to present to servers that only accept known stations (set when building)
+    pub const PROVISIONING_TOKEN: Option<&str> = option_env!("PROVISIONING_TOKEN");
+    /// altitude of the station in meters (set when building, and checked by `build.rs`)
+    pub const STATION_ALTITUDE: Option<&str> = option_env!("STATION_ALTITUDE");
+
+    pub fn station_altitude() -> Option<f32> {
+        STATION_ALTITUDE.map(|altitude| altitude.parse().unwrap())
+    }
 }
 
 esp_app_desc!();
@@ -132,12 +178,14 @@ fn main() {
         peripherals.i2c0,
         pins.gpio1, //sda
         pins.gpio3, //scl
-        &i2c::config::Config::new().baudrate(100.kHz().into()),
+        // timeout so that a disconnected sensor fails (and is caught by the self test) instead of hanging
+        &i2c::config::Config::new()
+            .baudrate(100.kHz().into())
+            .timeout(Duration::from_millis(100).into()),
     )
     .unwrap_hwerr("failed to initialize battery monitor");
-    //let i2c_bus = shared_bus::new_std!(I2cDriver = i2c_driver)
-    //    .expect("[sanity check] can only create one shared bus instance");
-    let i2c_bus = i2c_driver;
+    // each user of the bus gets a `RefCellDevice`
+    let i2c_bus = RefCell::new(i2c_driver);
 
     // -- initializing peripherals --
     // lightning
@@ -189,71 +237,72 @@ fn main() {
     //     (sensor, setup_interrupt)
     // };
 
-    // wind{speed,direction}, rainfall quantity
-    // {
-    //     let speed = pins.gpio6;
-    //     let direction = pins.gpio4;
-    //     let rainfall = pins.gpio7;
-    //
-    //     let speed_flag = unsafe {
-    //         let flag = Flag::new();
-    //         let flag2 = flag.clone();
-    //         let driver = Box::leak(Box::new(PinDriver::input(speed).unwrap()));
-    //         driver.set_pull(esp_idf_hal::gpio::Pull::Down).unwrap();
-    //         driver
-    //             .set_interrupt_type(esp_idf_hal::gpio::InterruptType::PosEdge)
-    //             .unwrap();
-    //         driver
-    //             .subscribe(move || flag2.signal())
-    //             .unwrap_hwerr("failed to set interrupt");
-    //         driver.enable_interrupt().unwrap();
-    //         flag
-    //     };
-    //
-    //     let mut direction_reader = {
-    //         let mut driver = AdcChannelDriver::<'_, _, adc::Atten11dB<_>>::new(direction)
-    //             .unwrap_hwerr("failed to init adc channel");
-    //         move |adc1: &mut AdcDriver<'_, adc::ADC1>| {
-    //             adc1.read(&mut driver).unwrap_hwerr("failed to read adc")
-    //         }
-    //     };
-    //
-    //     let rainfall_flag = unsafe {
-    //         let flag = Flag::new();
-    //         let flag2 = flag.clone();
-    //         let driver = Box::leak(Box::new(PinDriver::input(rainfall).unwrap()));
-    //         driver.set_pull(esp_idf_hal::gpio::Pull::Down).unwrap();
-    //         driver
-    //             .set_interrupt_type(esp_idf_hal::gpio::InterruptType::PosEdge)
-    //             .unwrap();
-    //         driver
-    //             .subscribe(move || flag2.signal())
-    //             .unwrap_hwerr("failed to set interrupt");
-    //         driver.enable_interrupt().unwrap();
-    //         flag
-    //     };
-    //
-    //     wifictl::util::fix_networking().unwrap();
-    //     tokio::runtime::Builder::new_current_thread()
-    //         .enable_all()
-    //         .build()
-    //         .unwrap()
-    //         .block_on(async {
-    //             println!("running");
-    //             loop {
-    //                 println!("{}", direction_reader(&mut adc1));
-    //                 sleep(Duration::from_millis(100)).await;
-    //                 // rainfall_flag.clone().await;
-    //                 // rainfall_flag.reset();
-    //                 // println!("tick");
-    //             }
-    //         });
-    // }
-    //
+    // wind speed, rainfall quantity
+    let mut wind = PeriphWind::new(pins.gpio6, WindCalibration::default())
+        .unwrap_hwerr("failed to initialize anemometer");
+    let mut rain = PeriphRain::new(pins.gpio7, RainCalibration::default())
+        .unwrap_hwerr("failed to initialize rain gauge");
+
+    // wind direction
+    let mut wind_dir = PeriphWindDirection::new(pins.gpio4, WindVaneCalibration::default())
+        .unwrap_hwerr("failed to initialize wind vane");
+
     // temp/humidity/pressure
-    // if this call ever fails (no error, just waiting forever) check the connection with the sensor
-    warn!("connecting to BME sensor - if it is disconnected this will hang here");
-    let mut bme280 = PeriphBME280::new(i2c_bus);
+    info!("connecting to BME sensor");
+    let mut bme280 = PeriphBME280::new(
+        RefCellDevice::new(&i2c_bus),
+        RefCellDevice::new(&i2c_bus),
+        Bme280Settings::default(),
+    );
+    // sea-level pressure can only be derived if the station's altitude is known
+    if let Some(altitude) = build::station_altitude() {
+        bme280 = bme280.with_altitude(altitude);
+    }
+
+    // status display
+    #[cfg(feature = "display")]
+    let mut display = StatusDisplay::new(
+        match display::init_ssd1306(RefCellDevice::new(&i2c_bus)) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                warn!("failed to initialize status display ({e:?}), continuing without it");
+                None
+            }
+        },
+    );
+    #[cfg(not(feature = "display"))]
+    let mut display = StatusDisplay::new(display::NoDisplay);
+
+    // -- self test --
+    // probes each sensor, failed sensors have their channels reported as degraded rather than stopping the station
+    let selftest = {
+        let mut test = SelfTest::new();
+        let names = |channels: Vec<Channel>| -> Vec<ChannelName> {
+            channels.into_iter().map(|ch| ch.name).collect()
+        };
+        // initialization checks the chip id
+        test.probe(
+            "BME280",
+            names(bme280.channels()),
+            match bme280.health() {
+                PeriphHealth::Ok => Ok(()),
+                PeriphHealth::Degraded(e) | PeriphHealth::Failed(e) => Err(e),
+            },
+        );
+        test.probe(
+            "battery ADC",
+            vec!["battery".into()],
+            batt_mon
+                .read(&mut adc1)
+                .map(|_| ())
+                .map_err(|e| format!("{e:?}")),
+        );
+        // the lightning sensor should be probed here (reading a register) once it is in use again
+        if !test.passed() {
+            warn!("self test failed, running in degraded mode");
+        }
+        test
+    };
 
     // see [fix_networking] docs -- if not present UdpSocket::bind fails
     // - also needed for tokio
@@ -291,9 +340,31 @@ fn main() {
                 StationStoreCached::init(nvs_partition.clone()).unwrap_hwerr("error accessing NVS"),
             );
             info!("Loaded station info: {:#?}", store.read());
+            let mut seq_store = SeqStore::new(nvs_partition.clone()).unwrap_hwerr("error accessing NVS");
+            let mut seq = seq::SeqCounter::resume(
+                seq_store.load().unwrap_hwerr("error reading data sequence number from NVS"),
+            );
+
+            // local log of readings (if the partition can not be used, the station still works without it)
+            #[cfg(feature = "csv-log")]
+            let mut csv_log = match csvlog::mount() {
+                Ok(()) => Some(csvlog::CsvLog::new(
+                    std::path::Path::new(csvlog::MOUNT_POINT).join("readings.csv"),
+                    csvlog::MAX_BYTES,
+                )),
+                Err(e) => {
+                    error!("failed to mount the log partition ({e:?}), readings will not be logged locally");
+                    None
+                }
+            };
 
             // -- here is code that needs to go before the error-retry loops --
 
+            // syncs once wifi is connected
+            let timesync = timesync::TimeSync::start().unwrap_hwerr("failed to start SNTP");
+            // commands from the console (only with the `serial_cmd` feature)
+            let serial = SerialCommands::start();
+
             println!();
             // not an error, just make the message stand out
             error!("----     ----    main loop starting    ----    ----\n");
@@ -304,11 +375,16 @@ fn main() {
 
             // -- init some persistant information for use later --
             let mut uid_gen = UidGenerator::new();
+            // how well the connection to the server is working, sent every `DIAGNOSTICS_EVERY` readings
+            let mut transport_stats = TransportStats::default();
+            let mut readings_since_diagnostics = 0u32;
             let mut channels = vec![
                 Channel {
                     name: "battery".into(),
                     value: ChannelValue::Float,
                     ty: ChannelType::Periodic,
+                    unit: Some("V".into()),
+                    description: Some("battery voltage".into()),
                 },
                 Channel {
                     name: "lightning".into(),
@@ -320,29 +396,65 @@ fn main() {
                         ("lightning".into(), vec!["distance".into()]),
                     ])),
                     ty: ChannelType::Triggered,
+                    unit: None,
+                    description: Some("lightning detector events".into()),
                 },
             ];
             // add channels from sensors
             channels.extend_from_slice(&bme280.channels());
+            channels.extend_from_slice(&wind.channels());
+            channels.extend_from_slice(&wind_dir.channels());
+            channels.extend_from_slice(&rain.channels());
             // setup timers for when to measure things
             // todo: not hardcode
-            let config = MeasureConfig::default();
+            let mut config = MeasureConfig::default();
             let mut timers = MeasureTimers::with_config(&config);
 
             // if this call fails, (or any other socket binds) try messing with the number in `wifictl::util::fix_networking`
-            let sock = UdpSocket::bind("0.0.0.0:0")
+            // re-bound (as IPv6) if the server turns out to only have a IPv6 address
+            let mut sock = UdpSocket::bind("0.0.0.0:0")
                 .await
                 .unwrap_hwerr("call to UdpSocket bind failed [unkwnown cause]");
 
             'retry_wifi: loop {
-                connect_wifi(&mut wifi).await;
+                display.set_lines(&["connecting wifi"]);
+                match connect_wifi(&mut wifi).await {
+                    Ok((ssid, ip)) => {
+                        display.show_connected(&ssid, ip);
+                        // readings are sent without a timestamp if this times out
+                        timesync.wait(Duration::from_secs(15)).await;
+                    }
+                    Err(e) => {
+                        error!("could not connect to wifi ({e:?}), retrying in {NO_WIFI_RETRY_INTERVAL:?}");
+                        display.show_error("could not connect to wifi");
+                        tokio::time::sleep(NO_WIFI_RETRY_INTERVAL).await;
+                        continue 'retry_wifi;
+                    }
+                }
 
+                // which of the server's addresses to use (only matters if it resolves to more than one)
+                let mut candidates = ServerCandidates::new();
+                // how long to wait after the server does not respond (reset once it does)
+                let mut backoff = Backoff::new(SERVER_BACKOFF_MIN, SERVER_BACKOFF_MAX);
                 'retry_server: loop {
                     // re-do DNS in here (not the wifi loop) just in case it changing causing this
-                    let ips = resolve(conf::SERVER)
+                    #[allow(unused_mut)]
+                    let mut ips = resolve(conf::SERVER)
                         .await
                         .unwrap_hwerr("call to DNS resolve failed [unknown cause]")
                         .collect::<Vec<_>>();
+                    #[cfg(feature = "mdns")]
+                    if ips.is_empty() {
+                        warn!("DNS lookup for {:?} returned no results, trying mDNS ({})", conf::SERVER, mdns::SERVICE);
+                        match mdns::discover(mdns::SERVICE, Duration::from_secs(3)).await {
+                            Ok(Some(addr)) => {
+                                info!("found server over mDNS at {addr:?}");
+                                ips.push(addr);
+                            }
+                            Ok(None) => warn!("no response to mDNS query"),
+                            Err(e) => warn!("mDNS query failed: {e:?}"),
+                        }
+                    }
                     if ips.len() == 0 {
                         error!("failed to resolve server address (DNS lookup for {:?} returned no IP results)", conf::SERVER);
                         if !wifi
@@ -352,15 +464,22 @@ fn main() {
                             warn!("[cause of error]: wifi was not connected");
                             continue 'retry_wifi;
                         }
+                        tokio::time::sleep(NO_SERVER_RETRY_INTERVAL).await;
+                        continue 'retry_server;
                     } else if ips.len() > 1 {
-                        _panic_hwerr(
-                            error::EmptyError,
-                            "Faild to respolve server address -- multiple results ({ips:?})",
-                        );
+                        debug!("server address resolved to multiple results ({ips:?})");
+                    }
+                    let ip = candidates.current(&ips).unwrap();
+                    if sock.local_addr().unwrap_hwerr("socket.local_addr failed [unknown cause]").is_ipv4() != ip.is_ipv4() {
+                        let bind_addr = if ip.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                        debug!("server address {ip:?} is a different address family than the socket, re-binding to {bind_addr}");
+                        sock = UdpSocket::bind(bind_addr)
+                            .await
+                            .unwrap_hwerr("call to UdpSocket bind failed [unkwnown cause]");
                     }
 
                     // works even if wifi is not connected. only operations that actually use the network will break.
-                    sock.connect(ips[0])
+                    sock.connect(ip)
                         .await
                         .unwrap_hwerr("call to sock.connect failed [unknown cause]");
                     info!(
@@ -375,46 +494,60 @@ fn main() {
                         ($res:expr) => {
                             match $res {
                                 Ok(v) => v,
-                                Err(SendError::IOError(e)) if e.kind() == io::ErrorKind::HostUnreachable => {
-                                    error!("I/O Error: host unreachable (the network is down)");
+                                Err(SendError::IOError(e)) => {
+                                    error!("I/O Error: {e:?} (the network is likely down)");
                                     error!("attempting to reconnect WIFI");
                                     continue 'retry_wifi;
                                 }
-                                Err(e @ SendError::IOError(..)) => {
+                                Err(e @ SendError::Fatal(..)) => {
                                     _panic_hwerr(e, "I/O Error went unhandled (not known to be caused by a fixable problem)");
                                 },
                                 Err(SendError::TimedOut) => {
+                                    let wait = backoff.failed(unsafe { esp_idf_sys::esp_random() });
                                     error!("initial communication with the server failed (connection timed out -- is it running?)");
-                                    error!("trying to connect with the server [again]");
+                                    error!("trying to connect with the server [again] in {wait:?} (at its next address, if it has multiple)");
+                                    candidates.failed();
+                                    tokio::time::sleep(wait).await;
                                     continue 'retry_server;
                                 }
                             }
                         };
                     }
 
+                    // chosen by the server once connected (packets before that are never compressed)
+                    let mut compression = Compression::None;
+
                     macro_rules! send {
-                        ($packet:expr) => {
+                        ($packet:expr) => {{
                             handle_netres!(
                                 mvp_send(
                                     &sock,
-                                    &rmp_serde::to_vec_named(&$packet)
-                                        .unwrap_hwerr("failed to serialize data to send"),
+                                    &compression.compress(
+                                        &rmp_serde::to_vec_named(&$packet)
+                                            .unwrap_hwerr("failed to serialize data to send"),
+                                    ),
                                     &mut uid_gen,
+                                    &mut transport_stats,
                                 )
                                 .await
-                            )
-                        };
+                            );
+                            backoff.succeeded();
+                        }};
                     }
 
                     macro_rules! recv {
                         ($kind:path) => {
                             match rmp_serde::from_slice(&loop {
-                                match handle_netres!(mvp_recv(&sock, &mut uid_gen).await) {
-                                    Some(packet) => break packet,
+                                match handle_netres!(mvp_recv(&sock, &mut uid_gen, &mut transport_stats).await) {
+                                    Some(packet) => {
+                                        backoff.succeeded();
+                                        break packet;
+                                    }
                                     None => {
-                                        warn!("receive timed out (got empty response, retrying in 5s)");
+                                        let wait = backoff.failed(unsafe { esp_idf_sys::esp_random() });
+                                        warn!("receive timed out (got empty response, retrying in {wait:?})");
                                         //TODO: have some sort of failure mode that does not loop forever
-                                        tokio::time::sleep(Duration::from_secs(5)).await;
+                                        tokio::time::sleep(wait).await;
                                     }
                                 }
                             }) {
@@ -445,11 +578,30 @@ fn main() {
                         station_build_rev: build::GIT_REV.to_string(),
                         station_build_date: build::DATETIME.to_string(),
                         channels: channels.clone(),
+                        degraded_channels: selftest.degraded_channels(),
+                        provisioning_token: build::PROVISIONING_TOKEN.map(str::to_string),
+                        compression: Compression::SUPPORTED.to_vec(),
                     }));
                     info!("server is up");
+                    display.set_line(3, "server up");
                     info!("requesting channel mappings");
                     let mappings = recv!(PacketKind::ChannelMappings);
                     info!("received channel mappings: {mappings:#?}");
+                    if !mappings.conflicts.is_empty() {
+                        // (the server already knows channels by these names, with a different value or type)
+                        error!("server refused to map channels {:?}", mappings.conflicts);
+                    }
+                    compression = mappings.compression;
+                    // this image works well enough to reach the server, so keep it (if it is a new one from an update)
+                    #[cfg(feature = "ota")]
+                    if let Err(e) = ota::mark_valid() {
+                        error!("failed to mark the running firmware as valid: {e:?}");
+                    }
+
+                    let mut keepalive = Keepalive::new();
+                    let mut keepalive_timer = interval(KEEPALIVE_INTERVAL);
+                    // (the first tick is immediate)
+                    keepalive_timer.reset();
 
                     loop {
                         select_biased! {
@@ -491,9 +643,19 @@ fn main() {
                             //         }
                             //     }))
                             // }
+                            _ = wind.pulse().fuse() => wind.rearm(),
+                            _ = rain.pulse().fuse() => rain.rearm(),
                             _ = timers.read_timer.tick().fuse() => {
                                 info!("reading sensors and sending");
+                                display.set_line(4, "reading");
                                 let map_fn = |id: &str| *mappings.map.get(&ChannelName::from(id)).expect("could not find mapping for id {id:?}");
+                                #[cfg(feature = "deep-sleep")]
+                                {
+                                    bme280.wake();
+                                    wind.wake();
+                                    wind_dir.wake();
+                                    rain.wake();
+                                }
                                 let bme_readings = match bme280.read(&map_fn) {
                                     Some(v) => v,
                                     None => {
@@ -503,19 +665,171 @@ fn main() {
                                     }
                                 };
 
+                                let wind_readings = match wind.read(&map_fn) {
+                                    Some(v) => v,
+                                    None => {
+                                        warn!("anemometer peripheral error: {:?}, fixing...", wind.err());
+                                        wind.fix();
+                                        Default::default()
+                                    }
+                                };
+                                wind_dir.sample(&mut adc1);
+                                let wind_dir_readings = match wind_dir.read(&map_fn) {
+                                    Some(v) => v,
+                                    None => {
+                                        warn!("wind vane peripheral error: {:?}, fixing...", wind_dir.err());
+                                        wind_dir.fix();
+                                        Default::default()
+                                    }
+                                };
+                                let rain_readings = match rain.read(&map_fn) {
+                                    Some(v) => v,
+                                    None => {
+                                        warn!("rain gauge peripheral error: {:?}, fixing...", rain.err());
+                                        rain.fix();
+                                        Default::default()
+                                    }
+                                };
+
                                 let battery_voltage = std::iter::repeat_with(|| batt_mon.read(&mut adc1).unwrap_hwerr("failed to read battery voltage"))
                                     .take(50)
                                     .sum::<f32>() / 50.0;
 
-                                send!(PacketKind::Data(SomeData {
+                                let (seq_num, reserve) = seq.next();
+                                if let Some(reserved) = reserve {
+                                    seq_store.store(reserved).unwrap_hwerr("error storing data sequence number in NVS");
+                                }
+                                let data = SomeData {
                                     per_channel: {
                                         let mut map = HashMap::<ChannelID, ChannelData>::new();
                                         let mut set = |id, val| mappings.map.get(&ChannelName::from(id)).map(|uuid| map.insert(*uuid, val));
                                         set("battery", ChannelData::Float(battery_voltage));
                                         bme_readings.into_iter().for_each(|(k, v)| { map.insert(k, v); });
+                                        map.extend(wind_readings);
+                                        map.extend(wind_dir_readings);
+                                        map.extend(rain_readings);
                                         map
+                                    },
+                                    recorded_at: timesync::now_if_synced(),
+                                    seq: seq_num,
+                                };
+                                #[cfg(feature = "csv-log")]
+                                if let Some(csv_log) = &mut csv_log {
+                                    let rows = csvlog::format_rows(data.recorded_at, data.seq, &data.per_channel, |id| {
+                                        mappings.map.iter().find(|(_, v)| *v == id).map_or_else(|| id.to_string(), |(name, _)| name.as_ref().clone())
+                                    });
+                                    if let Err(e) = csv_log.append(&rows) {
+                                        warn!("failed to log readings locally: {e}");
+                                    }
+                                }
+                                send!(PacketKind::Data(data));
+                                display.set_line(4, "sent reading");
+
+                                readings_since_diagnostics += 1;
+                                if readings_since_diagnostics >= DIAGNOSTICS_EVERY {
+                                    readings_since_diagnostics = 0;
+                                    let transport = std::mem::take(&mut transport_stats);
+                                    debug!("sending transport diagnostics: {transport:?}");
+                                    send!(PacketKind::Diagnostics(Diagnostics { transport }));
+                                }
+
+                                // check for anything the server has queued for us
+                                if let Some(packet) = handle_netres!(mvp_recv(&sock, &mut uid_gen, &mut transport_stats).await) {
+                                    match rmp_serde::from_slice(&packet) {
+                                        #[cfg(feature = "ota")]
+                                        Ok(PacketKind::BeginOTA(begin)) => {
+                                            display.set_lines(&["updating firmware"]);
+                                            match ota::update(&begin) {
+                                                Ok(never) => match never {},
+                                                Err(e) => {
+                                                    error!("firmware update failed: {e}");
+                                                    display.show_error("firmware update failed");
+                                                }
+                                            }
+                                        }
+                                        #[cfg(not(feature = "ota"))]
+                                        Ok(PacketKind::BeginOTA(..)) => warn!("the server sent a firmware update, but this firmware was built without the `ota` feature"),
+                                        Ok(other) => warn!("received unexpected packet from the server: {other:?}"),
+                                        Err(e) => warn!("failed to deserialize packet from the server: {e:?}"),
                                     }
-                                }));
+                                }
+
+                                #[cfg(feature = "deep-sleep")]
+                                {
+                                    info!("sleeping until the next reading");
+                                    bme280.sleep();
+                                    wind.sleep();
+                                    wind_dir.sleep();
+                                    rain.sleep();
+                                    sleep::sleep_for(config.read_interval);
+                                }
+                            }
+                            // between transactions, the server only sends pings and pongs
+                            res = async {
+                                let mut buf = [0u8; UDP_MAX_SIZE_NEGOTIATED];
+                                sock.recv(&mut buf).await.map(|n| read_packet(&buf[..n]))
+                            }.fuse() => {
+                                let packet = handle_netres!(res.map_err(SendError::from));
+                                if let Some(reply) = packet.and_then(|p| keepalive.handle(p, &mut uid_gen)) {
+                                    handle_netres!(sock.send(reply.as_bytes()).await.map_err(SendError::from));
+                                }
+                            }
+                            _ = serial.wait().fuse() => {
+                                for cmd in serial.take() {
+                                    match cmd {
+                                        Err(e) => println!("{e}"),
+                                        Ok(Command::Help) => println!("{}", serialcmd::HELP),
+                                        Ok(Command::Status) => {
+                                            println!("station {}", store.read().station_uuid);
+                                            println!("server {:?} (missed pings: {})", sock.peer_addr(), keepalive.missed());
+                                            println!("clock synced: {}", timesync::now_if_synced().is_some());
+                                            println!("read interval: {:?}", config.read_interval);
+                                            println!("bme280: {:?}", bme280.health());
+                                            println!("anemometer: {:?}", wind.health());
+                                            println!("wind vane: {:?}", wind_dir.health());
+                                            println!("rain gauge: {:?}", rain.health());
+                                        }
+                                        Ok(Command::Reconnect) => {
+                                            info!("reconnecting (requested over serial)");
+                                            let _ = wifi.disconnect().await;
+                                            continue 'retry_wifi;
+                                        }
+                                        Ok(Command::ReadNow) => timers.read_timer.reset_immediately(),
+                                        Ok(Command::SetInterval(read_interval)) => {
+                                            info!("read interval changed to {read_interval:?} (requested over serial)");
+                                            config.read_interval = read_interval;
+                                            timers.update_new_cfg(&config);
+                                        }
+                                        Ok(Command::DumpConfig) => {
+                                            println!("firmware rev {} built {}", build::GIT_REV, build::DATETIME_PRETTY);
+                                            println!("server: {:?}", conf::SERVER);
+                                            println!("include open networks: {}", conf::INCLUDE_OPEN_NETWORKS);
+                                            println!("station altitude: {:?}", build::station_altitude());
+                                            println!("provisioning token set: {}", build::PROVISIONING_TOKEN.is_some());
+                                            println!("{config:#?}");
+                                        }
+                                        Ok(Command::DumpLog) => {
+                                            #[cfg(feature = "csv-log")]
+                                            match &csv_log {
+                                                Some(csv_log) => if let Err(e) = csv_log.dump(&mut std::io::stdout()) {
+                                                    println!("failed to read the log: {e}");
+                                                },
+                                                None => println!("the log partition is not mounted"),
+                                            }
+                                            #[cfg(not(feature = "csv-log"))]
+                                            println!("this firmware was built without the `csv-log` feature");
+                                        }
+                                    }
+                                }
+                            }
+                            _ = keepalive_timer.tick().fuse() => {
+                                if keepalive.missed() >= KEEPALIVE_MAX_MISSED {
+                                    warn!("the server has not answered {} pings, reconnecting", keepalive.missed());
+                                    candidates.failed();
+                                    continue 'retry_server;
+                                }
+                                let ping = keepalive.ping(&mut uid_gen);
+                                handle_netres!(sock.send(ping.as_bytes()).await.map_err(SendError::from));
                             }
                         }
                     }
@@ -526,68 +840,53 @@ fn main() {
 
 // called once on reset to handle any special reset reasons (e.g. panic)
 fn on_reset() {
-    // stored in RTC fast memory, not powered off by default even in deep sleep
-    // saftey of access: pinky promise that this code is single threadded
-    #[link_section = ".rtc.data"]
-    static DEEP_SLEEP_CAUSE: SyncUnsafeCell<SleepCause> = SyncUnsafeCell::new(SleepCause::None);
-
-    #[derive(Clone, Copy)]
-    enum SleepCause {
-        None,
-        Panic,
-    }
-
     use ResetReason::*;
-    match ResetReason::get() {
+    let reset = match ResetReason::get() {
         // should be normal reset conditions
-        ExternalPin | PowerOn => {}
-        // could be used for something in the future
-        // see https://github.com/esp-rs/esp-idf-hal/issues/128 for a way of storing info between sleeps
-        //  (storing in RTC fast memory, with `#[link_section=".rtc.data"] static mut VAR`)
-        DeepSleep => {
-            match unsafe { *DEEP_SLEEP_CAUSE.get() } {
-                SleepCause::None => {} // hmmmmm
-                SleepCause::Panic => {
-                    // esp docs LIE! (somehow, the chip was woken from deep sleep)
-                    // leave the cause as is
-                    // sleep forever (or is it)
-                    unsafe {
-                        esp_deep_sleep_start();
-                    }
-                }
-            }
-        }
+        ExternalPin | PowerOn => sleep::Reset::Normal,
+        // either a timed wake between measurements, or something strange (see `sleep::on_wake`)
+        DeepSleep => sleep::Reset::DeepSleep,
         //????
-        Software => {}
+        Software => sleep::Reset::Normal,
         // report and wait (?) -- caused by some software issue (Sdio - unknown what it is)
-        _reason @ (Watchdog | InterruptWatchdog | TaskWatchdog | Sdio) => {}
+        _reason @ (Watchdog | InterruptWatchdog | TaskWatchdog | Sdio) => sleep::Reset::Normal,
         // tentatively continue as normal
-        Unknown => {}
+        Unknown => sleep::Reset::Normal,
+        // the new image from a firmware update panicked, and the bootloader rolled back to this one
+        #[cfg(feature = "ota")]
+        Panic if ota::pending() => {
+            eprintln!("firmware update failed (the new image panicked), rolled back to the previous image");
+            ota::clear_pending();
+            sleep::Reset::Normal
+        }
         // report and wait for reset
-        Panic => {
+        Panic => sleep::Reset::Panic,
+        // wait for battery to raise above some level
+        Brownout => sleep::Reset::Normal,
+    };
+    let (action, cause) = sleep::on_wake(reset, sleep::stored_cause());
+    sleep::set_stored_cause(cause);
+    match action {
+        sleep::WakeAction::Run => {}
+        // sleep forever (or is it)
+        sleep::WakeAction::Halt => sleep::halt(),
+        sleep::WakeAction::HaltAfterPanic => {
             // if printing fails, avoid panicing again
             let _ = std::panic::catch_unwind(|| {
                 eprintln!("Chip restarted due to panic -- halting to avoid repeated panicing");
                 eprintln!("restart chip to exit halted mode");
             });
-            unsafe {
-                // sleep forever
-                *DEEP_SLEEP_CAUSE.get() = SleepCause::Panic;
-
-                std::thread::sleep(Duration::from_secs(10 * 60));
-
-                esp_sleep_disable_wakeup_source(
-                    esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL,
-                );
-                esp_deep_sleep_start();
-            }
+            std::thread::sleep(Duration::from_secs(10 * 60));
+            sleep::halt()
         }
-        // wait for battery to raise above some level
-        Brownout => {}
     }
 }
 
-async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'_>>) {
+/// connect to the best available network, falling through to the next best if connecting fails
+///
+/// returns the network's SSID and the station's IP, or the last error if connecting to every available network failed
+async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'_>>) -> Result<(String, Ipv4Addr), EspError> {
+    const ATTEMPTS_PER_NETWORK: u32 = 5;
     info!("Connecting to WIFI");
     assert!(wifi
         .is_started()
@@ -600,42 +899,60 @@ async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'_>>) {
     // while let Ok(wifictl::WifiStatusUpdate::Disconnected) = wifi_status_recv.try_recv() {}
     // // -- connecting to wifi --
     let before = Instant::now();
-    // -- finding a known network --
-    let chosen = loop {
-        if let Some(chosen) = {
-            let aps = wifi
-                .scan()
-                .await
-                .unwrap_hwerr("error scaning for WIFI networks");
-            let mut useable = wifictl::filter_networks(aps, conf::INCLUDE_OPEN_NETWORKS);
-            useable.sort_by_key(|x| x.0.signal_strength);
-            (!useable.is_empty()).then(|| useable.remove(0))
-        } {
-            break chosen;
-        } else {
-            error!("scan returned no available networks, retrying in {NO_WIFI_RETRY_INTERVAL:?}");
-            std::thread::sleep(NO_WIFI_RETRY_INTERVAL);
+    // -- finding known networks --
+    let useable = loop {
+        let aps = wifi
+            .scan()
+            .await
+            .unwrap_hwerr("error scaning for WIFI networks");
+        let mut useable = wifictl::filter_networks(aps, conf::INCLUDE_OPEN_NETWORKS);
+        if !useable.is_empty() {
+            // best first
+            useable.reverse();
+            break useable;
         }
+        error!("scan returned no available networks, retrying in {NO_WIFI_RETRY_INTERVAL:?}");
+        std::thread::sleep(NO_WIFI_RETRY_INTERVAL);
     };
-    info!("Connecting to: {}", chosen.0.ssid);
-    wifi.set_configuration(&wifi::Configuration::Client(wifi::ClientConfiguration {
-        ssid: chosen.0.ssid,
-        password: <_ as FromStr>::from_str(chosen.1.unwrap_or_default()).unwrap(),
-        channel: Some(chosen.0.channel),
-        ..Default::default()
-    }))
-    .unwrap_hwerr("failed to set wifi config");
-    for i in 1..=5 {
-        info!("Connecting (attempt {i} / 5)");
-        if let Err(e) = wifi.connect().await {
-            warn!("Attempt {i}/5 failed");
-            if i == 5 {
-                _panic_hwerr(e, "Failed to connect to wifi (attempt 5 errored)");
+    let mut plan = wifictl::ConnectPlan::new(useable, ATTEMPTS_PER_NETWORK);
+    let mut last_err = None;
+    let mut ssid = String::new();
+    let mut configure = true;
+    while let Some(chosen) = plan.current() {
+        if configure {
+            info!("Connecting to: {}", chosen.0.ssid);
+            wifi.set_configuration(&wifi::Configuration::Client(wifi::ClientConfiguration {
+                ssid: chosen.0.ssid.clone(),
+                password: <_ as FromStr>::from_str(chosen.1.unwrap_or_default()).unwrap(),
+                channel: Some(chosen.0.channel),
+                ..Default::default()
+            }))
+            .unwrap_hwerr("failed to set wifi config");
+        }
+        let i = plan.attempt();
+        info!("Connecting (attempt {i} / {ATTEMPTS_PER_NETWORK})");
+        match wifi.connect().await {
+            Ok(()) => {
+                last_err = None;
+                ssid = chosen.0.ssid.to_string();
+                break;
+            }
+            Err(e) => {
+                warn!("Attempt {i}/{ATTEMPTS_PER_NETWORK} failed: {e:?}");
+                last_err = Some(e);
+                configure = plan.failed();
+                if configure {
+                    if let Some(next) = plan.current() {
+                        warn!("giving up on this network, trying {}", next.0.ssid);
+                    }
+                }
             }
-        } else {
-            break;
         }
     }
+    if let Some(e) = last_err {
+        error!("Failed to connect to any available network");
+        return Err(e);
+    }
     info!("waiting for association");
     wifi.ip_wait_while(|wifi| wifi.is_up().map(|x| !x), None)
         .await
@@ -647,6 +964,7 @@ async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'_>>) {
         .get_ip_info()
         .unwrap_hwerr("failed to get DHCP info - may be caused by TOCTOU error if the wifi disconnected immedietally after connecting");
     info!("WIFI DHCP info: {ip_info:?}");
+    Ok((ssid, ip_info.ip))
 }
 
 #[derive(Debug, Clone)]
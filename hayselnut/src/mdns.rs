@@ -0,0 +1,177 @@
+//! Minimal mDNS (multicast DNS) service discovery, used to find the server when DNS does not know about it.
+//!
+//! only what is needed to find a single service is implemented (a PTR query, and reading the SRV + A records
+//! from the response), this is not a general purpose mDNS client.
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, time::timeout};
+
+/// service that the server advertises itself as
+pub const SERVICE: &str = "_hayselnut._udp.local";
+
+const MDNS_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// set in the class of a question to request a unicast response (so it is sent back to our port, not 5353)
+const CLASS_UNICAST_RESPONSE: u16 = 0x8000;
+
+/// Query for `service`, returning the address of the first responder (if any respond within `wait`)
+pub async fn discover(service: &str, wait: Duration) -> io::Result<Option<SocketAddr>> {
+    let sock = UdpSocket::bind("0.0.0.0:0").await?;
+    sock.send_to(&build_query(service), MDNS_ADDR).await?;
+    let mut buf = [0u8; 1500];
+    let res = timeout(wait, async {
+        loop {
+            let (amnt, from) = sock.recv_from(&mut buf).await?;
+            match parse_response(&buf[..amnt], service) {
+                Some(addr) => break io::Result::Ok(addr),
+                None => debug!("ignoring unrelated mDNS response from {from:?}"),
+            }
+        }
+    })
+    .await;
+    match res {
+        Ok(res) => res.map(Some),
+        Err(..) => Ok(None),
+    }
+}
+
+/// build a PTR query for `service`
+pub fn build_query(service: &str) -> Vec<u8> {
+    let mut buf = vec![];
+    // id, flags, 1 question, 0 answers, 0 authority, 0 additional
+    for field in [0u16, 0, 1, 0, 0, 0] {
+        buf.extend_from_slice(&field.to_be_bytes());
+    }
+    for label in service.split('.').filter(|l| !l.is_empty()) {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    buf.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    buf.extend_from_slice(&(CLASS_IN | CLASS_UNICAST_RESPONSE).to_be_bytes());
+    buf
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(msg.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+/// read a (possibly compressed) name at `pos`, returning it and the position after it
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    // position after the name, set once the first pointer is followed
+    let mut end = None;
+    // bounds pointer loops
+    for _ in 0..64 {
+        let len = *msg.get(pos)? as usize;
+        if len & 0xC0 == 0xC0 {
+            let ptr = (read_u16(msg, pos)? & 0x3FFF) as usize;
+            end.get_or_insert(pos + 2);
+            pos = ptr;
+        } else if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        } else {
+            let label = msg.get(pos + 1..pos + 1 + len)?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(std::str::from_utf8(label).ok()?);
+            pos += 1 + len;
+        }
+    }
+    None
+}
+
+/// Find the address of `service` in a mDNS response (from its SRV record, and the A record of the SRV's target)
+pub fn parse_response(msg: &[u8], service: &str) -> Option<SocketAddr> {
+    let flags = read_u16(msg, 2)?;
+    // must be a response
+    if flags & 0x8000 == 0 {
+        return None;
+    }
+    let questions = read_u16(msg, 4)?;
+    let records =
+        read_u16(msg, 6)? as usize + read_u16(msg, 8)? as usize + read_u16(msg, 10)? as usize;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+    let mut srv = None;
+    let mut a_records = vec![];
+    for _ in 0..records {
+        let (name, next) = read_name(msg, pos)?;
+        let ty = read_u16(msg, next)?;
+        let rdlen = read_u16(msg, next + 8)? as usize;
+        let rdata = next + 10;
+        msg.get(rdata..rdata + rdlen)?;
+        match ty {
+            // the SRV record is named `<instance>.<service>`
+            TYPE_SRV if srv.is_none() && name.to_lowercase().ends_with(&service.to_lowercase()) => {
+                let port = read_u16(msg, rdata + 4)?;
+                let (target, _) = read_name(msg, rdata + 6)?;
+                srv = Some((target, port));
+            }
+            TYPE_A if rdlen == 4 => {
+                let ip = Ipv4Addr::new(msg[rdata], msg[rdata + 1], msg[rdata + 2], msg[rdata + 3]);
+                a_records.push((name, ip));
+            }
+            _ => {}
+        }
+        pos = rdata + rdlen;
+    }
+    let (target, port) = srv?;
+    a_records
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&target))
+        .map(|(_, ip)| SocketAddr::V4(SocketAddrV4::new(ip, port)))
+}
+
+#[cfg(test)]
+#[test]
+fn parse_canned_response() {
+    // response to `build_query(SERVICE)`, from avahi advertising `haysel._hayselnut._udp.local` on port 43210
+    // at `weather.local` (192.168.1.20)
+    #[rustfmt::skip]
+    let response: &[u8] = &[
+        // header: id 0, flags (response, authoritative), 0 questions, 1 answer, 0 authority, 2 additional
+        0x00, 0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02,
+        // answer @12: _hayselnut._udp.local PTR haysel._hayselnut._udp.local
+        10, b'_', b'h', b'a', b'y', b's', b'e', b'l', b'n', b'u', b't',
+        4, b'_', b'u', b'd', b'p',
+        5, b'l', b'o', b'c', b'a', b'l', 0,
+        0x00, 0x0C, 0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x09,
+        // rdata @45: haysel + pointer to @12
+        6, b'h', b'a', b'y', b's', b'e', b'l', 0xC0, 0x0C,
+        // additional: pointer to @45 (the instance name) SRV
+        0xC0, 0x2D,
+        0x00, 0x21, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x10,
+        // priority 0, weight 0, port 43210, target weather.local (@72)
+        0x00, 0x00, 0x00, 0x00, 0xA8, 0xCA,
+        7, b'w', b'e', b'a', b't', b'h', b'e', b'r', 0xC0, 0x1C,
+        // additional: pointer to @72 (weather.local) A 192.168.1.20
+        0xC0, 0x48,
+        0x00, 0x01, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04,
+        192, 168, 1, 20,
+    ];
+    assert_eq!(
+        parse_response(response, SERVICE),
+        Some(SocketAddr::from(([192, 168, 1, 20], 43210)))
+    );
+    assert_eq!(parse_response(response, "_other._udp.local"), None);
+    // truncated
+    assert_eq!(
+        parse_response(&response[..response.len() - 2], SERVICE),
+        None
+    );
+    // queries are not responses
+    assert_eq!(parse_response(&build_query(SERVICE), SERVICE), None);
+}
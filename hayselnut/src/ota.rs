@@ -0,0 +1,311 @@
+//! Over the air firmware updates, started by the server sending `PacketKind::BeginOTA`.
+//!
+//! the image is downloaded over HTTPS into the inactive OTA partition, and is only booted if its SHA-256 matches
+//! and the hash is signed by `conf::OTA_PUBLIC_KEY`. after rebooting, the new image is pending verification until
+//! it reaches the server (`mark_valid`). if it resets before then the bootloader rolls back to the old image.
+//!
+//! only built with the `ota` feature
+
+use std::{cell::SyncUnsafeCell, convert::Infallible, mem::MaybeUninit};
+
+use embedded_svc::{http::client::Client, io::Read};
+use esp_idf_svc::{
+    http::client::{Configuration as HttpConfig, EspHttpConnection},
+    io::EspIOError,
+    ota::EspOta,
+};
+use esp_idf_sys::EspError;
+use squirrel::api::BeginOTA;
+
+use crate::conf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OtaError {
+    #[error("refusing to download an update over plain HTTP ({0})")]
+    NotHttps(String),
+    #[error("server responded with status {0}")]
+    Status(u16),
+    #[error("image hash does not match")]
+    HashMismatch,
+    #[error("image signature is invalid")]
+    BadSignature,
+    #[error("HTTP error: {0:?}")]
+    Http(#[from] EspIOError),
+    #[error("OTA error: {0:?}")]
+    Esp(#[from] EspError),
+}
+
+// set before rebooting into a new image, cleared when it is marked valid.
+// `.rtc_noinit` survives every reset except power on (hence the magic number), and is the only value in that section
+// so it is at the same address in both the old and new image
+#[link_section = ".rtc_noinit"]
+static OTA_PENDING: SyncUnsafeCell<u32> = SyncUnsafeCell::new(0);
+const PENDING_MAGIC: u32 = 0x07A_BEEF;
+
+/// if an update was started but never marked valid (the new image failed, and was rolled back)
+pub fn pending() -> bool {
+    unsafe { *OTA_PENDING.get() == PENDING_MAGIC }
+}
+
+pub fn clear_pending() {
+    unsafe { *OTA_PENDING.get() = 0 }
+}
+
+/// Mark the running image as good, cancelling rollback (called once the server has been reached)
+pub fn mark_valid() -> Result<(), EspError> {
+    EspOta::new()?.mark_running_slot_valid()?;
+    if pending() {
+        info!("firmware update complete");
+        clear_pending();
+    }
+    Ok(())
+}
+
+/// Download, verify, and reboot into the image described by `begin`.
+///
+/// the current image is left untouched if anything fails
+pub fn update(begin: &BeginOTA) -> Result<Infallible, OtaError> {
+    if !begin.url.starts_with("https://") {
+        return Err(OtaError::NotHttps(begin.url.clone()));
+    }
+    info!("downloading firmware update from {}", begin.url);
+    let mut client = Client::wrap(EspHttpConnection::new(&HttpConfig {
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?);
+    let mut resp = client.get(&begin.url)?.submit()?;
+    if resp.status() != 200 {
+        return Err(OtaError::Status(resp.status()));
+    }
+
+    let mut ota = EspOta::new()?;
+    let mut write = ota.initiate_update()?;
+    let mut check = ImageCheck::new(begin.sha256);
+    let mut buf = vec![0u8; 4096];
+    let res = loop {
+        match resp.read(&mut buf) {
+            Ok(0) => break check.finish(&begin.signature, &MbedtlsVerifier),
+            Ok(amnt) => {
+                check.update(&buf[..amnt]);
+                if let Err(e) = write.write(&buf[..amnt]) {
+                    break Err(e.into());
+                }
+            }
+            Err(e) => break Err(e.into()),
+        }
+    };
+    if let Err(e) = res {
+        write.abort()?;
+        return Err(e);
+    }
+    write.complete()?;
+    info!("firmware update written, rebooting into it");
+    unsafe { *OTA_PENDING.get() = PENDING_MAGIC };
+    esp_idf_hal::reset::restart();
+}
+
+/// Checks a signature of an image's hash
+pub trait SignatureVerifier {
+    fn verify(&self, digest: &[u8; 32], signature: &[u8]) -> bool;
+}
+
+/// ECDSA verification (using mbedtls) with `conf::OTA_PUBLIC_KEY` (PEM, nul terminated)
+pub struct MbedtlsVerifier;
+
+impl SignatureVerifier for MbedtlsVerifier {
+    fn verify(&self, digest: &[u8; 32], signature: &[u8]) -> bool {
+        use esp_idf_sys::*;
+        let key: &[u8] = conf::OTA_PUBLIC_KEY;
+        unsafe {
+            let mut pk = MaybeUninit::<mbedtls_pk_context>::uninit();
+            mbedtls_pk_init(pk.as_mut_ptr());
+            let valid = mbedtls_pk_parse_public_key(pk.as_mut_ptr(), key.as_ptr(), key.len()) == 0
+                && mbedtls_pk_verify(
+                    pk.as_mut_ptr(),
+                    mbedtls_md_type_t_MBEDTLS_MD_SHA256,
+                    digest.as_ptr(),
+                    digest.len(),
+                    signature.as_ptr(),
+                    signature.len(),
+                ) == 0;
+            mbedtls_pk_free(pk.as_mut_ptr());
+            valid
+        }
+    }
+}
+
+/// Verifies an image as it is downloaded
+pub struct ImageCheck {
+    hasher: Sha256,
+    expected: [u8; 32],
+}
+
+impl ImageCheck {
+    pub fn new(expected: [u8; 32]) -> Self {
+        Self {
+            hasher: Sha256::new(),
+            expected,
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// check the hash of everything passed to `update`, and the signature of that hash
+    pub fn finish(
+        self,
+        signature: &[u8],
+        verifier: &impl SignatureVerifier,
+    ) -> Result<(), OtaError> {
+        if self.hasher.finish() != self.expected {
+            return Err(OtaError::HashMismatch);
+        }
+        if !verifier.verify(&self.expected, signature) {
+            return Err(OtaError::BadSignature);
+        }
+        Ok(())
+    }
+}
+
+/// SHA-256 (using mbedtls, which is hardware accelerated where the chip supports it), streaming
+pub struct Sha256 {
+    ctx: esp_idf_sys::mbedtls_sha256_context,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        use esp_idf_sys::*;
+        unsafe {
+            let mut ctx = MaybeUninit::<mbedtls_sha256_context>::uninit();
+            mbedtls_sha256_init(ctx.as_mut_ptr());
+            // (0: SHA-256, not SHA-224)
+            let res = mbedtls_sha256_starts(ctx.as_mut_ptr(), 0);
+            assert_eq!(res, 0, "failed to start SHA-256");
+            Self {
+                ctx: ctx.assume_init(),
+            }
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let res =
+            unsafe { esp_idf_sys::mbedtls_sha256_update(&mut self.ctx, data.as_ptr(), data.len()) };
+        assert_eq!(res, 0, "failed to update SHA-256");
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        let res = unsafe { esp_idf_sys::mbedtls_sha256_finish(&mut self.ctx, out.as_mut_ptr()) };
+        assert_eq!(res, 0, "failed to finish SHA-256");
+        out
+    }
+}
+
+impl Drop for Sha256 {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::mbedtls_sha256_free(&mut self.ctx) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// "signs" by xoring the digest with a key
+    struct MockVerifier(u8);
+
+    impl MockVerifier {
+        fn sign(&self, digest: &[u8; 32]) -> Vec<u8> {
+            digest.iter().map(|b| b ^ self.0).collect()
+        }
+    }
+
+    impl SignatureVerifier for MockVerifier {
+        fn verify(&self, digest: &[u8; 32], signature: &[u8]) -> bool {
+            self.sign(digest) == signature
+        }
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // two blocks after padding
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        // chunking does not matter
+        let data = (0..1000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(37) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), sha256(&data));
+    }
+
+    #[test]
+    fn image_verification() {
+        let image = (0..10_000u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let digest = sha256(&image);
+        let key = MockVerifier(0x5A);
+        let signature = key.sign(&digest);
+
+        let check = |image: &[u8], digest: [u8; 32], signature: &[u8]| {
+            let mut check = ImageCheck::new(digest);
+            for chunk in image.chunks(4096) {
+                check.update(chunk);
+            }
+            check.finish(signature, &key)
+        };
+
+        assert!(check(&image, digest, &signature).is_ok());
+
+        // tampered image
+        let mut tampered = image.clone();
+        tampered[1234] ^= 1;
+        assert!(matches!(
+            check(&tampered, digest, &signature),
+            Err(OtaError::HashMismatch)
+        ));
+        // truncated image
+        assert!(matches!(
+            check(&image[..9000], digest, &signature),
+            Err(OtaError::HashMismatch)
+        ));
+        // tampered image, with a hash to match (but no valid signature for it)
+        let tampered_digest = sha256(&tampered);
+        assert!(matches!(
+            check(&tampered, tampered_digest, &signature),
+            Err(OtaError::BadSignature)
+        ));
+        // signed with a different key
+        assert!(matches!(
+            check(&image, digest, &MockVerifier(0x11).sign(&digest)),
+            Err(OtaError::BadSignature)
+        ));
+        assert!(matches!(
+            check(&image, digest, &[]),
+            Err(OtaError::BadSignature)
+        ));
+    }
+}
@@ -1,8 +1,13 @@
 use squirrel::api::station::capabilities::{Channel, ChannelData, ChannelID};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Debug};
 
 pub mod battery;
 pub mod bme280;
+pub mod display;
+pub mod pulse;
+pub mod rain;
+pub mod wind;
+pub mod wind_direction;
 
 #[derive(Debug)]
 pub struct PeripheralState<TOk, TErr, E> {
@@ -79,6 +84,31 @@ impl<TOk, TErr, E> PeripheralState<TOk, TErr, E> {
     pub fn err(&self) -> Option<&E> {
         self.error.as_ref()
     }
+
+    pub fn health(&self) -> PeriphHealth
+    where
+        E: Debug,
+    {
+        match (self.is_init(), &self.error) {
+            (true, None) => PeriphHealth::Ok,
+            (true, Some(e)) => PeriphHealth::Degraded(format!("{e:?}")),
+            (false, e) => PeriphHealth::Failed(
+                e.as_ref()
+                    .map(|e| format!("{e:?}"))
+                    .unwrap_or_else(|| "state lost while retrying initialization".into()),
+            ),
+        }
+    }
+}
+
+/// Health of a peripheral, including the last error if it is not `Ok`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeriphHealth {
+    Ok,
+    /// initialized, but the last operation failed (`Peripheral::fix` may resolve it)
+    Degraded(String),
+    /// initialization failed
+    Failed(String),
 }
 
 pub trait Peripheral {
@@ -89,8 +119,40 @@ pub trait Peripheral {
 
 pub trait SensorPeripheral: Peripheral {
     fn channels(&self) -> Vec<Channel>;
+    /// current health, without performing a read
+    fn health(&self) -> PeriphHealth;
     fn read(
         &mut self,
         map_fn: &impl Fn(&str) -> ChannelID,
     ) -> Option<HashMap<ChannelID, ChannelData>>;
+    /// put the sensor in its lowest power state until [`wake`][Self::wake] is called (in low power mode, between
+    /// readings). failures are reported like a failed read
+    fn sleep(&mut self) {}
+    /// undo [`sleep`][Self::sleep], before the next read
+    fn wake(&mut self) {}
+}
+
+#[cfg(test)]
+#[test]
+fn health_follows_errors() {
+    // simulates a sensor whose I2C transactions fail until it is reconnected
+    let mut state = PeripheralState::new(|| Err::<(), _>((false, "i2c: no ack")));
+    assert_eq!(
+        state.health(),
+        PeriphHealth::Failed("\"i2c: no ack\"".into())
+    );
+    // still disconnected
+    state.retry_init(|_, _| Err((false, "i2c: no ack")));
+    assert!(matches!(state.health(), PeriphHealth::Failed(..)));
+    // reconnected
+    state.retry_init(|_, _| Ok(()));
+    assert_eq!(state.health(), PeriphHealth::Ok);
+
+    assert_eq!(state.map(|_| Err::<(), _>("i2c: timeout")), None);
+    assert_eq!(
+        state.health(),
+        PeriphHealth::Degraded("\"i2c: timeout\"".into())
+    );
+    state.resolve_err(|_, _| Ok(()));
+    assert_eq!(state.health(), PeriphHealth::Ok);
 }
@@ -7,37 +7,184 @@ use squirrel::api::station::capabilities::{
     Channel, ChannelData, ChannelID, ChannelType, ChannelValue,
 };
 
-use super::{Peripheral, PeripheralState, SensorPeripheral};
+use super::{PeriphHealth, Peripheral, PeripheralState, SensorPeripheral};
+
+const ADDRESS: u8 = 0x77;
+/// times initialization is tried in [`PeriphBME280::new`] before the sensor is reported as failed.
+///
+/// each failed attempt takes at most the bus timeout, so a disconnected sensor does not stop the station from starting
+const INIT_ATTEMPTS: u32 = 3;
+
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+
+/// the `mode` field of `ctrl_meas`: no measurements are taken, and the sensor draws the least power
+const MODE_SLEEP: u8 = 0b00;
+
+/// Oversampling for one of the BME280's measurements (the `osrs_*` register fields)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversampling {
+    /// the measurement is not taken (it will read as garbage)
+    Skip = 0b000,
+    #[default]
+    X1 = 0b001,
+    X2 = 0b010,
+    X4 = 0b011,
+    X8 = 0b100,
+    X16 = 0b101,
+}
+
+/// IIR filter coefficient (the `filter` field of the `config` register)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IirFilter {
+    #[default]
+    Off = 0b000,
+    X2 = 0b001,
+    X4 = 0b010,
+    X8 = 0b011,
+    X16 = 0b100,
+}
+
+/// Measurement settings for the BME280, applied every time it is (re)initialized
+///
+/// the default matches the driver's own configuration (1x oversampling, no filter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bme280Settings {
+    pub temp_oversample: Oversampling,
+    pub humidity_oversample: Oversampling,
+    pub pressure_oversample: Oversampling,
+    pub iir_filter: IirFilter,
+}
+
+impl Bme280Settings {
+    /// (register, value) pairs to write, in order.
+    ///
+    /// changes to `ctrl_hum` only take effect once `ctrl_meas` is written, so it must come first.
+    /// the mode bits of `ctrl_meas` are left at sleep, the driver switches to forced mode for each measurement
+    fn registers(&self) -> [(u8, u8); 3] {
+        [
+            (REG_CTRL_HUM, self.humidity_oversample as u8),
+            (REG_CTRL_MEAS, self.ctrl_meas(MODE_SLEEP)),
+            (REG_CONFIG, (self.iir_filter as u8) << 2),
+        ]
+    }
+
+    /// value of `ctrl_meas` with these settings, in `mode`
+    fn ctrl_meas(&self, mode: u8) -> u8 {
+        (self.temp_oversample as u8) << 5 | (self.pressure_oversample as u8) << 2 | mode
+    }
+
+    /// write these settings to the sensor at `address`. it must be in sleep mode (as it is after init)
+    pub fn apply<I: I2c>(&self, i2c: &mut I, address: u8) -> Result<(), I::Error> {
+        for (reg, value) in self.registers() {
+            i2c.write(address, &[reg, value])?;
+        }
+        Ok(())
+    }
+
+    /// put the sensor at `address` in sleep mode, keeping these settings (any measurement in progress is stopped)
+    pub fn sleep<I: I2c>(&self, i2c: &mut I, address: u8) -> Result<(), I::Error> {
+        i2c.write(address, &[REG_CTRL_MEAS, self.ctrl_meas(MODE_SLEEP)])
+    }
+}
+
+#[derive(Debug)]
+pub enum BME280Error<E> {
+    Driver(bme280::Error<E>),
+    /// failed to write the measurement settings
+    Configure(E),
+}
 
 #[derive(Debug)]
 pub struct PeriphBME280<T: I2c> {
-    inner: PeripheralState<BME280<T>, BME280<T>, bme280::Error<T::Error>>,
+    inner: PeripheralState<BME280<T>, BME280<T>, BME280Error<T::Error>>,
+    /// a second handle to the bus, used to write the settings (the driver does not expose its own)
+    config_bus: T,
+    settings: Bme280Settings,
+    /// station altitude (meters above sea level), if known
+    altitude: Option<f32>,
 }
 
 impl<T: I2c> PeriphBME280<T> {
-    pub fn new(i2c: T) -> Self {
-        let mut bme = BME280::new(i2c, 0x77);
+    /// `i2c` and `config_bus` must be devices on the same bus
+    ///
+    /// if the sensor does not respond after [`INIT_ATTEMPTS`] tries, it is returned in the failed state
+    /// (see [`SensorPeripheral::health`]), and initialization is retried by [`Peripheral::fix`]
+    pub fn new(i2c: T, mut config_bus: T, settings: Bme280Settings) -> Self {
+        let mut bme = BME280::new(i2c, ADDRESS);
         Self {
-            inner: PeripheralState::new(move || match bme.init(&mut delay::Ets) {
-                Ok(..) => Ok(bme),
-                Err(e) => Err((bme, e)),
+            inner: PeripheralState::new(|| {
+                let mut attempt = 1;
+                loop {
+                    match init(&mut bme, &mut config_bus, &settings) {
+                        Ok(..) => break Ok(bme),
+                        Err(e) if attempt >= INIT_ATTEMPTS => break Err((bme, e)),
+                        Err(e) => {
+                            warn!("BME280 init failed (attempt {attempt}/{INIT_ATTEMPTS}): {e:?}");
+                            attempt += 1;
+                        }
+                    }
+                }
             }),
+            config_bus,
+            settings,
+            altitude: None,
+        }
+    }
+
+    /// also report sea-level adjusted pressure (`pressure_sealevel`), for a station `altitude` meters above sea level
+    pub fn with_altitude(self, altitude: f32) -> Self {
+        Self {
+            altitude: Some(altitude),
+            ..self
         }
     }
 }
 
+/// Temperature lapse rate of the standard atmosphere (K/m)
+const LAPSE_RATE: f32 = 0.0065;
+/// `g * M / (R * L)` for the standard atmosphere
+const BAROMETRIC_EXPONENT: f32 = 5.255_88;
+
+/// Sea-level pressure (QNH) from station `pressure` (any unit, the result is in the same one),
+/// `temperature` (°C) and `altitude` (meters above sea level), using the barometric formula.
+///
+/// the column of air between the station and sea level is assumed to follow the standard lapse rate from the measured temperature
+pub fn sea_level_pressure(pressure: f32, temperature: f32, altitude: f32) -> f32 {
+    let drop = LAPSE_RATE * altitude;
+    pressure * (1.0 - drop / (temperature + drop + 273.15)).powf(-BAROMETRIC_EXPONENT)
+}
+
+/// init the driver (which resets the sensor), then apply `settings`
+fn init<T: I2c>(
+    bme: &mut BME280<T>,
+    config_bus: &mut T,
+    settings: &Bme280Settings,
+) -> Result<(), BME280Error<T::Error>> {
+    bme.init(&mut delay::Ets).map_err(BME280Error::Driver)?;
+    settings
+        .apply(config_bus, ADDRESS)
+        .map_err(BME280Error::Configure)
+}
+
 impl<T: I2c> Peripheral for PeriphBME280<T> {
-    type Error = bme280::Error<T::Error>;
+    type Error = BME280Error<T::Error>;
     fn fix(&mut self) {
-        self.inner
-            .retry_init(|mut bme, _err| match bme.init(&mut delay::Ets) {
-                Ok(..) => Ok(bme),
-                Err(e) => Err((bme, e)),
-            });
-        self.inner.resolve_err(|bme, _err| {
+        let Self {
+            inner,
+            config_bus,
+            settings,
+            ..
+        } = self;
+        inner.retry_init(|mut bme, _err| match init(&mut bme, config_bus, settings) {
+            Ok(..) => Ok(bme),
+            Err(e) => Err((bme, e)),
+        });
+        inner.resolve_err(|bme, _err| {
             // re-init, if connected it will work.
             // this will fix things if it disconencted due to loosing power
-            bme.init(&mut delay::Ets)
+            init(bme, config_bus, settings)
         });
     }
     fn err(&self) -> Option<&Self::Error> {
@@ -47,43 +194,256 @@ impl<T: I2c> Peripheral for PeriphBME280<T> {
 
 impl<T: I2c> SensorPeripheral for PeriphBME280<T> {
     fn channels(&self) -> Vec<Channel> {
-        vec![
+        let mut channels = vec![
             Channel {
                 name: "temperature".into(),
                 value: ChannelValue::Float,
                 ty: ChannelType::Periodic,
+                unit: Some("°C".into()),
+                description: Some("air temperature".into()),
             },
             Channel {
                 name: "humidity".into(),
                 value: ChannelValue::Float,
                 ty: ChannelType::Periodic,
+                unit: Some("%".into()),
+                description: Some("relative humidity".into()),
             },
             Channel {
                 name: "pressure".into(),
                 value: ChannelValue::Float,
                 ty: ChannelType::Periodic,
+                unit: Some("Pa".into()),
+                description: Some("station (unadjusted) air pressure".into()),
             },
-        ]
+        ];
+        if self.altitude.is_some() {
+            channels.push(Channel {
+                name: "pressure_sealevel".into(),
+                value: ChannelValue::Float,
+                ty: ChannelType::Periodic,
+                unit: Some("Pa".into()),
+                description: Some("air pressure adjusted to sea level".into()),
+            });
+        }
+        channels
+    }
+
+    fn health(&self) -> PeriphHealth {
+        self.inner.health()
     }
 
     fn read(
         &mut self,
         map_fn: &impl Fn(&str) -> ChannelID,
     ) -> Option<HashMap<ChannelID, ChannelData>> {
+        let altitude = self.altitude;
         self.inner.map(|bme| {
             let mut map = HashMap::new();
             let mut set = |key, val| map.insert(map_fn(key), ChannelData::Float(val));
-            let _ = bme.measure(&mut delay::Ets)?;
+            let _ = bme.measure(&mut delay::Ets).map_err(BME280Error::Driver)?;
             let Measurements {
                 temperature,
                 humidity,
                 pressure,
                 ..
-            } = bme.measure(&mut delay::Ets)?;
+            } = bme.measure(&mut delay::Ets).map_err(BME280Error::Driver)?;
             set("temperature", temperature);
             set("humidity", humidity);
             set("pressure", pressure);
+            if let Some(altitude) = altitude {
+                set(
+                    "pressure_sealevel",
+                    sea_level_pressure(pressure, temperature, altitude),
+                );
+            }
             Ok(map)
         })
     }
+
+    fn sleep(&mut self) {
+        let Self {
+            inner,
+            config_bus,
+            settings,
+            ..
+        } = self;
+        inner.map(|_| {
+            settings
+                .sleep(config_bus, ADDRESS)
+                .map_err(BME280Error::Configure)
+        });
+    }
+
+    /// the sensor stays in sleep mode (the driver switches to forced mode for each measurement), but its settings
+    /// are re-applied in case it lost power while the station slept
+    fn wake(&mut self) {
+        let Self {
+            inner,
+            config_bus,
+            settings,
+            ..
+        } = self;
+        inner.map(|_| {
+            settings
+                .apply(config_bus, ADDRESS)
+                .map_err(BME280Error::Configure)
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, rc::Rc};
+
+    use embedded_hal::i2c::{self, ErrorKind, NoAcknowledgeSource, Operation};
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    use super::*;
+
+    /// a bus with nothing connected (every transaction times out), counting transactions
+    #[derive(Debug, Clone, Default)]
+    struct Unresponsive(Rc<Cell<u32>>);
+
+    impl i2c::ErrorType for Unresponsive {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Unresponsive {
+        fn transaction(&mut self, _: u8, _: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+            self.0.set(self.0.get() + 1);
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        }
+    }
+
+    #[test]
+    fn disconnected_sensor_fails_init() {
+        let bus = Unresponsive::default();
+        let mut bme = PeriphBME280::new(bus.clone(), bus.clone(), Bme280Settings::default());
+        // gave up, after retrying
+        assert!(matches!(bme.health(), PeriphHealth::Failed(..)));
+        let attempts = bus.0.get();
+        assert!(attempts >= INIT_ATTEMPTS, "{attempts}");
+        assert!(matches!(bme.err(), Some(BME280Error::Driver(..))));
+        // no readings (and no more transactions) while failed
+        assert_eq!(bme.read(&|_| ChannelID::nil()), None);
+        assert_eq!(bus.0.get(), attempts);
+        // fixing tries again (once)
+        bme.fix();
+        assert!(matches!(bme.health(), PeriphHealth::Failed(..)));
+        assert!(bus.0.get() > attempts);
+    }
+
+    /// station pressure (Pa) at `altitude` in the standard atmosphere
+    fn isa_pressure(altitude: f32) -> f32 {
+        101_325.0 * (1.0 - 2.255_77e-5 * altitude).powf(5.255_88)
+    }
+
+    /// standard atmosphere temperature (°C) at `altitude`
+    fn isa_temperature(altitude: f32) -> f32 {
+        15.0 - LAPSE_RATE * altitude
+    }
+
+    #[test]
+    fn sea_level_pressure_standard_atmosphere() {
+        for altitude in [-400.0, 0.0, 100.0, 1000.0, 3000.0] {
+            let qnh =
+                sea_level_pressure(isa_pressure(altitude), isa_temperature(altitude), altitude);
+            assert!(
+                (qnh - 101_325.0).abs() < 5.0,
+                "{altitude}m: {qnh} != 101325"
+            );
+        }
+    }
+
+    #[test]
+    fn sea_level_pressure_reference_values() {
+        // at sea level, nothing changes
+        assert_eq!(sea_level_pressure(98_000.0, 30.0, 0.0), 98_000.0);
+        // 1000hPa at 500m
+        let warm = sea_level_pressure(100_000.0, 20.0, 500.0);
+        let cold = sea_level_pressure(100_000.0, -10.0, 500.0);
+        assert!((warm - 105_966.0).abs() < 2.0, "{warm}");
+        assert!((cold - 106_664.0).abs() < 2.0, "{cold}");
+        // (colder air is denser, so the correction is larger)
+        assert!(cold > warm);
+    }
+
+    #[test]
+    fn default_settings_registers() {
+        let mut i2c = Mock::new(&[
+            Transaction::write(ADDRESS, vec![REG_CTRL_HUM, 0b001]),
+            Transaction::write(ADDRESS, vec![REG_CTRL_MEAS, 0b001_001_00]),
+            Transaction::write(ADDRESS, vec![REG_CONFIG, 0b000_000_00]),
+        ]);
+        Bme280Settings::default().apply(&mut i2c, ADDRESS).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn outdoor_settings_registers() {
+        let settings = Bme280Settings {
+            temp_oversample: Oversampling::X2,
+            humidity_oversample: Oversampling::X16,
+            pressure_oversample: Oversampling::X8,
+            iir_filter: IirFilter::X4,
+        };
+        let mut i2c = Mock::new(&[
+            Transaction::write(ADDRESS, vec![REG_CTRL_HUM, 0b101]),
+            Transaction::write(ADDRESS, vec![REG_CTRL_MEAS, 0b010_100_00]),
+            Transaction::write(ADDRESS, vec![REG_CONFIG, 0b000_010_00]),
+        ]);
+        settings.apply(&mut i2c, ADDRESS).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn skipped_measurement_and_max_filter() {
+        let settings = Bme280Settings {
+            temp_oversample: Oversampling::X16,
+            humidity_oversample: Oversampling::Skip,
+            pressure_oversample: Oversampling::X16,
+            iir_filter: IirFilter::X16,
+        };
+        let mut i2c = Mock::new(&[
+            Transaction::write(ADDRESS, vec![REG_CTRL_HUM, 0b000]),
+            Transaction::write(ADDRESS, vec![REG_CTRL_MEAS, 0b101_101_00]),
+            Transaction::write(ADDRESS, vec![REG_CONFIG, 0b000_100_00]),
+        ]);
+        settings.apply(&mut i2c, ADDRESS).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn sleep_and_wake_registers() {
+        let settings = Bme280Settings {
+            temp_oversample: Oversampling::X2,
+            humidity_oversample: Oversampling::X16,
+            pressure_oversample: Oversampling::X8,
+            iir_filter: IirFilter::X4,
+        };
+        let mut i2c = Mock::new(&[
+            // sleep: the oversampling is kept, with the mode bits cleared
+            Transaction::write(ADDRESS, vec![REG_CTRL_MEAS, 0b010_100_00]),
+            // wake: everything is re-applied, ctrl_hum first
+            Transaction::write(ADDRESS, vec![REG_CTRL_HUM, 0b101]),
+            Transaction::write(ADDRESS, vec![REG_CTRL_MEAS, 0b010_100_00]),
+            Transaction::write(ADDRESS, vec![REG_CONFIG, 0b000_010_00]),
+        ]);
+        settings.sleep(&mut i2c, ADDRESS).unwrap();
+        settings.apply(&mut i2c, ADDRESS).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn write_failure_stops_configuration() {
+        let mut i2c = Mock::new(&[
+            Transaction::write(ADDRESS, vec![REG_CTRL_HUM, 0b001]).with_error(ErrorKind::Other)
+        ]);
+        assert_eq!(
+            Bme280Settings::default().apply(&mut i2c, ADDRESS),
+            Err(ErrorKind::Other)
+        );
+        i2c.done();
+    }
 }
@@ -0,0 +1,231 @@
+//! Status display (a SSD1306 OLED in terminal mode), showing what the station is doing.
+//!
+//! the display is not critical, so errors talking to it are logged and otherwise ignored.
+//! the SSD1306 driver is only included with the `display` feature, without it `NoDisplay` is used
+
+use std::fmt::{Debug, Display};
+
+#[cfg(feature = "display")]
+use embedded_hal::i2c::I2c;
+#[cfg(feature = "display")]
+use ssd1306::{
+    mode::{TerminalMode, TerminalModeError},
+    prelude::*,
+    size::DisplaySize128x64,
+    I2CDisplayInterface, Ssd1306,
+};
+
+/// lines of text that fit on the display
+pub const LINES: usize = 8;
+/// characters per line
+pub const COLUMNS: usize = 16;
+
+/// A display that can show lines of text (implemented for the SSD1306, and mocked in tests)
+pub trait TextDisplay {
+    type Error: Debug;
+    fn clear(&mut self) -> Result<(), Self::Error>;
+    /// overwrite line `n` with `text` (which is exactly `COLUMNS` characters)
+    fn write_line(&mut self, n: usize, text: &str) -> Result<(), Self::Error>;
+}
+
+/// Line buffer over a `TextDisplay`, only re-drawing lines that changed
+pub struct StatusDisplay<D: TextDisplay> {
+    display: D,
+    /// what is currently shown (None if unknown, e.g. after a failed write)
+    shown: [Option<String>; LINES],
+}
+
+impl<D: TextDisplay> StatusDisplay<D> {
+    pub fn new(mut display: D) -> Self {
+        if let Err(e) = display.clear() {
+            warn!("failed to clear status display: {e:?}");
+        }
+        Self {
+            display,
+            shown: std::array::from_fn(|_| Some(render_line(""))),
+        }
+    }
+
+    /// show `text` on line `n` (truncated to fit). lines past the bottom of the display are ignored
+    pub fn set_line(&mut self, n: usize, text: &str) {
+        let Some(shown) = self.shown.get_mut(n) else {
+            return;
+        };
+        let line = render_line(text);
+        if shown.as_ref() == Some(&line) {
+            return;
+        }
+        match self.display.write_line(n, &line) {
+            Ok(()) => *shown = Some(line),
+            Err(e) => {
+                warn!("failed to write to status display: {e:?}");
+                *shown = None;
+            }
+        }
+    }
+
+    /// show `lines` from the top of the display, blanking the rest
+    pub fn set_lines(&mut self, lines: &[&str]) {
+        for n in 0..LINES {
+            self.set_line(n, lines.get(n).copied().unwrap_or(""));
+        }
+    }
+
+    /// show an error, wrapped over the lines below the heading
+    pub fn show_error(&mut self, msg: &str) {
+        let chars = msg.chars().collect::<Vec<_>>();
+        let wrapped = chars
+            .chunks(COLUMNS)
+            .map(|c| c.iter().collect::<String>())
+            .collect::<Vec<_>>();
+        let mut lines = vec!["-- ERROR --"];
+        lines.extend(wrapped.iter().map(String::as_str));
+        self.set_lines(&lines);
+    }
+
+    pub fn show_connected(&mut self, ssid: &str, ip: impl Display) {
+        self.set_lines(&["wifi connected", ssid, &ip.to_string()]);
+    }
+
+    pub fn into_inner(self) -> D {
+        self.display
+    }
+}
+
+/// truncate or pad `text` to exactly `COLUMNS` characters
+pub fn render_line(text: &str) -> String {
+    let mut line = text.chars().take(COLUMNS).collect::<String>();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat(' ').take(COLUMNS - len));
+    line
+}
+
+/// used when there is no display (or it failed to initialize)
+pub struct NoDisplay;
+
+impl TextDisplay for NoDisplay {
+    type Error = std::convert::Infallible;
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn write_line(&mut self, _n: usize, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// `None` if the display failed to initialize
+impl<D: TextDisplay> TextDisplay for Option<D> {
+    type Error = D::Error;
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.as_mut().map_or(Ok(()), D::clear)
+    }
+    fn write_line(&mut self, n: usize, text: &str) -> Result<(), Self::Error> {
+        self.as_mut().map_or(Ok(()), |d| d.write_line(n, text))
+    }
+}
+
+#[cfg(feature = "display")]
+pub type Ssd1306Display<I> = Ssd1306<I2CInterface<I>, DisplaySize128x64, TerminalMode>;
+
+/// initialize a 128x64 SSD1306 on `i2c`, in terminal mode
+#[cfg(feature = "display")]
+pub fn init_ssd1306<I: I2c>(i2c: I) -> Result<Ssd1306Display<I>, TerminalModeError> {
+    let mut display = Ssd1306::new(
+        I2CDisplayInterface::new(i2c),
+        DisplaySize128x64,
+        DisplayRotation::Rotate0,
+    )
+    .into_terminal_mode();
+    display.init()?;
+    Ok(display)
+}
+
+#[cfg(feature = "display")]
+impl<I: I2c> TextDisplay for Ssd1306Display<I> {
+    type Error = TerminalModeError;
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        Ssd1306::clear(self)
+    }
+    fn write_line(&mut self, n: usize, text: &str) -> Result<(), Self::Error> {
+        self.set_position(0, n as u8)?;
+        text.chars().try_for_each(|c| self.print_char(c))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockDisplay {
+        lines: [String; LINES],
+        writes: usize,
+        fail: bool,
+    }
+
+    impl TextDisplay for MockDisplay {
+        type Error = ();
+        fn clear(&mut self) -> Result<(), ()> {
+            self.lines = std::array::from_fn(|_| render_line(""));
+            Ok(())
+        }
+        fn write_line(&mut self, n: usize, text: &str) -> Result<(), ()> {
+            if self.fail {
+                return Err(());
+            }
+            assert_eq!(text.chars().count(), COLUMNS);
+            self.writes += 1;
+            self.lines[n] = text.to_string();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn render_truncates_and_pads() {
+        assert_eq!(render_line(""), " ".repeat(COLUMNS));
+        assert_eq!(render_line("hi"), format!("hi{}", " ".repeat(COLUMNS - 2)));
+        assert_eq!(render_line("0123456789abcdefXYZ"), "0123456789abcdef");
+        // counts characters, not bytes
+        assert_eq!(render_line("°C").chars().count(), COLUMNS);
+    }
+
+    #[test]
+    fn only_changed_lines_are_written() {
+        let mut display = StatusDisplay::new(MockDisplay::default());
+        display.set_line(0, "connecting");
+        display.set_line(0, "connecting");
+        display.set_line(LINES, "off the bottom");
+        assert_eq!(display.display.writes, 1);
+        display.show_connected("home", std::net::Ipv4Addr::new(10, 0, 0, 2));
+        let mock = display.into_inner();
+        // the three lines of `show_connected`, the rest were already blank
+        assert_eq!(mock.writes, 4);
+        assert_eq!(mock.lines[0], render_line("wifi connected"));
+        assert_eq!(mock.lines[1], render_line("home"));
+        assert_eq!(mock.lines[2], render_line("10.0.0.2"));
+    }
+
+    #[test]
+    fn error_wraps_and_clears_old_lines() {
+        let mut display = StatusDisplay::new(MockDisplay::default());
+        display.set_lines(&["a", "b", "c", "d"]);
+        display.show_error("server did not respond in time");
+        let mock = display.into_inner();
+        assert_eq!(mock.lines[0], render_line("-- ERROR --"));
+        assert_eq!(mock.lines[1], "server did not r");
+        assert_eq!(mock.lines[2], render_line("espond in time"));
+        assert_eq!(mock.lines[3], render_line(""));
+    }
+
+    #[test]
+    fn failed_write_is_retried() {
+        let mut display = StatusDisplay::new(MockDisplay {
+            fail: true,
+            ..Default::default()
+        });
+        display.set_line(0, "reading");
+        display.display.fail = false;
+        display.set_line(0, "reading");
+        assert_eq!(display.into_inner().lines[0], render_line("reading"));
+    }
+}
@@ -0,0 +1,116 @@
+//! Counting pulses from a GPIO interrupt (the reed switches in the anemometer and rain gauge)
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering::Relaxed},
+    Arc,
+};
+
+use esp_idf_hal::gpio::{Input, InputPin, InterruptType, OutputPin, PinDriver, Pull};
+use esp_idf_sys::EspError;
+
+use crate::flag::Flag;
+
+/// Pulse count, shared with an ISR
+///
+/// only does atomic operations, so it is safe to use from an ISR context
+#[derive(Debug, Clone, Default)]
+pub struct PulseCount(Arc<AtomicU32>);
+
+impl PulseCount {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// record a pulse (called from the ISR)
+    pub fn pulse(&self) {
+        self.0.fetch_add(1, Relaxed);
+    }
+
+    /// pulses since the last call to `take`, resetting the count
+    ///
+    /// pulses that arrive during the call are never lost, they are either returned or counted for next time
+    pub fn take(&self) -> u32 {
+        self.0.swap(0, Relaxed)
+    }
+}
+
+/// A GPIO pin counting pos-edge pulses
+///
+/// the interrupt is disabled after each pulse, so `rearm` must be called whenever `pulse` resolves
+pub struct PulseInput<P: InputPin + OutputPin> {
+    driver: PinDriver<'static, P, Input>,
+    count: PulseCount,
+    flag: Flag,
+    /// error from re-enabling the interrupt (pulses will not be counted until this is fixed)
+    error: Option<EspError>,
+}
+
+impl<P: InputPin + OutputPin> PulseInput<P> {
+    pub fn new(pin: P) -> Result<Self, EspError> {
+        let count = PulseCount::new();
+        let flag = Flag::new();
+        let mut driver = PinDriver::input(pin)?;
+        driver.set_pull(Pull::Down)?;
+        driver.set_interrupt_type(InterruptType::PosEdge)?;
+        let (isr_count, isr_flag) = (count.clone(), flag.clone());
+        unsafe {
+            driver.subscribe(move || {
+                // Saftey (this itself is safe, but its executing in an ISR context)
+                // this is only doing atomic memory accesses, which should be fine :shrug:
+                isr_count.pulse();
+                isr_flag.signal();
+            })?;
+        }
+        driver.enable_interrupt()?;
+        Ok(Self {
+            driver,
+            count,
+            flag,
+            error: None,
+        })
+    }
+
+    /// resolves once a pulse has been received
+    pub fn pulse(&self) -> Flag {
+        self.flag.clone()
+    }
+
+    /// re-enable the interrupt after a pulse
+    pub fn rearm(&mut self) {
+        self.flag.reset();
+        if let Err(e) = self.driver.enable_interrupt() {
+            self.error = Some(e);
+        }
+    }
+
+    /// pulses since the last call to `take`, resetting the count
+    pub fn take(&self) -> u32 {
+        self.count.take()
+    }
+
+    pub fn fix(&mut self) {
+        if self.error.take().is_some() {
+            self.rearm();
+        }
+    }
+
+    pub fn err(&self) -> Option<&EspError> {
+        self.error.as_ref()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn pulse_count_reset() {
+    let count = PulseCount::new();
+    assert_eq!(count.take(), 0);
+    let isr = count.clone();
+    for _ in 0..5 {
+        isr.pulse();
+    }
+    assert_eq!(count.take(), 5);
+    // taking resets the count
+    assert_eq!(count.take(), 0);
+    isr.pulse();
+    assert_eq!(count.take(), 1);
+}
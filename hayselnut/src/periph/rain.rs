@@ -0,0 +1,109 @@
+//! Rainfall, from a tipping bucket rain gauge that closes a reed switch once per tip
+
+use std::collections::HashMap;
+
+use esp_idf_hal::gpio::{InputPin, OutputPin};
+use esp_idf_sys::EspError;
+use squirrel::api::station::capabilities::{
+    Channel, ChannelData, ChannelID, ChannelType, ChannelValue,
+};
+
+use super::{pulse::PulseInput, PeriphHealth, Peripheral, SensorPeripheral};
+use crate::flag::Flag;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RainCalibration {
+    /// rainfall (mm) per tip of the bucket
+    pub mm_per_tip: f32,
+}
+
+impl Default for RainCalibration {
+    fn default() -> Self {
+        // 0.011 in per tip, common for cheap rain gauges
+        Self { mm_per_tip: 0.2794 }
+    }
+}
+
+/// rainfall (mm) from `tips` of the bucket
+pub fn tips_to_rainfall(tips: u32, cal: &RainCalibration) -> f32 {
+    tips as f32 * cal.mm_per_tip
+}
+
+pub struct PeriphRain<P: InputPin + OutputPin> {
+    input: PulseInput<P>,
+    cal: RainCalibration,
+}
+
+impl<P: InputPin + OutputPin> PeriphRain<P> {
+    pub fn new(pin: P, cal: RainCalibration) -> Result<Self, EspError> {
+        Ok(Self {
+            input: PulseInput::new(pin)?,
+            cal,
+        })
+    }
+
+    /// resolves on every tip, `rearm` must then be called
+    pub fn pulse(&self) -> Flag {
+        self.input.pulse()
+    }
+
+    pub fn rearm(&mut self) {
+        self.input.rearm()
+    }
+}
+
+impl<P: InputPin + OutputPin> Peripheral for PeriphRain<P> {
+    type Error = EspError;
+    fn fix(&mut self) {
+        self.input.fix()
+    }
+    fn err(&self) -> Option<&Self::Error> {
+        self.input.err()
+    }
+}
+
+impl<P: InputPin + OutputPin> SensorPeripheral for PeriphRain<P> {
+    fn channels(&self) -> Vec<Channel> {
+        vec![Channel {
+            name: "rainfall".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: Some("mm".into()),
+            description: Some("rainfall since the last reading".into()),
+        }]
+    }
+
+    fn health(&self) -> PeriphHealth {
+        match self.err() {
+            None => PeriphHealth::Ok,
+            Some(e) => PeriphHealth::Degraded(format!("{e:?}")),
+        }
+    }
+
+    /// rainfall since the last read
+    fn read(
+        &mut self,
+        map_fn: &impl Fn(&str) -> ChannelID,
+    ) -> Option<HashMap<ChannelID, ChannelData>> {
+        if self.err().is_some() {
+            return None;
+        }
+        let rainfall = tips_to_rainfall(self.input.take(), &self.cal);
+        Some(HashMap::from([(
+            map_fn("rainfall"),
+            ChannelData::Float(rainfall),
+        )]))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn tips_to_rainfall_mm() {
+    let cal = RainCalibration::default();
+    assert_eq!(tips_to_rainfall(0, &cal), 0.0);
+    assert!((tips_to_rainfall(1, &cal) - 0.2794).abs() < 1e-6);
+    // 1 inch
+    assert!((tips_to_rainfall(91, &cal) - 25.4254).abs() < 1e-3);
+    let cal = RainCalibration { mm_per_tip: 0.5 };
+    assert_eq!(tips_to_rainfall(4, &cal), 2.0);
+}
@@ -0,0 +1,123 @@
+//! Wind speed, from a cup anemometer that closes a reed switch once per rotation
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use esp_idf_hal::gpio::{InputPin, OutputPin};
+use esp_idf_sys::EspError;
+use squirrel::api::station::capabilities::{
+    Channel, ChannelData, ChannelID, ChannelType, ChannelValue,
+};
+
+use super::{pulse::PulseInput, PeriphHealth, Peripheral, SensorPeripheral};
+use crate::flag::Flag;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WindCalibration {
+    /// wind speed (m/s) at one pulse per second
+    pub mps_per_hz: f32,
+}
+
+impl Default for WindCalibration {
+    fn default() -> Self {
+        // 2.4 km/h per Hz, common for cheap anemometers
+        Self { mps_per_hz: 0.667 }
+    }
+}
+
+/// average wind speed (m/s) from `pulses` counted over `elapsed`
+pub fn pulses_to_speed(pulses: u32, elapsed: Duration, cal: &WindCalibration) -> f32 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    pulses as f32 / elapsed.as_secs_f32() * cal.mps_per_hz
+}
+
+pub struct PeriphWind<P: InputPin + OutputPin> {
+    input: PulseInput<P>,
+    cal: WindCalibration,
+    /// start of the period that the current count covers
+    since: Instant,
+}
+
+impl<P: InputPin + OutputPin> PeriphWind<P> {
+    pub fn new(pin: P, cal: WindCalibration) -> Result<Self, EspError> {
+        Ok(Self {
+            input: PulseInput::new(pin)?,
+            cal,
+            since: Instant::now(),
+        })
+    }
+
+    /// resolves on every rotation, `rearm` must then be called
+    pub fn pulse(&self) -> Flag {
+        self.input.pulse()
+    }
+
+    pub fn rearm(&mut self) {
+        self.input.rearm()
+    }
+}
+
+impl<P: InputPin + OutputPin> Peripheral for PeriphWind<P> {
+    type Error = EspError;
+    fn fix(&mut self) {
+        self.input.fix()
+    }
+    fn err(&self) -> Option<&Self::Error> {
+        self.input.err()
+    }
+}
+
+impl<P: InputPin + OutputPin> SensorPeripheral for PeriphWind<P> {
+    fn channels(&self) -> Vec<Channel> {
+        vec![Channel {
+            name: "wind_speed".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: Some("m/s".into()),
+            description: Some("average wind speed since the last reading".into()),
+        }]
+    }
+
+    fn health(&self) -> PeriphHealth {
+        match self.err() {
+            None => PeriphHealth::Ok,
+            Some(e) => PeriphHealth::Degraded(format!("{e:?}")),
+        }
+    }
+
+    /// average speed since the last read
+    fn read(
+        &mut self,
+        map_fn: &impl Fn(&str) -> ChannelID,
+    ) -> Option<HashMap<ChannelID, ChannelData>> {
+        if self.err().is_some() {
+            return None;
+        }
+        let now = Instant::now();
+        let speed = pulses_to_speed(self.input.take(), now - self.since, &self.cal);
+        self.since = now;
+        Some(HashMap::from([(
+            map_fn("wind_speed"),
+            ChannelData::Float(speed),
+        )]))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn pulses_to_wind_speed() {
+    let cal = WindCalibration::default();
+    assert_eq!(pulses_to_speed(0, Duration::from_secs(30), &cal), 0.0);
+    // 1 Hz
+    assert!((pulses_to_speed(30, Duration::from_secs(30), &cal) - 0.667).abs() < 1e-6);
+    // 10 Hz, over a non-integer period
+    assert!((pulses_to_speed(25, Duration::from_millis(2500), &cal) - 6.67).abs() < 1e-4);
+    // no time has passed
+    assert_eq!(pulses_to_speed(3, Duration::ZERO, &cal), 0.0);
+    let cal = WindCalibration { mps_per_hz: 1.0 };
+    assert_eq!(pulses_to_speed(60, Duration::from_secs(30), &cal), 2.0);
+}
@@ -0,0 +1,194 @@
+//! Wind direction, from a wind vane whose resistor network gives a distinct voltage for each heading
+
+use std::collections::HashMap;
+
+use esp_idf_hal::{
+    adc::{self, Adc, AdcChannelDriver, AdcDriver},
+    gpio::ADCPin,
+};
+use esp_idf_sys::EspError;
+use squirrel::api::station::capabilities::{
+    Channel, ChannelData, ChannelID, ChannelType, ChannelValue,
+};
+
+use super::{PeriphHealth, Peripheral, SensorPeripheral};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionBand {
+    /// reading (mV) at the center of this band
+    pub millivolts: u16,
+    pub degrees: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindVaneCalibration {
+    pub bands: Vec<DirectionBand>,
+    /// maximum distance (mV) from the nearest band for a reading to be accepted
+    pub tolerance_mv: u16,
+    /// rotation (degrees) of the vane's north from true north
+    pub offset: f32,
+}
+
+impl Default for WindVaneCalibration {
+    fn default() -> Self {
+        // common 16 direction vane, with a 10k pull up to 3.3v
+        let bands = [
+            (2533, 0.0),
+            (1305, 22.5),
+            (1485, 45.0),
+            (270, 67.5),
+            (297, 90.0),
+            (211, 112.5),
+            (594, 135.0),
+            (409, 157.5),
+            (924, 180.0),
+            (785, 202.5),
+            (2033, 225.0),
+            (1933, 247.5),
+            (3049, 270.0),
+            (2667, 292.5),
+            (2857, 315.0),
+            (2264, 337.5),
+        ]
+        .map(|(millivolts, degrees)| DirectionBand {
+            millivolts,
+            degrees,
+        })
+        .to_vec();
+        Self {
+            bands,
+            tolerance_mv: 50,
+            offset: 0.0,
+        }
+    }
+}
+
+/// Heading (degrees, in `[0, 360)`) of the band nearest to `millivolts`, or None if it is not near any band
+pub fn decode_heading(millivolts: u16, cal: &WindVaneCalibration) -> Option<f32> {
+    let band = cal
+        .bands
+        .iter()
+        .min_by_key(|band| band.millivolts.abs_diff(millivolts))?;
+    if band.millivolts.abs_diff(millivolts) > cal.tolerance_mv {
+        return None;
+    }
+    Some((band.degrees + cal.offset).rem_euclid(360.0))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WindDirectionError {
+    #[error("failed to read the ADC: {0:?}")]
+    Adc(EspError),
+    /// likely a disconnected vane, or a bad calibration
+    #[error("reading of {0}mV does not match any direction")]
+    OutOfBand(u16),
+}
+
+pub struct PeriphWindDirection<'a, P: ADCPin> {
+    driver: AdcChannelDriver<'a, { adc::attenuation::DB_11 }, P>,
+    cal: WindVaneCalibration,
+    /// heading from the last sample
+    heading: Option<f32>,
+    error: Option<WindDirectionError>,
+}
+
+impl<'a, P: ADCPin> PeriphWindDirection<'a, P> {
+    pub fn new(pin: P, cal: WindVaneCalibration) -> Result<Self, EspError> {
+        Ok(Self {
+            driver: AdcChannelDriver::new(pin)?,
+            cal,
+            heading: None,
+            error: None,
+        })
+    }
+
+    /// sample the vane (the ADC is shared, so this is separate from `read`)
+    pub fn sample<'b, ADC: Adc>(&mut self, adc: &mut AdcDriver<'b, ADC>)
+    where
+        P: ADCPin<Adc = ADC>,
+    {
+        self.heading = None;
+        match adc.read(&mut self.driver) {
+            Ok(mv) => match decode_heading(mv, &self.cal) {
+                Some(heading) => self.heading = Some(heading),
+                None => self.error = Some(WindDirectionError::OutOfBand(mv)),
+            },
+            Err(e) => self.error = Some(WindDirectionError::Adc(e)),
+        }
+    }
+}
+
+impl<'a, P: ADCPin> Peripheral for PeriphWindDirection<'a, P> {
+    type Error = WindDirectionError;
+    fn fix(&mut self) {
+        // nothing to re-initialize, the next sample will tell if it is still broken
+        self.error = None;
+    }
+    fn err(&self) -> Option<&Self::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<'a, P: ADCPin> SensorPeripheral for PeriphWindDirection<'a, P> {
+    fn channels(&self) -> Vec<Channel> {
+        vec![Channel {
+            name: "wind_direction".into(),
+            value: ChannelValue::Float,
+            ty: ChannelType::Periodic,
+            unit: Some("°".into()),
+            description: Some("direction the wind is blowing from (0 is north)".into()),
+        }]
+    }
+
+    fn health(&self) -> PeriphHealth {
+        match self.err() {
+            None => PeriphHealth::Ok,
+            Some(e) => PeriphHealth::Degraded(e.to_string()),
+        }
+    }
+
+    /// heading (degrees) from the last sample
+    fn read(
+        &mut self,
+        map_fn: &impl Fn(&str) -> ChannelID,
+    ) -> Option<HashMap<ChannelID, ChannelData>> {
+        if self.err().is_some() {
+            return None;
+        }
+        let heading = self.heading.take()?;
+        Some(HashMap::from([(
+            map_fn("wind_direction"),
+            ChannelData::Float(heading),
+        )]))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn adc_to_heading() {
+    let cal = WindVaneCalibration::default();
+    assert_eq!(decode_heading(2533, &cal), Some(0.0));
+    assert_eq!(decode_heading(924, &cal), Some(180.0));
+    // noisy readings go to the nearest band
+    assert_eq!(decode_heading(2560, &cal), Some(0.0));
+    assert_eq!(decode_heading(280, &cal), Some(67.5));
+    assert_eq!(decode_heading(290, &cal), Some(90.0));
+    assert_eq!(decode_heading(2250, &cal), Some(337.5));
+    // disconnected (pulled up) or shorted
+    assert_eq!(decode_heading(3300, &cal), None);
+    assert_eq!(decode_heading(0, &cal), None);
+
+    // the vane is mounted with its north pointing east, so headings wrap past 360
+    let cal = WindVaneCalibration {
+        offset: 90.0,
+        ..WindVaneCalibration::default()
+    };
+    assert_eq!(decode_heading(2533, &cal), Some(90.0));
+    assert_eq!(decode_heading(3049, &cal), Some(0.0));
+    assert_eq!(decode_heading(2264, &cal), Some(67.5));
+    let cal = WindVaneCalibration {
+        offset: -45.0,
+        ..WindVaneCalibration::default()
+    };
+    assert_eq!(decode_heading(2533, &cal), Some(315.0));
+}
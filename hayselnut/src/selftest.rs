@@ -0,0 +1,95 @@
+//! Hardware self test, run at boot after the peripherals are initialized.
+//!
+//! a failing peripheral does not stop the station, its channels are instead reported to the server as degraded
+//! (in the `Connect` packet) so the missing data is explained.
+
+use squirrel::api::station::capabilities::ChannelName;
+
+#[derive(Debug)]
+pub struct Probe {
+    pub periph: &'static str,
+    /// channels that this peripheral provides data for
+    pub channels: Vec<ChannelName>,
+    /// description of the failure, None if it passed
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SelfTest {
+    probes: Vec<Probe>,
+}
+
+impl SelfTest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// record (and report) the result of probing `periph`
+    pub fn probe(
+        &mut self,
+        periph: &'static str,
+        channels: Vec<ChannelName>,
+        result: Result<(), String>,
+    ) {
+        match &result {
+            Ok(()) => info!("[selftest] {periph}: pass"),
+            Err(e) => error!("[selftest] {periph}: FAIL ({e}), continuing without it"),
+        }
+        self.probes.push(Probe {
+            periph,
+            channels,
+            failure: result.err(),
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.probes.iter().all(|p| p.failure.is_none())
+    }
+
+    /// channels provided by a failed peripheral, that are not also provided by one that passed
+    pub fn degraded_channels(&self) -> Vec<ChannelName> {
+        let mut degraded = vec![];
+        for ch in self
+            .probes
+            .iter()
+            .filter(|p| p.failure.is_some())
+            .flat_map(|p| &p.channels)
+        {
+            let provided = self
+                .probes
+                .iter()
+                .any(|p| p.failure.is_none() && p.channels.contains(ch));
+            if !provided && !degraded.contains(ch) {
+                degraded.push(ch.clone());
+            }
+        }
+        degraded
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn degraded_channel_set() {
+    let mut test = SelfTest::new();
+    test.probe("battery ADC", vec!["battery".into()], Ok(()));
+    assert!(test.passed());
+    assert!(test.degraded_channels().is_empty());
+
+    let bme = || {
+        ["temperature", "humidity", "pressure"]
+            .map(ChannelName::from)
+            .to_vec()
+    };
+    test.probe("BME280", bme(), Err("chip id mismatch".into()));
+    assert!(!test.passed());
+    assert_eq!(test.degraded_channels(), bme());
+
+    // the same channel from two failed sensors is only reported once, and a channel provided by a working sensor
+    // is not degraded
+    test.probe("BME280 (2)", bme(), Err("no ack".into()));
+    test.probe("SHT31", vec!["humidity".into()], Ok(()));
+    assert_eq!(
+        test.degraded_channels(),
+        vec!["temperature".into(), "pressure".into()]
+    );
+}
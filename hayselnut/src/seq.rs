@@ -0,0 +1,69 @@
+//! Sequence numbers for `Data` packets, so the server can drop duplicates.
+//!
+//! they must keep increasing across reboots (and deep sleep), but writing NVS on every send would wear out the
+//! flash. instead a block of numbers is reserved in NVS at a time, and a reboot skips the rest of the block.
+
+/// how many sequence numbers are reserved at once
+pub const RESERVE: u64 = 64;
+
+#[derive(Debug)]
+pub struct SeqCounter {
+    next: u64,
+    /// everything below this has been reserved in NVS
+    reserved: u64,
+}
+
+impl SeqCounter {
+    /// resume counting from the value stored in NVS (everything below it may have been used already)
+    pub fn resume(stored: u64) -> Self {
+        // 0 means unsequenced
+        let start = stored.max(1);
+        Self {
+            next: start,
+            reserved: start,
+        }
+    }
+
+    /// The next sequence number, and a new value that must be stored in NVS before it is used (if one is needed)
+    pub fn next(&mut self) -> (u64, Option<u64>) {
+        let store = if self.next >= self.reserved {
+            self.reserved = self.next + RESERVE;
+            Some(self.reserved)
+        } else {
+            None
+        };
+        let seq = self.next;
+        self.next += 1;
+        (seq, store)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn seq_continues_across_reboots() {
+    // NVS
+    let mut stored = 0;
+    let mut counter = SeqCounter::resume(stored);
+    let mut seen = vec![];
+    let mut writes = 0;
+    for i in 0..1000 {
+        let (seq, store) = counter.next();
+        if let Some(store) = store {
+            stored = store;
+            writes += 1;
+        }
+        seen.push(seq);
+        // reboot every so often
+        if i % 150 == 149 {
+            counter = SeqCounter::resume(stored);
+        }
+    }
+    assert_eq!(seen[0], 1, "0 is reserved for unsequenced data");
+    assert!(
+        seen.windows(2).all(|w| w[0] < w[1]),
+        "not strictly increasing"
+    );
+    // NVS is written far less often than once per packet
+    // (once per block, plus once per reboot)
+    assert!(writes <= 1000 / RESERVE as usize + 1 + 6);
+}
@@ -0,0 +1,191 @@
+//! Line based commands over the console UART, for field technicians with a serial cable.
+//!
+//! lines are read on a background thread (the console is blocking / polled) and handled in the main loop.
+//! without the `serial_cmd` feature nothing is read, and no commands are ever received
+
+use std::{
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use crate::flag::Flag;
+
+pub const HELP: &str = "\
+commands:
+  status              connection and sensor status
+  reconnect           reconnect to wifi and the server
+  read-now            take (and send) a reading now
+  set-interval <secs> change the time between readings (until reset)
+  dump-config         print the compiled in configuration
+  dump-log            print the local log of readings (`csv-log` feature)
+  help                print this message";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Status,
+    Reconnect,
+    ReadNow,
+    SetInterval(Duration),
+    DumpConfig,
+    DumpLog,
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+    #[error("unknown command {0:?} (try `help`)")]
+    Unknown(String),
+    #[error("`{0}` takes {1}")]
+    BadArgs(&'static str, &'static str),
+    #[error("the interval must be at least one second")]
+    ZeroInterval,
+}
+
+/// Parses one line. blank lines are ignored (None)
+pub fn parse(line: &str) -> Result<Option<Command>, ParseError> {
+    let mut words = line.split_whitespace();
+    let Some(cmd) = words.next() else {
+        return Ok(None);
+    };
+    let args = words.collect::<Vec<_>>();
+    let no_args = |name, cmd| {
+        if args.is_empty() {
+            Ok(Some(cmd))
+        } else {
+            Err(ParseError::BadArgs(name, "no arguments"))
+        }
+    };
+    match cmd.to_ascii_lowercase().as_str() {
+        "status" => no_args("status", Command::Status),
+        "reconnect" => no_args("reconnect", Command::Reconnect),
+        "read-now" => no_args("read-now", Command::ReadNow),
+        "set-interval" => match args.as_slice() {
+            [secs] => match secs.parse::<u64>() {
+                Ok(0) => Err(ParseError::ZeroInterval),
+                Ok(secs) => Ok(Some(Command::SetInterval(Duration::from_secs(secs)))),
+                Err(..) => Err(ParseError::BadArgs("set-interval", "a number of seconds")),
+            },
+            _ => Err(ParseError::BadArgs("set-interval", "a number of seconds")),
+        },
+        "dump-config" => no_args("dump-config", Command::DumpConfig),
+        "dump-log" => no_args("dump-log", Command::DumpLog),
+        "help" | "?" => no_args("help", Command::Help),
+        _ => Err(ParseError::Unknown(cmd.to_string())),
+    }
+}
+
+pub struct SerialCommands {
+    lines: Receiver<String>,
+    ready: Flag,
+}
+
+impl SerialCommands {
+    /// start reading commands from the console
+    pub fn start() -> Self {
+        #[allow(unused_variables)]
+        let (send, lines) = mpsc::channel();
+        let ready = Flag::new();
+        #[cfg(feature = "serial_cmd")]
+        {
+            let ready = ready.clone();
+            std::thread::Builder::new()
+                .name("serial_cmd".into())
+                .stack_size(4096)
+                .spawn(move || read_lines(send, ready))
+                .expect("failed to start the serial command thread");
+        }
+        Self { lines, ready }
+    }
+
+    /// waits until a line has been received
+    pub async fn wait(&self) {
+        self.ready.clone().await
+    }
+
+    /// the commands received since the last call
+    pub fn take(&self) -> Vec<Result<Command, ParseError>> {
+        // (reset first, so that a line received while draining is not missed)
+        self.ready.reset();
+        self.lines
+            .try_iter()
+            .filter_map(|line| parse(&line).transpose())
+            .collect()
+    }
+}
+
+/// reads lines from stdin (the console) forever, passing them to `send`
+#[cfg(feature = "serial_cmd")]
+fn read_lines(send: mpsc::Sender<String>, ready: Flag) {
+    use std::io::{ErrorKind, Read};
+
+    let mut stdin = std::io::stdin().lock();
+    let mut line = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(n) if n > 0 => n,
+            // (the console does not block waiting for input)
+            Ok(..) => {
+                std::thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {
+                std::thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(e) => {
+                error!("failed to read from the console ({e:?}), serial commands will not work");
+                return;
+            }
+        };
+        for &b in &buf[..n] {
+            if b == b'\n' || b == b'\r' {
+                if !line.is_empty() {
+                    let _ = send.send(String::from_utf8_lossy(&line).into_owned());
+                    ready.signal();
+                    line.clear();
+                }
+            } else {
+                line.push(b);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn parse_commands() {
+    use Command as C;
+
+    assert_eq!(parse("status"), Ok(Some(C::Status)));
+    assert_eq!(parse("  reconnect \r"), Ok(Some(C::Reconnect)));
+    assert_eq!(parse("READ-NOW"), Ok(Some(C::ReadNow)));
+    assert_eq!(
+        parse("set-interval 300"),
+        Ok(Some(C::SetInterval(Duration::from_secs(300))))
+    );
+    assert_eq!(parse("dump-config"), Ok(Some(C::DumpConfig)));
+    assert_eq!(parse("dump-log"), Ok(Some(C::DumpLog)));
+    assert_eq!(parse("?"), Ok(Some(C::Help)));
+    assert_eq!(parse(""), Ok(None));
+    assert_eq!(parse("   "), Ok(None));
+
+    assert_eq!(parse("reboot"), Err(ParseError::Unknown("reboot".into())));
+    assert_eq!(
+        parse("status now"),
+        Err(ParseError::BadArgs("status", "no arguments"))
+    );
+    for bad in [
+        "set-interval",
+        "set-interval soon",
+        "set-interval 1 2",
+        "set-interval -5",
+    ] {
+        assert_eq!(
+            parse(bad),
+            Err(ParseError::BadArgs("set-interval", "a number of seconds")),
+            "{bad}"
+        );
+    }
+    assert_eq!(parse("set-interval 0"), Err(ParseError::ZeroInterval));
+}
@@ -0,0 +1,111 @@
+//! Deep sleep bookkeeping: remembering why the chip went to sleep, so the reason it woke can be acted on.
+//!
+//! deep sleep powers down everything except the RTC, so waking is a reset -- `main` runs from the start,
+//! and wifi + the server connection are re-established as they would be on power on.
+
+use std::{cell::SyncUnsafeCell, time::Duration};
+
+use esp_idf_sys::{
+    esp_deep_sleep_start, esp_sleep_disable_wakeup_source, esp_sleep_enable_timer_wakeup,
+};
+
+// stored in RTC fast memory, not powered off by default even in deep sleep
+// saftey of access: pinky promise that this code is single threadded
+#[link_section = ".rtc.data"]
+static DEEP_SLEEP_CAUSE: SyncUnsafeCell<SleepCause> = SyncUnsafeCell::new(SleepCause::None);
+
+/// why the chip last went into deep sleep
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SleepCause {
+    None,
+    /// halted after a panic, should never wake
+    Panic,
+    /// sleeping between measurements (low power mode)
+    Timer,
+}
+
+/// the parts of `ResetReason` that matter for deciding what to do on wake
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reset {
+    /// power on, external reset pin, or anything else that should start as normal
+    Normal,
+    DeepSleep,
+    Panic,
+}
+
+/// what to do after a reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeAction {
+    /// start up as normal
+    Run,
+    /// the chip panicked, report it and halt
+    HaltAfterPanic,
+    /// woken from a halt (this should not happen) go back to sleep
+    Halt,
+}
+
+/// Decide what to do on reset, from the reset reason and the stored sleep cause.
+///
+/// returns the action, and the sleep cause that should be stored
+pub fn on_wake(reset: Reset, stored: SleepCause) -> (WakeAction, SleepCause) {
+    match (reset, stored) {
+        (Reset::Panic, _) => (WakeAction::HaltAfterPanic, SleepCause::Panic),
+        // esp docs LIE! (somehow, the chip was woken from deep sleep)
+        // leave the cause as is
+        (Reset::DeepSleep, SleepCause::Panic) => (WakeAction::Halt, SleepCause::Panic),
+        // normal timed wake between measurements, or a wake with no known cause (hmmmmm)
+        (Reset::DeepSleep, SleepCause::Timer | SleepCause::None) => {
+            (WakeAction::Run, SleepCause::None)
+        }
+        (Reset::Normal, _) => (WakeAction::Run, SleepCause::None),
+    }
+}
+
+/// the sleep cause stored before the last reset
+pub fn stored_cause() -> SleepCause {
+    unsafe { *DEEP_SLEEP_CAUSE.get() }
+}
+
+pub fn set_stored_cause(cause: SleepCause) {
+    unsafe { *DEEP_SLEEP_CAUSE.get() = cause }
+}
+
+/// Sleep forever (or at least until the chip is reset)
+pub fn halt() -> ! {
+    set_stored_cause(SleepCause::Panic);
+    unsafe {
+        esp_sleep_disable_wakeup_source(esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL);
+        esp_deep_sleep_start()
+    }
+}
+
+/// Deep sleep for `time`, waking with a reset.
+pub fn sleep_for(time: Duration) -> ! {
+    set_stored_cause(SleepCause::Timer);
+    unsafe {
+        esp_sleep_enable_timer_wakeup(time.as_micros() as u64);
+        esp_deep_sleep_start()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn reset_state_machine() {
+    use SleepCause as C;
+    use WakeAction as A;
+    // power on, regardless of what garbage is in RTC memory
+    assert_eq!(on_wake(Reset::Normal, C::None), (A::Run, C::None));
+    assert_eq!(on_wake(Reset::Normal, C::Timer), (A::Run, C::None));
+    // panic -> halt, and stay halted if woken
+    let (action, cause) = on_wake(Reset::Panic, C::None);
+    assert_eq!((action, cause), (A::HaltAfterPanic, C::Panic));
+    assert_eq!(on_wake(Reset::DeepSleep, cause), (A::Halt, C::Panic));
+    // a panic while in low power mode still halts
+    assert_eq!(
+        on_wake(Reset::Panic, C::Timer),
+        (A::HaltAfterPanic, C::Panic)
+    );
+    // timed wakes run as normal, and clear the cause so a later unexpected wake is not mistaken for one
+    assert_eq!(on_wake(Reset::DeepSleep, C::Timer), (A::Run, C::None));
+    assert_eq!(on_wake(Reset::DeepSleep, C::None), (A::Run, C::None));
+}
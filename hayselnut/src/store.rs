@@ -1,18 +1,20 @@
-use std::mem::size_of;
-
 use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsPartitionId};
 use esp_idf_sys::EspError;
 use serde::{Deserialize, Serialize};
 use static_assertions::const_assert;
 use uuid::Uuid;
 
-//TODO: implement a way of upgrading prev versions
-pub const CURRENT_VERSION: u64 = 1;
+/// version of the `StationStoreData` blob. it is the first byte of the blob (see [`decode`] for older versions)
+pub const CURRENT_VERSION: u8 = 2;
 
 pub const NAMESPACE: &str = "haysel_store";
 pub const STATION_STORE_ID: &str = "data";
+/// version of the blob, only used by version 1 (newer versions store it in the blob itself, so it is updated with it)
 pub const STATION_STORE_VERSION_ID: &str = "id";
-// might need to increase if StationStoreData gets too large
+/// data sequence numbers reserved so far (see `seq`)
+pub const SEQ_ID: &str = "seq";
+/// space reserved for the blob. the current data uses 33 bytes of this (version, map marker, the field name, and the
+/// UUID), [`encode`] errors if it ever grows past it
 pub const STORE_DATA_SIZE: usize = 48;
 
 const_assert!(NAMESPACE.len() <= 15); // namespace must be <15 chars
@@ -23,13 +25,11 @@ pub struct StationStoreCached<T: NvsPartitionId> {
 }
 
 impl<T: NvsPartitionId> StationStoreCached<T> {
-    pub fn init(partition: EspNvsPartition<T>) -> Result<Self, EspError> {
+    pub fn init(partition: EspNvsPartition<T>) -> Result<Self, StoreError> {
         let mut store = StationStoreAccess::new(partition)?;
         let station_info = if !store.exists()? {
             warn!("Performing first-time initialization of station information");
-            let default = StationStoreData {
-                station_uuid: Uuid::new_v4(),
-            };
+            let default = StationStoreData::generate();
             warn!("Picked a UUID of {}", default.station_uuid);
             store.write(&default)?;
             default
@@ -48,7 +48,7 @@ impl<T: NvsPartitionId> StationStore for StationStoreCached<T> {
         &self.cache
     }
     #[doc(hidden)]
-    fn write(&mut self, new: StationStoreData) -> Result<(), EspError> {
+    fn write(&mut self, new: StationStoreData) -> Result<(), StoreError> {
         self.access.write(&new)?;
         self.cache = new;
         Ok(())
@@ -58,7 +58,7 @@ impl<T: NvsPartitionId> StationStore for StationStoreCached<T> {
 // trait objects cant use generics, you say?
 impl dyn StationStore {
     #[allow(unused)]
-    fn modify(&mut self, f: impl FnOnce(&mut StationStoreData)) -> Result<(), EspError> {
+    fn modify(&mut self, f: impl FnOnce(&mut StationStoreData)) -> Result<(), StoreError> {
         let mut v = *self.read(); // copy
         f(&mut v);
         if v != *self.read() {
@@ -70,14 +70,91 @@ impl dyn StationStore {
 
 pub trait StationStore {
     fn read(&self) -> &StationStoreData;
-    fn write(&mut self, new: StationStoreData) -> Result<(), EspError>;
+    fn write(&mut self, new: StationStoreData) -> Result<(), StoreError>;
 }
 
+/// Persistent station information
+///
+/// stored as a map, so fields can be added without a new version (they must have a `#[serde(default)]`)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StationStoreData {
     pub station_uuid: Uuid,
 }
 
+impl StationStoreData {
+    /// new information, for a station that has none stored
+    pub fn generate() -> Self {
+        Self {
+            station_uuid: Uuid::new_v4(),
+        }
+    }
+}
+
+/// the version 1 layout (serialized as an array, so it can not be extended)
+#[derive(Deserialize)]
+struct StationStoreDataV1 {
+    station_uuid: Uuid,
+}
+
+impl From<StationStoreDataV1> for StationStoreData {
+    fn from(v1: StationStoreDataV1) -> Self {
+        Self {
+            station_uuid: v1.station_uuid,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("stored data is from a newer firmware (version {0}, this firmware supports up to {CURRENT_VERSION})")]
+    Newer(u8),
+    #[error("stored data is empty")]
+    Empty,
+    #[error("failed to deserialize stored data: {0}")]
+    Deserialize(#[from] rmp_serde::decode::Error),
+    #[error("encoded data is {0} bytes, more than the {STORE_DATA_SIZE} reserved for it")]
+    TooLarge(usize),
+    #[error("NVS error: {0}")]
+    Nvs(#[from] EspError),
+}
+
+/// the blob for `data`, starting with [`CURRENT_VERSION`]
+pub fn encode(data: &StationStoreData) -> Result<Vec<u8>, StoreError> {
+    encode_blob(data)
+}
+
+fn encode_blob(data: &impl Serialize) -> Result<Vec<u8>, StoreError> {
+    let mut blob = vec![CURRENT_VERSION];
+    rmp_serde::encode::write_named(&mut blob, data).expect("Failed to serialize");
+    if blob.len() > STORE_DATA_SIZE {
+        return Err(StoreError::TooLarge(blob.len()));
+    }
+    Ok(blob)
+}
+
+/// Reads a stored blob, upgrading it from older versions. returns the data, and if it was upgraded (and should be written back)
+///
+/// version 1 had no version byte (the version was stored under [`STATION_STORE_VERSION_ID`]), its blobs start with a msgpack
+/// array marker (0x90..=0x9f), so versions must stay below that
+pub fn decode(blob: &[u8]) -> Result<(StationStoreData, bool), StoreError> {
+    match *blob.first().ok_or(StoreError::Empty)? {
+        CURRENT_VERSION => Ok((rmp_serde::from_slice(&blob[1..])?, false)),
+        0x90..=0x9f => {
+            let v1 = rmp_serde::from_slice::<StationStoreDataV1>(blob)?;
+            Ok((v1.into(), true))
+        }
+        version => Err(StoreError::Newer(version)),
+    }
+}
+
+/// Reads the station information from a blob written by a newer firmware (see [`decode`]), if it can be.
+///
+/// the blob is a map, and newer versions are expected to keep the fields this one knows (unknown ones are ignored),
+/// but a version may change anything, so this may fail
+pub fn decode_newer(blob: &[u8]) -> Option<StationStoreData> {
+    rmp_serde::from_slice(blob.get(1..)?).ok()
+}
+
 pub struct StationStoreAccess<T: NvsPartitionId> {
     nvs: EspNvs<T>,
 }
@@ -90,79 +167,144 @@ impl<T: NvsPartitionId> StationStoreAccess<T> {
     }
 
     pub fn exists(&mut self) -> Result<bool, EspError> {
-        Ok(
-            match (
-                self.nvs.contains(STATION_STORE_VERSION_ID)?,
-                self.nvs.contains(STATION_STORE_ID)?,
-            ) {
-                (false, false) => false,
-                (true, false) | (false, true) => {
-                    panic!("[one of] StationStore version/data is in NVS flash, but not the other!")
-                }
-                (true, true) => true,
-            },
-        )
+        self.nvs.contains(STATION_STORE_ID)
     }
 
-    pub fn read(&mut self) -> Result<Option<StationStoreData>, EspError> {
-        let mut id_buf = [0u8; size_of::<u64>()];
-        let Some(version) = self.nvs.get_raw(STATION_STORE_VERSION_ID, &mut id_buf)? else {
-            return Ok(None);
-        };
-        assert_eq!(
-            version.len(),
-            size_of::<u64>(),
-            "Size of stored version ID is too small/large!"
-        );
-
-        let mut id_buf2 = [0u8; size_of::<u64>()];
-        id_buf2.copy_from_slice(version);
-        let version = u64::from_be_bytes(id_buf2);
-        assert_eq!(
-            version, CURRENT_VERSION,
-            "Version of stored NVS data is mismatched (expected {CURRENT_VERSION} found {version})"
-        );
-
+    /// Reads the stored data, upgrading it to the current version (and writing it back) if it is older
+    ///
+    /// data from a newer firmware is left alone (so it is still there if that firmware is reinstalled). its station
+    /// information is used if it can be read (see [`decode_newer`]), and [`StoreError::Newer`] is returned if it can
+    /// not (new information would give the station a different id on every boot)
+    pub fn read(&mut self) -> Result<Option<StationStoreData>, StoreError> {
         let mut store_buf = [0u8; STORE_DATA_SIZE];
-        let Some(store) = self.nvs.get_raw(STATION_STORE_ID, &mut store_buf)? else {
+        let Some(blob) = self.nvs.get_raw(STATION_STORE_ID, &mut store_buf)? else {
             return Ok(None);
         };
-        let store = rmp_serde::from_slice(store).expect("Faild to deserialize NVS store");
+        let (store, upgraded) = match decode(blob) {
+            Ok(decoded) => decoded,
+            Err(StoreError::Newer(version)) => {
+                let Some(store) = decode_newer(blob) else {
+                    error!(
+                        "StationStore NVS data is version {version}, newer than this firmware supports ({CURRENT_VERSION}), \
+                        and could not be read"
+                    );
+                    return Err(StoreError::Newer(version));
+                };
+                warn!(
+                    "StationStore NVS data is version {version}, newer than this firmware supports ({CURRENT_VERSION}). \
+                    using its station information (it will not be modified)"
+                );
+                return Ok(Some(store));
+            }
+            Err(e) => return Err(e),
+        };
+        if upgraded {
+            warn!("Upgrading StationStore NVS data to version {CURRENT_VERSION}");
+            self.write(&store)?;
+        }
         Ok(Some(store))
     }
 
-    pub fn write(&mut self, store: &StationStoreData) -> Result<(), EspError> {
-        let mut id_buf = [0u8; size_of::<u64>()];
-        let version =
-            if let Some(version) = self.nvs.get_raw(STATION_STORE_VERSION_ID, &mut id_buf)? {
-                version
-            } else {
-                log::warn!(
-                    "Performing first-time initialization of StationStore NVS version information"
-                );
-                self.nvs
-                    .set_raw(STATION_STORE_VERSION_ID, &CURRENT_VERSION.to_be_bytes())?;
-                id_buf = CURRENT_VERSION.to_be_bytes();
-                &id_buf
-            };
-        assert_eq!(
-            version.len(),
-            size_of::<u64>(),
-            "Size of stored version ID is too small/large!"
-        );
-
-        let mut id_buf2 = [0u8; size_of::<u64>()];
-        id_buf2.copy_from_slice(version);
-        let version = u64::from_be_bytes(id_buf2);
-        assert_eq!(
-            version, CURRENT_VERSION,
-            "Version of stored NVS data is mismatched (expected {CURRENT_VERSION} found {version})"
-        );
-
-        let ser = rmp_serde::to_vec(store).expect("Failed to serialize");
+    pub fn write(&mut self, store: &StationStoreData) -> Result<(), StoreError> {
+        let blob = encode(store)?;
         let mut store_buf = [0u8; STORE_DATA_SIZE];
-        store_buf[0..ser.len()].copy_from_slice(&ser);
+        store_buf[0..blob.len()].copy_from_slice(&blob);
         self.nvs.set_raw(STATION_STORE_ID, &store_buf)?;
+        // (left from version 1)
+        self.nvs.remove(STATION_STORE_VERSION_ID)?;
         Ok(())
     }
 }
+
+/// Storage for data sequence numbers, separate from `StationStoreData` because it is written far more often
+pub struct SeqStore<T: NvsPartitionId> {
+    nvs: EspNvs<T>,
+}
+
+impl<T: NvsPartitionId> SeqStore<T> {
+    pub fn new(partition: EspNvsPartition<T>) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// 0 if nothing has been stored yet
+    pub fn load(&self) -> Result<u64, EspError> {
+        Ok(self.nvs.get_u64(SEQ_ID)?.unwrap_or(0))
+    }
+
+    pub fn store(&mut self, reserved: u64) -> Result<(), EspError> {
+        self.nvs.set_u64(SEQ_ID, reserved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn migrate_v1() {
+        let uuid = Uuid::new_v4();
+        // as written by version 1 (padded to `STORE_DATA_SIZE`)
+        let mut blob = rmp_serde::to_vec(&(uuid,)).unwrap();
+        assert_eq!(blob[0], 0x91);
+        blob.resize(STORE_DATA_SIZE, 0);
+        let (data, upgraded) = decode(&blob).unwrap();
+        assert!(upgraded);
+        assert_eq!(data.station_uuid, uuid);
+
+        // written back as the current version
+        let mut blob = encode(&data).unwrap();
+        assert_eq!(blob[0], CURRENT_VERSION);
+        blob.resize(STORE_DATA_SIZE, 0);
+        let (data, upgraded) = decode(&blob).unwrap();
+        assert!(!upgraded);
+        assert_eq!(data.station_uuid, uuid);
+    }
+
+    #[test]
+    fn encoded_size() {
+        // (update the note on `STORE_DATA_SIZE` if this changes)
+        assert_eq!(encode(&StationStoreData::generate()).unwrap().len(), 33);
+        assert!(matches!(
+            encode_blob(&[0u8; STORE_DATA_SIZE]),
+            Err(StoreError::TooLarge(..))
+        ));
+    }
+
+    #[test]
+    fn newer_keeps_uuid() {
+        #[derive(Serialize)]
+        struct Newer {
+            station_uuid: Uuid,
+            added: u32,
+        }
+        let uuid = Uuid::new_v4();
+        let mut blob = vec![CURRENT_VERSION + 1];
+        rmp_serde::encode::write_named(
+            &mut blob,
+            &Newer {
+                station_uuid: uuid,
+                added: 7,
+            },
+        )
+        .unwrap();
+        blob.resize(STORE_DATA_SIZE, 0);
+        assert!(matches!(decode(&blob), Err(StoreError::Newer(3))));
+        assert_eq!(decode_newer(&blob).unwrap().station_uuid, uuid);
+    }
+
+    #[test]
+    fn reject_unknown() {
+        assert!(matches!(
+            decode(&[CURRENT_VERSION + 1, 0x80]),
+            Err(StoreError::Newer(3))
+        ));
+        assert!(matches!(decode(&[]), Err(StoreError::Empty)));
+        assert!(decode_newer(&[CURRENT_VERSION + 1, 0xc1]).is_none());
+        assert!(matches!(
+            decode(&[CURRENT_VERSION, 0xc1]),
+            Err(StoreError::Deserialize(..))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+//! Setting the clock with SNTP, so that readings can be timestamped.
+//!
+//! the RTC usually starts at 1970 on boot. until it is synced readings are sent without a timestamp
+//! (and the server uses the time it received them instead)
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use esp_idf_svc::sntp::{EspSntp, SyncStatus};
+use esp_idf_sys::EspError;
+
+/// Midnight, Jan 1 2020 (unix timestamp, seconds). the server can only store times after this
+const EPOCH: i64 = 1577836800;
+/// the latest time the server can store (in 2156)
+const LATEST_PLAUSIBLE: i64 = EPOCH + u32::MAX as i64;
+
+/// if `unix_secs` could be the actual time (not an unset clock), and can be stored by the server
+pub fn clock_plausible(unix_secs: i64) -> bool {
+    unix_secs > EPOCH && unix_secs <= LATEST_PLAUSIBLE
+}
+
+/// the current time (unix timestamp, seconds), or None if the clock has not been set
+pub fn now_if_synced() -> Option<i64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    clock_plausible(now).then_some(now)
+}
+
+pub struct TimeSync {
+    // kept alive for the clock to be periodically re-synced
+    sntp: EspSntp<'static>,
+}
+
+impl TimeSync {
+    /// start syncing in the background (works before wifi is connected)
+    pub fn start() -> Result<Self, EspError> {
+        Ok(Self {
+            sntp: EspSntp::new_default()?,
+        })
+    }
+
+    /// wait until the clock has been set, or `timeout` passes. returns if it was set
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            if self.sntp.get_sync_status() == SyncStatus::Completed || now_if_synced().is_some() {
+                info!("clock synced (took {:?})", start.elapsed());
+                return true;
+            }
+            if start.elapsed() >= timeout {
+                warn!("clock was not synced within {timeout:?}, readings will not be timestamped");
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn plausible_clock() {
+    // unset
+    assert!(!clock_plausible(0));
+    assert!(!clock_plausible(60 * 60 * 24));
+    assert!(!clock_plausible(-1));
+    // 2020, exactly (not storable)
+    assert!(!clock_plausible(EPOCH));
+    assert!(clock_plausible(EPOCH + 1));
+    // 2023-11-14
+    assert!(clock_plausible(1_700_000_000));
+    // the last time the server can store
+    assert!(clock_plausible(LATEST_PLAUSIBLE));
+    assert!(!clock_plausible(LATEST_PLAUSIBLE + 1));
+    assert!(!clock_plausible(i64::MAX));
+}
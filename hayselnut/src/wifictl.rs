@@ -59,6 +59,69 @@ pub fn filter_networks(
     found
 }
 
+/// The order to try connecting to networks in: each candidate gets `attempts_per` attempts, and then
+/// the next one is tried (e.g. if the password for one is wrong)
+#[derive(Debug)]
+pub struct ConnectPlan<T> {
+    candidates: Vec<T>,
+    current: usize,
+    attempt: u32,
+    attempts_per: u32,
+}
+
+impl<T> ConnectPlan<T> {
+    /// `candidates` should be best first
+    pub fn new(candidates: Vec<T>, attempts_per: u32) -> Self {
+        assert!(attempts_per > 0);
+        Self {
+            candidates,
+            current: 0,
+            attempt: 1,
+            attempts_per,
+        }
+    }
+
+    /// the candidate to try next (None once all candidates have been exhausted)
+    pub fn current(&self) -> Option<&T> {
+        self.candidates.get(self.current)
+    }
+
+    /// attempt number for the current candidate (1..=attempts_per)
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// the current attempt failed. returns true if this moved on to a new candidate
+    pub fn failed(&mut self) -> bool {
+        if self.attempt < self.attempts_per {
+            self.attempt += 1;
+            false
+        } else {
+            self.attempt = 1;
+            self.current += 1;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn connect_plan_falls_through() {
+    let mut plan = ConnectPlan::new(vec!["wrong-password", "good"], 2);
+    assert_eq!(plan.current(), Some(&"wrong-password"));
+    assert!(!plan.failed());
+    assert_eq!((plan.current(), plan.attempt()), (Some(&"wrong-password"), 2));
+    assert!(plan.failed());
+    assert_eq!((plan.current(), plan.attempt()), (Some(&"good"), 1));
+    // all candidates exhausted
+    assert!(!plan.failed());
+    assert!(plan.failed());
+    assert_eq!(plan.current(), None);
+
+    let plan = ConnectPlan::<&str>::new(vec![], 5);
+    assert_eq!(plan.current(), None);
+}
+
 #[derive(Debug, Clone)]
 pub enum WifiStatusUpdate {
     Disconnected,